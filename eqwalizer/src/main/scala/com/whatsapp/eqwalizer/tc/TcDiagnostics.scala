@@ -21,6 +21,10 @@ object TcDiagnostics {
     def errorName: String // stable identifier for the class of error, to be used in metrics
     def docURL: String = s"https://fb.me/eqwalizer_errors#$errorName"
     def erroneousExpr: Option[Expr]
+
+    /** The types the error is about, for tools to find the definitions of the remote ones
+      */
+    def types: List[Type] = Nil
   }
   case class ExpectedSubtype(pos: Pos, expr: Expr, expected: Type, got: Type)(implicit pipelineContext: PipelineContext)
       extends TypeError {
@@ -29,6 +33,7 @@ object TcDiagnostics {
 
     def errorName = "expected_subtype"
     override def erroneousExpr: Option[Expr] = Some(expr)
+    override def types: List[Type] = List(expected, got)
     override lazy val explanation = pipelineContext.subtypeDetail.explain(expected = expected, got = got)
   }
   case class ExpectedFunType(pos: Pos, expr: Expr, expectedArity: Int, got: Type)(implicit
@@ -37,6 +42,7 @@ object TcDiagnostics {
     val msg: String = s"Expected fun type with arity $expectedArity\nGot: ${show(got)}"
     def errorName = "expected_fun_type"
     override def erroneousExpr: Option[Expr] = Some(expr)
+    override def types: List[Type] = List(got)
   }
   case class NoDynamicRemoteFun(pos: Pos, expr: Expr)(implicit pipelineContext: PipelineContext) extends TypeError {
     val msg: String = s"Dynamic calls of unknown functions are not supported."
@@ -49,6 +55,7 @@ object TcDiagnostics {
     override val msg: String = s"Not enough info to branch. Arg types: $argTysString"
     def errorName = "not_enough_info_to_branch"
     override def erroneousExpr: Option[Expr] = Some(expr)
+    override def types: List[Type] = argTys
   }
   case class LambdaArityMismatch(pos: Pos, expr: Expr, lambdaArity: Int, argsArity: Int) extends TypeError {
     override val msg: String = s"fun with arity $lambdaArity used as fun with $argsArity arguments"
@@ -98,6 +105,7 @@ object TcDiagnostics {
 
     def errorName = "incorrect_return_type_in_cb_implementation"
     override def erroneousExpr: Option[Expr] = None
+    override def types: List[Type] = List(expected, got)
   }
   case class IncorrectCallbackParams(
       behaviourName: String,
@@ -111,6 +119,7 @@ object TcDiagnostics {
       s"Parameter ${paramIndex + 1} in implementation of $behaviourName:$callback has no overlap with expected parameter type. Expected: ${show(expected)}, Got: ${show(got)}."
     def errorName = "incorrect_param_type_in_cb_implementation"
     override def erroneousExpr: Option[Expr] = None
+    override def types: List[Type] = List(expected, got)
   }
   case class UnhandledOp(pos: Pos, op: String) extends IllegalStateException(s"Position: $pos, Unhandled op: $op")
   case class RevealTypeHint(t: Type)(val pos: Pos)(implicit pipelineContext: PipelineContext) extends TypeError {
@@ -118,6 +127,7 @@ object TcDiagnostics {
     override val errorName = "reveal_type"
     override val msg = typeS
     override def erroneousExpr: Option[Expr] = None
+    override def types: List[Type] = List(t)
   }
   case class RedundantFixme(pos: Pos) extends TypeError {
     override val msg: String = "redundant fixme"
@@ -136,6 +146,7 @@ object TcDiagnostics {
       s"Redundant type test.\nVariable ${variable} has type ${show(got)} which is always a subtype of ${show(test)}"
     def errorName = "redundant_guard"
     override def erroneousExpr: Option[Expr] = None
+    override def types: List[Type] = List(test, got)
   }
   case class AmbiguousUnion(pos: Pos, expr: Expr, expected: Type, got: Type)(implicit pipelineContext: PipelineContext)
       extends TypeError {
@@ -143,5 +154,6 @@ object TcDiagnostics {
       s"Expression has type ${show(got)} which matches multiple generic types in ${show(expected)}"
     def errorName = "ambiguous_union"
     override def erroneousExpr: Option[Expr] = Some(expr)
+    override def types: List[Type] = List(expected, got)
   }
 }
@@ -10,9 +10,11 @@ import scala.collection.mutable
 
 import com.whatsapp.eqwalizer.{Pipeline, ast}
 import com.whatsapp.eqwalizer.ast.Forms.{ElpMetadata, FuncDecl, InternalForm, InvalidForm, MisBehaviour}
-import com.whatsapp.eqwalizer.ast.{Pos, Show, TextRange}
+import com.whatsapp.eqwalizer.ast.{Pos, RemoteId, Show, TextRange, TypeVars}
+import com.whatsapp.eqwalizer.ast.Types.{OpaqueType, RemoteType, Type, builtinTypes}
 import com.whatsapp.eqwalizer.ast.stub.DbApi
 import com.whatsapp.eqwalizer.io.Ipc
+import com.whatsapp.eqwalizer.tc.TcDiagnostics.{ExpectedSubtype, TypeError}
 import com.whatsapp.eqwalizer.tc.{Options, noOptions}
 
 object ELPDiagnostics {
@@ -23,6 +25,8 @@ object ELPDiagnostics {
       errorName: String,
       explanation: Option[String],
       shownExpression: Option[String],
+      remoteTypes: List[RemoteId],
+      opaqueTypes: List[RemoteId],
   )
 
   def getDiagnosticsString(module: String, astStorage: DbApi.AstStorage, options: Options = noOptions): String =
@@ -71,10 +75,43 @@ object ELPDiagnostics {
         te.errorName,
         explanation = te.explanation,
         shownExpression = te.erroneousExpr.map(Show.show),
+        remoteTypes = te.types.flatMap(remoteIds).filterNot(isLocal(module, _)).distinct.sortBy(_.toString),
+        opaqueTypes = reliedOnOpaques(te).filterNot(_.module == module).distinct.sortBy(_.toString),
       )
     }
   }
 
+  /** The ids of the remote and opaque types in `ty`
+    */
+  private def remoteIds(ty: Type): List[RemoteId] = ty match {
+    case RemoteType(id, argTys) => id :: argTys.flatMap(remoteIds)
+    case OpaqueType(id, argTys) => id :: argTys.flatMap(remoteIds)
+    case _                      => TypeVars.children(ty).flatMap(remoteIds)
+  }
+
+  /** The opaque types whose definition `te` relies on: those of the value got that the expected type doesn't have,
+    * e.g. a value of an opaque type given where its representation is expected
+    */
+  private def reliedOnOpaques(te: TypeError): List[RemoteId] = te match {
+    case ExpectedSubtype(_, _, expected, got) =>
+      val expectedOpaques = opaqueIds(expected).toSet
+      opaqueIds(got).filterNot(expectedOpaques)
+    case _ =>
+      Nil
+  }
+
+  private def opaqueIds(ty: Type): List[RemoteId] = ty match {
+    case OpaqueType(id, argTys) => id :: argTys.flatMap(opaqueIds)
+    case _                      => TypeVars.children(ty).flatMap(opaqueIds)
+  }
+
+  /** Whether `rid` is a type of `module` or a builtin one, which have no definition elsewhere to point to
+    */
+  private def isLocal(module: String, rid: RemoteId): Boolean =
+    rid.module == module ||
+      (rid.module == "erlang" && builtinTypes.contains(rid.name)) ||
+      rid == RemoteId("eqwalizer", "dynamic", 0)
+
   def toJsonObj(errorsByModule: collection.Map[String, List[Error]]): ujson.Obj = {
     ujson.Obj.from(errorsByModule.map { case (module, errors) =>
       module -> ujson.Arr.from(errors.map { e =>
@@ -104,8 +141,15 @@ object ELPDiagnostics {
           "code" -> ujson.Str(e.errorName),
           "expressionOrNull" -> expressionOrNull,
           "explanationOrNull" -> explanationOrNull,
+          "remoteTypes" -> idsToJson(e.remoteTypes),
+          "opaqueTypes" -> idsToJson(e.opaqueTypes),
         )
       })
     })
   }
+
+  private def idsToJson(ids: List[RemoteId]): ujson.Arr =
+    ujson.Arr.from(ids.map { id =>
+      ujson.Obj("module" -> id.module, "name" -> id.name, "arity" -> id.arity)
+    })
 }
@@ -0,0 +1,59 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Defines a unit of change that can be applied to the database to get the
+//! next state.
+
+use std::fmt;
+use std::sync::Arc;
+
+use vfs::FileId;
+
+use crate::SourceDatabaseExt;
+
+/// Encapsulate a bunch of raw `.set` calls on the database.
+#[derive(Default)]
+pub struct Change {
+    pub files_changed: Vec<(FileId, Option<Arc<String>>)>,
+}
+
+impl fmt::Debug for Change {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Change")
+            .field("files_changed", &self.files_changed.len())
+            .finish()
+    }
+}
+
+impl Change {
+    pub fn new() -> Change {
+        Change::default()
+    }
+
+    /// Sets the text of the file, `None` for a deleted file, whose text
+    /// becomes empty.
+    pub fn change_file(&mut self, file_id: FileId, new_text: Option<Arc<String>>) {
+        self.files_changed.push((file_id, new_text))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files_changed.is_empty()
+    }
+
+    /// Applies the change, returning the changed files, in order of their
+    /// first change.
+    pub fn apply(self, db: &mut dyn SourceDatabaseExt) -> Vec<FileId> {
+        let mut changed = Vec::new();
+        for (file_id, text) in self.files_changed {
+            let text = text.unwrap_or_default();
+            db.set_file_text(file_id, text);
+            if !changed.contains(&file_id) {
+                changed.push(file_id);
+            }
+        }
+        changed
+    }
+}
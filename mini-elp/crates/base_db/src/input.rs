@@ -94,11 +94,37 @@ pub struct ProjectData {
     pub source_roots: Vec<SourceRootId>,
     pub root_dir: AbsPathBuf,
     pub deps_ebins: Vec<AbsPathBuf>,
+    /// Additional code paths (as in `erl -pa`) for the parse server, e.g.
+    /// directories of generated modules outside of the project apps. They
+    /// are absolute: callers resolve relative paths against `root_dir`, not
+    /// the working directory. A removed path stays on the code path of a
+    /// running parse server until it is restarted.
+    pub extra_code_paths: Vec<AbsPathBuf>,
     pub build_info_path: Option<AbsPathBuf>,
+    /// Directory of hand-written specs for modules lacking their own, see
+    /// `elp_ide_db::stubs`. In-source specs win over those of stubs.
+    pub stubs_dir: Option<AbsPathBuf>,
+    /// The source root the files of `stubs_dir` are loaded in, when they
+    /// aren't in the source roots of the project. Stubs are only looked up
+    /// in the files of the database, like modules.
+    pub stubs_root: Option<SourceRootId>,
     pub otp: Otp,
+    /// OTP release the project is built for, e.g. `25`, when it isn't the
+    /// one of `otp`. The specs of OTP modules are then those of the stubs
+    /// of that release, `otp-<version>/m.erl` in the `stubs_dir`, looked up
+    /// in the files of the database like the other stubs, see
+    /// `elp_ide_db::stubs`.
+    pub otp_version: Option<String>,
     pub app_roots: AppRoots,
 }
 
+impl ProjectData {
+    /// Everything the parse server needs on its code path.
+    pub fn code_paths(&self) -> impl Iterator<Item = &AbsPathBuf> {
+        self.deps_ebins.iter().chain(self.extra_code_paths.iter())
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct AppData {
     pub project_id: ProjectId,
@@ -205,6 +231,12 @@ impl AppRoots {
         self.app_map.get(app).cloned()
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = (&AppName, SourceRootId)> + '_ {
+        self.app_map
+            .iter()
+            .map(|(app, &source_root_id)| (app, source_root_id))
+    }
+
     pub fn merge(&mut self, other: AppRoots) {
         self.app_map.extend(other.app_map);
     }
@@ -285,8 +317,12 @@ impl<'a> ProjectApps<'a> {
                 source_roots: project_source_roots,
                 root_dir: project.rebar.root.clone(),
                 deps_ebins: project.deps_ebins(),
+                extra_code_paths: vec![],
                 build_info_path: project.build_info_file(),
+                stubs_dir: None,
+                stubs_root: None,
                 otp: project.otp.clone(),
+                otp_version: None,
                 app_roots: self.app_roots(*project_id),
             };
             app_structure.add_project_data(*project_id, project_data);
@@ -7,15 +7,18 @@
 use std::sync::Arc;
 
 use elp_project_model::AppType;
+use fxhash::FxHashSet;
 use text_size::TextRange;
 use text_size::TextSize;
 
+mod change;
 mod input;
 mod module_index;
 
 // ---------------------------------------------------------------------
 // Public API
 
+pub use change::Change;
 pub use input::AppData;
 pub use input::AppRoots;
 pub use input::AppStructure;
@@ -92,6 +95,15 @@ pub trait SourceDatabase: FileLoader + salsa::Database {
     fn file_app_type(&self, file_id: FileId) -> Option<AppType>;
 
     fn file_app_name(&self, file_id: FileId) -> Option<String>;
+
+    /// The files whose text is unsaved contents instead of the one on disk.
+    #[salsa::input]
+    fn overlaid_files(&self) -> Arc<FxHashSet<FileId>>;
+
+    /// Whether the file is in `overlaid_files`. Queries about a file depend
+    /// on this rather than on `overlaid_files`, to only be recomputed when
+    /// the overlay of their own file is set or cleared.
+    fn file_has_overlay(&self, file_id: FileId) -> bool;
 }
 
 fn module_index(db: &dyn SourceDatabase, project_id: ProjectId) -> Arc<ModuleIndex> {
@@ -125,6 +137,10 @@ fn file_app_name(db: &dyn SourceDatabase, file_id: FileId) -> Option<String> {
         .map(|s| s.to_string())
 }
 
+fn file_has_overlay(db: &dyn SourceDatabase, file_id: FileId) -> bool {
+    db.overlaid_files().contains(&file_id)
+}
+
 /// We don't want to give HIR knowledge of source roots, hence we extract these
 /// methods into a separate DB.
 #[salsa::query_group(SourceDatabaseExtStorage)]
@@ -46,6 +46,9 @@ impl Borrow<str> for ModuleName {
 pub struct ModuleIndex {
     mod2file: FxHashMap<ModuleName, (FileSource, FileId)>,
     file2mod: FxHashMap<FileId, ModuleName>,
+    /// Other files defining a module of `mod2file`, which the index doesn't
+    /// resolve to.
+    shadowed: FxHashMap<ModuleName, Vec<FileId>>,
 }
 
 impl fmt::Debug for ModuleIndex {
@@ -92,14 +95,32 @@ impl ModuleIndex {
             .iter()
             .map(|(name, (source, id))| (name, *source, *id))
     }
+
+    /// The modules defined by more than one file, with the file the index
+    /// resolves them to and the other ones, shadowed by it.
+    pub fn duplicates(&self) -> impl Iterator<Item = (&ModuleName, FileId, &[FileId])> + '_ {
+        self.shadowed.iter().filter_map(move |(name, shadowed)| {
+            let (_source, id) = self.mod2file.get(name)?;
+            Some((name, *id, shadowed.as_slice()))
+        })
+    }
 }
 
 #[derive(Default)]
-pub struct Builder(FxHashMap<ModuleName, (FileSource, FileId)>);
+pub struct Builder(
+    FxHashMap<ModuleName, (FileSource, FileId)>,
+    FxHashMap<ModuleName, Vec<FileId>>,
+);
 
 impl Builder {
+    /// Adds a module, shadowing the file previously added for that name, if
+    /// any.
     pub fn insert(&mut self, file_id: FileId, source: FileSource, name: ModuleName) {
-        self.0.insert(name, (source, file_id));
+        if let Some((_source, previous)) = self.0.insert(name.clone(), (source, file_id)) {
+            if previous != file_id {
+                self.1.entry(name).or_default().push(previous);
+            }
+        }
     }
 
     pub fn build(self) -> ModuleIndex {
@@ -112,6 +133,7 @@ impl Builder {
         ModuleIndex {
             mod2file: self.0,
             file2mod,
+            shadowed: self.1,
         }
     }
 }
@@ -93,6 +93,11 @@ impl LoadResult {
     pub fn analysis(&self) -> Analysis {
         self.analysis_host.analysis()
     }
+
+    #[cfg(test)]
+    pub fn analysis_host_mut(&mut self) -> &mut AnalysisHost {
+        &mut self.analysis_host
+    }
 }
 
 pub fn load_json_project_at(json: &Path) -> Result<LoadResult> {
@@ -76,14 +76,49 @@ fn try_main(
 // cargo test --package elp --bin elp
 #[cfg(test)]
 mod tests {
+    use std::path::Path;
     use std::path::PathBuf;
     use std::str;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use std::time::Instant;
 
     use codespan_reporting::term::termcolor::Buffer;
+    use elp_eqwalizer::EqwalizerDiagnostic;
+    use elp_eqwalizer::EqwalizerDiagnostics;
+    use elp_ide::daemon;
+    use elp_ide::daemon::Command;
+    use elp_ide::output::ModuleFile;
+    use elp_ide::output::ModuleFiles;
+    use elp_ide::AnalysisHost;
+    use elp_ide::AppFilter;
+    use elp_ide::Baseline;
+    use elp_ide::BaselineEntry;
+    use elp_ide::Change;
+    use elp_ide::CheckMode;
+    use elp_ide::CheckScope;
+    use elp_ide::DiffCounts;
+    use elp_ide::DynamicPolicy;
+    use elp_ide::FingerprintedDiagnostic;
+    use elp_ide::MissingSpecPolicy;
+    use elp_ide::ModuleDiagnostics;
+    use elp_ide::Severity;
+    use elp_ide::STATE_VERSION;
+    use elp_ide_db::elp_base_db::AbsPathBuf;
+    use elp_ide_db::elp_base_db::FileId;
+    use elp_ide_db::elp_base_db::ProjectId;
+    use elp_ide_db::elp_base_db::SourceDatabase;
+    use elp_ide_db::elp_base_db::SourceDatabaseExt;
+    use elp_ide_db::DiskCache;
+    use elp_ide_db::LineIndex;
+    use elp_parse_server::Format;
+    use elp_project_model::Profile;
     use expect_test::expect_file;
     use expect_test::ExpectFile;
     use tempfile::Builder;
     use test_case::test_case;
+    use text_size::TextRange;
 
     use super::*;
 
@@ -197,6 +232,878 @@ mod tests {
         eqwalize_snapshot("parse_error", "parse_error_a", false).unwrap();
     }
 
+    #[test]
+    fn editing_a_module_keeps_diagnostics_of_independent_modules() {
+        let project_path = Path::new("../../test_projects/standard");
+        let mut loaded = load_rebar::load_project_at(project_path, &Profile::default()).unwrap();
+        let project_id = loaded.project_id;
+        let module_index = loaded.analysis().module_index(project_id);
+        let edited = module_index.file_for_module("app_a_no_errors").unwrap();
+        let independent = module_index.file_for_module("app_b").unwrap();
+
+        let host = loaded.analysis_host_mut();
+        let diagnostics = |host: &AnalysisHost| {
+            host.eqwalizer_diagnostics(project_id, vec![independent], Format::OffsetEtf, false)
+        };
+        let before = diagnostics(host);
+        let text = SourceDatabaseExt::file_text(host.raw_database(), edited);
+        host.set_file_overlay(edited, Arc::new(format!("{}\n%% edited\n", text)));
+        let after = diagnostics(host);
+        // The query would return a new `Arc` if it was recomputed
+        assert!(Arc::ptr_eq(&before, &after));
+    }
+
+    #[test]
+    fn clearing_an_overlay_reverts_to_the_latest_text_on_disk() {
+        let (mut loaded, _, file_id) = load_with_overlay("-module(app_a_no_errors).\n");
+        let host = loaded.analysis_host_mut();
+        let on_disk = Arc::new("-module(app_a_no_errors).\n%% saved\n".to_string());
+        let mut change = Change::new();
+        change.change_file(file_id, Some(on_disk.clone()));
+        host.apply_change(change);
+        let text = |host: &AnalysisHost| SourceDatabaseExt::file_text(host.raw_database(), file_id);
+        // The overlay is kept while it is set
+        assert_eq!(*text(host), "-module(app_a_no_errors).\n");
+        host.clear_file_overlay(file_id);
+        assert_eq!(text(host), on_disk);
+    }
+
+    /// The standard project, with `app_a_no_errors` replaced by `text`,
+    /// which must keep its `-module` attribute.
+    fn load_with_overlay(text: &str) -> (load_rebar::LoadResult, ProjectId, FileId) {
+        let project_path = Path::new("../../test_projects/standard");
+        let mut loaded = load_rebar::load_project_at(project_path, &Profile::default()).unwrap();
+        let project_id = loaded.project_id;
+        let file_id = loaded
+            .analysis()
+            .module_index(project_id)
+            .file_for_module("app_a_no_errors")
+            .unwrap();
+        loaded
+            .analysis_host_mut()
+            .set_file_overlay(file_id, Arc::new(text.to_string()));
+        (loaded, project_id, file_id)
+    }
+
+    #[test]
+    fn type_alias_cycles_follow_alias_chains() {
+        let (loaded, _, file_id) = load_with_overlay(
+            "-module(app_a_no_errors).\n\
+             -type a() :: b().\n\
+             -type b() :: atom() | c().\n\
+             -type c() :: a().\n\
+             -type d() :: {e()}.\n\
+             -type e() :: d().\n",
+        );
+        let cycles = loaded.analysis().type_alias_cycles(file_id).unwrap();
+        let names: Vec<Vec<(&str, u32)>> = cycles
+            .iter()
+            .map(|cycle| {
+                cycle
+                    .iter()
+                    .map(|ty| (ty.name.as_str(), ty.arity))
+                    .collect()
+            })
+            .collect();
+        // `d()` refers to `e()` in a tuple, expanding it terminates
+        assert_eq!(names, vec![vec![("a", 0), ("b", 0), ("c", 0)]]);
+    }
+
+    #[test]
+    fn module_forms_are_shared_until_the_module_changes() {
+        let (mut loaded, _, file_id) = load_with_overlay("-module(app_a_no_errors).\nf() -> ok.\n");
+        let analysis = loaded.analysis();
+        let first = analysis.module_forms(file_id, Format::OffsetEtf).unwrap();
+        let second = analysis.module_forms(file_id, Format::OffsetEtf).unwrap();
+        assert!(Arc::ptr_eq(
+            first.as_ref().unwrap(),
+            second.as_ref().unwrap()
+        ));
+        drop(analysis);
+
+        loaded.analysis_host_mut().set_file_overlay(
+            file_id,
+            Arc::new("-module(app_a_no_errors).\nf() -> ok.\ng() -> ok.\n".to_string()),
+        );
+        let changed = loaded
+            .analysis()
+            .module_forms(file_id, Format::OffsetEtf)
+            .unwrap();
+        let (first, changed) = (first.unwrap(), changed.unwrap());
+        assert!(!Arc::ptr_eq(&first, &changed));
+        assert_eq!(changed.len(), first.len() + 1);
+    }
+
+    // To run the benchmark
+    // cargo test --package elp --bin elp -- --ignored bench_module_forms --nocapture
+    #[test]
+    #[ignore]
+    fn bench_module_forms() {
+        let text: String = std::iter::once("-module(app_a_no_errors).\n".to_string())
+            .chain((0..2_000).map(|i| format!("f{}(X) -> {{X, [{}, <<\"{}\">>]}}.\n", i, i, i)))
+            .collect();
+        let (loaded, _, file_id) = load_with_overlay(&text);
+        let analysis = loaded.analysis();
+        // Parse first, for only the decoding to be timed
+        analysis.module_ast(file_id, Format::OffsetEtf).unwrap();
+        let start = Instant::now();
+        analysis
+            .module_forms(file_id, Format::OffsetEtf)
+            .unwrap()
+            .unwrap();
+        let decoded = start.elapsed();
+        let runs: u32 = 1_000;
+        let start = Instant::now();
+        for _ in 0..runs {
+            analysis
+                .module_forms(file_id, Format::OffsetEtf)
+                .unwrap()
+                .unwrap();
+        }
+        let memoized = start.elapsed() / runs;
+        // Only reported, timings vary too much between machines to assert on
+        eprintln!("decoded: {:?}, memoized: {:?}", decoded, memoized);
+    }
+
+    #[test]
+    fn type_environment_reveals_local_definitions() {
+        let (loaded, _, file_id) = load_with_overlay(
+            "-module(app_a_no_errors).\n\
+             -type pair(A) :: {A, A}.\n\
+             -opaque id() :: integer().\n",
+        );
+        let env = loaded.analysis().type_environment(file_id).unwrap();
+        assert_eq!(env.module, "app_a_no_errors");
+        assert_eq!(env.types.keys().collect::<Vec<_>>(), vec!["id/0", "pair/1"]);
+        let pair = &env.types["pair/1"];
+        assert_eq!(pair.params, vec!["A"]);
+        // Opaque types are visible in their own module
+        for ty in env.types.values() {
+            assert!(ty.definition.is_some());
+            assert_ne!(ty.definition.as_deref(), Some(elp_ide_db::reveal::NO_TYPE));
+        }
+    }
+
+    #[test]
+    fn guards_narrow_clauses_out_of_reach() {
+        let text = "-module(app_a_no_errors).\n\
+             -typing([eqwalizer]).\n\
+             -export([f/1, g/1]).\n\
+             -spec f(atom() | integer()) -> ok.\n\
+             f(X) when is_atom(X) -> ok;\n\
+             f(X) when is_integer(X) -> ok;\n\
+             f(X) when is_binary(X) -> ok.\n\
+             -spec g(none()) -> ok.\n\
+             g(Y) -> ok.\n";
+        let (loaded, _, file_id) = load_with_overlay(text);
+        let branches = loaded.analysis().unreachable_branches(file_id).unwrap();
+        let found: Vec<(usize, &str)> = branches
+            .iter()
+            .map(|branch| (branch.range.start().into(), branch.reason.as_str()))
+            .collect();
+        // `g/1` takes no value by its spec, which isn't dead code
+        assert_eq!(
+            found,
+            vec![(
+                text.find("f(X) when is_binary").unwrap(),
+                "no value of `X` passes the guard"
+            )]
+        );
+    }
+
+    /// A module opted in, with three functions returning an integer where
+    /// their specs say an atom.
+    const THREE_ERRORS: &str = "-module(app_a_no_errors).\n\
+        -typing([eqwalizer]).\n\
+        -export([a/0, b/0, c/0]).\n\
+        -spec a() -> atom().\n\
+        a() -> 1.\n\
+        -spec b() -> atom().\n\
+        b() -> 2.\n\
+        -spec c() -> atom().\n\
+        c() -> 3.\n";
+
+    /// The diagnostics of `diagnostics` for `app_a_no_errors`.
+    fn module_diagnostics(diagnostics: &EqwalizerDiagnostics) -> Vec<EqwalizerDiagnostic> {
+        match diagnostics {
+            EqwalizerDiagnostics::Diagnostics(by_module) => by_module
+                .get("app_a_no_errors")
+                .cloned()
+                .unwrap_or_default(),
+            EqwalizerDiagnostics::NoAst { module } => panic!("No AST for {}", module),
+        }
+    }
+
+    #[test]
+    fn truncated_diagnostics_keep_the_first_ones() {
+        let (loaded, project_id, file_id) = load_with_overlay(THREE_ERRORS);
+        let analysis = loaded.analysis();
+        let truncated = analysis
+            .eqwalizer_diagnostics_truncated(
+                project_id,
+                vec![file_id],
+                Format::OffsetEtf,
+                false,
+                Some(1),
+            )
+            .unwrap();
+        let truncated = module_diagnostics(&truncated);
+        let codes: Vec<&str> = truncated.iter().map(|d| d.code.as_str()).collect();
+        assert_eq!(
+            codes,
+            vec!["incompatible_types", elp_eqwalizer::TRUNCATED_CODE]
+        );
+        assert_eq!(truncated[1].message, "2 more diagnostic(s) not shown");
+
+        let all = analysis
+            .eqwalizer_diagnostics_truncated(
+                project_id,
+                vec![file_id],
+                Format::OffsetEtf,
+                false,
+                None,
+            )
+            .unwrap();
+        assert_eq!(module_diagnostics(&all).len(), 3);
+        assert_eq!(module_diagnostics(&all)[0], truncated[0]);
+    }
+
+    #[test]
+    fn code_ignores_apply_to_timed_and_tracked_checks() {
+        let text = "-module(app_a_no_errors).\n\
+             -typing([eqwalizer]).\n\
+             -export([a/0, b/0]).\n\
+             -spec a() -> atom().\n\
+             % eqwalizer:ignore incompatible_types\n\
+             a() -> 1.\n\
+             -spec b() -> atom().\n\
+             b() -> 2.\n";
+        let (loaded, project_id, file_id) = load_with_overlay(text);
+        let analysis = loaded.analysis();
+        let timed = analysis
+            .eqwalizer_diagnostics_with_timeout(
+                project_id,
+                vec![file_id],
+                Format::OffsetEtf,
+                false,
+                Duration::from_secs(60),
+            )
+            .unwrap();
+        let tracked = analysis
+            .eqwalizer_diagnostics_with_progress(
+                project_id,
+                vec![file_id],
+                Format::OffsetEtf,
+                false,
+                |_| (),
+            )
+            .unwrap();
+        for diagnostics in [timed, tracked] {
+            let found: Vec<(usize, String)> = module_diagnostics(&diagnostics)
+                .iter()
+                .map(|d| (d.range.start().into(), d.code.clone()))
+                .collect();
+            // The error of `a/0` is ignored, the ignore being used
+            assert_eq!(
+                found,
+                vec![(text.find("2.").unwrap(), "incompatible_types".to_string())]
+            );
+        }
+    }
+
+    #[test]
+    fn disk_cache_hits_make_no_parse_requests() {
+        let tmp = Builder::new().prefix("elp_disk_cache_").tempdir().unwrap();
+        let cache_dir = AbsPathBuf::assert(tmp.path().to_path_buf());
+        // As in separate runs, each starting with an empty database
+        let check = || {
+            let (mut loaded, project_id, file_id) = load_with_overlay(
+                "-module(app_a_no_errors).\n\
+                 -typing([eqwalizer]).\n\
+                 -export([a/0]).\n\
+                 -spec a() -> atom().\n\
+                 a() -> ok.\n",
+            );
+            let db = loaded.analysis_host_mut().raw_database_mut();
+            let cache = DiskCache::new(&cache_dir, db.eqwalizer()).unwrap();
+            db.set_disk_cache(Some(cache));
+            let before = loaded.analysis_host_mut().metrics_snapshot();
+            let diagnostics = loaded.analysis().eqwalizer_diagnostics(
+                project_id,
+                vec![file_id],
+                Format::OffsetEtf,
+                false,
+            );
+            assert!(module_diagnostics(&diagnostics).is_empty());
+            let after = loaded.analysis_host_mut().metrics_snapshot();
+            (
+                after.cache_hits - before.cache_hits,
+                after.parse_latency.count - before.parse_latency.count,
+            )
+        };
+        let (hits, _) = check();
+        assert_eq!(hits, 0);
+        let (hits, parses) = check();
+        assert_eq!((hits, parses), (1, 0));
+    }
+
+    #[test]
+    fn entry_points_share_the_severity_overrides_of_the_host() {
+        let (mut loaded, project_id, file_id) = load_with_overlay(THREE_ERRORS);
+        let overrides = [("incompatible_types".to_string(), Severity::Warning)];
+        let host = loaded.analysis_host_mut();
+        let revision = host.snapshot_revision();
+        host.set_severity_overrides(overrides.into_iter().collect());
+        // Presentation only, memoized results stay valid
+        assert_eq!(host.snapshot_revision(), revision);
+        let analysis = loaded.analysis();
+        let first_error = analysis
+            .eqwalizer_diagnostics_fail_fast(project_id, vec![file_id], Format::OffsetEtf)
+            .unwrap();
+        assert_eq!(first_error, None);
+        for item in
+            analysis.eqwalizer_diagnostics_stream(project_id, vec![file_id], Format::OffsetEtf)
+        {
+            match item.unwrap() {
+                (_, ModuleDiagnostics::Diagnostics(diagnostics)) => {
+                    assert_eq!(diagnostics.len(), 3);
+                    assert!(diagnostics
+                        .iter()
+                        .all(|d| d.severity() == Severity::Warning));
+                }
+                (_, ModuleDiagnostics::NoAst { module }) => panic!("No AST for {}", module),
+            }
+        }
+        let all_warnings = |diagnostics: &[EqwalizerDiagnostic]| {
+            assert!(!diagnostics.is_empty());
+            assert!(diagnostics
+                .iter()
+                .all(|d| d.severity() == Severity::Warning));
+        };
+        let files = || vec![file_id];
+        let format = Format::OffsetEtf;
+        let timeout = Duration::from_secs(60);
+        let results = [
+            analysis
+                .eqwalizer_diagnostics_truncated(project_id, files(), format, false, Some(10))
+                .unwrap(),
+            analysis
+                .eqwalizer_diagnostics_speculative(project_id, files(), format)
+                .unwrap(),
+            analysis
+                .eqwalizer_diagnostics_speculative_with_timeout(
+                    project_id,
+                    files(),
+                    format,
+                    timeout,
+                )
+                .unwrap(),
+            analysis
+                .eqwalizer_diagnostics_scoped(
+                    project_id,
+                    files(),
+                    format,
+                    CheckMode::Default,
+                    CheckScope::Full,
+                )
+                .unwrap(),
+            analysis
+                .eqwalizer_diagnostics_with_timeout(project_id, files(), format, false, timeout)
+                .unwrap(),
+            analysis
+                .eqwalizer_diagnostics_with_progress(project_id, files(), format, false, |_| ())
+                .unwrap(),
+            analysis
+                .eqwalizer_diagnostics_for_apps(
+                    project_id,
+                    AppFilter::IncludeApps(vec!["app_a".to_string()]),
+                    format,
+                )
+                .unwrap(),
+        ];
+        for diagnostics in &results {
+            all_warnings(&module_diagnostics(diagnostics));
+        }
+        let function = analysis
+            .eqwalizer_diagnostics_for_function(project_id, file_id, "a".to_string(), 0)
+            .unwrap()
+            .unwrap();
+        all_warnings(&function);
+        assert!(analysis
+            .module_is_clean(project_id, file_id, false)
+            .unwrap());
+    }
+
+    #[test]
+    fn functions_without_a_spec_are_reported_once() {
+        let (mut loaded, project_id, file_id) = load_with_overlay(
+            "-module(app_a_no_errors).\n\
+             -typing([eqwalizer]).\n\
+             -export([exported/0]).\n\
+             exported() -> local().\n\
+             local() -> ok.\n",
+        );
+        let host = loaded.analysis_host_mut();
+        host.set_dynamic_policy(DynamicPolicy::Warn);
+        host.set_missing_spec_policy(MissingSpecPolicy::WarnExported);
+        let diagnostics = loaded.analysis().eqwalizer_diagnostics(
+            project_id,
+            vec![file_id],
+            Format::OffsetEtf,
+            false,
+        );
+        let diagnostics = module_diagnostics(&diagnostics);
+        let codes: Vec<&str> = diagnostics.iter().map(|d| d.code.as_str()).collect();
+        // `exported/0` as a missing spec only, `local/0` as implicitly dynamic
+        assert_eq!(
+            codes,
+            vec![
+                elp_eqwalizer::MISSING_SPEC_CODE,
+                elp_eqwalizer::IMPLICIT_DYNAMIC_CODE
+            ]
+        );
+    }
+
+    #[test]
+    fn macro_contexts_point_at_user_macro_calls_only() {
+        let text = "-module(app_a_no_errors).\n\
+             -typing([eqwalizer]).\n\
+             -export([a/0, b/0]).\n\
+             -define(ONE, 1).\n\
+             -spec a() -> atom().\n\
+             a() -> ?ONE.\n\
+             -spec b() -> atom().\n\
+             b() -> ?LINE.\n";
+        let (loaded, project_id, file_id) = load_with_overlay(text);
+        let diagnostics = loaded.analysis().eqwalizer_diagnostics(
+            project_id,
+            vec![file_id],
+            Format::OffsetEtf,
+            false,
+        );
+        let found: Vec<(usize, Option<(String, usize, usize)>)> = module_diagnostics(&diagnostics)
+            .iter()
+            .map(|d| {
+                let context = d.macro_context.as_ref().map(|context| {
+                    (
+                        context.name.clone(),
+                        context.call_site.start().into(),
+                        context.call_site.end().into(),
+                    )
+                });
+                (d.range.start().into(), context)
+            })
+            .collect();
+        // `?LINE` is predefined, there is no definition to expand
+        let one = text.find("?ONE").unwrap();
+        assert_eq!(
+            found,
+            vec![
+                (one, Some(("ONE".to_string(), one, one + "?ONE".len()))),
+                (text.find("?LINE").unwrap(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn overloaded_specs_differ_from_the_enforced_type() {
+        let (loaded, _, file_id) = load_with_overlay(
+            "-module(app_a_no_errors).\n\
+             -typing([eqwalizer]).\n\
+             -export([f/1]).\n\
+             -spec f(atom()) -> atom(); (integer()) -> integer().\n\
+             f(X) -> X.\n",
+        );
+        let spec = loaded
+            .analysis()
+            .effective_function_spec(file_id, "f".to_string(), 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            spec.declared,
+            "-spec f(atom()) -> atom(); (integer()) -> integer()"
+        );
+        assert_eq!(spec.declared_clauses.len(), 2);
+        assert!(
+            spec.declared_clauses
+                .iter()
+                .all(|clause| clause.starts_with("fun(")),
+            "{:?}",
+            spec.declared_clauses
+        );
+        assert!(spec.differs());
+    }
+
+    #[test]
+    fn opaque_violations_rely_on_foreign_opaque_types() {
+        let text = "-module(app_a_no_errors).\n\
+             -typing([eqwalizer]).\n\
+             -export([open/1, local/0]).\n\
+             -spec open(app_a_mod2:o()) -> {o, atom()}.\n\
+             open(O) -> O.\n\
+             -spec local() -> atom().\n\
+             local() -> 1.\n";
+        let (mut loaded, project_id, file_id) = load_with_overlay(text);
+        let mod2 = loaded
+            .analysis()
+            .module_index(project_id)
+            .file_for_module("app_a_mod2")
+            .unwrap();
+        loaded.analysis_host_mut().set_file_overlay(
+            mod2,
+            Arc::new(
+                "-module(app_a_mod2).\n\
+                 -export_type([o/0]).\n\
+                 -opaque o() :: {o, atom()}.\n"
+                    .to_string(),
+            ),
+        );
+        let violations = loaded
+            .analysis()
+            .opaque_violations(project_id, vec![file_id])
+            .unwrap();
+        let found: Vec<(&str, usize, Vec<String>)> = violations
+            .iter()
+            .map(|(module, d)| {
+                let opaques = d
+                    .opaque_types
+                    .iter()
+                    .map(|id| format!("{}:{}/{}", id.module, id.name, id.arity))
+                    .collect();
+                (module.as_str(), d.range.start().into(), opaques)
+            })
+            .collect();
+        // The error of `local/0` relies on no opaque type
+        assert_eq!(
+            found,
+            vec![(
+                "app_a_no_errors",
+                text.find("O.\n").unwrap(),
+                vec!["app_a_mod2:o/0".to_string()]
+            )]
+        );
+    }
+
+    #[test]
+    fn metrics_count_queries_and_checks() {
+        let (mut loaded, project_id, file_id) = load_with_overlay(THREE_ERRORS);
+        let before = loaded.analysis_host_mut().metrics_snapshot();
+        for _ in 0..2 {
+            loaded.analysis().eqwalizer_diagnostics(
+                project_id,
+                vec![file_id],
+                Format::OffsetEtf,
+                false,
+            );
+        }
+        let after = loaded.analysis_host_mut().metrics_snapshot();
+        // Both queries are counted, the module is checked by the first only
+        assert_eq!(after.queries - before.queries, 2);
+        assert_eq!(after.modules_checked - before.modules_checked, 1);
+        assert_eq!(after.diagnostics - before.diagnostics, 3);
+        assert!(after.check_latency.count > before.check_latency.count);
+    }
+
+    #[test]
+    fn restored_states_keep_the_texts_but_not_the_overlays() {
+        let (mut loaded, project_id, file_id) = load_with_overlay(THREE_ERRORS);
+        let bytes = loaded.analysis_host_mut().serialize_state(&[project_id]);
+        let restored = AnalysisHost::deserialize_state(&bytes).unwrap();
+        let db = restored.raw_database();
+        assert_eq!(*SourceDatabaseExt::file_text(db, file_id), THREE_ERRORS);
+        assert!(db.overlaid_files().is_empty());
+        let app_b = |host: &AnalysisHost| {
+            host.analysis()
+                .module_index(project_id)
+                .file_for_module("app_b")
+        };
+        assert!(app_b(&restored).is_some());
+        assert_eq!(app_b(&restored), app_b(loaded.analysis_host_mut()));
+    }
+
+    #[test]
+    fn states_of_another_version_are_rejected() {
+        let (mut loaded, project_id, _) = load_with_overlay(THREE_ERRORS);
+        let bytes = loaded.analysis_host_mut().serialize_state(&[project_id]);
+        let mut state: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        state["version"] = (STATE_VERSION + 1).into();
+        let bytes = serde_json::to_vec(&state).unwrap();
+        let error = AnalysisHost::deserialize_state(&bytes).err().unwrap();
+        assert!(error.to_string().starts_with("state of version"));
+    }
+
+    /// Diagnostics of a module `exported` whose file is at `path`, with the
+    /// characters the exports escape. Columns of the first one differ in
+    /// bytes, characters and UTF-16 code units: its `1` is at byte 18 of
+    /// its line, character 14 and code unit 15.
+    fn exported_diagnostics(path: &str) -> (EqwalizerDiagnostics, ModuleFiles) {
+        let text = "-module(exported).\nf() -> {\"é😀\", 1}.\ng() -> 2.\n";
+        let diagnostic =
+            |at: &str, code: &str, message: &str, explanation: Option<&str>| EqwalizerDiagnostic {
+                range: TextRange::at((text.find(at).unwrap() as u32).into(), 1.into()),
+                message: message.to_string(),
+                uri: format!("https://fb.me/eqwalizer_errors#{}", code),
+                code: code.to_string(),
+                expression: None,
+                explanation: explanation.map(str::to_string),
+                fixes: vec![],
+                remote_types: vec![],
+                opaque_types: vec![],
+                related: vec![],
+                severity_override: None,
+                macro_context: None,
+            };
+        let diagnostics = vec![
+            diagnostic(
+                "1}",
+                "incompatible_types",
+                "`1`.\nExpression has type:   number()\nContext expected type: 'ok' | <<\"é\">>",
+                None,
+            ),
+            diagnostic(
+                "2.",
+                "incompatible_types",
+                "`2`.\nExpression has type:   number()\nContext expected type: atom()",
+                Some("number() & atom() have no value in common"),
+            ),
+            diagnostic("2.", "reveal_type", "number(), 100% of the time", None),
+        ];
+        let file = ModuleFile {
+            path: path.to_string(),
+            line_index: Arc::new(LineIndex::new(text)),
+        };
+        (
+            EqwalizerDiagnostics::Diagnostics(
+                [("exported".to_string(), diagnostics)]
+                    .into_iter()
+                    .collect(),
+            ),
+            [("exported".to_string(), file)].into_iter().collect(),
+        )
+    }
+
+    #[test]
+    fn sarif_export_matches_snapshot() {
+        let (diagnostics, files) = exported_diagnostics("src/exported.erl");
+        let sarif = elp_ide::sarif::to_sarif(&diagnostics, &files);
+        expect_file!["../resources/test/exports/exported.sarif"]
+            .assert_eq(&format!("{:#}\n", sarif));
+    }
+
+    #[test]
+    fn junit_export_matches_snapshot() {
+        let (diagnostics, files) = exported_diagnostics("src/exported.erl");
+        let junit = elp_ide::junit::to_junit(&diagnostics, &files);
+        expect_file!["../resources/test/exports/exported.junit.xml"].assert_eq(&junit);
+    }
+
+    #[test]
+    fn github_annotations_match_snapshot() {
+        // Separators of properties in the path are escaped too
+        let (diagnostics, files) = exported_diagnostics("apps/a,b:c%/src/exported.erl");
+        let annotations = elp_ide::github::to_github_annotations(&diagnostics, &files);
+        expect_file!["../resources/test/exports/exported.github"].assert_eq(&annotations);
+    }
+
+    #[test]
+    fn compiler_format_export_matches_snapshot() {
+        let (diagnostics, files) = exported_diagnostics("src/exported.erl");
+        let messages = elp_ide::compiler_format::to_compiler_format(&diagnostics, &files);
+        expect_file!["../resources/test/exports/exported.compiler"].assert_eq(&messages);
+    }
+
+    #[test]
+    fn daemon_cancels_the_queries_before_a_change_and_drains_on_shutdown() {
+        let (mut loaded, project_id, file_id) = load_with_overlay(THREE_ERRORS);
+        let other = loaded
+            .analysis()
+            .module_index(project_id)
+            .file_for_module("app_b")
+            .unwrap();
+        let host = std::mem::take(loaded.analysis_host_mut());
+        let text = SourceDatabaseExt::file_text(host.raw_database(), other);
+        let mut change = Change::new();
+        change.change_file(other, Some(Arc::new(format!("{}\n%% edited\n", text))));
+        let query = || {
+            let (reply, replies) = mpsc::channel();
+            let command = Command::Diagnostics {
+                project_id,
+                file_ids: vec![file_id],
+                format: Format::OffsetEtf,
+                strict: false,
+                reply,
+            };
+            (command, replies)
+        };
+        let (before, before_replies) = query();
+        let (after, after_replies) = query();
+        let (tx, rx) = mpsc::channel();
+        // Sent upfront, for the change to come while the first query runs
+        for command in [
+            before,
+            Command::ApplyChange(change),
+            after,
+            Command::Shutdown,
+        ] {
+            tx.send(command).unwrap();
+        }
+        daemon::run_with_threads(host, rx, 1).unwrap();
+        // Both queries replied before the loop stopped
+        assert!(matches!(before_replies.try_recv(), Ok(Err(_))));
+        let diagnostics = after_replies.try_recv().unwrap().unwrap();
+        assert_eq!(module_diagnostics(&diagnostics).len(), 3);
+    }
+
+    /// `THREE_ERRORS` with lines added above the functions, moving their
+    /// diagnostics down.
+    fn moved_down(text: &str) -> String {
+        text.replace("-spec a()", "%% Moved down\n\n-spec a()")
+    }
+
+    #[test]
+    fn fingerprints_survive_edits_above_the_diagnostics() {
+        let (mut loaded, project_id, file_id) = load_with_overlay(THREE_ERRORS);
+        let fingerprinted = |host: &AnalysisHost| {
+            host.analysis()
+                .eqwalizer_diagnostics_with_fingerprints(
+                    project_id,
+                    vec![file_id],
+                    Format::OffsetEtf,
+                    false,
+                )
+                .unwrap()
+        };
+        let fingerprints = |diagnostics: &[FingerprintedDiagnostic]| {
+            let mut fingerprints: Vec<u64> = diagnostics.iter().map(|d| d.fingerprint).collect();
+            fingerprints.sort_unstable();
+            fingerprints
+        };
+        let before = fingerprinted(loaded.analysis_host_mut());
+        let host = loaded.analysis_host_mut();
+        host.set_file_overlay(file_id, Arc::new(moved_down(THREE_ERRORS)));
+        let after = fingerprinted(host);
+        assert_ne!(before[0].diagnostic.range, after[0].diagnostic.range);
+        assert_eq!(fingerprints(&before), fingerprints(&after));
+        // The three errors are about different expressions
+        let mut distinct = fingerprints(&before);
+        distinct.dedup();
+        assert_eq!(distinct.len(), 3);
+    }
+
+    /// `THREE_ERRORS` moved down, with `c/0` fixed and a new error in `d/0`.
+    fn fixed_c_broke_d() -> String {
+        format!(
+            "{}-spec d() -> atom().\nd() -> 4.\n",
+            moved_down(THREE_ERRORS)
+                .replace("c/0]", "c/0, d/0]")
+                .replace("c() -> 3.", "c() -> three.")
+        )
+    }
+
+    #[test]
+    fn diffs_match_the_diagnostics_that_moved() {
+        let (mut loaded, project_id, file_id) = load_with_overlay(THREE_ERRORS);
+        let base = loaded.analysis().eqwalizer_diagnostics(
+            project_id,
+            vec![file_id],
+            Format::OffsetEtf,
+            false,
+        );
+        let text = fixed_c_broke_d();
+        let host = loaded.analysis_host_mut();
+        host.set_file_overlay(file_id, Arc::new(text.clone()));
+        let diff = host
+            .analysis()
+            .eqwalizer_diagnostics_diff(project_id, &base, vec![file_id], Format::OffsetEtf, false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            diff.counts(),
+            DiffCounts {
+                added: 1,
+                removed: 1,
+                unchanged: 2,
+            }
+        );
+        let start = |d: &FingerprintedDiagnostic| -> usize { d.diagnostic.range.start().into() };
+        assert_eq!(start(&diff.added[0]), text.find("4.").unwrap());
+        assert_eq!(start(&diff.removed[0]), THREE_ERRORS.find("3.").unwrap());
+    }
+
+    #[test]
+    fn baselines_only_report_new_diagnostics_and_stale_entries() {
+        let (mut loaded, project_id, file_id) = load_with_overlay(THREE_ERRORS);
+        let known = loaded
+            .analysis()
+            .eqwalizer_diagnostics_with_fingerprints(
+                project_id,
+                vec![file_id],
+                Format::OffsetEtf,
+                false,
+            )
+            .unwrap();
+        let tmp = Builder::new().prefix("elp_baseline_").tempdir().unwrap();
+        let path = tmp.path().join("baseline.json");
+        Baseline::new(&known).save(&path).unwrap();
+        let baseline = Baseline::load(&path).unwrap();
+        assert_eq!(baseline, Baseline::new(&known));
+
+        let text = fixed_c_broke_d();
+        let host = loaded.analysis_host_mut();
+        host.set_file_overlay(file_id, Arc::new(text.clone()));
+        let check = host
+            .analysis()
+            .eqwalizer_diagnostics_against_baseline(
+                project_id,
+                vec![file_id],
+                Format::OffsetEtf,
+                &baseline,
+            )
+            .unwrap()
+            .unwrap();
+        let new: Vec<usize> = check
+            .new
+            .iter()
+            .map(|d| d.diagnostic.range.start().into())
+            .collect();
+        assert_eq!(new, vec![text.find("4.").unwrap()]);
+        let fixed = known
+            .iter()
+            .find(|d| usize::from(d.diagnostic.range.start()) == THREE_ERRORS.find("3.").unwrap())
+            .unwrap();
+        assert_eq!(
+            check.stale,
+            vec![BaselineEntry {
+                module: "app_a_no_errors".to_string(),
+                fingerprint: fixed.fingerprint,
+                code: "incompatible_types".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn only_leading_comments_mark_files_generated() {
+        // Split, for this file not to be seen as generated itself
+        let marker = concat!("%% @", "generated");
+        let skipped = |text: String| {
+            let (loaded, project_id, file_id) = load_with_overlay(&text);
+            loaded
+                .analysis()
+                .eqwalizer_diagnostics_skipping_generated(
+                    project_id,
+                    vec![file_id],
+                    Format::OffsetEtf,
+                    false,
+                    true,
+                )
+                .unwrap()
+                .skipped_generated
+        };
+        let module = "-module(app_a_no_errors).\n";
+        assert_eq!(skipped(format!("%% Header\n\n{}\n{}", marker, module)), 1);
+        assert_eq!(skipped(format!("{}{}\n", module, marker)), 0);
+    }
+
     #[test_case(false ; "rebar")]
     #[test_case(true  ; "JSON")]
     fn eqwalize_module_diagnostics_match_snapshot_jsonl(json_config: bool) {
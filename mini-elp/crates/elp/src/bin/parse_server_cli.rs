@@ -98,7 +98,7 @@ pub fn do_parse_one(
     format: parse_server::Format,
 ) -> Result<Vec<ParseDiagnostic>> {
     let ext = match format {
-        parse_server::Format::OffsetEtf { .. } => "etf",
+        parse_server::Format::OffsetEtf { .. } | parse_server::Format::ExpandedForms => "etf",
         parse_server::Format::Text => panic!("text format is for test purposes only!"),
     };
 
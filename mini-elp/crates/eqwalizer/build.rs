@@ -5,6 +5,7 @@
  */
 
 use std::env;
+use std::fmt::Write;
 use std::fs;
 use std::path::Path;
 use std::process::Command;
@@ -16,6 +17,8 @@ fn main() {
     let dest_path = Path::new(&out_dir).join("eqwalizer");
     let extension;
 
+    write_diagnostic_codes(source_directory, Path::new(&out_dir));
+
     if let Some(path) = env::var_os("ELP_EQWALIZER_PATH") {
         let from = Path::new(&path);
         extension = from
@@ -55,6 +58,98 @@ fn main() {
     println!("cargo:rerun-if-env-changed=ELP_EQWALIZER_PATH");
 }
 
+/// Generates the table of `codes::all_diagnostic_codes` from the eqWAlizer
+/// sources: every `errorName` defined there, described by the first
+/// paragraph of its section in the error reference.
+fn write_diagnostic_codes(source_directory: &Path, out_dir: &Path) {
+    let scala_dir = source_directory.join("src/main/scala");
+    let errors_doc = source_directory.join("../docs/reference/errors.md");
+    rerun_if_changed(&scala_dir);
+    rerun_if_changed(&errors_doc);
+
+    let mut codes = Vec::new();
+    if scala_dir.exists() {
+        collect_error_names(&scala_dir, &mut codes);
+    } else {
+        println!(
+            "cargo:warning=eqWAlizer sources not found at {}, no diagnostic codes available",
+            scala_dir.display()
+        );
+    }
+    codes.sort();
+    codes.dedup_by(|(code1, _), (code2, _)| code1 == code2);
+    let doc = fs::read_to_string(&errors_doc).unwrap_or_default();
+
+    let mut out = String::from("static DIAGNOSTIC_CODES: &[DiagnosticDescriptor] = &[\n");
+    for (code, class) in &codes {
+        writeln!(
+            out,
+            "    DiagnosticDescriptor {{ code: {:?}, title: {:?}, explanation: {:?} }},",
+            code,
+            title(class),
+            explanation(&doc, code)
+        )
+        .unwrap();
+    }
+    out.push_str("];\n");
+    fs::write(out_dir.join("diagnostic_codes.rs"), out).expect("Writing diagnostic codes failed");
+}
+
+/// Pairs of `errorName` and name of the defining case class.
+fn collect_error_names(dir: &Path, acc: &mut Vec<(String, String)>) {
+    for entry in fs::read_dir(dir).expect("Reading eqWAlizer sources failed") {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect_error_names(&path, acc);
+        } else if path.extension().map_or(false, |ext| ext == "scala") {
+            let source = fs::read_to_string(&path).unwrap();
+            let mut class = "";
+            for line in source.lines() {
+                if let Some(rest) = line.trim_start().strip_prefix("case class ") {
+                    class = rest.split(|c: char| !c.is_alphanumeric()).next().unwrap();
+                }
+                if let Some((_, rest)) = line.split_once("errorName = \"") {
+                    if let Some((code, _)) = rest.split_once('"') {
+                        acc.push((code.to_string(), class.to_string()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// `ExpectedSubtype` -> `Expected subtype`
+fn title(class: &str) -> String {
+    let mut title = String::new();
+    for c in class.chars() {
+        if c.is_uppercase() && !title.is_empty() {
+            title.push(' ');
+            title.extend(c.to_lowercase());
+        } else {
+            title.push(c);
+        }
+    }
+    title
+}
+
+/// First paragraph of the `### code` section of the error reference.
+fn explanation(doc: &str, code: &str) -> String {
+    let heading = format!("### {}", code);
+    let lines = doc
+        .lines()
+        .skip_while(|line| line.trim_end() != heading)
+        .skip(1)
+        .skip_while(|line| line.trim().is_empty());
+    let mut paragraph: Vec<&str> = Vec::new();
+    for line in lines {
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with("```") {
+            break;
+        }
+        paragraph.push(line.trim());
+    }
+    paragraph.join(" ")
+}
+
 fn rerun_if_changed(path: impl AsRef<Path>) {
     println!("cargo:rerun-if-changed={}", path.as_ref().display());
 }
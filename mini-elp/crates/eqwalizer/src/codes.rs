@@ -0,0 +1,39 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! The diagnostic codes eqWAlizer can report. The table is generated by
+//! the build script from the eqWAlizer sources and the error reference, see
+//! `write_diagnostic_codes` in `build.rs`.
+
+use crate::Severity;
+
+/// Description of a diagnostic code, for legends and documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiagnosticDescriptor {
+    /// The code, as in `EqwalizerDiagnostic::code`.
+    pub code: &'static str,
+    /// A short title, e.g. "Expected subtype".
+    pub title: &'static str,
+    /// The introduction of the code in the error reference, empty for
+    /// undocumented codes.
+    pub explanation: &'static str,
+}
+
+impl DiagnosticDescriptor {
+    /// The severity of diagnostics with this code, see
+    /// `EqwalizerDiagnostic::severity`.
+    pub fn default_severity(&self) -> Severity {
+        Severity::for_code(self.code)
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/diagnostic_codes.rs"));
+
+/// All the codes eqWAlizer reports, sorted. Codes added by ELP itself, such
+/// as timeouts, aren't included.
+pub fn all_diagnostic_codes() -> &'static [DiagnosticDescriptor] {
+    DIAGNOSTIC_CODES
+}
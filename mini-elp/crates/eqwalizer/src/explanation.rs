@@ -0,0 +1,138 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Explanations of subtyping failures, as a chain of obligations.
+//!
+//! eqWAlizer flattens the chain into `EqwalizerDiagnostic::explanation`,
+//! see `SubtypeDetail.explain`: one `T1 is not a subtype of T2` line per
+//! obligation, optionally preceded and followed by a reason, the
+//! obligations being separated by `because`. This recovers the chain from
+//! that text.
+
+use text_size::TextRange;
+
+use crate::EqwalizerDiagnostic;
+
+const SEPARATOR: &str = "\n  because\n";
+const NOT_SUBTYPE: &str = " is not a subtype of ";
+const INDENT: &str = "  ";
+
+/// Why eqWAlizer found a type not to be a subtype of another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    /// eqWAlizer only reports the span of the diagnostic itself, which all
+    /// the obligations apply to.
+    pub range: TextRange,
+    pub root: Obligation,
+}
+
+/// A subtyping obligation that doesn't hold, because of `because` if
+/// eqWAlizer went further.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Obligation {
+    pub got: String,
+    pub expected: String,
+    /// Where the obligation comes from, e.g. `at tuple index 1:`.
+    pub context: Option<String>,
+    /// Why the obligation doesn't hold, when not because of another one,
+    /// e.g. the keys of maps that don't match.
+    pub reason: Option<String>,
+    pub because: Option<Box<Obligation>>,
+}
+
+impl Explanation {
+    /// The chain as an indented tree, one obligation per level.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut obligation = Some(&self.root);
+        let mut depth = 0;
+        while let Some(o) = obligation {
+            let indent = INDENT.repeat(depth);
+            let because = if depth > 0 { "because " } else { "" };
+            if let Some(context) = &o.context {
+                push_lines(&mut out, &indent, context);
+            }
+            out.push_str(&format!(
+                "{}{}{}{}{}\n",
+                indent, because, o.got, NOT_SUBTYPE, o.expected
+            ));
+            if let Some(reason) = &o.reason {
+                push_lines(&mut out, &format!("{}{}", indent, INDENT), reason);
+            }
+            obligation = o.because.as_deref();
+            depth += 1;
+        }
+        out
+    }
+}
+
+fn push_lines(out: &mut String, indent: &str, text: &str) {
+    for line in text.lines() {
+        out.push_str(indent);
+        out.push_str(line);
+        out.push('\n');
+    }
+}
+
+impl EqwalizerDiagnostic {
+    /// The explanation of the diagnostic as a chain of obligations, `None`
+    /// if eqWAlizer gave none or it can't be read.
+    pub fn explanation_tree(&self) -> Option<Explanation> {
+        let explanation = self.explanation.as_deref()?;
+        let mut obligations = Vec::new();
+        if explanation.contains(NOT_SUBTYPE) {
+            for step in explanation.split(SEPARATOR) {
+                obligations.push(parse_obligation(step)?);
+            }
+        } else {
+            // A single obligation with a reason, whose types are only in
+            // the message
+            let (expected, got) = self.message.split_once("\nGot     : ")?;
+            obligations.push(Obligation {
+                got: got.to_string(),
+                expected: expected.strip_prefix("Expected: ")?.to_string(),
+                context: None,
+                reason: Some(unindent(explanation.lines())),
+                because: None,
+            });
+        }
+        let root = obligations
+            .into_iter()
+            .rev()
+            .reduce(|because, mut obligation| {
+                obligation.because = Some(Box::new(because));
+                obligation
+            })?;
+        Some(Explanation {
+            range: self.range,
+            root,
+        })
+    }
+}
+
+/// `[context\n]  T1 is not a subtype of T2[\n  reason]`, the types being
+/// on a single line.
+fn parse_obligation(step: &str) -> Option<Obligation> {
+    let lines: Vec<&str> = step.lines().collect();
+    let i = lines.iter().position(|line| line.contains(NOT_SUBTYPE))?;
+    let (got, expected) = lines[i].trim_start().split_once(NOT_SUBTYPE)?;
+    let context = Some(unindent(lines[..i].iter().copied())).filter(|c| !c.is_empty());
+    let reason = Some(unindent(lines[i + 1..].iter().copied())).filter(|r| !r.is_empty());
+    Some(Obligation {
+        got: got.to_string(),
+        expected: expected.to_string(),
+        context,
+        reason,
+        because: None,
+    })
+}
+
+fn unindent<'a>(lines: impl Iterator<Item = &'a str>) -> String {
+    lines
+        .map(|line| line.strip_prefix(INDENT).unwrap_or(line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -52,6 +52,9 @@ pub enum MsgToEqWAlizer {
     CannotCompleteRequest,
 }
 
+/// How long to wait for a single message from eqWAlizer by default.
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(20);
+
 pub struct IpcHandle {
     writer: BufWriter<TimeoutWriter<ChildStdin>>,
     reader: BufReader<TimeoutReader<ChildStdout>>,
@@ -59,7 +62,7 @@ pub struct IpcHandle {
 }
 
 impl IpcHandle {
-    pub fn from_command(cmd: &mut Command) -> Result<Self> {
+    pub fn from_command(cmd: &mut Command, read_timeout: Duration) -> Result<Self> {
         cmd.stdin(Stdio::piped())
             .stdout(Stdio::piped())
             // for debugging purposes
@@ -77,7 +80,7 @@ impl IpcHandle {
 
         let _child_for_drop = JodChild(child);
         let writer = BufWriter::new(TimeoutWriter::new(stdin, Duration::from_secs(1)));
-        let reader = BufReader::new(TimeoutReader::new(stdout, Duration::from_secs(20)));
+        let reader = BufReader::new(TimeoutReader::new(stdout, read_timeout));
         Ok(Self {
             writer,
             reader,
@@ -4,10 +4,14 @@
  * the LICENSE file in the root directory of this source tree.
  */
 
+use std::collections::BTreeMap;
 use std::convert::TryInto;
 use std::env;
 use std::ffi::OsString;
+use std::fmt;
 use std::fs;
+use std::hash::Hasher;
+use std::io;
 use std::io::Write;
 use std::marker::PhantomData;
 use std::ops::Deref;
@@ -18,16 +22,28 @@ use std::path::PathBuf;
 use std::process::Command;
 use std::process::ExitStatus;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 
 use anyhow::Context;
 use anyhow::Result;
+use elp_parse_server::Format;
 use fxhash::FxHashMap;
+use fxhash::FxHasher64;
 use serde::Deserialize;
+use serde::Serialize;
 use tempfile::Builder;
 use tempfile::TempPath;
 use text_size::TextRange;
+use vfs::FileId;
 
+mod codes;
+mod explanation;
 mod ipc;
+pub use codes::all_diagnostic_codes;
+pub use codes::DiagnosticDescriptor;
+pub use explanation::Explanation;
+pub use explanation::Obligation;
 use ipc::IpcHandle;
 use ipc::MsgFromEqWAlizer;
 use ipc::MsgToEqWAlizer;
@@ -37,20 +53,54 @@ use ipc::MsgToEqWAlizer;
 pub struct Eqwalizer {
     cmd: OsString,
     args: Vec<OsString>,
+    version: String,
     // Used only for the Drop implementation
     _file: Option<Arc<TempPath>>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// The versions of the eqWAlizer executable and of the parse server
+/// protocol in use, see `Eqwalizer::version_info`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionInfo {
+    /// See `Eqwalizer::version`.
+    pub eqwalizer: String,
+    /// See `elp_parse_server::PROTOCOL_VERSION`.
+    pub parse_server_protocol: u32,
+    /// The AST formats the parse server supports.
+    pub formats: Vec<Format>,
+}
+
+impl fmt::Display for VersionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let formats: Vec<String> = self
+            .formats
+            .iter()
+            .map(|format| format!("{:?}", format))
+            .collect();
+        write!(
+            f,
+            "eqWAlizer {}, parse server protocol {}, formats {}",
+            self.eqwalizer,
+            self.parse_server_protocol,
+            formats.join(", ")
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
 pub enum EqwalizerDiagnostics {
     Diagnostics(FxHashMap<String, Vec<EqwalizerDiagnostic>>),
     NoAst { module: String },
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct EqwalizerDiagnostic {
-    #[serde(deserialize_with = "deserialize_text_range")]
+    #[serde(
+        deserialize_with = "deserialize_text_range",
+        serialize_with = "serialize_text_range"
+    )]
     pub range: TextRange,
     pub message: String,
     pub uri: String,
@@ -59,11 +109,521 @@ pub struct EqwalizerDiagnostic {
     pub expression: Option<String>,
     #[serde(rename(deserialize = "explanationOrNull"))]
     pub explanation: Option<String>,
+    /// Suggested fix, as edits of the text the diagnostic was computed
+    /// from. eqWAlizer itself doesn't suggest any, they are added by ELP.
+    #[serde(default, skip_deserializing, skip_serializing_if = "Vec::is_empty")]
+    pub fixes: Vec<TextEdit>,
+    /// The types of other modules the diagnostic is about, as eqWAlizer
+    /// reports them, builtin types left out. Only used to find their
+    /// definitions, see `related`.
+    #[serde(default, rename(deserialize = "remoteTypes"), skip_serializing)]
+    pub remote_types: Vec<RemoteTypeId>,
+    /// The opaque types of other modules whose definition the code relies
+    /// on, as eqWAlizer reports them, e.g. for a value of one given where
+    /// its representation is expected.
+    #[serde(default, rename(deserialize = "opaqueTypes"), skip_serializing)]
+    pub opaque_types: Vec<RemoteTypeId>,
+    /// Definitions of the types of other modules the diagnostic is about.
+    /// eqWAlizer doesn't report them either, they are added by ELP.
+    #[serde(default, skip_deserializing, skip_serializing_if = "Vec::is_empty")]
+    pub related: Vec<RelatedLocation>,
+    /// Severity replacing the one derived from the code, e.g. when a
+    /// diagnostic filter downgrades it or a severity override applies to
+    /// the code. Set by ELP only.
+    #[serde(
+        default,
+        rename(serialize = "severity_override"),
+        skip_deserializing,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub severity_override: Option<Severity>,
+    /// The macro call the diagnostic comes from the expansion of, if any.
+    /// Added by ELP.
+    #[serde(
+        default,
+        rename(serialize = "macro_context"),
+        skip_deserializing,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub macro_context: Option<MacroContext>,
+}
+
+/// A macro call, e.g. `?assertEqual(A, B)`.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct MacroContext {
+    /// Name of the macro, without the `?`.
+    pub name: String,
+    /// The whole call, arguments included. Diagnostics of the expansion
+    /// have this range, or one starting with it.
+    #[serde(serialize_with = "serialize_text_range")]
+    pub call_site: TextRange,
+}
+
+/// Replaces `range` with `replacement`.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct TextEdit {
+    #[serde(serialize_with = "serialize_text_range")]
+    pub range: TextRange,
+    pub replacement: String,
+}
+
+/// A type of another module, `module:name/arity`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub struct RemoteTypeId {
+    pub module: String,
+    pub name: String,
+    pub arity: u32,
+}
+
+/// A location outside of the diagnostic's module, e.g. where a remote type
+/// it mentions is defined.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct RelatedLocation {
+    #[serde(serialize_with = "serialize_file_id")]
+    pub file_id: FileId,
+    #[serde(serialize_with = "serialize_text_range")]
+    pub range: TextRange,
+    pub message: String,
+}
+
+/// Code of the diagnostic recorded for a module eqWAlizer didn't finish
+/// checking in time, see `Eqwalizer::typecheck_with_timeout`.
+pub const TIMEOUT_CODE: &str = "timeout";
+
+/// Code of the diagnostic recorded for a `% eqwalizer:ignore <code>` comment
+/// that didn't suppress anything.
+pub const UNUSED_IGNORE_CODE: &str = "unused_ignore";
+
+/// Codes of the diagnostics eqWAlizer reports about the callbacks of the
+/// behaviours of a module: those it doesn't implement, and those whose
+/// implementation has a spec that doesn't fit the callback's.
+pub const CALLBACK_CODES: [&str; 3] = [
+    "missing_cb_implementation",
+    "incorrect_return_type_in_cb_implementation",
+    "incorrect_param_type_in_cb_implementation",
+];
+
+/// Code of the diagnostic standing for the diagnostics of a module dropped
+/// by `EqwalizerDiagnostics::truncate`.
+pub const TRUNCATED_CODE: &str = "truncated";
+
+/// Codes of the diagnostics recorded for the functions without a spec,
+/// which gradual mode checks as if all their types were `dynamic()`, under
+/// `DynamicPolicy::Warn` and `DynamicPolicy::Error` respectively.
+pub const IMPLICIT_DYNAMIC_CODE: &str = "implicit_dynamic";
+pub const IMPLICIT_DYNAMIC_ERROR_CODE: &str = "implicit_dynamic_error";
+
+/// Codes of the diagnostics recorded for the exported functions without a
+/// spec, under `MissingSpecPolicy::WarnExported` and
+/// `MissingSpecPolicy::ErrorExported` respectively.
+pub const MISSING_SPEC_CODE: &str = "missing_spec";
+pub const MISSING_SPEC_ERROR_CODE: &str = "missing_spec_error";
+
+/// How serious a diagnostic is, ordered from least to most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Hint,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    /// The next less severe severity, `Hint` for `Hint`.
+    pub fn downgraded(self) -> Severity {
+        match self {
+            Severity::Error => Severity::Warning,
+            Severity::Warning | Severity::Hint => Severity::Hint,
+        }
+    }
+
+    fn for_code(code: &str) -> Severity {
+        match code {
+            "reveal_type" => Severity::Hint,
+            "redundant_fixme"
+            | "redundant_nowarn_function"
+            | "redundant_guard"
+            | TIMEOUT_CODE
+            | TRUNCATED_CODE
+            | IMPLICIT_DYNAMIC_CODE
+            | MISSING_SPEC_CODE
+            | UNUSED_IGNORE_CODE => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+/// How strictly eqWAlizer checks modules.
+///
+/// In the default, gradual, mode `dynamic()` is compatible with every type,
+/// functions without a spec are checked as if all their types were
+/// `dynamic()`, calls to them giving `dynamic()`, and
+/// `EQWALIZER_TOLERATE_ERRORS` is honoured. In strict mode `dynamic()` is an
+/// ordinary type and functions without a spec aren't checked at all:
+/// nothing is reported in their bodies, while calls to them from checked
+/// functions are reported as `unbound_var`. Diagnostics keep the severity
+/// of their code in both modes: the modes differ by the diagnostics
+/// reported, strict mode reporting none in the functions without a spec,
+/// and more `unbound_var`, `expected_subtype` and `incompatible_types` in
+/// the functions calling them or using `dynamic()`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum CheckMode {
+    #[default]
+    Default,
+    Strict,
+}
+
+impl CheckMode {
+    pub fn is_strict(self) -> bool {
+        self == CheckMode::Strict
+    }
+}
+
+/// What eqWAlizer checks in the modules it is asked to check.
+///
+/// `SpecsOnly` leaves the functions out, checking the type definitions,
+/// records and specs only, which is much faster, e.g. for dependencies.
+/// Only the diagnostics about invalid types can then be reported, such as
+/// `unknown_id`, `unbound_type_var`, `type_alias_is_non_productive` or
+/// `reference_to_invalid_type`. Those about expressions can't, from
+/// `expected_subtype` to `redundant_guard`, nor the ones about fixmes,
+/// `eqwalizer:ignore` comments, `-eqwalizer` attributes and behaviour
+/// callbacks: `redundant_fixme`, `redundant_nowarn_function`,
+/// `unused_ignore`, `missing_cb_implementation`,
+/// `incorrect_return_type_in_cb_implementation` and
+/// `incorrect_param_type_in_cb_implementation`. The modules the checked
+/// ones depend on are seen in full either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum CheckScope {
+    #[default]
+    Full,
+    SpecsOnly,
+}
+
+/// How the places gradual mode implicitly introduces `dynamic()` at, the
+/// functions without a spec, are reported: not at all, as
+/// `IMPLICIT_DYNAMIC_CODE` warnings or as `IMPLICIT_DYNAMIC_ERROR_CODE`
+/// errors. These are found from the forms of the module, by a syntactic
+/// pass next to eqWAlizer: eqWAlizer isn't told the policy, and its own
+/// diagnostics are the same under every policy. Strict mode doesn't check
+/// functions without a spec, so the policy doesn't apply to it. Exported
+/// functions `MissingSpecPolicy` reports are left to it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum DynamicPolicy {
+    #[default]
+    Allow,
+    Warn,
+    Error,
+}
+
+/// How the exported functions without a `-spec` are reported: not at all,
+/// as `MISSING_SPEC_CODE` warnings or as `MISSING_SPEC_ERROR_CODE` errors,
+/// each with a fix adding a spec. Unlike `DynamicPolicy`, it applies in
+/// strict mode too, and the functions that aren't exported are left out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum MissingSpecPolicy {
+    #[default]
+    Ignore,
+    WarnExported,
+    ErrorExported,
+}
+
+impl EqwalizerDiagnostic {
+    /// The synthetic diagnostic standing for all the diagnostics of a module
+    /// that couldn't be checked within `timeout`. It covers the start of the
+    /// module.
+    pub fn timeout(timeout: Duration) -> Self {
+        EqwalizerDiagnostic {
+            range: TextRange::default(),
+            message: format!(
+                "eqWAlizer didn't finish checking the module within {}s",
+                timeout.as_secs_f32()
+            ),
+            uri: String::new(),
+            code: TIMEOUT_CODE.to_string(),
+            expression: None,
+            explanation: None,
+            fixes: vec![],
+            remote_types: vec![],
+            opaque_types: vec![],
+            related: vec![],
+            severity_override: None,
+            macro_context: None,
+        }
+    }
+
+    /// The diagnostic recorded under `policy` for `function`, without a
+    /// spec, at `range`. `None` under `DynamicPolicy::Allow`.
+    pub fn implicit_dynamic(
+        policy: DynamicPolicy,
+        function: &str,
+        range: TextRange,
+    ) -> Option<Self> {
+        let code = match policy {
+            DynamicPolicy::Allow => return None,
+            DynamicPolicy::Warn => IMPLICIT_DYNAMIC_CODE,
+            DynamicPolicy::Error => IMPLICIT_DYNAMIC_ERROR_CODE,
+        };
+        Some(EqwalizerDiagnostic {
+            range,
+            message: format!(
+                "{} has no spec, its arguments and result are dynamic()",
+                function
+            ),
+            uri: String::new(),
+            code: code.to_string(),
+            expression: None,
+            explanation: None,
+            fixes: vec![],
+            remote_types: vec![],
+            opaque_types: vec![],
+            related: vec![],
+            severity_override: None,
+            macro_context: None,
+        })
+    }
+
+    /// The diagnostic recorded under `policy` for the exported `function`,
+    /// without a spec, at `range`, with `spec` as its fix. `None` under
+    /// `MissingSpecPolicy::Ignore`.
+    pub fn missing_spec(
+        policy: MissingSpecPolicy,
+        function: &str,
+        range: TextRange,
+        spec: TextEdit,
+    ) -> Option<Self> {
+        let code = match policy {
+            MissingSpecPolicy::Ignore => return None,
+            MissingSpecPolicy::WarnExported => MISSING_SPEC_CODE,
+            MissingSpecPolicy::ErrorExported => MISSING_SPEC_ERROR_CODE,
+        };
+        Some(EqwalizerDiagnostic {
+            range,
+            message: format!("{} is exported but has no spec", function),
+            uri: String::new(),
+            code: code.to_string(),
+            expression: None,
+            explanation: None,
+            fixes: vec![spec],
+            remote_types: vec![],
+            opaque_types: vec![],
+            related: vec![],
+            severity_override: None,
+            macro_context: None,
+        })
+    }
+
+    pub fn truncated(dropped: usize) -> Self {
+        EqwalizerDiagnostic {
+            range: TextRange::default(),
+            message: format!("{} more diagnostic(s) not shown", dropped),
+            uri: String::new(),
+            code: TRUNCATED_CODE.to_string(),
+            expression: None,
+            explanation: None,
+            fixes: vec![],
+            remote_types: vec![],
+            opaque_types: vec![],
+            related: vec![],
+            severity_override: None,
+            macro_context: None,
+        }
+    }
+
+    /// eqWAlizer doesn't report a severity itself, so it is derived from the
+    /// diagnostic code: revealed types are informational, redundant
+    /// annotations are worth fixing but harmless, as are timeouts, everything
+    /// else is an error. `severity_override` wins, if set.
+    pub fn severity(&self) -> Severity {
+        self.severity_override
+            .unwrap_or_else(|| Severity::for_code(&self.code))
+    }
+
+    /// A hash identifying this diagnostic across edits that don't touch the
+    /// offending code, for baselining known issues.
+    ///
+    /// Only the module name, the code and the offending expression (or the
+    /// message, when there is none) are taken into account, with whitespace
+    /// normalized, so that unrelated changes moving the diagnostic around
+    /// keep the fingerprint stable.
+    pub fn fingerprint(&self, module: &str) -> u64 {
+        let snippet = self.expression.as_deref().unwrap_or(&self.message);
+        let normalized = snippet.split_whitespace().collect::<Vec<_>>().join(" ");
+        let mut hasher = FxHasher64::default();
+        for part in [module, self.code.as_str(), normalized.as_str()] {
+            hasher.write(part.as_bytes());
+            hasher.write_u8(0xff);
+        }
+        hasher.finish()
+    }
+}
+
+/// A diagnostic together with the module it was reported in and its
+/// fingerprint, see `EqwalizerDiagnostic::fingerprint`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FingerprintedDiagnostic {
+    pub module: String,
+    pub fingerprint: u64,
+    pub diagnostic: EqwalizerDiagnostic,
+}
+
+/// How the diagnostics of two runs compare, matching diagnostics by their
+/// fingerprint so that code moving around doesn't count as a change.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DiagnosticsDiff {
+    /// Diagnostics of the current run without a match in the base one.
+    pub added: Vec<FingerprintedDiagnostic>,
+    /// Diagnostics of the base run without a match in the current one.
+    pub removed: Vec<FingerprintedDiagnostic>,
+    /// Diagnostics present in both, as seen in the current run.
+    pub unchanged: Vec<FingerprintedDiagnostic>,
+}
+
+/// Sizes of the buckets of a `DiagnosticsDiff`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DiffCounts {
+    pub added: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+impl DiagnosticsDiff {
+    /// Diagnostics sharing a fingerprint are matched pairwise, in order: if
+    /// the same error appears three times in `base` and twice in `current`,
+    /// one of them is removed.
+    pub fn new(
+        base: Vec<FingerprintedDiagnostic>,
+        current: Vec<FingerprintedDiagnostic>,
+    ) -> DiagnosticsDiff {
+        let mut unmatched: FxHashMap<u64, Vec<FingerprintedDiagnostic>> = FxHashMap::default();
+        for diagnostic in base.into_iter().rev() {
+            unmatched
+                .entry(diagnostic.fingerprint)
+                .or_default()
+                .push(diagnostic);
+        }
+
+        let mut diff = DiagnosticsDiff::default();
+        for diagnostic in current {
+            let matched = unmatched
+                .get_mut(&diagnostic.fingerprint)
+                .and_then(|base| base.pop());
+            match matched {
+                Some(_) => diff.unchanged.push(diagnostic),
+                None => diff.added.push(diagnostic),
+            }
+        }
+        diff.removed = unmatched.into_values().flatten().collect();
+        diff.removed.sort_by(|d1, d2| {
+            (&d1.module, d1.diagnostic.range.start())
+                .cmp(&(&d2.module, d2.diagnostic.range.start()))
+        });
+        diff
+    }
+
+    pub fn counts(&self) -> DiffCounts {
+        DiffCounts {
+            added: self.added.len(),
+            removed: self.removed.len(),
+            unchanged: self.unchanged.len(),
+        }
+    }
+}
+
+impl fmt::Display for DiffCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} removed, {} unchanged",
+            self.added, self.removed, self.unchanged
+        )
+    }
+}
+
+/// Diagnostics known and tolerated, identified by their fingerprint, see
+/// `Baseline::check`. Saved to and loaded from JSON files.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Baseline {
+    pub entries: Vec<BaselineEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BaselineEntry {
+    pub module: String,
+    pub fingerprint: u64,
+    /// For readers of the file, matching only uses the fingerprint.
+    pub code: String,
+}
+
+/// Result of `Baseline::check`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BaselineCheck {
+    /// Diagnostics without a match in the baseline.
+    pub new: Vec<FingerprintedDiagnostic>,
+    /// Entries of the baseline without a matching diagnostic, which can be
+    /// pruned.
+    pub stale: Vec<BaselineEntry>,
+}
+
+impl Baseline {
+    pub fn new(diagnostics: &[FingerprintedDiagnostic]) -> Baseline {
+        Baseline {
+            entries: diagnostics
+                .iter()
+                .map(|d| BaselineEntry {
+                    module: d.module.clone(),
+                    fingerprint: d.fingerprint,
+                    code: d.diagnostic.code.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Baseline> {
+        let bytes = fs::read(path)
+            .with_context(|| format!("Could not read baseline {}", path.display()))?;
+        serde_json::from_slice(&bytes)
+            .with_context(|| format!("Invalid baseline {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json)
+            .with_context(|| format!("Could not write baseline {}", path.display()))
+    }
+
+    /// Matches `current` against the entries, pairwise by fingerprint like
+    /// `DiagnosticsDiff::new`, so that known diagnostics moving around or
+    /// reformatted stay matched.
+    pub fn check(&self, current: Vec<FingerprintedDiagnostic>) -> BaselineCheck {
+        let mut unmatched: FxHashMap<u64, Vec<usize>> = FxHashMap::default();
+        for (i, entry) in self.entries.iter().enumerate().rev() {
+            unmatched.entry(entry.fingerprint).or_default().push(i);
+        }
+        let mut check = BaselineCheck::default();
+        for diagnostic in current {
+            let matched = unmatched
+                .get_mut(&diagnostic.fingerprint)
+                .and_then(|entries| entries.pop());
+            if matched.is_none() {
+                check.new.push(diagnostic);
+            }
+        }
+        let mut stale: Vec<usize> = unmatched.into_values().flatten().collect();
+        stale.sort_unstable();
+        check.stale = stale.into_iter().map(|i| self.entries[i].clone()).collect();
+        check
+    }
 }
 
 pub trait DbApi {
     fn unwind_if_cancelled(&self) -> ();
     fn get_ast(&mut self, module: &str) -> Option<Arc<Vec<u8>>>;
+    /// What eqWAlizer sees of `module` when checking other modules, its AST
+    /// unless stubs add to it.
+    fn get_stubs(&mut self, module: &str) -> Option<Arc<Vec<u8>>> {
+        self.get_ast(module)
+    }
     fn eqwalizing_start(&self, module: String) -> ();
     fn eqwalizing_done(&mut self, module: String) -> ();
 }
@@ -82,10 +642,34 @@ where
     Ok(TextRange::new(range.start.into(), range.end.into()))
 }
 
+fn serialize_file_id<S>(file_id: &FileId, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_u32(file_id.0)
+}
+
+fn serialize_text_range<S>(range: &TextRange, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    #[derive(Serialize)]
+    struct RawTextRange {
+        start: u32,
+        end: u32,
+    }
+
+    RawTextRange {
+        start: range.start().into(),
+        end: range.end().into(),
+    }
+    .serialize(serializer)
+}
+
 impl Default for Eqwalizer {
     fn default() -> Self {
         let env = env::var("ELP_EQWALIZER_PATH");
-        let (path, ext, temp_file) = if let Ok(path) = env {
+        let (path, ext, version, temp_file) = if let Ok(path) = env {
             let path = PathBuf::from(path);
             let ext = path
                 .extension()
@@ -93,7 +677,16 @@ impl Default for Eqwalizer {
                 .to_str()
                 .unwrap()
                 .to_string();
-            (path, ext, None)
+            let version = match fs::metadata(&path) {
+                Ok(metadata) => format!(
+                    "{:?}-{}-{:?}",
+                    path,
+                    metadata.len(),
+                    metadata.modified().ok()
+                ),
+                Err(_) => format!("{:?}", path),
+            };
+            (path, ext, version, None)
         } else {
             let extension = env!("ELP_EQWALIZER_EXT").to_string();
             let eqwalizer_src = include_bytes!(concat!(env!("OUT_DIR"), "/eqwalizer"));
@@ -113,7 +706,12 @@ impl Default for Eqwalizer {
             perm.set_mode(0o755);
             fs::set_permissions(&temp_file, perm).expect("can't create eqwalizer temp executable");
 
-            (temp_file.to_path_buf(), extension, Some(temp_file))
+            let version = format!(
+                "bundled-{}-{}",
+                env!("CARGO_PKG_VERSION"),
+                eqwalizer_src.len()
+            );
+            (temp_file.to_path_buf(), extension, version, Some(temp_file))
         };
 
         let (cmd, args) = match ext.as_str() {
@@ -128,6 +726,7 @@ impl Default for Eqwalizer {
         Self {
             cmd,
             args,
+            version,
             _file: temp_file.map(Arc::new),
         }
     }
@@ -141,12 +740,89 @@ impl Eqwalizer {
         CommandProxy::new(cmd)
     }
 
+    /// Identifies the eqWAlizer executable, changing when it is replaced.
+    /// Meant for invalidating results saved across runs.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// What to include in bug reports and exports to reproduce results. Cheap,
+    /// eqWAlizer isn't run.
+    pub fn version_info(&self) -> VersionInfo {
+        VersionInfo {
+            eqwalizer: self.version.clone(),
+            parse_server_protocol: elp_parse_server::PROTOCOL_VERSION,
+            formats: Format::ALL.to_vec(),
+        }
+    }
+
     pub fn typecheck(
+        &self,
+        build_info_path: &Path,
+        db_api: impl DbApi,
+        modules: Vec<&str>,
+        strict: bool,
+    ) -> Result<EqwalizerDiagnostics> {
+        self.typecheck_with_env(build_info_path, db_api, modules, strict, &[])
+    }
+
+    /// Same as `typecheck`, passing additional environment variables to
+    /// eqWAlizer, e.g. `EQWALIZER_TOLERATE_ERRORS`.
+    pub fn typecheck_with_env(
+        &self,
+        build_info_path: &Path,
+        db_api: impl DbApi,
+        modules: Vec<&str>,
+        strict: bool,
+        env: &[(&str, &str)],
+    ) -> Result<EqwalizerDiagnostics> {
+        self.run(build_info_path, db_api, modules, strict, env, None)
+    }
+
+    /// Type-checks a single module, giving up if eqWAlizer takes longer than
+    /// `timeout`. The module then gets a single `timeout` diagnostic instead
+    /// of its actual ones.
+    ///
+    /// The eqWAlizer process is killed when giving up, as well as when the
+    /// check is cancelled. Time spent by `db_api` loading ASTs counts towards
+    /// the budget, but a load in progress isn't interrupted.
+    pub fn typecheck_with_timeout(
+        &self,
+        build_info_path: &Path,
+        db_api: impl DbApi,
+        module: &str,
+        strict: bool,
+        timeout: Duration,
+    ) -> Result<EqwalizerDiagnostics> {
+        match self.run(
+            build_info_path,
+            db_api,
+            vec![module],
+            strict,
+            &[],
+            Some(timeout),
+        ) {
+            Err(error) if is_timeout(&error) => {
+                log::warn!("EqWAlizing {} timed out after {:?}", module, timeout);
+                let mut diagnostics = FxHashMap::default();
+                diagnostics.insert(
+                    module.to_string(),
+                    vec![EqwalizerDiagnostic::timeout(timeout)],
+                );
+                Ok(EqwalizerDiagnostics::Diagnostics(diagnostics))
+            }
+            result => result,
+        }
+    }
+
+    fn run(
         &self,
         build_info_path: &Path,
         mut db_api: impl DbApi,
         modules: Vec<&str>,
         strict: bool,
+        env: &[(&str, &str)],
+        timeout: Option<Duration>,
     ) -> Result<EqwalizerDiagnostics> {
         let mut cmd = self.cmd();
         cmd.arg("ipc");
@@ -154,37 +830,36 @@ impl Eqwalizer {
         cmd.env("EQWALIZER_IPC", "true");
         let gradual_typing = if strict { "false" } else { "true" };
         cmd.env("EQWALIZER_GRADUAL_TYPING", gradual_typing);
+        cmd.envs(env.iter().copied());
         add_env(&mut cmd, build_info_path, None);
 
-        let handle = &mut IpcHandle::from_command(&mut cmd)?;
+        // Dropping the handle kills eqWAlizer, be it on error, on timeout or
+        // when unwinding after a cancellation
+        let read_timeout = timeout.unwrap_or(ipc::DEFAULT_READ_TIMEOUT);
+        let handle = &mut IpcHandle::from_command(&mut cmd, read_timeout)?;
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
 
         loop {
             db_api.unwind_if_cancelled();
+            if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                return Err(io::Error::from(io::ErrorKind::TimedOut).into());
+            }
             match handle.receive()? {
-                MsgFromEqWAlizer::GetAstBytes { module }
-                | MsgFromEqWAlizer::GetStubsBytes { module } => {
+                MsgFromEqWAlizer::GetAstBytes { module } => {
                     log::debug!("received from eqwalizer: GetAstBytes for module {}", module);
-                    match db_api.get_ast(&module) {
-                        Some(ast_bytes) => {
-                            log::debug!(
-                                "sending to eqwalizer: GetAstBytesReply for module {}",
-                                module
-                            );
-                            let ast_bytes_len = ast_bytes.len().try_into()?;
-                            let reply = &MsgToEqWAlizer::GetAstBytesReply { ast_bytes_len };
-                            handle.send(reply)?;
-                            handle.receive_newline()?;
-                            handle.send_bytes(&ast_bytes)?;
-                        }
-                        None => {
-                            log::debug!(
-                                "sending to eqwalizer: CannotCompleteRequest for module {}",
-                                module
-                            );
-                            let reply = &MsgToEqWAlizer::CannotCompleteRequest;
-                            handle.send(reply)?;
-                            return Ok(EqwalizerDiagnostics::NoAst { module });
-                        }
+                    let ast_bytes = db_api.get_ast(&module);
+                    if !send_ast_bytes(handle, &module, ast_bytes)? {
+                        return Ok(EqwalizerDiagnostics::NoAst { module });
+                    }
+                }
+                MsgFromEqWAlizer::GetStubsBytes { module } => {
+                    log::debug!(
+                        "received from eqwalizer: GetStubsBytes for module {}",
+                        module
+                    );
+                    let ast_bytes = db_api.get_stubs(&module);
+                    if !send_ast_bytes(handle, &module, ast_bytes)? {
+                        return Ok(EqwalizerDiagnostics::NoAst { module });
                     }
                 }
                 MsgFromEqWAlizer::EqwalizingStart { module } => db_api.eqwalizing_start(module),
@@ -214,6 +889,44 @@ impl Eqwalizer {
     }
 }
 
+fn is_timeout(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<io::Error>()
+        .map_or(false, |error| error.kind() == io::ErrorKind::TimedOut)
+}
+
+/// Replies to a request for the AST or stubs of `module`, `false` if there
+/// are none, in which case eqWAlizer can't go on.
+fn send_ast_bytes(
+    handle: &mut IpcHandle,
+    module: &str,
+    ast_bytes: Option<Arc<Vec<u8>>>,
+) -> Result<bool> {
+    match ast_bytes {
+        Some(ast_bytes) => {
+            log::debug!(
+                "sending to eqwalizer: GetAstBytesReply for module {}",
+                module
+            );
+            let ast_bytes_len = ast_bytes.len().try_into()?;
+            let reply = &MsgToEqWAlizer::GetAstBytesReply { ast_bytes_len };
+            handle.send(reply)?;
+            handle.receive_newline()?;
+            handle.send_bytes(&ast_bytes)?;
+            Ok(true)
+        }
+        None => {
+            log::debug!(
+                "sending to eqwalizer: CannotCompleteRequest for module {}",
+                module
+            );
+            let reply = &MsgToEqWAlizer::CannotCompleteRequest;
+            handle.send(reply)?;
+            Ok(false)
+        }
+    }
+}
+
 fn add_env(cmd: &mut Command, build_info_path: &Path, elp_ast_dir: Option<&Path>) {
     cmd.env("EQWALIZER_BUILD_INFO", build_info_path);
     if let Some(elp_ast_dir) = elp_ast_dir {
@@ -230,6 +943,149 @@ impl Default for EqwalizerDiagnostics {
     }
 }
 
+impl EqwalizerDiagnostics {
+    /// Pairs every diagnostic with its fingerprint, ordered by module name.
+    /// Returns `None` if eqWAlizer couldn't get the AST of some module.
+    pub fn fingerprinted(&self) -> Option<Vec<FingerprintedDiagnostic>> {
+        match self {
+            EqwalizerDiagnostics::Diagnostics(diagnostics) => {
+                let mut modules: Vec<_> = diagnostics.iter().collect();
+                modules.sort_by(|(m1, _), (m2, _)| m1.cmp(m2));
+                Some(
+                    modules
+                        .into_iter()
+                        .flat_map(|(module, diags)| {
+                            diags.iter().map(move |d| FingerprintedDiagnostic {
+                                module: module.clone(),
+                                fingerprint: d.fingerprint(module),
+                                diagnostic: d.clone(),
+                            })
+                        })
+                        .collect(),
+                )
+            }
+            EqwalizerDiagnostics::NoAst { .. } => None,
+        }
+    }
+
+    /// Sorts the diagnostics of every module by start offset, then by code.
+    /// The sort is stable: diagnostics with the same start and code stay in
+    /// the order eqWAlizer reported them in.
+    pub fn sort(&mut self) {
+        if let EqwalizerDiagnostics::Diagnostics(diagnostics) = self {
+            for diags in diagnostics.values_mut() {
+                diags.sort_by(|d1, d2| {
+                    (d1.range.start(), &d1.code).cmp(&(d2.range.start(), &d2.code))
+                });
+            }
+        }
+    }
+
+    /// The diagnostics of every module, ordered by module name, then as
+    /// sorted by `sort`. Modules without diagnostics are left out. Returns
+    /// `None` if eqWAlizer couldn't get the AST of some module.
+    pub fn by_module(&self) -> Option<BTreeMap<&str, Vec<&EqwalizerDiagnostic>>> {
+        match self {
+            EqwalizerDiagnostics::Diagnostics(diagnostics) => Some(
+                diagnostics
+                    .iter()
+                    .filter(|(_, diags)| !diags.is_empty())
+                    .map(|(module, diags)| {
+                        let mut diags: Vec<_> = diags.iter().collect();
+                        diags.sort_by(|d1, d2| {
+                            (d1.range.start(), &d1.code).cmp(&(d2.range.start(), &d2.code))
+                        });
+                        (module.as_str(), diags)
+                    })
+                    .collect(),
+            ),
+            EqwalizerDiagnostics::NoAst { .. } => None,
+        }
+    }
+
+    /// All the diagnostics with their module, in a total order: by
+    /// `module_key` (e.g. the path of the module), then by module name, then
+    /// as sorted by `sort`. Returns `None` if eqWAlizer couldn't get the AST
+    /// of some module.
+    pub fn sorted<K: Ord>(
+        &self,
+        module_key: impl Fn(&str) -> K,
+    ) -> Option<Vec<(&str, &EqwalizerDiagnostic)>> {
+        match self {
+            EqwalizerDiagnostics::Diagnostics(diagnostics) => {
+                let mut modules: Vec<_> = diagnostics
+                    .iter()
+                    .map(|(module, diags)| ((module_key(module), module.as_str()), diags))
+                    .collect();
+                modules.sort_by(|(k1, _), (k2, _)| k1.cmp(k2));
+                Some(
+                    modules
+                        .into_iter()
+                        .flat_map(|((_, module), diags)| {
+                            let mut diags: Vec<_> = diags.iter().collect();
+                            diags.sort_by(|d1, d2| {
+                                (d1.range.start(), &d1.code).cmp(&(d2.range.start(), &d2.code))
+                            });
+                            diags.into_iter().map(move |d| (module, d))
+                        })
+                        .collect(),
+                )
+            }
+            EqwalizerDiagnostics::NoAst { .. } => None,
+        }
+    }
+
+    /// Keeps only diagnostics at least as severe as `min_severity`,
+    /// dropping modules left without any.
+    pub fn filter_severity(&self, min_severity: Severity) -> EqwalizerDiagnostics {
+        match self {
+            EqwalizerDiagnostics::Diagnostics(diagnostics) => EqwalizerDiagnostics::Diagnostics(
+                diagnostics
+                    .iter()
+                    .filter_map(|(module, diags)| {
+                        let diags: Vec<_> = diags
+                            .iter()
+                            .filter(|d| d.severity() >= min_severity)
+                            .cloned()
+                            .collect();
+                        if diags.is_empty() {
+                            None
+                        } else {
+                            Some((module.clone(), diags))
+                        }
+                    })
+                    .collect(),
+            ),
+            EqwalizerDiagnostics::NoAst { module } => EqwalizerDiagnostics::NoAst {
+                module: module.clone(),
+            },
+        }
+    }
+
+    /// Keeps only the first `max_per_module` diagnostics of each module,
+    /// followed by a `TRUNCATED_CODE` diagnostic telling how many were
+    /// dropped, if any.
+    pub fn truncate(&self, max_per_module: usize) -> EqwalizerDiagnostics {
+        match self {
+            EqwalizerDiagnostics::Diagnostics(diagnostics) => EqwalizerDiagnostics::Diagnostics(
+                diagnostics
+                    .iter()
+                    .map(|(module, diags)| {
+                        let mut kept: Vec<_> = diags.iter().take(max_per_module).cloned().collect();
+                        if diags.len() > max_per_module {
+                            kept.push(EqwalizerDiagnostic::truncated(diags.len() - max_per_module));
+                        }
+                        (module.clone(), kept)
+                    })
+                    .collect(),
+            ),
+            EqwalizerDiagnostics::NoAst { module } => EqwalizerDiagnostics::NoAst {
+                module: module.clone(),
+            },
+        }
+    }
+}
+
 impl<'file> CommandProxy<'file> {
     pub fn new(cmd: Command) -> Self {
         Self(cmd, PhantomData)
@@ -0,0 +1,157 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Futures for blocking work, run on the threads of a `Pool`. This doesn't
+//! depend on any async runtime: the future only wakes its task once the
+//! work is done.
+
+use std::future::Future;
+use std::io;
+use std::panic;
+use std::pin::Pin;
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+use std::thread;
+use std::thread::JoinHandle;
+
+use lazy_static::lazy_static;
+
+lazy_static! {
+    /// The pool shared by the async methods of `Analysis`, one thread per
+    /// CPU.
+    static ref SHARED: io::Result<Pool> = Pool::new(
+        "eqwalizer-diagnostics",
+        thread::available_parallelism().map_or(1, |n| n.get()),
+    );
+}
+
+/// The pool shared by the async methods of `Analysis`, `None` if none of
+/// its threads could be spawned.
+pub(crate) fn shared() -> Option<&'static Pool> {
+    SHARED.as_ref().ok()
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed number of threads running the work given to `spawn`, in order.
+/// The threads stop once the pool is dropped and the work queued is done.
+pub(crate) struct Pool {
+    jobs: Mutex<Sender<Job>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Pool {
+    /// A pool of up to `threads` threads named `name`, at least one. There
+    /// are fewer if the system runs out of threads, and an error if none
+    /// could be spawned.
+    pub(crate) fn new(name: &str, threads: usize) -> io::Result<Pool> {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let mut handles = Vec::new();
+        for i in 0..threads.max(1) {
+            let receiver = receiver.clone();
+            let spawned = thread::Builder::new()
+                .name(name.to_string())
+                .spawn(move || loop {
+                    // The lock is released before running the job
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                });
+            match spawned {
+                Ok(handle) => handles.push(handle),
+                Err(err) if i == 0 => return Err(err),
+                Err(_) => break,
+            }
+        }
+        Ok(Pool {
+            jobs: Mutex::new(sender),
+            threads: handles,
+        })
+    }
+
+    /// Waits for the work queued so far to be done, and for the threads to
+    /// stop.
+    pub(crate) fn join(self) {
+        drop(self.jobs);
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+    }
+
+    /// The result of `f`, run on a thread of the pool once the work queued
+    /// before it is done.
+    pub(crate) fn spawn<T, F>(&self, f: F) -> Blocking<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let shared: Arc<Mutex<Shared<T>>> = Arc::default();
+        let job_shared = shared.clone();
+        let job: Job = Box::new(move || {
+            let result = panic::catch_unwind(panic::AssertUnwindSafe(f));
+            let mut shared = job_shared.lock().unwrap();
+            shared.result = Some(result);
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        });
+        // The threads keep receiving as long as the pool is alive
+        let _ = self.jobs.lock().unwrap().send(job);
+        Blocking(shared)
+    }
+}
+
+struct Shared<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+impl<T> Default for Shared<T> {
+    fn default() -> Self {
+        Shared {
+            result: None,
+            waker: None,
+        }
+    }
+}
+
+/// Resolves to the result of the work, or resumes its panic. Dropping it
+/// doesn't stop the work.
+pub(crate) struct Blocking<T>(Arc<Mutex<Shared<T>>>);
+
+impl<T> Blocking<T> {
+    /// Resolves to `result` right away, for work done on the caller.
+    pub(crate) fn ready(result: T) -> Blocking<T> {
+        Blocking(Arc::new(Mutex::new(Shared {
+            result: Some(Ok(result)),
+            waker: None,
+        })))
+    }
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut shared = self.0.lock().unwrap();
+        match shared.result.take() {
+            Some(Ok(result)) => Poll::Ready(result),
+            Some(Err(panic)) => panic::resume_unwind(panic),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
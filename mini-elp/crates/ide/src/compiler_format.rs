@@ -0,0 +1,82 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Export of eqwalizer diagnostics in the format of the Erlang compiler's
+//! messages, for tools parsing the output of `erlc` or `rebar3 compile`.
+
+use std::fmt::Write;
+
+use elp_ide_db::EqwalizerDiagnostics;
+use elp_ide_db::Severity;
+
+use crate::output::position;
+use crate::output::ModuleFiles;
+use crate::output::OutputOptions;
+
+/// One `Path:Line:Col: Error: Message` line per diagnostic of the modules
+/// of `files`, by path then position, with `Warning:` instead of `Error:`
+/// for diagnostics less severe than errors.
+///
+/// `files` has the path used in the messages, typically relative to the
+/// project root, and the line index of the file of each module. Messages
+/// spanning several lines are joined into one. Diagnostics of modules not
+/// in `files` are left out.
+pub fn to_compiler_format(diags: &EqwalizerDiagnostics, files: &ModuleFiles) -> String {
+    to_compiler_format_with_options(diags, files, &OutputOptions::default())
+}
+
+/// Same as `to_compiler_format`, with the paths and line endings `options`
+/// say.
+pub fn to_compiler_format_with_options(
+    diags: &EqwalizerDiagnostics,
+    files: &ModuleFiles,
+    options: &OutputOptions,
+) -> String {
+    let mut out = String::new();
+    match diags {
+        EqwalizerDiagnostics::Diagnostics(diagnostics) => {
+            let mut files: Vec<_> = files.iter().collect();
+            files.sort_by(|(_, f1), (_, f2)| f1.path.cmp(&f2.path));
+            for (module, file) in files {
+                let path = options.path(&file.path);
+                let mut diagnostics: Vec<_> =
+                    diagnostics.get(module).into_iter().flatten().collect();
+                diagnostics.sort_by_key(|d| (d.range.start(), d.code.clone()));
+                for diagnostic in diagnostics {
+                    let (line, column) = position(&file.line_index, diagnostic.range.start());
+                    let prefix = match diagnostic.severity() {
+                        Severity::Error => "Error",
+                        Severity::Warning | Severity::Hint => "Warning",
+                    };
+                    let _ = writeln!(
+                        out,
+                        "{}:{}:{}: {}: {}",
+                        path,
+                        line,
+                        column,
+                        prefix,
+                        one_line(&diagnostic.message)
+                    );
+                }
+            }
+        }
+        EqwalizerDiagnostics::NoAst { module } => {
+            let path = files
+                .get(module)
+                .map_or_else(|| module.clone(), |file| options.path(&file.path));
+            let _ = writeln!(
+                out,
+                "{}: Error: eqWAlizer couldn't get the AST of {}",
+                path, module
+            );
+        }
+    }
+    options.lines(&out)
+}
+
+fn one_line(message: &str) -> String {
+    message.split_whitespace().collect::<Vec<_>>().join(" ")
+}
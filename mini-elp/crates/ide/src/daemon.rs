@@ -0,0 +1,95 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! A long-running loop over an `AnalysisHost`, taking changes and
+//! diagnostics queries from a channel, so that callers don't have to get
+//! the discipline of changes and snapshots right themselves.
+//!
+//! Commands are handled in the order they are received:
+//! - a query is answered on a snapshot taken when it is received, so it
+//!   sees all the changes received before it and none received after it;
+//! - queries run on a fixed number of threads, concurrently with each
+//!   other, so their replies may come in any order. Queries received while
+//!   all the threads are busy wait for one of them;
+//! - a change cancels the queries still running or waiting, which reply
+//!   `Cancelled`, and is applied once they are done. It is up to the
+//!   caller to send a cancelled query again.
+
+use std::io;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+
+use elp_ide_db::elp_base_db::salsa::Cancelled;
+use elp_ide_db::elp_base_db::FileId;
+use elp_ide_db::elp_base_db::ProjectId;
+use elp_ide_db::parse_server::Format;
+use elp_ide_db::EqwalizerDiagnostics;
+
+use crate::blocking::Pool;
+use crate::AnalysisHost;
+use crate::Cancellable;
+use crate::Change;
+
+pub enum Command {
+    /// See `AnalysisHost::apply_change`.
+    ApplyChange(Change),
+    /// See `Analysis::eqwalizer_diagnostics`.
+    Diagnostics {
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: Format,
+        strict: bool,
+        reply: Sender<Cancellable<Arc<EqwalizerDiagnostics>>>,
+    },
+    /// Stops the loop once the queries still running are done. Commands
+    /// sent after it are left in the channel.
+    Shutdown,
+}
+
+/// Handles the commands of `rx` until a `Shutdown`, or until all the
+/// senders are gone, then hands `host` back. Queries are answered by one
+/// thread per CPU. Fails before taking any command if none of these
+/// threads could be spawned.
+pub fn run(host: AnalysisHost, rx: Receiver<Command>) -> io::Result<AnalysisHost> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    run_with_threads(host, rx, threads)
+}
+
+/// Same as `run`, with `threads` threads answering queries, at least one.
+pub fn run_with_threads(
+    mut host: AnalysisHost,
+    rx: Receiver<Command>,
+    threads: usize,
+) -> io::Result<AnalysisHost> {
+    let pool = Pool::new("eqwalizer-daemon-query", threads)?;
+    for command in rx.iter() {
+        match command {
+            Command::ApplyChange(change) => host.apply_change(change),
+            Command::Diagnostics {
+                project_id,
+                file_ids,
+                format,
+                strict,
+                reply,
+            } => {
+                let analysis = host.analysis();
+                // Replies are sent from the pool
+                let _ = pool.spawn(move || {
+                    let result = Cancelled::catch(std::panic::AssertUnwindSafe(|| {
+                        analysis.eqwalizer_diagnostics(project_id, file_ids, format, strict)
+                    }));
+                    // The caller may not wait for the reply
+                    let _ = reply.send(result);
+                });
+            }
+            Command::Shutdown => break,
+        }
+    }
+    pool.join();
+    Ok(host)
+}
@@ -0,0 +1,86 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Export of eqwalizer diagnostics as GitHub Actions workflow commands,
+//! which GitHub shows as annotations on the diff of pull requests.
+
+use std::fmt::Write;
+
+use elp_ide_db::EqwalizerDiagnostics;
+use elp_ide_db::Severity;
+
+use crate::output::position;
+use crate::output::ModuleFiles;
+
+/// One `::error file=Path,line=Line,...::Message` command per diagnostic
+/// of the modules of `files`, by path then position, with `::warning` and
+/// `::notice` for warnings and hints. The code of a diagnostic is the
+/// title of its annotation.
+///
+/// `files` has the line index of the file of each module and its path
+/// relative to the root of the repository, which GitHub needs to find the
+/// file. Diagnostics of modules not in `files` are left out.
+pub fn to_github_annotations(diags: &EqwalizerDiagnostics, files: &ModuleFiles) -> String {
+    let mut out = String::new();
+    match diags {
+        EqwalizerDiagnostics::Diagnostics(diagnostics) => {
+            let mut files: Vec<_> = files.iter().collect();
+            files.sort_by(|(_, f1), (_, f2)| f1.path.cmp(&f2.path));
+            for (module, file) in files {
+                let mut diagnostics: Vec<_> =
+                    diagnostics.get(module).into_iter().flatten().collect();
+                diagnostics.sort_by_key(|d| (d.range.start(), d.code.clone()));
+                for diagnostic in diagnostics {
+                    let (start_line, start_column) =
+                        position(&file.line_index, diagnostic.range.start());
+                    let (end_line, end_column) = position(&file.line_index, diagnostic.range.end());
+                    let command = match diagnostic.severity() {
+                        Severity::Error => "error",
+                        Severity::Warning => "warning",
+                        Severity::Hint => "notice",
+                    };
+                    let _ = writeln!(
+                        out,
+                        "::{} file={},line={},col={},endLine={},endColumn={},title={}::{}",
+                        command,
+                        escape_property(&file.path),
+                        start_line,
+                        start_column,
+                        end_line,
+                        end_column,
+                        escape_property(&diagnostic.code),
+                        escape_data(&diagnostic.message)
+                    );
+                }
+            }
+        }
+        EqwalizerDiagnostics::NoAst { module } => {
+            let path = files
+                .get(module)
+                .map_or(module.as_str(), |file| file.path.as_str());
+            let _ = writeln!(
+                out,
+                "::error file={}::{}",
+                escape_property(path),
+                escape_data(&format!("eqWAlizer couldn't get the AST of {}", module))
+            );
+        }
+    }
+    out
+}
+
+/// Escapes the message of a command, which must fit on one line.
+fn escape_data(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Same as `escape_data`, for property values, which can't contain the
+/// separators of properties either.
+fn escape_property(text: &str) -> String {
+    escape_data(text).replace(':', "%3A").replace(',', "%2C")
+}
@@ -0,0 +1,184 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Machine-readable rendering of eqwalizer diagnostics.
+//!
+//! The output is a single JSON object:
+//!
+//! ```json
+//! {
+//!   "schema_version": 1,
+//!   "diagnostics": [
+//!     {
+//!       "path": "app_a/src/app_a.erl",
+//!       "module": "app_a",
+//!       "range": {
+//!         "start": { "line": 9, "column": 5 },
+//!         "end": { "line": 9, "column": 9 }
+//!       },
+//!       "code": "expected_subtype",
+//!       "message": "...",
+//!       "severity": "error"
+//!     }
+//!   ],
+//!   "missing_ast": null
+//! }
+//! ```
+//!
+//! Lines and columns are 1-based, columns count UTF-16 code units. Paths are
+//! relative to the project root when possible, with the separators of the
+//! `OutputOptions`, `/` by default. `missing_ast` names the module eqWAlizer
+//! couldn't get an AST for, in which case `diagnostics` is empty.
+//!
+//! `schema_version` is bumped on every incompatible change to this layout.
+//!
+//! In JSON Lines, each line is one of the elements of `diagnostics`, on its
+//! own.
+
+use elp_ide_db::elp_base_db::ProjectId;
+use elp_ide_db::EqwalizerDiagnostic;
+use elp_ide_db::EqwalizerDiagnostics;
+use elp_ide_db::LineIndexDatabase;
+use elp_ide_db::RootDatabase;
+use elp_ide_db::Severity;
+use serde::Serialize;
+
+use crate::output::position;
+use crate::output::OutputOptions;
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Output<'a> {
+    schema_version: u32,
+    diagnostics: Vec<Diagnostic<'a>>,
+    missing_ast: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct Diagnostic<'a> {
+    path: String,
+    module: &'a str,
+    range: Range,
+    code: &'a str,
+    message: String,
+    severity: Severity,
+}
+
+#[derive(Serialize)]
+struct Range {
+    start: Position,
+    end: Position,
+}
+
+#[derive(Serialize)]
+struct Position {
+    line: u32,
+    column: u32,
+}
+
+pub(crate) fn eqwalizer_diagnostics_json(
+    db: &RootDatabase,
+    project_id: ProjectId,
+    diagnostics: &EqwalizerDiagnostics,
+    options: &OutputOptions,
+) -> String {
+    let output = match diagnostics {
+        EqwalizerDiagnostics::Diagnostics(diagnostics) => {
+            let mut modules: Vec<_> = diagnostics.iter().collect();
+            modules.sort_by(|(m1, _), (m2, _)| m1.cmp(m2));
+            let diagnostics = modules
+                .into_iter()
+                .flat_map(|(module, diags)| {
+                    diags
+                        .iter()
+                        .map(move |d| to_json_diagnostic(db, project_id, module, d, options))
+                })
+                .collect();
+            Output {
+                schema_version: SCHEMA_VERSION,
+                diagnostics,
+                missing_ast: None,
+            }
+        }
+        EqwalizerDiagnostics::NoAst { module } => Output {
+            schema_version: SCHEMA_VERSION,
+            diagnostics: vec![],
+            missing_ast: Some(module),
+        },
+    };
+    serde_json::to_string(&output).expect("diagnostics are always serializable")
+}
+
+/// One line per diagnostic, in the order of `eqwalizer_diagnostics_json`.
+/// There are none if eqWAlizer couldn't get an AST.
+pub(crate) fn eqwalizer_diagnostics_jsonl(
+    db: &RootDatabase,
+    project_id: ProjectId,
+    diagnostics: &EqwalizerDiagnostics,
+    options: &OutputOptions,
+) -> Vec<String> {
+    match diagnostics {
+        EqwalizerDiagnostics::Diagnostics(diagnostics) => {
+            let mut modules: Vec<_> = diagnostics.iter().collect();
+            modules.sort_by(|(m1, _), (m2, _)| m1.cmp(m2));
+            modules
+                .into_iter()
+                .flat_map(|(module, diags)| {
+                    diags.iter().map(move |d| {
+                        let diagnostic = to_json_diagnostic(db, project_id, module, d, options);
+                        serde_json::to_string(&diagnostic)
+                            .expect("diagnostics are always serializable")
+                    })
+                })
+                .collect()
+        }
+        EqwalizerDiagnostics::NoAst { .. } => vec![],
+    }
+}
+
+fn to_json_diagnostic<'a>(
+    db: &RootDatabase,
+    project_id: ProjectId,
+    module: &'a str,
+    diagnostic: &'a EqwalizerDiagnostic,
+    options: &OutputOptions,
+) -> Diagnostic<'a> {
+    let file_id = crate::module_file(db, project_id, module);
+    let path = file_id
+        .and_then(|file_id| crate::file_path(db, project_id, file_id))
+        .map(|path| options.path(&path))
+        .unwrap_or_else(|| module.to_string());
+    let range = match file_id {
+        Some(file_id) => {
+            let line_index = db.file_line_index(file_id);
+            let (start_line, start_column) = position(&line_index, diagnostic.range.start());
+            let (end_line, end_column) = position(&line_index, diagnostic.range.end());
+            Range {
+                start: Position {
+                    line: start_line,
+                    column: start_column,
+                },
+                end: Position {
+                    line: end_line,
+                    column: end_column,
+                },
+            }
+        }
+        None => Range {
+            start: Position { line: 1, column: 1 },
+            end: Position { line: 1, column: 1 },
+        },
+    };
+    Diagnostic {
+        path,
+        module,
+        range,
+        code: &diagnostic.code,
+        message: options.lines(&diagnostic.message),
+        severity: diagnostic.severity(),
+    }
+}
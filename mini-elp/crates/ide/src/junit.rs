@@ -0,0 +1,139 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! JUnit XML export of eqwalizer diagnostics, for CI test dashboards.
+
+use std::fmt::Write;
+
+use elp_ide_db::EqwalizerDiagnostic;
+use elp_ide_db::EqwalizerDiagnostics;
+use elp_ide_db::LineIndex;
+
+use crate::output::position;
+use crate::output::ModuleFiles;
+
+/// Builds a JUnit XML document for the diagnostics of the modules of
+/// `files`, which has the path and line index of the file of each.
+///
+/// Each module is a test suite, and each of its diagnostics a failing test
+/// case. A clean module has a single passing test case, so that it still
+/// shows up. Diagnostics of modules not in `files` are left out.
+pub fn to_junit(diags: &EqwalizerDiagnostics, files: &ModuleFiles) -> String {
+    let mut suites = String::new();
+    let mut tests = 0;
+    let mut failures = 0;
+    match diags {
+        EqwalizerDiagnostics::Diagnostics(diagnostics) => {
+            for (module, file) in files {
+                let module_diagnostics = diagnostics.get(module).map_or(&[][..], |d| &d[..]);
+                let mut cases = String::new();
+                for diagnostic in module_diagnostics {
+                    test_case(&mut cases, diagnostic, &file.line_index, &file.path);
+                }
+                if module_diagnostics.is_empty() {
+                    let _ = writeln!(
+                        cases,
+                        "    <testcase name=\"eqwalizer\" classname=\"{}\"/>",
+                        escape_attr(&file.path)
+                    );
+                }
+                let module_tests = module_diagnostics.len().max(1);
+                test_suite(
+                    &mut suites,
+                    module,
+                    module_tests,
+                    module_diagnostics.len(),
+                    &cases,
+                );
+                tests += module_tests;
+                failures += module_diagnostics.len();
+            }
+        }
+        EqwalizerDiagnostics::NoAst { module: no_ast } => {
+            let path = files
+                .get(no_ast)
+                .map_or(no_ast.as_str(), |file| file.path.as_str());
+            let cases = format!(
+                "    <testcase name=\"no_ast\" classname=\"{}\">\n      \
+                 <failure message=\"{}\" type=\"no_ast\"/>\n    </testcase>\n",
+                escape_attr(path),
+                escape_attr(&format!("eqWAlizer couldn't get the AST of {}", no_ast)),
+            );
+            test_suite(&mut suites, no_ast, 1, 1, &cases);
+            tests = 1;
+            failures = 1;
+        }
+    }
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites name=\"eqWAlizer\" tests=\"{}\" failures=\"{}\">",
+        tests, failures
+    );
+    xml.push_str(&suites);
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+fn test_suite(out: &mut String, module: &str, tests: usize, failures: usize, cases: &str) {
+    let _ = writeln!(
+        out,
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\">",
+        escape_attr(module),
+        tests,
+        failures
+    );
+    out.push_str(cases);
+    out.push_str("  </testsuite>\n");
+}
+
+fn test_case(
+    out: &mut String,
+    diagnostic: &EqwalizerDiagnostic,
+    line_index: &LineIndex,
+    path: &str,
+) {
+    let (line, column) = position(line_index, diagnostic.range.start());
+    let location = format!("{}:{}:{}", path, line, column);
+    let mut body = format!("{}: {}", location, diagnostic.message);
+    if let Some(explanation) = &diagnostic.explanation {
+        body.push_str("\n\n");
+        body.push_str(explanation);
+    }
+    let _ = write!(
+        out,
+        "    <testcase name=\"{} at {}\" classname=\"{}\">\n      \
+         <failure message=\"{}\" type=\"{}\">{}</failure>\n    </testcase>\n",
+        escape_attr(&diagnostic.code),
+        escape_attr(&location),
+        escape_attr(path),
+        escape_attr(&diagnostic.message),
+        escape_attr(&diagnostic.code),
+        escape(&body),
+    );
+}
+
+/// Escapes the XML entities of `text`, for contents.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Same as `escape`, for attribute values, which can't contain newlines.
+fn escape_attr(text: &str) -> String {
+    escape(text).replace('\n', "&#10;")
+}
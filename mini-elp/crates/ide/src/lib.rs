@@ -4,48 +4,744 @@
  * the LICENSE file in the root directory of this source tree.
  */
 
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::panic::RefUnwindSafe;
+use std::panic::UnwindSafe;
+use std::path::Path;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
 
 use anyhow::Result;
 pub use elp_ide_db;
+pub use elp_ide_db::all_diagnostic_codes;
 use elp_ide_db::elp_base_db::salsa;
+use elp_ide_db::elp_base_db::salsa::Cancelled;
 use elp_ide_db::elp_base_db::salsa::ParallelDatabase;
+use elp_ide_db::elp_base_db::AbsPath;
+use elp_ide_db::elp_base_db::AbsPathBuf;
+pub use elp_ide_db::elp_base_db::Change;
 use elp_ide_db::elp_base_db::FileId;
+use elp_ide_db::elp_base_db::FileSource;
 use elp_ide_db::elp_base_db::ModuleIndex;
 use elp_ide_db::elp_base_db::ModuleName;
 use elp_ide_db::elp_base_db::ProjectData;
 use elp_ide_db::elp_base_db::ProjectId;
+use elp_ide_db::elp_base_db::RelPathBuf;
 use elp_ide_db::elp_base_db::SourceDatabase;
+use elp_ide_db::elp_base_db::SourceDatabaseExt;
+use elp_ide_db::elp_base_db::SourceRootId;
 pub use elp_ide_db::parse_server;
 use elp_ide_db::parse_server::ParseError;
+pub use elp_ide_db::parse_server::ServerHealth;
+pub use elp_ide_db::AbstractForm;
+pub use elp_ide_db::ApiSurface;
+pub use elp_ide_db::ApiType;
+pub use elp_ide_db::AppFilter;
+pub use elp_ide_db::Arity;
+pub use elp_ide_db::Baseline;
+pub use elp_ide_db::BaselineCheck;
+pub use elp_ide_db::BaselineEntry;
+pub use elp_ide_db::CacheStats;
+pub use elp_ide_db::CallTypeInfo;
+pub use elp_ide_db::CancellationToken;
+pub use elp_ide_db::CheckMode;
+pub use elp_ide_db::CheckScope;
+pub use elp_ide_db::ConflictError;
+pub use elp_ide_db::Coverage;
+pub use elp_ide_db::DiagnosticDescriptor;
+pub use elp_ide_db::DiagnosticsDiff;
+pub use elp_ide_db::DiffCounts;
+use elp_ide_db::DiskCache;
+pub use elp_ide_db::DynamicPolicy;
+pub use elp_ide_db::EffectiveSpec;
 use elp_ide_db::Eqwalizer;
 use elp_ide_db::EqwalizerDatabase;
+use elp_ide_db::EqwalizerDiagnostic;
 use elp_ide_db::EqwalizerDiagnostics;
 use elp_ide_db::ErlAstDatabase;
+pub use elp_ide_db::Explanation;
+pub use elp_ide_db::FingerprintedDiagnostic;
+pub use elp_ide_db::FixError;
+pub use elp_ide_db::FixSelector;
+pub use elp_ide_db::FunctionCallers;
+pub use elp_ide_db::FunctionName;
+pub use elp_ide_db::Histogram;
 use elp_ide_db::LineIndex;
 use elp_ide_db::LineIndexDatabase;
+pub use elp_ide_db::MacroContext;
+pub use elp_ide_db::Metrics;
+pub use elp_ide_db::MissingSpecPolicy;
+use elp_ide_db::ModuleDepsDatabase;
+pub use elp_ide_db::ModuleExports;
+pub use elp_ide_db::Obligation;
+pub use elp_ide_db::ParseRetryPolicy;
+pub use elp_ide_db::Position;
+pub use elp_ide_db::PositionEncoding;
+pub use elp_ide_db::Progress;
+pub use elp_ide_db::ProjectStats;
+pub use elp_ide_db::RecordField;
+pub use elp_ide_db::RecordFieldType;
+pub use elp_ide_db::RecordType;
+pub use elp_ide_db::Reference;
+pub use elp_ide_db::RelatedLocation;
 use elp_ide_db::RootDatabase;
+pub use elp_ide_db::ScopedType;
+pub use elp_ide_db::Severity;
+pub use elp_ide_db::SpecValidation;
+pub use elp_ide_db::SuppressedDiagnostic;
+pub use elp_ide_db::TextEdit;
+pub use elp_ide_db::TimingPhase;
+pub use elp_ide_db::TimingSink;
+pub use elp_ide_db::TypeEnv;
+pub use elp_ide_db::TypeInfo;
+pub use elp_ide_db::TypeName;
+pub use elp_ide_db::UnreachableBranch;
+pub use elp_ide_db::UnusedExports;
+pub use elp_ide_db::VarName;
+pub use elp_ide_db::VersionInfo;
 use elp_project_model::AppType;
+use rayon::prelude::*;
+use text_size::TextRange;
+use text_size::TextSize;
+
+mod blocking;
+pub mod compiler_format;
+pub mod daemon;
+pub mod github;
+mod json;
+pub mod junit;
+pub mod manifest;
+pub mod output;
+pub mod sarif;
+mod state;
+#[cfg(feature = "lsp")]
+pub mod to_lsp;
+
+pub use json::SCHEMA_VERSION as JSON_SCHEMA_VERSION;
+pub use manifest::ProjectManifest;
+pub use output::ExportFormat;
+pub use output::ModuleFile;
+pub use output::ModuleFiles;
+pub use output::OutputOptions;
+pub use state::STATE_VERSION;
+
+pub type Cancellable<T> = Result<T, Cancelled>;
+
+/// Number of modules each eqWAlizer process checks in the entry points
+/// going through modules in batches, e.g.
+/// `Analysis::eqwalizer_diagnostics_stream`.
+pub const MODULES_PER_BATCH: usize = 32;
+
+/// Result of `Analysis::eqwalizer_diagnostics_incremental`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncrementalDiagnostics {
+    /// Files that were type-checked, because they changed or depend on a
+    /// changed file.
+    pub reanalyzed: Vec<FileId>,
+    /// The modules of the project no changed file affects, whose previous
+    /// diagnostics are still valid. Affected modules outside the project's
+    /// own applications are in neither list.
+    pub unaffected: Vec<FileId>,
+    /// Diagnostics of the `reanalyzed` files.
+    pub diagnostics: Arc<EqwalizerDiagnostics>,
+}
+
+/// Result of `Analysis::eqwalizer_diagnostics_skipping_generated`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeneratedSkipped {
+    pub diagnostics: Arc<EqwalizerDiagnostics>,
+    /// Number of files left out for being marked `@generated`.
+    pub skipped_generated: usize,
+}
+
+/// Result of `Analysis::module_all_problems`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleProblems {
+    pub parse_errors: Vec<ParseError>,
+    /// eqWAlizer diagnostics of the module. `None` if there is no type
+    /// information: the module failed to parse, or eqWAlizer couldn't get
+    /// the AST of a module it depends on.
+    pub diagnostics: Option<Vec<EqwalizerDiagnostic>>,
+}
+
+impl ModuleProblems {
+    /// Whether the module doesn't parse, in which case it wasn't checked.
+    pub fn parse_failed(&self) -> bool {
+        !self.parse_errors.is_empty()
+    }
+}
+
+/// Item of `Analysis::eqwalizer_diagnostics_with_context`: a diagnostic with
+/// what callers need to gate it, e.g. to report errors of test modules as
+/// warnings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiagnosticWithContext {
+    pub module: String,
+    pub file_id: FileId,
+    pub app_type: Option<AppType>,
+    /// Whether the module is in the extra source directories of its
+    /// application, e.g. `test`, rather than in its sources.
+    pub is_test: bool,
+    /// Whether the module is marked `@generated`.
+    pub is_generated: bool,
+    pub diagnostic: EqwalizerDiagnostic,
+}
+
+/// Item of `Analysis::eqwalizer_diagnostics_stream`, result of
+/// `Analysis::eqwalizer_diagnostics_in_range`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModuleDiagnostics {
+    Diagnostics(Vec<EqwalizerDiagnostic>),
+    /// eqWAlizer couldn't get the AST of `module`, be it the checked one or
+    /// one it depends on.
+    NoAst {
+        module: String,
+    },
+}
+
+impl ModuleDiagnostics {
+    fn new(diagnostics: &EqwalizerDiagnostics) -> ModuleDiagnostics {
+        match diagnostics {
+            EqwalizerDiagnostics::Diagnostics(diagnostics) => {
+                ModuleDiagnostics::Diagnostics(diagnostics.values().flatten().cloned().collect())
+            }
+            EqwalizerDiagnostics::NoAst { module } => ModuleDiagnostics::NoAst {
+                module: module.clone(),
+            },
+        }
+    }
+
+    /// Those of `module` in `diagnostics`, e.g. of a batch of modules.
+    fn of_module(diagnostics: &EqwalizerDiagnostics, module: &str) -> ModuleDiagnostics {
+        match diagnostics {
+            EqwalizerDiagnostics::Diagnostics(diagnostics) => {
+                ModuleDiagnostics::Diagnostics(diagnostics.get(module).cloned().unwrap_or_default())
+            }
+            EqwalizerDiagnostics::NoAst { module } => ModuleDiagnostics::NoAst {
+                module: module.clone(),
+            },
+        }
+    }
+}
+
+/// Item of `Analysis::duplicate_modules`: a module defined by more than
+/// one file of a project.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateModule {
+    pub module: ModuleName,
+    /// The file the module resolves to, e.g. for eqWAlizer.
+    pub resolved: FileId,
+    /// The other files defining it, sorted.
+    pub shadowed: Vec<FileId>,
+}
+
+/// Error of `Analysis::eqwalizer_diagnostics_for_module`: the project has
+/// no module of that name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleNotFound {
+    pub module: String,
+}
+
+impl fmt::Display for ModuleNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Module {} not found", self.module)
+    }
+}
+
+impl std::error::Error for ModuleNotFound {}
+
+/// Result of `Analysis::eqwalizer_diagnostics_fail_fast`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirstError {
+    pub file_id: FileId,
+    pub module: String,
+    pub diagnostic: EqwalizerDiagnostic,
+}
+
+/// Result of `Analysis::eqwalizer_diagnostics_pull`, as for LSP pull
+/// diagnostics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PullResult {
+    /// The diagnostics are the ones of `previous_result_id`.
+    Unchanged { result_id: String },
+    Full {
+        result_id: String,
+        diagnostics: Arc<ModuleDiagnostics>,
+    },
+}
+
+/// Result of `Analysis::resolve_module`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedModule {
+    pub file_id: FileId,
+    pub path: AbsPathBuf,
+    pub app_name: String,
+    pub app_type: AppType,
+}
+
+/// Step of `AnalysisHost::prime_caches`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrimePhase {
+    Parsing,
+    Checking,
+}
+
+/// Progress of `AnalysisHost::prime_caches`, `done` out of `total` modules
+/// of the current phase, for the apps of type `app_type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrimeProgress {
+    pub phase: PrimePhase,
+    pub app_type: AppType,
+    pub done: usize,
+    pub total: usize,
+}
+
+/// How modules are scheduled by app type, see
+/// `AnalysisHost::set_app_scheduling`. By default, only the project's own
+/// apps are primed, and batches are processed in the order given.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AppScheduling {
+    /// Whether `prime_caches` also primes the dependencies, then OTP, once
+    /// done with the project's own apps.
+    pub prime_deps: bool,
+    /// Whether batches of diagnostics process the modules of the project's
+    /// own apps first, then those of dependencies and of OTP.
+    pub own_apps_first: bool,
+    /// Number of modules of the project's own apps `prime_caches` parses
+    /// at the same time, `0` meaning the available parallelism.
+    pub app_concurrency: usize,
+    /// Same as `app_concurrency`, for dependencies and OTP.
+    pub dep_concurrency: usize,
+}
+
+impl AppScheduling {
+    fn concurrency(&self, app_type: AppType) -> usize {
+        match app_type {
+            AppType::App => self.app_concurrency,
+            AppType::Dep | AppType::Otp => self.dep_concurrency,
+        }
+    }
+}
 
 /// `AnalysisHost` stores the current state of the world.
 #[derive(Debug, Default)]
 pub struct AnalysisHost {
     db: RootDatabase,
+    /// Number of changes made to the state, see `snapshot_revision`.
+    revision: u64,
+    on_invalidated: InvalidationCallbacks,
+    dynamic_policy: DynamicPolicy,
+    /// See `AnalysisHost::set_missing_spec_policy`.
+    missing_spec_policy: MissingSpecPolicy,
+    diagnostic_filters: DiagnosticFilters,
+    /// See `AnalysisHost::set_deterministic`.
+    deterministic: bool,
+    /// See `AnalysisHost::set_app_scheduling`.
+    app_scheduling: AppScheduling,
+    /// See `AnalysisHost::set_severity_overrides`.
+    severity_overrides: Arc<HashMap<String, Severity>>,
+    /// Number of changes made to the severity overrides and the filters,
+    /// see `Analysis::eqwalizer_diagnostics_pull`.
+    filters_revision: u64,
+}
+
+/// Callbacks registered with `AnalysisHost::on_invalidated`.
+#[derive(Default)]
+struct InvalidationCallbacks(Vec<Box<dyn Fn(&[FileId]) + Send + Sync>>);
+
+impl fmt::Debug for InvalidationCallbacks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("InvalidationCallbacks")
+            .field(&self.0.len())
+            .finish()
+    }
+}
+
+/// What `AnalysisHost::add_diagnostic_filter` filters do with a diagnostic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterDecision {
+    Keep,
+    Drop,
+    /// Keep it with the next less severe severity, see
+    /// `Severity::downgraded`.
+    Downgrade,
+}
+
+pub type DiagnosticFilter =
+    Arc<dyn Fn(&EqwalizerDiagnostic) -> FilterDecision + Send + Sync + RefUnwindSafe>;
+
+/// Filters registered with `AnalysisHost::add_diagnostic_filter`, in order
+/// of registration.
+#[derive(Default, Clone)]
+struct DiagnosticFilters(Arc<Vec<DiagnosticFilter>>);
+
+impl fmt::Debug for DiagnosticFilters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DiagnosticFilters")
+            .field(&self.0.len())
+            .finish()
+    }
+}
+
+impl DiagnosticFilters {
+    /// Keeps the `diagnostics` the filters decide to, in order, each seeing
+    /// what the previous ones kept. Dropping stops there.
+    fn apply(&self, diagnostics: &mut Vec<EqwalizerDiagnostic>) {
+        diagnostics.retain_mut(|d| {
+            for filter in self.0.iter() {
+                match filter(d) {
+                    FilterDecision::Keep => {}
+                    FilterDecision::Drop => return false,
+                    FilterDecision::Downgrade => {
+                        d.severity_override = Some(d.severity().downgraded())
+                    }
+                }
+            }
+            true
+        });
+    }
+}
+
+/// Gives `diagnostics` the severities of `overrides` for their codes, see
+/// `AnalysisHost::set_severity_overrides`.
+fn override_severities(
+    overrides: &HashMap<String, Severity>,
+    diagnostics: &mut [EqwalizerDiagnostic],
+) {
+    for d in diagnostics {
+        if let Some(&severity) = overrides.get(&d.code) {
+            d.severity_override = Some(severity);
+        }
+    }
 }
 
+/// A version of the state of an `AnalysisHost`, to tell which one results
+/// were computed in. Revisions only increase, each change making a new one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Revision(u64);
+
 impl AnalysisHost {
+    /// A host saving eqWAlizer results in `path` and reusing those of
+    /// previous runs, see `elp_ide_db::disk_cache`.
+    pub fn with_disk_cache(path: &AbsPath) -> Result<AnalysisHost> {
+        let mut db = RootDatabase::default();
+        let cache = DiskCache::new(path, db.eqwalizer())?;
+        db.set_disk_cache(Some(cache));
+        Ok(AnalysisHost {
+            db,
+            revision: 0,
+            on_invalidated: Default::default(),
+            dynamic_policy: DynamicPolicy::default(),
+            missing_spec_policy: MissingSpecPolicy::default(),
+            diagnostic_filters: Default::default(),
+            deterministic: false,
+            app_scheduling: AppScheduling::default(),
+            severity_overrides: Default::default(),
+            filters_revision: 0,
+        })
+    }
+
     /// Returns a snapshot of the current state, which you can query for
     /// semantic information.
     pub fn analysis(&self) -> Analysis {
         Analysis {
             db: self.db.snapshot(),
+            revision: self.snapshot_revision(),
+            dynamic_policy: self.dynamic_policy,
+            missing_spec_policy: self.missing_spec_policy,
+            diagnostic_filters: self.diagnostic_filters.clone(),
+            deterministic: self.deterministic,
+            app_scheduling: self.app_scheduling,
+            severity_overrides: self.severity_overrides.clone(),
+            filters_revision: self.filters_revision,
+        }
+    }
+
+    /// Applies `change` to the state, cancelling the snapshots taken so far,
+    /// then calls the callbacks registered with `on_invalidated`.
+    pub fn apply_change(&mut self, change: Change) {
+        let changed = self.db.apply_change(change);
+        self.revision += 1;
+        if changed.is_empty() || self.on_invalidated.0.is_empty() {
+            return;
         }
+        let stale = self.stale_files(&changed);
+        for cb in &self.on_invalidated.0 {
+            cb(&stale);
+        }
+    }
+
+    /// Calls `cb` after each `apply_change` with the files whose results
+    /// are now stale: the changed files, and the modules whose eqWAlizer
+    /// results may depend on them, see `module_deps::affected_files`.
+    /// Sorted. Callbacks are called in order of registration, on the thread
+    /// applying the change, which they shouldn't block for long.
+    pub fn on_invalidated(&mut self, cb: impl Fn(&[FileId]) + Send + Sync + 'static) {
+        self.on_invalidated.0.push(Box::new(cb));
+    }
+
+    fn stale_files(&self, changed: &[FileId]) -> Vec<FileId> {
+        let mut by_project: BTreeMap<ProjectId, Vec<FileId>> = BTreeMap::new();
+        for &file_id in changed {
+            if let Some(app_data) = self.db.app_data(self.db.file_source_root(file_id)) {
+                by_project
+                    .entry(app_data.project_id)
+                    .or_default()
+                    .push(file_id);
+            }
+        }
+        let mut stale: BTreeSet<FileId> = changed.iter().copied().collect();
+        for (project_id, changed) in by_project {
+            stale.extend(elp_ide_db::module_deps::affected_files(
+                &self.db, project_id, &changed,
+            ));
+        }
+        stale.into_iter().collect()
+    }
+
+    /// The revision of the current state, which the snapshots returned by
+    /// `analysis` carry until the next change.
+    pub fn snapshot_revision(&self) -> Revision {
+        Revision(self.revision)
+    }
+
+    /// Computes ahead of time what the first diagnostics requests need, on a
+    /// snapshot: the ASTs of the modules of the project's own applications,
+    /// in parallel, then the eqwalizer diagnostics of the opted-in ones, in
+    /// non-strict mode. Diagnostics are computed in batches of
+    /// `MODULES_PER_BATCH` modules, one eqWAlizer process each, memoized
+    /// per batch. With a disk cache, see `with_disk_cache`, later requests
+    /// for single modules find their results there. Dependencies are then
+    /// primed the same way if the app scheduling says so, see
+    /// `set_app_scheduling`. Changes to the host cancel priming, which
+    /// stops at the first module or batch cancelled and returns `Err`: what
+    /// was primed until then stays memoized.
+    pub fn prime_caches(
+        &self,
+        project_id: ProjectId,
+        cb: impl Fn(PrimeProgress) + Sync + RefUnwindSafe,
+    ) -> Cancellable<()> {
+        self.analysis().prime_caches(project_id, cb)
+    }
+
+    /// Sets the additional code paths of the parse server of a project, see
+    /// `ProjectData::extra_code_paths`. Invalidates the ASTs of the project.
+    pub fn set_extra_code_paths(&mut self, project_id: ProjectId, paths: Vec<AbsPathBuf>) {
+        let mut project_data = (*self.db.project_data(project_id)).clone();
+        project_data.extra_code_paths = paths;
+        self.db.set_project_data(project_id, Arc::new(project_data));
+        self.revision += 1;
+    }
+
+    /// Sets the directory of the stubs of a project, and the source root
+    /// its files are loaded in if they aren't in those of the project, see
+    /// `elp_ide_db::stubs`. The files themselves are set separately, like
+    /// those of the apps. Invalidates the stubs of the project.
+    pub fn set_stubs_dir(
+        &mut self,
+        project_id: ProjectId,
+        stubs_dir: Option<AbsPathBuf>,
+        stubs_root: Option<SourceRootId>,
+    ) {
+        let mut project_data = (*self.db.project_data(project_id)).clone();
+        project_data.stubs_dir = stubs_dir;
+        project_data.stubs_root = stubs_root;
+        self.db.set_project_data(project_id, Arc::new(project_data));
+        self.revision += 1;
+    }
+
+    /// Sets the OTP release a project is built for, whose stubs then give
+    /// the specs of OTP modules, see `ProjectData::otp_version`.
+    /// The stubs of the release are files of the database, set separately
+    /// like the other stubs, see `set_stubs_dir`. Invalidates the stubs of
+    /// the project, and with them the diagnostics of the modules using OTP.
+    pub fn set_otp_version(&mut self, project_id: ProjectId, otp_version: Option<String>) {
+        let mut project_data = (*self.db.project_data(project_id)).clone();
+        project_data.otp_version = otp_version;
+        self.db.set_project_data(project_id, Arc::new(project_data));
+        self.revision += 1;
+    }
+
+    /// Sets the apps and projects of the database as `manifest` describes
+    /// them, without discovering the projects, see `manifest`. The source
+    /// roots and files of the apps are set separately. Fails, leaving the
+    /// database untouched, if a path of the manifest isn't absolute or a
+    /// term doesn't decode.
+    pub fn load_project_manifest(&mut self, manifest: ProjectManifest) -> Result<()> {
+        let app_structure = manifest.app_structure()?;
+        app_structure.apply(&mut self.db);
+        self.revision += 1;
+        Ok(())
+    }
+
+    /// The manifest of the given projects, for `load_project_manifest` to
+    /// set them up again, e.g. in a later session.
+    pub fn dump_project_manifest(&self, project_ids: &[ProjectId]) -> ProjectManifest {
+        ProjectManifest::dump(&self.db, project_ids)
+    }
+
+    /// The inputs of the database for the given projects, for
+    /// `deserialize_state` to set them up on another host, see `state`.
+    /// Derived results, e.g. ASTs and diagnostics, aren't included.
+    pub fn serialize_state(&self, project_ids: &[ProjectId]) -> Vec<u8> {
+        state::serialize(&self.db, project_ids)
+    }
+
+    /// A host with the inputs `serialize_state` gave, without discovering
+    /// the projects or reading their files. Fails if the state is of
+    /// another version of the format or of eqWAlizer, or doesn't decode.
+    pub fn deserialize_state(bytes: &[u8]) -> Result<AnalysisHost> {
+        let mut host = AnalysisHost::default();
+        state::deserialize(&mut host.db, bytes)?;
+        Ok(host)
+    }
+
+    /// Caps the number of AST requests in flight to the parse servers, `0`
+    /// meaning the available parallelism. Applies to all requests, including
+    /// those of `prime_caches` and of batches being type-checked, and to the
+    /// snapshots already taken. By default, requests are not capped.
+    pub fn set_parse_server_concurrency(&mut self, n: usize) {
+        self.db.set_parse_server_concurrency(n);
+    }
+
+    /// Retries requests the parse server dies before answering up to
+    /// `max_retries` times, with a new server each time, waiting `backoff`
+    /// between retries. Parse errors are never retried. Applies to the
+    /// snapshots already taken. By default, requests are retried once.
+    pub fn set_parse_retry_policy(&mut self, max_retries: usize, backoff: Duration) {
+        self.db.set_parse_retry_policy(ParseRetryPolicy {
+            max_retries,
+            backoff,
+        });
+    }
+
+    /// Reports the functions without a spec, whose types gradual mode
+    /// implicitly makes `dynamic()`, in the results of
+    /// `Analysis::eqwalizer_diagnostics` as `policy` says: not at all, with
+    /// `implicit_dynamic` warnings or with `implicit_dynamic_error` errors.
+    /// These are found from the forms of the modules, eqWAlizer itself
+    /// checks the same way under every policy. Applies to the snapshots
+    /// taken from now on. Results are memoized per policy, and
+    /// `DynamicPolicy::Allow`, the default, gives the diagnostics of
+    /// eqWAlizer as they are.
+    pub fn set_dynamic_policy(&mut self, policy: DynamicPolicy) {
+        self.dynamic_policy = policy;
+        self.revision += 1;
+    }
+
+    /// Reports the exported functions without a spec in the results of
+    /// `Analysis::eqwalizer_diagnostics` as `policy` says: not at all, with
+    /// `missing_spec` warnings or with `missing_spec_error` errors, each
+    /// with a fix adding a spec inferred from the clauses. Applies to the
+    /// snapshots taken from now on. Results are memoized per policy, and
+    /// `MissingSpecPolicy::Ignore`, the default, reports nothing more.
+    pub fn set_missing_spec_policy(&mut self, policy: MissingSpecPolicy) {
+        self.missing_spec_policy = policy;
+        self.revision += 1;
+    }
+
+    /// Makes the snapshots taken from now on process modules one at a
+    /// time, in the order of their paths, so that the logs of eqWAlizer and
+    /// of the parse servers are the same from one run to the next, e.g. to
+    /// reproduce a bug. Applies to `Analysis::eqwalizer_diagnostics`, to
+    /// the variants taking a list of files, and to the batches of
+    /// `Analysis::module_asts` and `AnalysisHost::prime_caches`.
+    ///
+    /// This is for debugging only: ASTs are then requested one after the
+    /// other instead of concurrently, which makes parsing a large project
+    /// several times slower, and the sorted lists of files are memoized
+    /// apart from the lists given, so results computed before are computed
+    /// again.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+        self.revision += 1;
+    }
+
+    /// Makes the snapshots taken from now on schedule modules by the type
+    /// of their app as `policy` says, so that a large dependency doesn't
+    /// hold up the project's own apps: `prime_caches` is done with these
+    /// before it starts with dependencies, each with its own concurrency,
+    /// and batches of diagnostics may check them first. Batches are still
+    /// memoized by the lists given, whatever the order they are checked in.
+    pub fn set_app_scheduling(&mut self, policy: AppScheduling) {
+        self.app_scheduling = policy;
+        self.db.set_own_apps_first(policy.own_apps_first);
+        self.revision += 1;
+    }
+
+    /// Gives the diagnostics of `Analysis::eqwalizer_diagnostics` the
+    /// severity `overrides` maps their code to, before they are passed
+    /// through the filters. Diagnostics of other codes keep the severity of
+    /// their code, see `EqwalizerDiagnostic::severity` for the effective
+    /// one. Overrides apply to the memoized results every time, so setting
+    /// them doesn't invalidate anything, nor make a new revision. Applies
+    /// to the snapshots taken from now on, and to every entry point giving
+    /// eqWAlizer diagnostics, `module_is_clean` included. Replaces the
+    /// overrides set before.
+    pub fn set_severity_overrides(&mut self, overrides: HashMap<String, Severity>) {
+        self.severity_overrides = Arc::new(overrides);
+        self.filters_revision += 1;
+    }
+
+    /// Passes the results of `Analysis::eqwalizer_diagnostics` through
+    /// `filter`, after the filters added before, e.g. to drop known false
+    /// positives. Filters run on the memoized results every time, so adding
+    /// one doesn't invalidate them, nor make a new revision. Applies to the
+    /// snapshots taken from now on, and to every entry point giving
+    /// eqWAlizer diagnostics, as the severity overrides do.
+    pub fn add_diagnostic_filter(&mut self, filter: DiagnosticFilter) {
+        let mut filters = (*self.diagnostic_filters.0).clone();
+        filters.push(filter);
+        self.diagnostic_filters = DiagnosticFilters(Arc::new(filters));
+        self.filters_revision += 1;
+    }
+
+    /// Reports to `sink` the time spent parsing, decoding, type-checking and
+    /// assembling the diagnostics of each module, see
+    /// `elp_ide_db::timing`. Applies to the snapshots taken from now on.
+    /// Without a sink, only the metrics are measured, see
+    /// `metrics_snapshot`.
+    pub fn set_timing_sink(&mut self, sink: Arc<dyn TimingSink>) {
+        self.db.set_timing_sink(Some(sink));
+    }
+
+    /// The counters and latency histograms recorded by the host and its
+    /// snapshots so far, see `elp_ide_db::metrics`. Cheap enough to be
+    /// taken on every scrape of a `/metrics` handler, e.g. with
+    /// `Metrics::to_prometheus`.
+    pub fn metrics_snapshot(&self) -> Metrics {
+        self.db.metrics()
+    }
+
+    /// Type-checks the file with unsaved `contents` instead of its text on
+    /// disk, until `clear_file_overlay`.
+    pub fn set_file_overlay(&mut self, file_id: FileId, contents: Arc<String>) {
+        self.db.set_file_overlay(file_id, contents);
+        self.revision += 1;
+    }
+
+    /// Reverts the file to its text on disk: the one of the latest
+    /// `apply_change` changing it, which doesn't replace the overlay, or
+    /// else its text before `set_file_overlay`.
+    pub fn clear_file_overlay(&mut self, file_id: FileId) {
+        self.db.clear_file_overlay(file_id);
+        self.revision += 1;
     }
 
     pub fn raw_database(&self) -> &RootDatabase {
         &self.db
     }
+    /// The database, to change it. Counts as a change of the state, see
+    /// `snapshot_revision`.
     pub fn raw_database_mut(&mut self) -> &mut RootDatabase {
+        self.revision += 1;
         &mut self.db
     }
 }
@@ -57,6 +753,20 @@ impl AnalysisHost {
 #[derive(Debug)]
 pub struct Analysis {
     db: salsa::Snapshot<RootDatabase>,
+    revision: Revision,
+    dynamic_policy: DynamicPolicy,
+    /// See `AnalysisHost::set_missing_spec_policy`.
+    missing_spec_policy: MissingSpecPolicy,
+    diagnostic_filters: DiagnosticFilters,
+    /// See `AnalysisHost::set_deterministic`.
+    deterministic: bool,
+    /// See `AnalysisHost::set_app_scheduling`.
+    app_scheduling: AppScheduling,
+    /// See `AnalysisHost::set_severity_overrides`.
+    severity_overrides: Arc<HashMap<String, Severity>>,
+    /// Number of changes made to the severity overrides and the filters,
+    /// see `Analysis::eqwalizer_diagnostics_pull`.
+    filters_revision: u64,
 }
 
 // As a general design guideline, `Analysis` API are intended to be independent
@@ -64,13 +774,71 @@ pub struct Analysis {
 // we should think in terms of "what API makes most sense" and not in terms of
 // "what types LSP uses". We have at least 2 consumers of the API - LSP and CLI
 impl Analysis {
+    /// A snapshot of the same state whose computations are also cancelled
+    /// by `token`, e.g. to stop checking a module the user no longer looks
+    /// at without changing the database. Methods of the returned `Analysis`
+    /// then return `Err(Cancelled)`, see `elp_ide_db::CancellationToken`.
+    pub fn with_cancellation(&self, token: &CancellationToken) -> Analysis {
+        Analysis {
+            db: self.db.snapshot_with_cancellation(token),
+            revision: self.revision,
+            dynamic_policy: self.dynamic_policy,
+            missing_spec_policy: self.missing_spec_policy,
+            diagnostic_filters: self.diagnostic_filters.clone(),
+            deterministic: self.deterministic,
+            app_scheduling: self.app_scheduling,
+            severity_overrides: self.severity_overrides.clone(),
+            filters_revision: self.filters_revision,
+        }
+    }
+
+    /// The revision of the state this is a snapshot of. Results of the
+    /// methods are those of this revision: once the state changes, they
+    /// return `Err(Cancelled)` instead of results of another one.
+    pub fn revision(&self) -> Revision {
+        self.revision
+    }
+
     /// Gets the file's `LineIndex`: data structure to convert between absolute
     /// offsets and line/column representation.
     pub fn line_index(&self, file_id: FileId) -> Arc<LineIndex> {
         self.db.file_line_index(file_id)
     }
 
-    /// Computes the set of eqwalizer diagnostics for the given file.
+    /// The position of `offset` in the file, with columns counted as
+    /// `encoding` says. The end of the file if `offset` is past it.
+    pub fn offset_to_position(
+        &self,
+        file_id: FileId,
+        offset: TextSize,
+        encoding: PositionEncoding,
+    ) -> Cancellable<Position> {
+        self.with_db(|db| db.file_line_index(file_id).position(offset, encoding))
+    }
+
+    /// The offset of `position` in the file, with columns counted as
+    /// `encoding` says. `None` if the position isn't in the file, see
+    /// `LineIndex::checked_offset`.
+    pub fn position_to_offset(
+        &self,
+        file_id: FileId,
+        position: Position,
+        encoding: PositionEncoding,
+    ) -> Cancellable<Option<TextSize>> {
+        self.with_db(|db| {
+            db.file_line_index(file_id)
+                .checked_offset(position, encoding)
+        })
+    }
+
+    /// Computes the set of eqwalizer diagnostics for the given file, with
+    /// the functions without a spec reported as the dynamic policy of the
+    /// host says, see `AnalysisHost::set_dynamic_policy`, the exported ones
+    /// as its missing spec policy says, see
+    /// `AnalysisHost::set_missing_spec_policy`, with the severity
+    /// overrides of the host, see `AnalysisHost::set_severity_overrides`,
+    /// then passed through the filters of the host, see
+    /// `AnalysisHost::add_diagnostic_filter`.
     pub fn eqwalizer_diagnostics(
         &self,
         project_id: ProjectId,
@@ -78,66 +846,1753 @@ impl Analysis {
         format: parse_server::Format,
         strict: bool,
     ) -> Arc<EqwalizerDiagnostics> {
-        self.db
-            .eqwalizer_diagnostics(project_id, file_ids, format, strict)
+        self.host_diagnostics(&self.db, project_id, file_ids, format, strict)
     }
 
-    /// Low-level access to eqwalizer
-    pub fn eqwalizer(&self) -> &Eqwalizer {
-        self.db.eqwalizer()
+    /// Same as `eqwalizer_diagnostics`, leaving out the files marked
+    /// `@generated` if `skip_generated` is set. Nothing is left out
+    /// otherwise, skipping being opt-in. Generated files are found from
+    /// the comments at the top of their text alone, without parsing them.
+    pub fn eqwalizer_diagnostics_skipping_generated(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+        strict: bool,
+        skip_generated: bool,
+    ) -> Cancellable<GeneratedSkipped> {
+        self.with_db(|db| {
+            let total = file_ids.len();
+            let file_ids: Vec<FileId> = if skip_generated {
+                file_ids
+                    .into_iter()
+                    .filter(|&file_id| !is_generated(&db.file_text(file_id)))
+                    .collect()
+            } else {
+                file_ids
+            };
+            let skipped_generated = total - file_ids.len();
+            let diagnostics = if file_ids.is_empty() {
+                Default::default()
+            } else {
+                self.host_diagnostics(db, project_id, file_ids, format, strict)
+            };
+            GeneratedSkipped {
+                diagnostics,
+                skipped_generated,
+            }
+        })
     }
 
-    /// ETF for the module's abstract forms
-    pub fn module_ast(
+    /// The text of the module in `file_id` with the suggested fixes of its
+    /// diagnostics in non-strict mode applied, as they are after the
+    /// policies and filters of the host, for those `fix_selector` selects.
+    /// See `elp_ide_db::apply_fixes` for when none can be applied. The text
+    /// as it is if the file isn't in a project.
+    pub fn apply_eqwalizer_fixes(
         &self,
         file_id: FileId,
+        fix_selector: FixSelector,
+    ) -> Cancellable<Result<String, FixError>> {
+        self.with_db(|db| {
+            let text = db.file_text(file_id);
+            let project_id = match db.app_data(db.file_source_root(file_id)) {
+                Some(app_data) => app_data.project_id,
+                None => return Ok(text.to_string()),
+            };
+            let diagnostics = self.host_diagnostics(
+                db,
+                project_id,
+                vec![file_id],
+                parse_server::Format::OffsetEtf,
+                false,
+            );
+            let diagnostics: Vec<EqwalizerDiagnostic> = match &*diagnostics {
+                EqwalizerDiagnostics::Diagnostics(diagnostics) => {
+                    diagnostics.values().flatten().cloned().collect()
+                }
+                EqwalizerDiagnostics::NoAst { .. } => vec![],
+            };
+            elp_ide_db::apply_fixes(&text, &diagnostics, &fix_selector)
+        })
+    }
+
+    /// The diagnostics `% eqwalizer:ignore <code>` comments suppress from
+    /// the results of `eqwalizer_diagnostics` in non-strict mode, each with
+    /// the comment suppressing it, see
+    /// `eqwalizer::suppressed_diagnostics`.
+    pub fn suppressed_diagnostics(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
         format: parse_server::Format,
-    ) -> Result<Arc<Vec<u8>>, Arc<Vec<ParseError>>> {
-        self.db.module_ast(file_id, format)
+    ) -> Cancellable<Vec<SuppressedDiagnostic>> {
+        self.with_db(|db| {
+            elp_ide_db::eqwalizer::suppressed_diagnostics(db, project_id, file_ids, format)
+        })
     }
 
-    pub fn project_data(&self, file_id: FileId) -> Option<Arc<ProjectData>> {
-        Some(
-            self.db.project_data(
-                self.db
-                    .app_data(self.db.file_source_root(file_id))?
-                    .project_id,
-            ),
-        )
+    /// Same as `eqwalizer_diagnostics`, keeping at most `max_per_module`
+    /// diagnostics per module followed by one telling how many were
+    /// dropped, to keep huge outputs, e.g. of generated modules, in check.
+    /// `None` keeps them all.
+    pub fn eqwalizer_diagnostics_truncated(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+        strict: bool,
+        max_per_module: Option<usize>,
+    ) -> Cancellable<Arc<EqwalizerDiagnostics>> {
+        self.with_db(|db| {
+            db.record_diagnostics_query();
+            self.presented(db.eqwalizer_diagnostics_truncated(
+                project_id,
+                file_ids,
+                format,
+                strict,
+                max_per_module,
+            ))
+        })
     }
 
-    /// Returns module name
-    pub fn module_name(&self, file_id: FileId) -> Option<ModuleName> {
-        let db = &self.db;
-        let app_data = db.app_data(db.file_source_root(file_id))?;
-        db.module_index(app_data.project_id)
-            .module_for_file(file_id)
-            .cloned()
+    /// Same as `eqwalizer_diagnostics` in non-strict mode, type-checking the
+    /// modules as if they had a `-typing([eqwalizer])` attribute, e.g. to
+    /// preview what opting them in would report. Memoized separately from
+    /// `eqwalizer_diagnostics`. Only ETF ASTs can be rewritten this way:
+    /// with `Format::Text`, the check fails and nothing is reported.
+    pub fn eqwalizer_diagnostics_speculative(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+    ) -> Cancellable<Arc<EqwalizerDiagnostics>> {
+        self.with_db(|db| {
+            db.record_diagnostics_query();
+            self.presented(db.eqwalizer_diagnostics_speculative(project_id, file_ids, format))
+        })
     }
 
-    pub fn module_index(&self, project_id: ProjectId) -> Arc<ModuleIndex> {
-        self.db.module_index(project_id)
+    /// Same as `eqwalizer_diagnostics_speculative`, one module at a time: a
+    /// module taking longer than `timeout` gets a single `timeout`
+    /// diagnostic, as with `eqwalizer_diagnostics_with_timeout`. Not
+    /// memoized.
+    pub fn eqwalizer_diagnostics_speculative_with_timeout(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+        timeout: Duration,
+    ) -> Cancellable<Arc<EqwalizerDiagnostics>> {
+        let file_ids = self.in_processing_order(project_id, file_ids);
+        self.with_db(|db| {
+            db.record_diagnostics_query();
+            self.presented(
+                elp_ide_db::eqwalizer::eqwalizer_diagnostics_speculative_with_timeout(
+                    db, project_id, file_ids, format, timeout,
+                ),
+            )
+        })
     }
 
-    pub fn module_file_id(&self, project_id: ProjectId, module: &str) -> Option<FileId> {
-        self.db.module_index(project_id).file_for_module(module)
+    /// Same as `eqwalizer_diagnostics`, checking only what `scope` says,
+    /// see `CheckScope` for the diagnostics `CheckScope::SpecsOnly` can't
+    /// report. Ignore comments apply to `SpecsOnly` results, unused ones
+    /// aside, and so do the severity overrides and filters of the host,
+    /// but its dynamic policy doesn't. `SpecsOnly` fails with `Format::Text` like
+    /// `eqwalizer_diagnostics_speculative`.
+    pub fn eqwalizer_diagnostics_scoped(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+        mode: CheckMode,
+        scope: CheckScope,
+    ) -> Cancellable<Arc<EqwalizerDiagnostics>> {
+        self.with_db(|db| {
+            db.record_diagnostics_query();
+            self.presented(db.eqwalizer_diagnostics_scoped(
+                project_id,
+                file_ids,
+                format,
+                mode.is_strict(),
+                scope,
+            ))
+        })
     }
 
-    /// Returns the app name for a file
-    pub fn file_app_name(&self, file_id: FileId) -> Option<String> {
-        self.db.file_app_name(file_id)
+    /// Same as `eqwalizer_diagnostics`, in the given mode. Results are
+    /// memoized per mode.
+    pub fn eqwalizer_diagnostics_with_mode(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+        mode: CheckMode,
+    ) -> Cancellable<Arc<EqwalizerDiagnostics>> {
+        self.with_db(|db| self.host_diagnostics(db, project_id, file_ids, format, mode.is_strict()))
     }
 
-    /// Returns the app_type for a file
-    pub fn file_app_type(&self, file_id: FileId) -> Option<AppType> {
-        self.db.file_app_type(file_id)
+    /// Same as `eqwalizer_diagnostics_with_mode`, for async callers: the
+    /// check runs on a pool of threads shared by all the async calls, one
+    /// per CPU, with a snapshot of its own, which stays valid until it's
+    /// done. The future works with any executor. The check is cancelled
+    /// like any other, by a change to the database, and also when the
+    /// future is dropped, the future then resolving to `Cancelled`. If the
+    /// pool can't be started, the check runs on the caller.
+    pub fn eqwalizer_diagnostics_async(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+        mode: CheckMode,
+    ) -> impl Future<Output = Cancellable<Arc<EqwalizerDiagnostics>>> + Send {
+        let token = CancellationToken::new();
+        let analysis = self.with_cancellation(&token);
+        let check =
+            move || analysis.eqwalizer_diagnostics_with_mode(project_id, file_ids, format, mode);
+        let result = match blocking::shared() {
+            Some(pool) => pool.spawn(check),
+            None => blocking::Blocking::ready(check()),
+        };
+        async move {
+            // Dropping the future drops the token, which cancels the check
+            let _token = token;
+            result.await
+        }
+    }
+
+    /// Computes the eqwalizer diagnostics for the given files, one module at
+    /// a time. A module taking longer than `timeout` gets a single `timeout`
+    /// diagnostic, the others are checked normally.
+    pub fn eqwalizer_diagnostics_with_timeout(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+        strict: bool,
+        timeout: Duration,
+    ) -> Cancellable<Arc<EqwalizerDiagnostics>> {
+        let file_ids = self.in_processing_order(project_id, file_ids);
+        self.with_db(|db| {
+            db.record_diagnostics_query();
+            self.presented(elp_ide_db::eqwalizer::eqwalizer_diagnostics_with_timeout(
+                db, project_id, file_ids, format, strict, timeout,
+            ))
+        })
+    }
+
+    /// Computes the eqwalizer diagnostics for all the modules of the
+    /// applications selected by `filter`, e.g. leaving out dependencies.
+    pub fn eqwalizer_diagnostics_for_apps(
+        &self,
+        project_id: ProjectId,
+        filter: AppFilter,
+        format: parse_server::Format,
+    ) -> Cancellable<Arc<EqwalizerDiagnostics>> {
+        self.with_db(|db| {
+            db.record_diagnostics_query();
+            self.presented(db.eqwalizer_diagnostics_for_apps(project_id, filter, format))
+        })
+    }
+
+    /// Whether eqWAlizer reports no errors for the module in `file_id`,
+    /// sharing the work with `eqwalizer_diagnostics` for that file alone.
+    /// Errors are counted as the severity overrides and filters of the
+    /// host leave them, e.g. a code overridden to a warning doesn't count.
+    pub fn module_is_clean(
+        &self,
+        project_id: ProjectId,
+        file_id: FileId,
+        strict: bool,
+    ) -> Cancellable<bool> {
+        self.with_db(|db| {
+            if self.presents_as_is() {
+                return db.module_is_clean(project_id, file_id, strict);
+            }
+            let diagnostics = self.presented(db.eqwalizer_diagnostics(
+                project_id,
+                vec![file_id],
+                parse_server::Format::OffsetEtf,
+                strict,
+            ));
+            match &*diagnostics {
+                EqwalizerDiagnostics::Diagnostics(diagnostics) => diagnostics
+                    .values()
+                    .flatten()
+                    .all(|d| d.severity() < Severity::Error),
+                EqwalizerDiagnostics::NoAst { .. } => false,
+            }
+        })
     }
+
+    /// Diagnostics of the body and spec of `function/arity`, in non-strict
+    /// mode. `None` if the module has no such function or eqWAlizer
+    /// couldn't get an AST. The whole module is type-checked, see
+    /// `EqwalizerDatabase::function_diagnostics` for what is memoized.
+    pub fn eqwalizer_diagnostics_for_function(
+        &self,
+        project_id: ProjectId,
+        file_id: FileId,
+        function: FunctionName,
+        arity: Arity,
+    ) -> Cancellable<Option<Arc<Vec<EqwalizerDiagnostic>>>> {
+        self.with_db(|db| {
+            let diagnostics =
+                db.function_diagnostics(project_id, file_id, function, arity, false)?;
+            if self.presents_as_is() {
+                return Some(diagnostics);
+            }
+            let mut diagnostics = (*diagnostics).clone();
+            self.present(&mut diagnostics);
+            Some(Arc::new(diagnostics))
+        })
+    }
+
+    /// Adoption of eqWAlizer in the project's own applications: how many
+    /// modules are opted in, fully or partially checked, and how many
+    /// diagnostics the opted-in ones have. Type-checks all of them.
+    pub fn eqwalizer_project_stats(&self, project_id: ProjectId) -> Cancellable<ProjectStats> {
+        self.with_db(|db| elp_ide_db::stats::project_stats(db, project_id))
+    }
+
+    /// Sorts the given modules into those eqWAlizer can check, those not
+    /// opted in, and those failing to parse, see `stats::coverage`.
+    pub fn eqwalizer_coverage(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+    ) -> Cancellable<Coverage> {
+        self.with_db(|db| elp_ide_db::stats::coverage(db, project_id, file_ids, format))
+    }
+
+    /// Modules of the project's own applications opted in to eqWAlizer, see
+    /// `stats::enabled_modules`. Much cheaper than type-checking them.
+    pub fn eqwalizer_enabled_modules(&self, project_id: ProjectId) -> Cancellable<Vec<FileId>> {
+        self.with_db(|db| elp_ide_db::stats::enabled_modules(db, project_id))
+    }
+
+    /// Computes the eqwalizer diagnostics for the given files, calling `cb`
+    /// as modules are type-checked. A change to the database cancels the
+    /// computation between two modules.
+    pub fn eqwalizer_diagnostics_with_progress(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+        strict: bool,
+        cb: impl Fn(Progress) + UnwindSafe,
+    ) -> Cancellable<Arc<EqwalizerDiagnostics>> {
+        self.with_db(|db| {
+            db.record_diagnostics_query();
+            self.presented(elp_ide_db::eqwalizer::eqwalizer_diagnostics_with_progress(
+                db, project_id, file_ids, format, strict, &cb,
+            ))
+        })
+    }
+
+    /// Computes the eqwalizer diagnostics for the given files in non-strict
+    /// mode, in batches of `MODULES_PER_BATCH` modules as the iterator is
+    /// consumed, so that they can be reported as they come. Each batch is
+    /// checked by one eqWAlizer process and memoized the same way as
+    /// `eqwalizer_diagnostics` for those modules, then yields one item per
+    /// module. Once the database changes, the remaining batches each yield
+    /// a single `Err(Cancelled)`, while the items already yielded describe
+    /// the previous state.
+    pub fn eqwalizer_diagnostics_stream(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+    ) -> impl Iterator<Item = Cancellable<(FileId, ModuleDiagnostics)>> + '_ {
+        batches(file_ids).flat_map(move |batch| {
+            let items = self.with_db(|db| {
+                let diagnostics =
+                    self.host_diagnostics(db, project_id, batch.clone(), format, false);
+                let module_index = db.module_index(project_id);
+                batch
+                    .iter()
+                    .map(|&file_id| {
+                        let module = module_index
+                            .module_for_file(file_id)
+                            .map_or("", |module| module.as_str());
+                        (file_id, ModuleDiagnostics::of_module(&diagnostics, module))
+                    })
+                    .collect::<Vec<_>>()
+            });
+            match items {
+                Ok(items) => items.into_iter().map(Ok).collect::<Vec<_>>(),
+                Err(cancelled) => vec![Err(cancelled)],
+            }
+        })
+    }
+
+    /// Computes the eqwalizer diagnostics of the module of the given name in
+    /// non-strict mode, memoized the same way as `eqwalizer_diagnostics` for
+    /// that module alone.
+    pub fn eqwalizer_diagnostics_for_module(
+        &self,
+        project_id: ProjectId,
+        module: &str,
+        format: parse_server::Format,
+    ) -> Cancellable<Result<Arc<ModuleDiagnostics>, ModuleNotFound>> {
+        self.with_db(|db| {
+            let file_id = db
+                .module_index(project_id)
+                .file_for_module(module)
+                .ok_or_else(|| ModuleNotFound {
+                    module: module.to_string(),
+                })?;
+            let diagnostics = self.host_diagnostics(db, project_id, vec![file_id], format, false);
+            Ok(Arc::new(ModuleDiagnostics::new(&diagnostics)))
+        })
+    }
+
+    /// The eqwalizer diagnostics of the module in `file_id` in non-strict
+    /// mode whose ranges intersect `range`, in order of position, e.g. to
+    /// render those of the visible part of a file first. The whole module
+    /// is still type-checked, memoized the same way as
+    /// `eqwalizer_diagnostics` for that module alone, so fetching the
+    /// diagnostics outside of `range` later on is cheap.
+    pub fn eqwalizer_diagnostics_in_range(
+        &self,
+        file_id: FileId,
+        range: TextRange,
+        format: parse_server::Format,
+    ) -> Cancellable<ModuleDiagnostics> {
+        self.with_db(|db| {
+            let project_id = match db.app_data(db.file_source_root(file_id)) {
+                Some(app_data) => app_data.project_id,
+                None => return ModuleDiagnostics::Diagnostics(vec![]),
+            };
+            let diagnostics = self.host_diagnostics(db, project_id, vec![file_id], format, false);
+            match ModuleDiagnostics::new(&diagnostics) {
+                ModuleDiagnostics::Diagnostics(diagnostics) => {
+                    let mut in_range: Vec<EqwalizerDiagnostic> = diagnostics
+                        .into_iter()
+                        .filter(|d| range.intersect(d.range).is_some())
+                        .collect();
+                    in_range.sort_by_key(|d| d.range.start());
+                    ModuleDiagnostics::Diagnostics(in_range)
+                }
+                no_ast => no_ast,
+            }
+        })
+    }
+
+    /// The eqwalizer diagnostics of the module in `file_id` in non-strict
+    /// mode, memoized the same way as `eqwalizer_diagnostics` for that
+    /// module alone, or `Unchanged` if they are those of
+    /// `previous_result_id`. Salsa doesn't tell when a memoized result last
+    /// changed, so result ids are fingerprints of the diagnostics, memoized
+    /// with them, see `eqwalizer_diagnostics_fingerprint`: one changes
+    /// exactly when an edit changes the result salsa hands out, or when the
+    /// severity overrides or the filters change. They are only valid for
+    /// the current session.
+    pub fn eqwalizer_diagnostics_pull(
+        &self,
+        file_id: FileId,
+        previous_result_id: Option<String>,
+    ) -> Cancellable<PullResult> {
+        self.with_db(|db| {
+            let (diagnostics, fingerprint) = match db.app_data(db.file_source_root(file_id)) {
+                Some(app_data) => {
+                    let diagnostics = ModuleDiagnostics::new(&self.host_diagnostics(
+                        db,
+                        app_data.project_id,
+                        vec![file_id],
+                        parse_server::Format::OffsetEtf,
+                        false,
+                    ));
+                    let fingerprint = db.eqwalizer_diagnostics_fingerprint(
+                        app_data.project_id,
+                        vec![file_id],
+                        parse_server::Format::OffsetEtf,
+                        false,
+                        self.dynamic_policy,
+                        self.missing_spec_policy,
+                    );
+                    (diagnostics, fingerprint)
+                }
+                None => (ModuleDiagnostics::Diagnostics(vec![]), 0),
+            };
+            let result_id = format!("{}-{:016x}", self.filters_revision, fingerprint);
+            if previous_result_id.as_deref() == Some(result_id.as_str()) {
+                PullResult::Unchanged { result_id }
+            } else {
+                PullResult::Full {
+                    result_id,
+                    diagnostics: Arc::new(diagnostics),
+                }
+            }
+        })
+    }
+
+    /// The first error of the given files in non-strict mode, checking them
+    /// one at a time in the order of their paths and stopping at the first
+    /// with an error, e.g. for pre-commit hooks. Warnings and hints don't
+    /// count, and modules eqWAlizer couldn't get an AST for are skipped.
+    /// Results are memoized per module, the same way as
+    /// `eqwalizer_diagnostics` for that module alone, so stopping early or
+    /// being cancelled leaves nothing half-computed.
+    pub fn eqwalizer_diagnostics_fail_fast(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+    ) -> Cancellable<Option<FirstError>> {
+        self.with_db(|db| {
+            let mut file_ids: Vec<(Option<String>, FileId)> = file_ids
+                .into_iter()
+                .map(|file_id| (file_path(db, project_id, file_id), file_id))
+                .collect();
+            file_ids.sort();
+            let module_index = db.module_index(project_id);
+            for (_path, file_id) in file_ids {
+                db.unwind_if_cancelled();
+                let module = match module_index.module_for_file(file_id) {
+                    Some(module) => module.as_str(),
+                    None => continue,
+                };
+                let error =
+                    match &*self.host_diagnostics(db, project_id, vec![file_id], format, false) {
+                        EqwalizerDiagnostics::Diagnostics(diagnostics) => diagnostics
+                            .get(module)
+                            .and_then(|diagnostics| {
+                                diagnostics.iter().find(|d| d.severity() >= Severity::Error)
+                            })
+                            .cloned(),
+                        EqwalizerDiagnostics::NoAst { .. } => None,
+                    };
+                if let Some(diagnostic) = error {
+                    return Some(FirstError {
+                        file_id,
+                        module: module.to_string(),
+                        diagnostic,
+                    });
+                }
+            }
+            None
+        })
+    }
+
+    /// Computes the eqwalizer diagnostics for the given files, keeping only
+    /// those at least as severe as `min_severity`, see
+    /// `EqwalizerDatabase::eqwalizer_diagnostics_filtered` for what is
+    /// memoized. With severity overrides or filters, which can change the
+    /// severities, the results of `eqwalizer_diagnostics` are filtered
+    /// instead.
+    pub fn eqwalizer_diagnostics_filtered(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+        strict: bool,
+        min_severity: Severity,
+    ) -> Arc<EqwalizerDiagnostics> {
+        if !self.presents_as_is() {
+            let diagnostics = self.eqwalizer_diagnostics(project_id, file_ids, format, strict);
+            return Arc::new(diagnostics.filter_severity(min_severity));
+        }
+        self.db.record_diagnostics_query();
+        self.db.eqwalizer_diagnostics_filtered(
+            project_id,
+            self.in_processing_order(project_id, file_ids),
+            format,
+            strict,
+            self.dynamic_policy,
+            self.missing_spec_policy,
+            min_severity,
+        )
+    }
+
+    /// Computes the eqwalizer diagnostics for the given files, each paired
+    /// with a fingerprint that is stable across unrelated edits.
+    /// Returns `None` if eqWAlizer couldn't get the AST of some module.
+    pub fn eqwalizer_diagnostics_with_fingerprints(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+        strict: bool,
+    ) -> Option<Vec<FingerprintedDiagnostic>> {
+        self.eqwalizer_diagnostics(project_id, file_ids, format, strict)
+            .fingerprinted()
+    }
+
+    /// The explanation of the diagnostic of the module in `file_id` with
+    /// fingerprint `diag_id`, in non-strict mode, see
+    /// `EqwalizerDiagnostic::explanation_tree`. `None` if there is no such
+    /// diagnostic or it has no explanation. eqWAlizer only reports the
+    /// explanation as text, which the chain is read back from: obligations
+    /// have no span of their own.
+    pub fn explain_diagnostic(
+        &self,
+        project_id: ProjectId,
+        file_id: FileId,
+        diag_id: u64,
+    ) -> Cancellable<Option<Explanation>> {
+        self.with_db(|db| {
+            self.host_diagnostics(
+                db,
+                project_id,
+                vec![file_id],
+                parse_server::Format::OffsetEtf,
+                false,
+            )
+            .fingerprinted()?
+            .into_iter()
+            .find(|d| d.fingerprint == diag_id)?
+            .diagnostic
+            .explanation_tree()
+        })
+    }
+
+    /// Computes the eqwalizer diagnostics for the given files, with their
+    /// module, ordered by path of the module, then by start offset, then by
+    /// code, see `EqwalizerDiagnostics::sorted`. Returns `None` if eqWAlizer
+    /// couldn't get the AST of some module.
+    pub fn eqwalizer_diagnostics_sorted(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+        strict: bool,
+    ) -> Cancellable<Option<Vec<(String, EqwalizerDiagnostic)>>> {
+        self.with_db(|db| {
+            let diagnostics = self.host_diagnostics(db, project_id, file_ids, format, strict);
+            let sorted = diagnostics.sorted(|module| {
+                module_file(db, project_id, module)
+                    .and_then(|file_id| file_path(db, project_id, file_id))
+            })?;
+            Some(
+                sorted
+                    .into_iter()
+                    .map(|(module, d)| (module.to_string(), d.clone()))
+                    .collect(),
+            )
+        })
+    }
+
+    /// Computes the eqwalizer diagnostics for the given files, grouped by
+    /// module and ordered by start offset, then by code, see
+    /// `EqwalizerDiagnostics::by_module`. Returns `None` if eqWAlizer
+    /// couldn't get the AST of some module.
+    pub fn eqwalizer_diagnostics_by_module(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+        strict: bool,
+    ) -> Cancellable<Option<BTreeMap<String, Vec<EqwalizerDiagnostic>>>> {
+        self.with_db(|db| {
+            let diagnostics = self.host_diagnostics(db, project_id, file_ids, format, strict);
+            Some(
+                diagnostics
+                    .by_module()?
+                    .into_iter()
+                    .map(|(module, diags)| {
+                        (module.to_string(), diags.into_iter().cloned().collect())
+                    })
+                    .collect(),
+            )
+        })
+    }
+
+    /// Computes the eqwalizer diagnostics for the given files in non-strict
+    /// mode, each with the application type of its module and whether it
+    /// is a test or generated module. Ordered as with
+    /// `eqwalizer_diagnostics_by_module`. Returns `None` if eqWAlizer
+    /// couldn't get the AST of some module.
+    pub fn eqwalizer_diagnostics_with_context(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+    ) -> Cancellable<Option<Vec<DiagnosticWithContext>>> {
+        self.with_db(|db| {
+            let diagnostics = self.host_diagnostics(db, project_id, file_ids, format, false);
+            let module_index = db.module_index(project_id);
+            let mut with_context = Vec::new();
+            for (module, diags) in diagnostics.by_module()? {
+                let file_id = match module_index.file_for_module(module) {
+                    Some(file_id) => file_id,
+                    None => continue,
+                };
+                let app_type = db.file_app_type(file_id);
+                let is_test = module_index.file_source_for_file(file_id) == Some(FileSource::Extra);
+                let is_generated = is_generated(&db.file_text(file_id));
+                with_context.extend(diags.into_iter().map(|d| DiagnosticWithContext {
+                    module: module.to_string(),
+                    file_id,
+                    app_type,
+                    is_test,
+                    is_generated,
+                    diagnostic: d.clone(),
+                }));
+            }
+            Some(with_context)
+        })
+    }
+
+    /// Computes the eqwalizer diagnostics for the given files and compares
+    /// them with `base`, typically the diagnostics of the same files before
+    /// a change. Diagnostics of `base` in other modules are ignored.
+    /// Returns `None` if eqWAlizer couldn't get the AST of some module, in
+    /// either run.
+    pub fn eqwalizer_diagnostics_diff(
+        &self,
+        project_id: ProjectId,
+        base: &EqwalizerDiagnostics,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+        strict: bool,
+    ) -> Cancellable<Option<DiagnosticsDiff>> {
+        self.with_db(|db| {
+            let module_index = db.module_index(project_id);
+            let modules: Vec<&str> = file_ids
+                .iter()
+                .filter_map(|&file_id| module_index.module_for_file(file_id))
+                .map(|module| module.as_str())
+                .collect();
+            let base: Vec<_> = base
+                .fingerprinted()?
+                .into_iter()
+                .filter(|d| modules.contains(&d.module.as_str()))
+                .collect();
+            let current = self
+                .host_diagnostics(db, project_id, file_ids, format, strict)
+                .fingerprinted()?;
+            Some(DiagnosticsDiff::new(base, current))
+        })
+    }
+
+    /// Computes the eqwalizer diagnostics for the given files in non-strict
+    /// mode and matches them against `baseline`, see `Baseline::check`.
+    /// Entries of the baseline for other modules are ignored. Returns `None`
+    /// if eqWAlizer couldn't get the AST of some module.
+    pub fn eqwalizer_diagnostics_against_baseline(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+        baseline: &Baseline,
+    ) -> Cancellable<Option<BaselineCheck>> {
+        self.with_db(|db| {
+            let module_index = db.module_index(project_id);
+            let modules: Vec<&str> = file_ids
+                .iter()
+                .filter_map(|&file_id| module_index.module_for_file(file_id))
+                .map(|module| module.as_str())
+                .collect();
+            let baseline = Baseline {
+                entries: baseline
+                    .entries
+                    .iter()
+                    .filter(|entry| modules.contains(&entry.module.as_str()))
+                    .cloned()
+                    .collect(),
+            };
+            let current = self
+                .host_diagnostics(db, project_id, file_ids, format, false)
+                .fingerprinted()?;
+            Some(baseline.check(current))
+        })
+    }
+
+    /// Computes the eqwalizer diagnostics for the given files and renders
+    /// them as JSON, see the `json` module for the schema.
+    pub fn eqwalizer_diagnostics_json(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+        strict: bool,
+    ) -> Cancellable<String> {
+        self.eqwalizer_diagnostics_json_with_options(
+            project_id,
+            file_ids,
+            format,
+            strict,
+            &OutputOptions::default(),
+        )
+    }
+
+    /// Same as `eqwalizer_diagnostics_json`, with the paths and line
+    /// endings `options` say.
+    pub fn eqwalizer_diagnostics_json_with_options(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+        strict: bool,
+        options: &OutputOptions,
+    ) -> Cancellable<String> {
+        self.with_db(|db| {
+            let diagnostics = self.host_diagnostics(db, project_id, file_ids, format, strict);
+            json::eqwalizer_diagnostics_json(db, project_id, &diagnostics, options)
+        })
+    }
+
+    /// Computes the eqwalizer diagnostics of the module in `file_id` and
+    /// renders them in `export`, with the path of the file as the JSON
+    /// export has it: relative to the project root, see `diagnostic_path`,
+    /// or absolute for files outside of it. `None` if the file is in no
+    /// project.
+    pub fn eqwalizer_diagnostics_export(
+        &self,
+        file_id: FileId,
+        format: parse_server::Format,
+        strict: bool,
+        export: ExportFormat,
+        options: &OutputOptions,
+    ) -> Cancellable<Option<String>> {
+        self.with_db(|db| {
+            let project_id = db.app_data(db.file_source_root(file_id))?.project_id;
+            let files = module_files(db, project_id, &[file_id], options);
+            let diagnostics = self.host_diagnostics(db, project_id, vec![file_id], format, strict);
+            let out = match export {
+                ExportFormat::CompilerFormat => {
+                    compiler_format::to_compiler_format_with_options(&diagnostics, &files, options)
+                }
+                ExportFormat::Sarif => {
+                    sarif::to_sarif_with_options(&diagnostics, &files, options).to_string()
+                }
+                ExportFormat::GithubAnnotations => {
+                    options.lines(&github::to_github_annotations(&diagnostics, &files))
+                }
+                ExportFormat::Junit => options.lines(&junit::to_junit(&diagnostics, &files)),
+            };
+            Some(out)
+        })
+    }
+
+    /// Computes the eqwalizer diagnostics for the given files in non-strict
+    /// mode in batches, like `eqwalizer_diagnostics_stream`, and renders
+    /// them as JSON Lines: each item is the JSON object of a single
+    /// diagnostic, see the `json` module. Modules eqWAlizer couldn't get an
+    /// AST for yield nothing.
+    pub fn eqwalizer_diagnostics_jsonl(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+    ) -> impl Iterator<Item = Cancellable<String>> + '_ {
+        self.eqwalizer_diagnostics_jsonl_with_options(
+            project_id,
+            file_ids,
+            format,
+            &OutputOptions::default(),
+        )
+    }
+
+    /// Same as `eqwalizer_diagnostics_jsonl`, with the paths and line
+    /// endings of messages `options` say. Items are single lines whatever
+    /// `options`.
+    pub fn eqwalizer_diagnostics_jsonl_with_options(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+        options: &OutputOptions,
+    ) -> impl Iterator<Item = Cancellable<String>> + '_ {
+        let options = *options;
+        batches(file_ids).flat_map(move |batch| {
+            let lines = self.with_db(|db| {
+                let diagnostics =
+                    self.host_diagnostics(db, project_id, batch.clone(), format, false);
+                json::eqwalizer_diagnostics_jsonl(db, project_id, &diagnostics, &options)
+            });
+            match lines {
+                Ok(lines) => lines.into_iter().map(Ok).collect::<Vec<_>>(),
+                Err(cancelled) => vec![Err(cancelled)],
+            }
+        })
+    }
+
+    /// Type-checks only the modules whose results may be affected by the
+    /// `changed` files (modules or headers), following the module dependency
+    /// graph. Only modules of the project's own applications are checked.
+    pub fn eqwalizer_diagnostics_incremental(
+        &self,
+        project_id: ProjectId,
+        changed: Vec<FileId>,
+        format: parse_server::Format,
+        strict: bool,
+    ) -> Cancellable<IncrementalDiagnostics> {
+        self.with_db(|db| {
+            let affected = elp_ide_db::module_deps::affected_files(db, project_id, &changed);
+            let reanalyzed: Vec<FileId> = affected
+                .iter()
+                .copied()
+                .filter(|&file_id| db.file_app_type(file_id) == Some(AppType::App))
+                .collect();
+            // Affected modules outside the applications aren't checked, but
+            // their previous diagnostics aren't valid either
+            let mut unaffected: Vec<FileId> = db
+                .module_index(project_id)
+                .iter()
+                .map(|(_name, _source, file_id)| file_id)
+                .filter(|file_id| affected.binary_search(file_id).is_err())
+                .collect();
+            unaffected.sort();
+            let diagnostics = if reanalyzed.is_empty() {
+                Default::default()
+            } else {
+                self.host_diagnostics(db, project_id, reanalyzed.clone(), format, strict)
+            };
+            IncrementalDiagnostics {
+                reanalyzed,
+                unaffected,
+                diagnostics,
+            }
+        })
+    }
+
+    /// Number of modules whose eqWAlizer results were found or not in the
+    /// disk cache so far, `None` without a cache.
+    pub fn disk_cache_stats(&self) -> Option<CacheStats> {
+        self.db.disk_cache().map(DiskCache::stats)
+    }
+
+    /// See `AnalysisHost::prime_caches`.
+    pub fn prime_caches(
+        &self,
+        project_id: ProjectId,
+        cb: impl Fn(PrimeProgress) + Sync + RefUnwindSafe,
+    ) -> Cancellable<()> {
+        let app_types: &[AppType] = if self.app_scheduling.prime_deps {
+            &[AppType::App, AppType::Dep, AppType::Otp]
+        } else {
+            &[AppType::App]
+        };
+        for &app_type in app_types {
+            self.prime_app_type(project_id, app_type, &cb)?;
+        }
+        Ok(())
+    }
+
+    /// Primes the modules of the apps of type `app_type`, see
+    /// `prime_caches`. Stops at the first module cancelled, leaving the
+    /// others as they are.
+    fn prime_app_type(
+        &self,
+        project_id: ProjectId,
+        app_type: AppType,
+        cb: &(impl Fn(PrimeProgress) + Sync),
+    ) -> Cancellable<()> {
+        let file_ids = self.with_db(|db| {
+            let mut file_ids: Vec<FileId> = db
+                .module_index(project_id)
+                .iter()
+                .map(|(_name, _source, file_id)| file_id)
+                .filter(|&file_id| db.file_app_type(file_id) == Some(app_type))
+                .collect();
+            file_ids.sort();
+            self.in_processing_order(project_id, file_ids)
+        })?;
+
+        let total = file_ids.len();
+        let done = AtomicUsize::new(0);
+        // The first cancellation, after which the other modules are skipped
+        let cancelled: Mutex<Option<Cancelled>> = Mutex::new(None);
+        let parse = |analysis: &mut Analysis, &file_id: &FileId| {
+            if cancelled.lock().unwrap().is_some() {
+                return;
+            }
+            let parsed = analysis.with_db(|db| {
+                let _ = db.module_ast(file_id, parse_server::Format::OffsetEtf);
+            });
+            if let Err(err) = parsed {
+                cancelled.lock().unwrap().get_or_insert(err);
+                return;
+            }
+            cb(PrimeProgress {
+                phase: PrimePhase::Parsing,
+                app_type,
+                done: done.fetch_add(1, Ordering::Relaxed) + 1,
+                total,
+            });
+        };
+        if self.deterministic {
+            let mut analysis = self.clone();
+            file_ids
+                .iter()
+                .for_each(|file_id| parse(&mut analysis, file_id));
+        } else {
+            let parse_all = || file_ids.par_iter().for_each_with(self.clone(), parse);
+            let pool = match self.app_scheduling.concurrency(app_type) {
+                0 => None,
+                n => rayon::ThreadPoolBuilder::new().num_threads(n).build().ok(),
+            };
+            match pool {
+                Some(pool) => pool.install(parse_all),
+                None => parse_all(),
+            }
+        }
+        if let Some(err) = cancelled.into_inner().unwrap() {
+            return Err(err);
+        }
+
+        let opted_in: Vec<FileId> = self.with_db(|db| {
+            file_ids
+                .into_iter()
+                .filter(|&file_id| elp_ide_db::stats::is_opted_in(db, file_id) == Some(true))
+                .collect()
+        })?;
+        let mut checked = 0;
+        for batch in batches(opted_in.clone()) {
+            checked += batch.len();
+            self.with_db(|db| {
+                db.eqwalizer_diagnostics_with_policies(
+                    project_id,
+                    batch.clone(),
+                    parse_server::Format::OffsetEtf,
+                    false,
+                    self.dynamic_policy,
+                    self.missing_spec_policy,
+                )
+            })?;
+            cb(PrimeProgress {
+                phase: PrimePhase::Checking,
+                app_type,
+                done: checked,
+                total: opted_in.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Low-level access to eqwalizer
+    pub fn eqwalizer(&self) -> &Eqwalizer {
+        self.db.eqwalizer()
+    }
+
+    /// ETF for the module's abstract forms
+    pub fn module_ast(
+        &self,
+        file_id: FileId,
+        format: parse_server::Format,
+    ) -> Result<Arc<Vec<u8>>, Arc<Vec<ParseError>>> {
+        self.db.module_ast(file_id, format)
+    }
+
+    /// The module's abstract forms, decoded from the ETF of `module_ast`.
+    /// Memoized the same way, so requests for an unchanged module share
+    /// the forms decoded the first time.
+    pub fn module_forms(
+        &self,
+        file_id: FileId,
+        format: parse_server::Format,
+    ) -> Cancellable<Result<Arc<Vec<AbstractForm>>, Arc<Vec<ParseError>>>> {
+        self.with_db(|db| db.module_forms(file_id, format))
+    }
+
+    /// ETF for the abstract forms of several modules. Requests are sent to
+    /// the parse server concurrently instead of one after the other. Results
+    /// are memoized the same way as with `module_ast`, and a module failing
+    /// to parse only affects its own result.
+    pub fn module_asts(
+        &self,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+    ) -> Cancellable<Vec<(FileId, Result<Arc<Vec<u8>>, Arc<Vec<ParseError>>>)>> {
+        if self.deterministic {
+            return self.with_db(|db| {
+                file_ids
+                    .into_iter()
+                    .map(|file_id| (file_id, db.module_ast(file_id, format)))
+                    .collect()
+            });
+        }
+        let analysis = self.clone();
+        self.catch(|| {
+            file_ids
+                .into_par_iter()
+                .map_with(analysis, |analysis, file_id| {
+                    (file_id, analysis.db.module_ast(file_id, format))
+                })
+                .collect()
+        })
+    }
+
+    /// Parse errors and eqwalizer diagnostics of a module, in non-strict
+    /// mode. The module is only type-checked if it parses.
+    pub fn module_all_problems(
+        &self,
+        file_id: FileId,
+        format: parse_server::Format,
+    ) -> Cancellable<ModuleProblems> {
+        self.with_db(|db| {
+            let parse_errors = match db.module_ast(file_id, format) {
+                Ok(_) => vec![],
+                Err(errors) => (*errors).clone(),
+            };
+            let project_id = match db.app_data(db.file_source_root(file_id)) {
+                Some(app_data) if parse_errors.is_empty() => app_data.project_id,
+                _ => {
+                    return ModuleProblems {
+                        parse_errors,
+                        diagnostics: None,
+                    };
+                }
+            };
+            let diagnostics =
+                match &*self.host_diagnostics(db, project_id, vec![file_id], format, false) {
+                    EqwalizerDiagnostics::Diagnostics(diagnostics) => {
+                        let module = db
+                            .module_index(project_id)
+                            .module_for_file(file_id)
+                            .cloned();
+                        Some(
+                            module
+                                .and_then(|module| diagnostics.get(module.as_str()).cloned())
+                                .unwrap_or_default(),
+                        )
+                    }
+                    EqwalizerDiagnostics::NoAst { .. } => None,
+                };
+            ModuleProblems {
+                parse_errors,
+                diagnostics,
+            }
+        })
+    }
+
+    pub fn project_data(&self, file_id: FileId) -> Option<Arc<ProjectData>> {
+        Some(
+            self.db.project_data(
+                self.db
+                    .app_data(self.db.file_source_root(file_id))?
+                    .project_id,
+            ),
+        )
+    }
+
+    /// Returns module name
+    pub fn module_name(&self, file_id: FileId) -> Option<ModuleName> {
+        let db = &self.db;
+        let app_data = db.app_data(db.file_source_root(file_id))?;
+        db.module_index(app_data.project_id)
+            .module_for_file(file_id)
+            .cloned()
+    }
+
+    /// The module names of `file_ids`, in the same order, as
+    /// `module_name` gives them, in a single snapshot. The module index of
+    /// each project is only looked up once.
+    pub fn module_names(
+        &self,
+        file_ids: Vec<FileId>,
+    ) -> Cancellable<Vec<(FileId, Option<ModuleName>)>> {
+        self.with_db(|db| {
+            let mut module_indexes: BTreeMap<ProjectId, Arc<ModuleIndex>> = BTreeMap::new();
+            file_ids
+                .into_iter()
+                .map(|file_id| {
+                    let project_id = match db.app_data(db.file_source_root(file_id)) {
+                        Some(app_data) => app_data.project_id,
+                        None => return (file_id, None),
+                    };
+                    let module_index = module_indexes
+                        .entry(project_id)
+                        .or_insert_with(|| db.module_index(project_id));
+                    (file_id, module_index.module_for_file(file_id).cloned())
+                })
+                .collect()
+        })
+    }
+
+    pub fn module_index(&self, project_id: ProjectId) -> Arc<ModuleIndex> {
+        self.db.module_index(project_id)
+    }
+
+    pub fn module_file_id(&self, project_id: ProjectId, module: &str) -> Option<FileId> {
+        self.db.module_index(project_id).file_for_module(module)
+    }
+
+    /// Path of the file relative to the root of its project, as the exports
+    /// of diagnostics show it. `None` if the file is in no project, or
+    /// lives outside of the root of its project, see `absolute_path`.
+    pub fn diagnostic_path(&self, file_id: FileId) -> Cancellable<Option<RelPathBuf>> {
+        self.with_db(|db| {
+            let project_id = db.app_data(db.file_source_root(file_id))?.project_id;
+            relative_path(db, project_id, file_id)
+        })
+    }
+
+    /// Path of the file, `None` if it isn't on disk.
+    pub fn absolute_path(&self, file_id: FileId) -> Cancellable<Option<AbsPathBuf>> {
+        self.with_db(|db| absolute_path(db, file_id))
+    }
+
+    /// All the modules of the project, including those of dependencies and
+    /// OTP, with their files, sorted by name.
+    pub fn all_modules(&self, project_id: ProjectId) -> Cancellable<Vec<(ModuleName, FileId)>> {
+        self.with_db(|db| {
+            let mut modules: Vec<(ModuleName, FileId)> = db
+                .module_index(project_id)
+                .iter()
+                .map(|(name, _source, file_id)| (name.clone(), file_id))
+                .collect();
+            modules.sort();
+            modules
+        })
+    }
+
+    /// The modules of the project defined by more than one file, e.g. by
+    /// two apps of an umbrella project, sorted by name. Only one of the
+    /// files is ever used for the module, the others being silently
+    /// shadowed.
+    pub fn duplicate_modules(&self, project_id: ProjectId) -> Cancellable<Vec<DuplicateModule>> {
+        self.with_db(|db| {
+            let mut duplicates: Vec<DuplicateModule> = db
+                .module_index(project_id)
+                .duplicates()
+                .map(|(module, resolved, shadowed)| {
+                    let mut shadowed = shadowed.to_vec();
+                    shadowed.sort();
+                    DuplicateModule {
+                        module: module.clone(),
+                        resolved,
+                        shadowed,
+                    }
+                })
+                .collect();
+            duplicates.sort_by(|d1, d2| d1.module.cmp(&d2.module));
+            duplicates
+        })
+    }
+
+    /// File, path and application of the module, `None` if the project has
+    /// no such module.
+    pub fn resolve_module(
+        &self,
+        project_id: ProjectId,
+        module: &str,
+    ) -> Cancellable<Option<ResolvedModule>> {
+        self.with_db(|db| {
+            let file_id = module_file(db, project_id, module)?;
+            let root = db.source_root(db.file_source_root(file_id));
+            let path = root.path_for_file(&file_id)?.as_path()?.to_path_buf();
+            Some(ResolvedModule {
+                file_id,
+                path,
+                app_name: db.file_app_name(file_id)?,
+                app_type: db.file_app_type(file_id)?,
+            })
+        })
+    }
+
+    /// Modules the given module directly depends on: the behaviours it
+    /// implements, the modules it imports from or calls, and the modules of
+    /// the remote types it refers to, including in included headers.
+    pub fn module_dependencies(
+        &self,
+        project_id: ProjectId,
+        module: &ModuleName,
+    ) -> Cancellable<Vec<ModuleName>> {
+        self.with_db(
+            |db| match db.module_index(project_id).file_for_module(module) {
+                Some(file_id) => db.module_dependencies(file_id).modules.clone(),
+                None => vec![],
+            },
+        )
+    }
+
+    /// Modules of the project directly depending on the given module, see
+    /// `module_dependencies`.
+    pub fn reverse_dependencies(
+        &self,
+        project_id: ProjectId,
+        module: &ModuleName,
+    ) -> Cancellable<Vec<ModuleName>> {
+        self.with_db(|db| {
+            db.reverse_dependencies(project_id)
+                .get(module)
+                .cloned()
+                .unwrap_or_default()
+        })
+    }
+
+    /// The variables bound in the clauses of `function/arity`, with their
+    /// binding occurrences and the types eqWAlizer narrows them to there,
+    /// see `EqwalizerDatabase::variable_types`. The module is checked on
+    /// the side, once for all the variables, and again only when it or
+    /// what it depends on changes.
+    pub fn variable_types(
+        &self,
+        file_id: FileId,
+        function: &str,
+        arity: Arity,
+    ) -> Cancellable<Vec<(VarName, TextRange, String)>> {
+        self.with_db(|db| {
+            db.variable_types(file_id, function.to_string(), arity)
+                .to_vec()
+        })
+    }
+
+    /// The type eqWAlizer infers for the innermost expression at `offset`.
+    /// The module is checked on the side, which may take a while.
+    pub fn type_at_offset(
+        &self,
+        file_id: FileId,
+        offset: TextSize,
+    ) -> Cancellable<Option<TypeInfo>> {
+        self.with_db(|db| elp_ide_db::reveal::type_at_offset(db, file_id, offset))
+    }
+
+    /// The innermost call at `offset`, with the types eqWAlizer checks its
+    /// arguments and result with, see `reveal::call_result_type`. `None` if
+    /// `offset` isn't within a call. The module is checked on the side,
+    /// which may take a while.
+    pub fn call_result_type(
+        &self,
+        file_id: FileId,
+        offset: TextSize,
+    ) -> Cancellable<Option<CallTypeInfo>> {
+        self.with_db(|db| elp_ide_db::reveal::call_result_type(db, file_id, offset))
+    }
+
+    /// Types of the exported functions of the module, as eqWAlizer
+    /// pretty-prints them, see `reveal::exported_function_types`. The
+    /// module is checked on the side, which may take a while.
+    pub fn module_function_types(
+        &self,
+        file_id: FileId,
+    ) -> Cancellable<Vec<(FunctionName, Arity, String)>> {
+        self.with_db(|db| elp_ide_db::reveal::exported_function_types(db, file_id))
+    }
+
+    /// The type eqWAlizer infers for `expr_src` in the scope of the module,
+    /// see `reveal::type_of_expression`. The module is checked on the side,
+    /// which may take a while.
+    pub fn type_of_expression(
+        &self,
+        file_id: FileId,
+        expr_src: &str,
+    ) -> Cancellable<Result<String, Vec<ParseError>>> {
+        self.with_db(|db| elp_ide_db::reveal::type_of_expression(db, file_id, expr_src))
+    }
+
+    /// The record `record` visible in the module, with the types eqWAlizer
+    /// gives its fields and the records they refer to, see
+    /// `reveal::record_type`. The module is checked on the side, which may
+    /// take a while.
+    pub fn record_type(&self, file_id: FileId, record: &str) -> Cancellable<Option<RecordType>> {
+        self.with_db(|db| elp_ide_db::reveal::record_type(db, file_id, record))
+    }
+
+    /// The exported functions and types of the module, see
+    /// `reveal::module_exports`. `None` if the module doesn't parse. Only
+    /// the module is parsed, nothing is type-checked.
+    pub fn module_exports(&self, file_id: FileId) -> Cancellable<Option<ModuleExports>> {
+        self.with_db(|db| elp_ide_db::reveal::module_exports(db, file_id))
+    }
+
+    /// The exported types, records and exported function types of the
+    /// module, sorted, as eqWAlizer pretty-prints them, see
+    /// `api_surface::module_api_surface`. `None` if the module doesn't
+    /// parse. The module is checked on the side, which may take a while.
+    pub fn module_api_surface(&self, file_id: FileId) -> Cancellable<Option<ApiSurface>> {
+        self.with_db(|db| elp_ide_db::api_surface::module_api_surface(db, file_id))
+    }
+
+    /// The smallest sub-expression causing the diagnostic of the module
+    /// whose fingerprint is `diag_id`, see `narrow::narrow_diagnostic`.
+    /// `None` if the module has no such diagnostic. Variants of the module
+    /// are checked on the side, at most `narrow::MAX_NARROWING_CHECKS`.
+    pub fn narrow_diagnostic(
+        &self,
+        file_id: FileId,
+        diag_id: u64,
+    ) -> Cancellable<Option<TextRange>> {
+        self.with_db(|db| elp_ide_db::narrow::narrow_diagnostic(db, file_id, diag_id))
+    }
+
+    /// The types in scope in the module, local, from its headers and
+    /// remote, with their definitions, see `type_env::type_environment`.
+    /// Empty if the module doesn't parse. Definitions are revealed on the
+    /// side, which may take a while, and approximate what eqWAlizer checks
+    /// the module with rather than come from its own environment.
+    pub fn type_environment(&self, file_id: FileId) -> Cancellable<TypeEnv> {
+        self.with_db(|db| elp_ide_db::type_env::type_environment(db, file_id).unwrap_or_default())
+    }
+
+    /// Diagnostics eqWAlizer reports for the callbacks of the module's
+    /// behaviours it doesn't implement, or implements with a spec that
+    /// doesn't fit, see `behaviours::behaviour_conformance`.
+    pub fn behaviour_conformance(&self, file_id: FileId) -> Cancellable<Vec<EqwalizerDiagnostic>> {
+        self.with_db(|db| elp_ide_db::behaviours::behaviour_conformance(db, file_id))
+    }
+
+    /// Type-checks the module with `spec` as the spec of `function/arity`,
+    /// on the side, see `spec::validate_spec`. `None` if the module has no
+    /// such function.
+    pub fn validate_spec(
+        &self,
+        file_id: FileId,
+        function: FunctionName,
+        arity: Arity,
+        spec: &str,
+    ) -> Cancellable<Option<SpecValidation>> {
+        self.with_db(|db| elp_ide_db::spec::validate_spec(db, file_id, &function, arity, spec))
+    }
+
+    /// The diagnostics of the given files, in non-strict mode, about code
+    /// relying on the definition of an opaque type of another module, see
+    /// `opacity::is_opacity_violation`, with the modules they are in,
+    /// sorted by module and position. Their related locations include the
+    /// definition of the opaque type.
+    pub fn opaque_violations(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+    ) -> Cancellable<Vec<(String, EqwalizerDiagnostic)>> {
+        self.with_db(|db| {
+            let diagnostics = self.host_diagnostics(
+                db,
+                project_id,
+                file_ids,
+                parse_server::Format::OffsetEtf,
+                false,
+            );
+            let mut violations: Vec<(String, EqwalizerDiagnostic)> = match &*diagnostics {
+                EqwalizerDiagnostics::Diagnostics(diagnostics) => diagnostics
+                    .iter()
+                    .flat_map(|(module, diagnostics)| {
+                        diagnostics
+                            .iter()
+                            .filter(|d| elp_ide_db::opacity::is_opacity_violation(d))
+                            .map(move |d| (module.clone(), d.clone()))
+                    })
+                    .collect(),
+                EqwalizerDiagnostics::NoAst { .. } => vec![],
+            };
+            violations
+                .sort_by(|(m1, d1), (m2, d2)| (m1, d1.range.start()).cmp(&(m2, d2.range.start())));
+            violations
+        })
+    }
+
+    /// The function and `case` clauses eqWAlizer proves no value reaches,
+    /// given the types narrowed by patterns, guards and the clauses before
+    /// them, see `EqwalizerDatabase::unreachable_branches`. The module is
+    /// checked on the side, which may take a while, and again only when it
+    /// or what it depends on changes.
+    pub fn unreachable_branches(&self, file_id: FileId) -> Cancellable<Vec<UnreachableBranch>> {
+        self.with_db(|db| db.unreachable_branches(file_id).to_vec())
+    }
+
+    /// The spec of `function/arity` and the type eqWAlizer enforces for
+    /// it, see `spec::effective_function_spec`. `None` if the function has
+    /// no spec.
+    pub fn effective_function_spec(
+        &self,
+        file_id: FileId,
+        function: FunctionName,
+        arity: Arity,
+    ) -> Cancellable<Option<EffectiveSpec>> {
+        self.with_db(|db| elp_ide_db::spec::effective_function_spec(db, file_id, &function, arity))
+    }
+
+    /// The modules including the header, directly or through other
+    /// headers, see `module_deps::header_users`.
+    pub fn header_impact(&self, header_file: FileId) -> Cancellable<Vec<FileId>> {
+        self.with_db(|db| elp_ide_db::module_deps::header_users(db, header_file))
+    }
+
+    /// Checks the modules of `header_impact`, in non-strict mode, as
+    /// `eqwalizer_diagnostics` does. Nothing is checked if no module
+    /// includes the header.
+    pub fn recheck_header_users(
+        &self,
+        header_file: FileId,
+        format: parse_server::Format,
+    ) -> Cancellable<Arc<EqwalizerDiagnostics>> {
+        self.with_db(|db| {
+            let project_id = db
+                .app_data(db.file_source_root(header_file))
+                .map(|app_data| app_data.project_id);
+            let users = elp_ide_db::module_deps::header_users(db, header_file);
+            match project_id {
+                Some(project_id) if !users.is_empty() => {
+                    self.host_diagnostics(db, project_id, users, format, false)
+                }
+                _ => Default::default(),
+            }
+        })
+    }
+
+    /// Headers included by the module, directly or transitively, see
+    /// `module_deps::include_closure`.
+    pub fn include_closure(&self, file_id: FileId) -> Cancellable<Vec<FileId>> {
+        self.with_db(|db| elp_ide_db::module_deps::include_closure(db, file_id))
+    }
+
+    /// The cycles of type aliases through the types of the module that
+    /// can't be expanded, eqWAlizer's `type_alias_is_non_productive`, see
+    /// `type_aliases::type_alias_cycles`.
+    pub fn type_alias_cycles(&self, file_id: FileId) -> Cancellable<Vec<Vec<TypeName>>> {
+        self.with_db(|db| elp_ide_db::type_aliases::type_alias_cycles(db, file_id))
+    }
+
+    /// Exported functions of the project's own modules that no module of
+    /// the project's applications refers to, see
+    /// `module_deps::unused_exports` for what can't be tracked. Modules
+    /// that don't parse are reported as unknown.
+    pub fn unused_exports(&self, project_id: ProjectId) -> Cancellable<UnusedExports> {
+        self.with_db(|db| elp_ide_db::module_deps::unused_exports(db, project_id))
+    }
+
+    /// The calls to `module:function/arity` in the project's own modules,
+    /// with the number of dynamic calls that may also call it, see
+    /// `module_deps::function_callers`.
+    pub fn function_callers(
+        &self,
+        project_id: ProjectId,
+        module: &str,
+        function: &str,
+        arity: Arity,
+    ) -> Cancellable<FunctionCallers> {
+        self.with_db(|db| {
+            elp_ide_db::module_deps::function_callers(db, project_id, module, function, arity)
+        })
+    }
+
+    /// Returns the app name for a file
+    pub fn file_app_name(&self, file_id: FileId) -> Option<String> {
+        self.db.file_app_name(file_id)
+    }
+
+    /// Returns the app_type for a file
+    pub fn file_app_type(&self, file_id: FileId) -> Option<AppType> {
+        self.db.file_app_type(file_id)
+    }
+
+    /// Whether the parse server of the project is still running, `None` if
+    /// it has none. Requests the server dies before answering are retried
+    /// with a new server, see `AnalysisHost::set_parse_retry_policy`, so
+    /// this is mostly useful for monitoring.
+    pub fn parse_server_health(&self, project_id: ProjectId) -> Option<ServerHealth> {
+        self.db.parse_server_health(project_id)
+    }
+
+    /// Number of times the AST requests of the module in `file_id` were
+    /// retried after the parse server died, see
+    /// `AnalysisHost::set_parse_retry_policy`.
+    pub fn parse_retries(&self, file_id: FileId) -> Cancellable<usize> {
+        self.with_db(|db| {
+            let root = db.source_root(db.file_source_root(file_id));
+            root.path_for_file(&file_id)
+                .and_then(|path| path.as_path())
+                .map_or(0, |path| db.parse_retries(path.as_ref()))
+        })
+    }
+
+    /// Replaces the parse server of the project with a new one.
+    pub fn restart_parse_server(&self, project_id: ProjectId) -> Result<()> {
+        self.db.restart_parse_server(project_id)
+    }
+
+    /// The diagnostics of `file_ids` as the host gives them: with its
+    /// dynamic and missing spec policies, then `presented`. The entry
+    /// points returning the diagnostics of `eqwalizer_diagnostics` get them
+    /// from here, so that they agree with each other. Counted in
+    /// `Metrics::queries`.
+    fn host_diagnostics(
+        &self,
+        db: &RootDatabase,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: parse_server::Format,
+        strict: bool,
+    ) -> Arc<EqwalizerDiagnostics> {
+        db.record_diagnostics_query();
+        let diagnostics = db.eqwalizer_diagnostics_with_policies(
+            project_id,
+            self.in_processing_order(project_id, file_ids),
+            format,
+            strict,
+            self.dynamic_policy,
+            self.missing_spec_policy,
+        );
+        self.presented(diagnostics)
+    }
+
+    /// `diagnostics` with the severity overrides of the host, then passed
+    /// through its filters. Every entry point giving diagnostics goes
+    /// through here, whether or not it applies the policies of the host.
+    fn presented(&self, diagnostics: Arc<EqwalizerDiagnostics>) -> Arc<EqwalizerDiagnostics> {
+        if self.presents_as_is() {
+            return diagnostics;
+        }
+        let mut diagnostics = (*diagnostics).clone();
+        if let EqwalizerDiagnostics::Diagnostics(by_module) = &mut diagnostics {
+            for module_diagnostics in by_module.values_mut() {
+                self.present(module_diagnostics);
+            }
+            by_module.retain(|_, module_diagnostics| !module_diagnostics.is_empty());
+        }
+        Arc::new(diagnostics)
+    }
+
+    /// Same as `presented`, for the diagnostics of a single module.
+    fn present(&self, diagnostics: &mut Vec<EqwalizerDiagnostic>) {
+        override_severities(&self.severity_overrides, diagnostics);
+        self.diagnostic_filters.apply(diagnostics);
+    }
+
+    /// Whether the host has neither severity overrides nor filters, for
+    /// `presented` to change nothing.
+    fn presents_as_is(&self) -> bool {
+        self.severity_overrides.is_empty() && self.diagnostic_filters.0.is_empty()
+    }
+
+    /// `file_ids` sorted by path if the snapshot is deterministic, see
+    /// `AnalysisHost::set_deterministic`, as they are otherwise.
+    fn in_processing_order(&self, project_id: ProjectId, file_ids: Vec<FileId>) -> Vec<FileId> {
+        if !self.deterministic {
+            return file_ids;
+        }
+        let mut file_ids: Vec<(Option<String>, FileId)> = file_ids
+            .into_iter()
+            .map(|file_id| (file_path(&self.db, project_id, file_id), file_id))
+            .collect();
+        file_ids.sort();
+        file_ids
+            .into_iter()
+            .map(|(_path, file_id)| file_id)
+            .collect()
+    }
+
+    /// Performs an operation on the database that may be canceled.
+    ///
+    /// ELP needs to be able to answer semantic questions about the code
+    /// while the code is being modified. A common problem is that a
+    /// long-running query is being calculated when a new change arrives.
+    /// Salsa then unwinds the query with a `Cancelled` panic, which is caught
+    /// here and turned into an `Err`.
+    fn with_db<F, T>(&self, f: F) -> Cancellable<T>
+    where
+        F: FnOnce(&RootDatabase) -> T + Clone + UnwindSafe,
+    {
+        self.catch(|| f(&self.db))
+    }
+
+    /// Runs `f`, `Err(Cancelled)` if the database changed or the token of
+    /// the snapshot, if any, was cancelled. A query `f` waits for being
+    /// cancelled by the token of another snapshot cancels nothing for this
+    /// one, `f` is then run again, see `RootDatabase::is_cancelled`.
+    fn catch<F, T>(&self, f: F) -> Cancellable<T>
+    where
+        F: FnOnce() -> T + Clone + UnwindSafe,
+    {
+        loop {
+            match Cancelled::catch(f.clone()) {
+                Err(Cancelled::PropagatedPanic) if !self.db.is_cancelled() => continue,
+                result => return result,
+            }
+        }
+    }
+}
+
+/// `file_ids` in batches of `MODULES_PER_BATCH`, in order.
+fn batches(file_ids: Vec<FileId>) -> impl Iterator<Item = Vec<FileId>> {
+    let batches: Vec<Vec<FileId>> = file_ids
+        .chunks(MODULES_PER_BATCH)
+        .map(<[FileId]>::to_vec)
+        .collect();
+    batches.into_iter()
+}
+
+/// Whether the text has the `@generated` marker of generated files in its
+/// leading comments, before the first form. A marker further down, e.g. in
+/// a comment about generated files, doesn't count.
+fn is_generated(text: &str) -> bool {
+    text.lines()
+        .map(str::trim_start)
+        .take_while(|line| line.is_empty() || line.starts_with('%'))
+        // Split, for this file not to be seen as generated itself
+        .any(|line| line.contains(concat!('@', "generated")))
+}
+
+/// File defining `module` in the given project.
+fn module_file(db: &RootDatabase, project_id: ProjectId, module: &str) -> Option<FileId> {
+    db.module_index(project_id).file_for_module(module)
+}
+
+/// Path of the file, relative to the project root when it lives inside it.
+/// The path all the exports of diagnostics use.
+fn file_path(db: &RootDatabase, project_id: ProjectId, file_id: FileId) -> Option<String> {
+    if let Some(relative) = relative_path(db, project_id, file_id) {
+        let relative: &Path = relative.as_ref();
+        return Some(relative.display().to_string());
+    }
+    let root = db.source_root(db.file_source_root(file_id));
+    let path = root.path_for_file(&file_id)?;
+    let path = match path.as_path() {
+        Some(path) => path,
+        None => return Some(path.to_string()),
+    };
+    Some(path.as_ref().display().to_string())
+}
+
+/// The files of the modules of `file_ids`, with their paths as
+/// `file_path` has them, in the style of `options`. Files without a module
+/// or a path are left out.
+fn module_files(
+    db: &RootDatabase,
+    project_id: ProjectId,
+    file_ids: &[FileId],
+    options: &OutputOptions,
+) -> ModuleFiles {
+    let module_index = db.module_index(project_id);
+    file_ids
+        .iter()
+        .filter_map(|&file_id| {
+            let module = module_index.module_for_file(file_id)?;
+            let path = file_path(db, project_id, file_id)?;
+            Some((
+                module.to_string(),
+                ModuleFile {
+                    path: options.path(&path),
+                    line_index: db.file_line_index(file_id),
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Path of the file relative to the project root, `None` if it lives
+/// outside of it or isn't on disk.
+fn relative_path(db: &RootDatabase, project_id: ProjectId, file_id: FileId) -> Option<RelPathBuf> {
+    let path = absolute_path(db, file_id)?;
+    let project = db.project_data(project_id);
+    let relative: &Path = path.strip_prefix(&project.root_dir)?.as_ref();
+    RelPathBuf::try_from(relative.to_path_buf()).ok()
+}
+
+/// Path of the file, `None` if it isn't on disk.
+fn absolute_path(db: &RootDatabase, file_id: FileId) -> Option<AbsPathBuf> {
+    let root = db.source_root(db.file_source_root(file_id));
+    let path = root.path_for_file(&file_id)?.as_path()?;
+    Some(path.to_path_buf())
 }
 
 impl Clone for Analysis {
     fn clone(&self) -> Self {
         Analysis {
             db: self.db.snapshot(),
+            revision: self.revision,
+            dynamic_policy: self.dynamic_policy,
+            missing_spec_policy: self.missing_spec_policy,
+            diagnostic_filters: self.diagnostic_filters.clone(),
+            deterministic: self.deterministic,
+            app_scheduling: self.app_scheduling,
+            severity_overrides: self.severity_overrides.clone(),
+            filters_revision: self.filters_revision,
         }
     }
 }
@@ -0,0 +1,259 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! A serialized description of the projects of a database: their apps,
+//! source roots and configuration, as `ProjectApps` derives them from the
+//! rebar3 build info. Loading one skips discovering the projects, e.g. in
+//! CI, where the layout is known in advance.
+//!
+//! Not to be confused with `elp_project_model::ProjectManifest`, which
+//! only tells where a project is to be discovered from. The files of the
+//! source roots are still the caller's to load, the source root of an app
+//! being its index in the manifest, as with `ProjectApps`, followed by one
+//! for the files of no project.
+
+use std::convert::TryFrom;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use elp_ide_db::elp_base_db::AbsPathBuf;
+use elp_ide_db::elp_base_db::AppData;
+use elp_ide_db::elp_base_db::AppRoots;
+use elp_ide_db::elp_base_db::AppStructure;
+use elp_ide_db::elp_base_db::ProjectData;
+use elp_ide_db::elp_base_db::ProjectId;
+use elp_ide_db::elp_base_db::SourceDatabase;
+use elp_ide_db::elp_base_db::SourceRootId;
+use elp_ide_db::RootDatabase;
+use elp_project_model::AppName;
+use elp_project_model::AppType;
+use elp_project_model::Otp;
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProjectManifest {
+    pub projects: Vec<ManifestProject>,
+}
+
+/// A project, see `ProjectData`. Paths are absolute.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestProject {
+    pub id: u32,
+    pub root_dir: String,
+    pub deps_ebins: Vec<String>,
+    pub extra_code_paths: Vec<String>,
+    pub build_info_path: Option<String>,
+    pub stubs_dir: Option<String>,
+    /// The source root of the files of `stubs_dir`, if they are loaded
+    /// apart from those of the apps, after the one for the files of no
+    /// project. See `ProjectData::stubs_root`.
+    pub stubs_root: Option<u32>,
+    /// The OTP apps themselves are apps of the project like the others.
+    pub otp_lib_dir: String,
+    pub otp_version: Option<String>,
+    pub apps: Vec<ManifestApp>,
+}
+
+/// An app of a project, see `AppData`. Paths are absolute, but for the
+/// source directories, relative to `dir`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestApp {
+    pub source_root: u32,
+    pub name: String,
+    pub dir: String,
+    pub include_path: Vec<String>,
+    pub src_dirs: Vec<String>,
+    pub extra_src_dirs: Vec<String>,
+    /// Terms in the external term format, hex-encoded.
+    pub macros: Vec<String>,
+    /// Same as `macros`.
+    pub parse_transforms: Vec<String>,
+    /// `app`, `dep` or `otp`.
+    pub app_type: String,
+}
+
+impl ProjectManifest {
+    pub fn from_json(json: &str) -> Result<ProjectManifest> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// The manifest of the projects of `db`, in the order of `project_ids`.
+    pub(crate) fn dump(db: &RootDatabase, project_ids: &[ProjectId]) -> ProjectManifest {
+        let projects = project_ids
+            .iter()
+            .map(|&project_id| {
+                let project_data = db.project_data(project_id);
+                let apps = project_data
+                    .source_roots
+                    .iter()
+                    .filter_map(|&source_root| {
+                        let app_data = db.app_data(source_root)?;
+                        let name = project_data
+                            .app_roots
+                            .iter()
+                            .find(|(_, root)| *root == source_root)
+                            .map(|(name, _)| name.0.clone())
+                            .unwrap_or_default();
+                        Some(ManifestApp {
+                            source_root: source_root.0,
+                            name,
+                            dir: path_string(&app_data.dir),
+                            include_path: app_data.include_path.iter().map(path_string).collect(),
+                            src_dirs: app_data.src_dirs.clone(),
+                            extra_src_dirs: app_data.extra_src_dirs.clone(),
+                            macros: app_data.macros.iter().map(encode_term).collect(),
+                            parse_transforms: app_data
+                                .parse_transforms
+                                .iter()
+                                .map(encode_term)
+                                .collect(),
+                            app_type: app_type_name(app_data.app_type).to_string(),
+                        })
+                    })
+                    .collect();
+                ManifestProject {
+                    id: project_id.0,
+                    root_dir: path_string(&project_data.root_dir),
+                    deps_ebins: project_data.deps_ebins.iter().map(path_string).collect(),
+                    extra_code_paths: project_data
+                        .extra_code_paths
+                        .iter()
+                        .map(path_string)
+                        .collect(),
+                    build_info_path: project_data.build_info_path.as_ref().map(path_string),
+                    stubs_dir: project_data.stubs_dir.as_ref().map(path_string),
+                    stubs_root: project_data.stubs_root.map(|root| root.0),
+                    otp_lib_dir: path_string(&project_data.otp.lib_dir),
+                    otp_version: project_data.otp_version.clone(),
+                    apps,
+                }
+            })
+            .collect();
+        ProjectManifest { projects }
+    }
+
+    /// The app structure the manifest describes, with the source root for
+    /// the files of no project after those of the apps.
+    pub(crate) fn app_structure(&self) -> Result<AppStructure> {
+        let mut app_structure = AppStructure::default();
+        for project in &self.projects {
+            let project_id = ProjectId(project.id);
+            let mut source_roots = vec![];
+            let mut app_roots = AppRoots::default();
+            for app in &project.apps {
+                let source_root = SourceRootId(app.source_root);
+                source_roots.push(source_root);
+                app_roots.insert(AppName(app.name.clone()), source_root);
+                let app_data = AppData {
+                    project_id,
+                    dir: abs_path(&app.dir)?,
+                    include_path: abs_paths(&app.include_path)?,
+                    src_dirs: app.src_dirs.clone(),
+                    extra_src_dirs: app.extra_src_dirs.clone(),
+                    macros: decode_terms(&app.macros)?,
+                    parse_transforms: decode_terms(&app.parse_transforms)?,
+                    app_type: parse_app_type(&app.app_type)?,
+                };
+                app_structure.add_app_data(source_root, Some(app_data));
+            }
+            let project_data = ProjectData {
+                source_roots,
+                root_dir: abs_path(&project.root_dir)?,
+                deps_ebins: abs_paths(&project.deps_ebins)?,
+                extra_code_paths: abs_paths(&project.extra_code_paths)?,
+                build_info_path: project
+                    .build_info_path
+                    .as_deref()
+                    .map(abs_path)
+                    .transpose()?,
+                stubs_dir: project.stubs_dir.as_deref().map(abs_path).transpose()?,
+                stubs_root: project.stubs_root.map(SourceRootId),
+                otp: Otp {
+                    lib_dir: abs_path(&project.otp_lib_dir)?,
+                    apps: vec![],
+                },
+                otp_version: project.otp_version.clone(),
+                app_roots,
+            };
+            app_structure.add_project_data(project_id, project_data);
+        }
+        app_structure.add_app_data(self.detached_root(), None);
+        Ok(app_structure)
+    }
+
+    /// The source root for the files of no project, after those of the
+    /// apps.
+    pub(crate) fn detached_root(&self) -> SourceRootId {
+        let last_root = self
+            .projects
+            .iter()
+            .flat_map(|project| &project.apps)
+            .map(|app| app.source_root)
+            .max();
+        SourceRootId(last_root.map_or(0, |root| root + 1))
+    }
+}
+
+fn path_string(path: &AbsPathBuf) -> String {
+    let path: &Path = path.as_ref();
+    path.display().to_string()
+}
+
+fn abs_path(path: &str) -> Result<AbsPathBuf> {
+    AbsPathBuf::try_from(PathBuf::from(path))
+        .map_err(|path| anyhow!("path isn't absolute: {}", path.display()))
+}
+
+fn abs_paths(paths: &[String]) -> Result<Vec<AbsPathBuf>> {
+    paths.iter().map(|path| abs_path(path)).collect()
+}
+
+fn encode_term(term: &eetf::Term) -> String {
+    let mut bytes = Vec::new();
+    term.encode(&mut bytes).unwrap();
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_terms(terms: &[String]) -> Result<Vec<eetf::Term>> {
+    terms
+        .iter()
+        .map(|hex| {
+            let bytes = (0..hex.len())
+                .step_by(2)
+                .map(|i| {
+                    hex.get(i..i + 2)
+                        .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                })
+                .collect::<Option<Vec<u8>>>()
+                .ok_or_else(|| anyhow!("invalid hex-encoded term: {}", hex))?;
+            Ok(eetf::Term::decode(&*bytes)?)
+        })
+        .collect()
+}
+
+fn app_type_name(app_type: AppType) -> &'static str {
+    match app_type {
+        AppType::App => "app",
+        AppType::Dep => "dep",
+        AppType::Otp => "otp",
+    }
+}
+
+fn parse_app_type(name: &str) -> Result<AppType> {
+    match name {
+        "app" => Ok(AppType::App),
+        "dep" => Ok(AppType::Dep),
+        "otp" => Ok(AppType::Otp),
+        _ => Err(anyhow!("unknown app type: {}", name)),
+    }
+}
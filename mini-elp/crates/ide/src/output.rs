@@ -0,0 +1,92 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Options of the exports of eqwalizer diagnostics, so that their output
+//! is the same on all platforms by default.
+
+use std::collections::BTreeMap;
+use std::path::MAIN_SEPARATOR;
+use std::sync::Arc;
+
+use elp_ide_db::LineIndex;
+use text_size::TextSize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PathStyle {
+    /// Separators are `/`, whatever the platform.
+    #[default]
+    Forward,
+    /// Separators are those of the platform, e.g. `\` on Windows.
+    Native,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+/// Exports of the diagnostics of a single file, see
+/// `Analysis::eqwalizer_diagnostics_export`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// See `compiler_format::to_compiler_format`.
+    CompilerFormat,
+    /// See `sarif::to_sarif`.
+    Sarif,
+    /// See `github::to_github_annotations`.
+    GithubAnnotations,
+    /// See `junit::to_junit`.
+    Junit,
+}
+
+/// The file of a module whose diagnostics are exported: the path the
+/// exports show for it, see `Analysis::diagnostic_path`, and its line
+/// index, to show the positions of the diagnostics.
+#[derive(Debug, Clone)]
+pub struct ModuleFile {
+    pub path: String,
+    pub line_index: Arc<LineIndex>,
+}
+
+/// The files of the modules whose diagnostics are exported, by module.
+pub type ModuleFiles = BTreeMap<String, ModuleFile>;
+
+/// The line and column of `offset` in the exports: both 1-based, as in
+/// compiler messages, the column counting UTF-16 code units, as editors
+/// do. `LineIndex` is 0-based.
+pub(crate) fn position(line_index: &LineIndex, offset: TextSize) -> (u32, u32) {
+    let line_col = line_index.line_col(offset);
+    (line_col.line + 1, line_col.col_utf16 + 1)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutputOptions {
+    pub path_style: PathStyle,
+    /// Line ending of the lines of the output, and of those of multi-line
+    /// messages.
+    pub line_ending: LineEnding,
+}
+
+impl OutputOptions {
+    /// `path`, with the separators of `path_style`.
+    pub(crate) fn path(&self, path: &str) -> String {
+        match self.path_style {
+            PathStyle::Forward => path.replace(MAIN_SEPARATOR, "/"),
+            PathStyle::Native => path.replace('/', &MAIN_SEPARATOR.to_string()),
+        }
+    }
+
+    /// `text`, whose lines end with `\n`, with the lines ending with
+    /// `line_ending`.
+    pub(crate) fn lines(&self, text: &str) -> String {
+        match self.line_ending {
+            LineEnding::Lf => text.to_string(),
+            LineEnding::CrLf => text.replace('\n', "\r\n"),
+        }
+    }
+}
@@ -0,0 +1,133 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! SARIF 2.1.0 export of eqwalizer diagnostics, as accepted by the GitHub
+//! code scanning upload API.
+
+use elp_ide_db::EqwalizerDiagnostic;
+use elp_ide_db::EqwalizerDiagnostics;
+use elp_ide_db::LineIndex;
+use elp_ide_db::Severity;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::output::position;
+use crate::output::ModuleFiles;
+use crate::output::OutputOptions;
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+const SARIF_VERSION: &str = "2.1.0";
+
+/// Builds a SARIF log for the diagnostics of the modules of `files`.
+///
+/// `files` has the line index of the file of each module and the URI used
+/// for the locations of its results, typically a path relative to the
+/// repository root. Diagnostics of modules not in `files` are left out.
+pub fn to_sarif(diags: &EqwalizerDiagnostics, files: &ModuleFiles) -> Value {
+    to_sarif_with_options(diags, files, &OutputOptions::default())
+}
+
+/// Same as `to_sarif`, with the separators of the URIs and the line
+/// endings of messages `options` say. URIs only have `/` separators, so
+/// `PathStyle::Native` is only for consumers reading them as paths.
+pub fn to_sarif_with_options(
+    diags: &EqwalizerDiagnostics,
+    files: &ModuleFiles,
+    options: &OutputOptions,
+) -> Value {
+    let mut rules: Vec<Value> = Vec::new();
+    let mut rule_ids: Vec<&str> = Vec::new();
+    let mut results: Vec<Value> = Vec::new();
+
+    if let EqwalizerDiagnostics::Diagnostics(diagnostics) = diags {
+        for (module, file) in files {
+            let file_uri = options.path(&file.path);
+            for diagnostic in diagnostics.get(module).into_iter().flatten() {
+                let rule_index = match rule_ids.iter().position(|id| *id == diagnostic.code) {
+                    Some(index) => index,
+                    None => {
+                        rule_ids.push(&diagnostic.code);
+                        rules.push(rule(diagnostic));
+                        rule_ids.len() - 1
+                    }
+                };
+                results.push(result(
+                    diagnostic,
+                    rule_index,
+                    &file.line_index,
+                    &file_uri,
+                    options,
+                ));
+            }
+        }
+    }
+
+    json!({
+        "$schema": SARIF_SCHEMA,
+        "version": SARIF_VERSION,
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "eqWAlizer",
+                    "informationUri": "https://github.com/WhatsApp/eqwalizer",
+                    "rules": rules,
+                }
+            },
+            "columnKind": "utf16CodeUnits",
+            "results": results,
+        }]
+    })
+}
+
+fn rule(diagnostic: &EqwalizerDiagnostic) -> Value {
+    json!({
+        "id": diagnostic.code,
+        "name": diagnostic.code,
+        "shortDescription": { "text": format!("eqWAlizer: {}", diagnostic.code) },
+        "helpUri": diagnostic.uri,
+    })
+}
+
+fn result(
+    diagnostic: &EqwalizerDiagnostic,
+    rule_index: usize,
+    line_index: &LineIndex,
+    file_uri: &str,
+    options: &OutputOptions,
+) -> Value {
+    let message = match &diagnostic.explanation {
+        Some(explanation) => format!("{}\n\n{}", diagnostic.message, explanation),
+        None => diagnostic.message.clone(),
+    };
+    let message = options.lines(&message);
+    let (start_line, start_column) = position(line_index, diagnostic.range.start());
+    let (end_line, end_column) = position(line_index, diagnostic.range.end());
+    json!({
+        "ruleId": diagnostic.code,
+        "ruleIndex": rule_index,
+        "level": level(diagnostic.severity()),
+        "message": { "text": message },
+        "locations": [{
+            "physicalLocation": {
+                "artifactLocation": { "uri": file_uri },
+                "region": {
+                    "startLine": start_line,
+                    "startColumn": start_column,
+                    "endLine": end_line,
+                    "endColumn": end_column,
+                }
+            }
+        }]
+    })
+}
+
+fn level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Hint => "note",
+    }
+}
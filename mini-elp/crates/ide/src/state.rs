@@ -0,0 +1,162 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! The inputs of a database, serialized for another host to start from, so
+//! that e.g. CI workers skip discovering the projects indexed once.
+//!
+//! The state covers the projects and their apps, as a `ProjectManifest`
+//! does, and the source roots of the apps, with the paths and texts of
+//! their files. Everything else is derived from these, the module indexes
+//! included, and computed again on demand. The parse server still reads
+//! the files of modules from disk, so the other host needs them at the
+//! same paths.
+//!
+//! The state is JSON, tagged with `STATE_VERSION` and the version of
+//! eqWAlizer, and only restored by a host with both the same.
+
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Result;
+use elp_ide_db::elp_base_db::AbsPathBuf;
+use elp_ide_db::elp_base_db::FileId;
+use elp_ide_db::elp_base_db::FileSet;
+use elp_ide_db::elp_base_db::ProjectId;
+use elp_ide_db::elp_base_db::SourceDatabase;
+use elp_ide_db::elp_base_db::SourceDatabaseExt;
+use elp_ide_db::elp_base_db::SourceRoot;
+use elp_ide_db::elp_base_db::SourceRootId;
+use elp_ide_db::elp_base_db::VfsPath;
+use elp_ide_db::RootDatabase;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ProjectManifest;
+
+/// Bumped on every incompatible change to the layout of the state.
+pub const STATE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct State {
+    version: u32,
+    eqwalizer_version: String,
+    manifest: ProjectManifest,
+    source_roots: Vec<StateSourceRoot>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StateSourceRoot {
+    id: u32,
+    files: Vec<StateFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StateFile {
+    id: u32,
+    /// An absolute path if `on_disk`, a virtual one otherwise.
+    path: String,
+    on_disk: bool,
+    text: String,
+}
+
+/// The state of the given projects of `db`. Files with an overlay are
+/// saved with it as their text.
+pub(crate) fn serialize(db: &RootDatabase, project_ids: &[ProjectId]) -> Vec<u8> {
+    let manifest = ProjectManifest::dump(db, project_ids);
+    let mut root_ids: Vec<SourceRootId> = project_ids
+        .iter()
+        .flat_map(|&project_id| db.project_data(project_id).source_roots.clone())
+        .collect();
+    root_ids.sort();
+    root_ids.dedup();
+    let source_roots = root_ids
+        .into_iter()
+        .map(|root_id| {
+            let root = db.source_root(root_id);
+            let mut files: Vec<StateFile> = root
+                .iter()
+                .filter_map(|file_id| {
+                    let path = root.path_for_file(&file_id)?;
+                    Some(StateFile {
+                        id: file_id.0,
+                        path: path.to_string(),
+                        on_disk: path.as_path().is_some(),
+                        text: SourceDatabaseExt::file_text(db, file_id).to_string(),
+                    })
+                })
+                .collect();
+            files.sort_by_key(|file| file.id);
+            StateSourceRoot {
+                id: root_id.0,
+                files,
+            }
+        })
+        .collect();
+    let state = State {
+        version: STATE_VERSION,
+        eqwalizer_version: db.eqwalizer().version().to_string(),
+        manifest,
+        source_roots,
+    };
+    serde_json::to_vec(&state).expect("the state is always serializable")
+}
+
+/// Sets the inputs of `db` to the state in `bytes`, as `serialize` gave
+/// it. The overlays of `db` are dropped, the state giving the files their
+/// text. Fails, leaving `db` untouched, if the state doesn't decode or is
+/// of another version.
+pub(crate) fn deserialize(db: &mut RootDatabase, bytes: &[u8]) -> Result<()> {
+    let state: State = serde_json::from_slice(bytes)?;
+    if state.version != STATE_VERSION {
+        bail!(
+            "state of version {}, expected {}",
+            state.version,
+            STATE_VERSION
+        );
+    }
+    if state.eqwalizer_version != db.eqwalizer().version() {
+        bail!(
+            "state of eqWAlizer {}, this is eqWAlizer {}",
+            state.eqwalizer_version,
+            db.eqwalizer().version()
+        );
+    }
+    let app_structure = state.manifest.app_structure()?;
+    let mut source_roots = Vec::with_capacity(state.source_roots.len());
+    for root in state.source_roots {
+        let mut file_set = FileSet::default();
+        for file in &root.files {
+            let path = if file.on_disk {
+                let path = AbsPathBuf::try_from(PathBuf::from(&file.path))
+                    .map_err(|path| anyhow!("path isn't absolute: {}", path.display()))?;
+                VfsPath::from(path)
+            } else {
+                VfsPath::new_virtual_path(file.path.clone())
+            };
+            file_set.insert(FileId(file.id), path);
+        }
+        source_roots.push((SourceRootId(root.id), file_set, root.files));
+    }
+
+    db.drop_file_overlays();
+    app_structure.apply(db);
+    db.set_source_root(
+        state.manifest.detached_root(),
+        Arc::new(SourceRoot::new(FileSet::default())),
+    );
+    for (root_id, file_set, files) in source_roots {
+        db.set_source_root(root_id, Arc::new(SourceRoot::new(file_set)));
+        for file in files {
+            let file_id = FileId(file.id);
+            db.set_file_source_root(file_id, root_id);
+            db.set_file_text(file_id, Arc::new(file.text));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Conversion of eqwalizer diagnostics to LSP diagnostics, with the `lsp`
+//! feature.
+
+use elp_ide_db::elp_base_db::FileId;
+use elp_ide_db::EqwalizerDiagnostic;
+use elp_ide_db::EqwalizerDiagnostics;
+use elp_ide_db::LineIndex;
+use elp_ide_db::Severity;
+use lsp_types::CodeDescription;
+use lsp_types::DiagnosticRelatedInformation;
+use lsp_types::DiagnosticSeverity;
+use lsp_types::Location;
+use lsp_types::NumberOrString;
+use lsp_types::Position;
+use lsp_types::Range;
+use lsp_types::Url;
+use text_size::TextRange;
+
+/// Converts the diagnostics of a single module, whose text `line_index` is
+/// the index of. Nothing is reported when eqWAlizer had no AST.
+///
+/// `EqwalizerDiagnostic::related` is left out of `related_information`,
+/// since it needs the URLs of the files they are in, see
+/// `to_lsp_diagnostic_with_related`.
+pub fn to_lsp_diagnostics(
+    diags: &EqwalizerDiagnostics,
+    line_index: &LineIndex,
+) -> Vec<lsp_types::Diagnostic> {
+    match diags {
+        EqwalizerDiagnostics::Diagnostics(diagnostics) => diagnostics
+            .values()
+            .flatten()
+            .map(|d| to_lsp_diagnostic(d, line_index))
+            .collect(),
+        EqwalizerDiagnostics::NoAst { .. } => vec![],
+    }
+}
+
+pub fn to_lsp_diagnostic(d: &EqwalizerDiagnostic, line_index: &LineIndex) -> lsp_types::Diagnostic {
+    let message = match &d.explanation {
+        Some(explanation) => format!("{}\n\n{}", d.message, explanation),
+        None => d.message.clone(),
+    };
+    lsp_types::Diagnostic {
+        range: range(line_index, d.range),
+        severity: Some(severity(d.severity())),
+        code: Some(NumberOrString::String(d.code.clone())),
+        code_description: Url::parse(&d.uri).ok().map(|href| CodeDescription { href }),
+        source: Some("eqwalizer".to_string()),
+        message,
+        related_information: None,
+        tags: None,
+        data: None,
+    }
+}
+
+/// Same as `to_lsp_diagnostic` for a diagnostic of the file at `uri`,
+/// with `related_information`: the locations of `related` that `location`
+/// converts, then the obligations of the explanation after the first, see
+/// `EqwalizerDiagnostic::explanation_tree`. eqWAlizer reports no span of
+/// their own for obligations, they are at the range of the diagnostic.
+pub fn to_lsp_diagnostic_with_related(
+    d: &EqwalizerDiagnostic,
+    line_index: &LineIndex,
+    uri: &Url,
+    location: impl Fn(FileId, TextRange) -> Option<Location>,
+) -> lsp_types::Diagnostic {
+    let mut related: Vec<DiagnosticRelatedInformation> = d
+        .related
+        .iter()
+        .filter_map(|r| {
+            Some(DiagnosticRelatedInformation {
+                location: location(r.file_id, r.range)?,
+                message: r.message.clone(),
+            })
+        })
+        .collect();
+    if let Some(explanation) = d.explanation_tree() {
+        let here = Location::new(uri.clone(), range(line_index, explanation.range));
+        let mut obligation = explanation.root.because.as_deref();
+        while let Some(o) = obligation {
+            related.push(DiagnosticRelatedInformation {
+                location: here.clone(),
+                message: format!("because {} is not a subtype of {}", o.got, o.expected),
+            });
+            obligation = o.because.as_deref();
+        }
+    }
+    lsp_types::Diagnostic {
+        related_information: Some(related).filter(|related| !related.is_empty()),
+        ..to_lsp_diagnostic(d, line_index)
+    }
+}
+
+fn severity(severity: Severity) -> DiagnosticSeverity {
+    match severity {
+        Severity::Error => DiagnosticSeverity::ERROR,
+        Severity::Warning => DiagnosticSeverity::WARNING,
+        Severity::Hint => DiagnosticSeverity::HINT,
+    }
+}
+
+fn range(line_index: &LineIndex, range: TextRange) -> Range {
+    let position = |offset| {
+        let line_col = line_index.line_col(offset);
+        Position::new(line_col.line, line_col.col_utf16)
+    };
+    Range::new(position(range.start()), position(range.end()))
+}
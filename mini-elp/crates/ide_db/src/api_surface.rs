@@ -0,0 +1,288 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! The public API of a module as eqWAlizer sees it, as a stable textual
+//! dump meant to be diffed between revisions to catch breaking changes.
+//!
+//! The surface is made of the exported types, the records defined in the
+//! module and the types of the exported functions. Each part is sorted,
+//! so the dump only depends on the API, not on the order of the forms.
+//! Opaque types are only shown by name, their definition isn't part of the
+//! API.
+
+use std::convert::TryInto;
+use std::fmt::Write;
+
+use elp_base_db::FileId;
+use elp_base_db::FileLoader;
+use elp_base_db::SourceDatabase;
+use elp_eqwalizer::EqwalizerDiagnostics;
+use elp_parse_server::Format;
+use fxhash::FxHashSet;
+use text_size::TextSize;
+
+use crate::eqwalizer::typecheck_text;
+use crate::etf;
+use crate::fixes;
+use crate::forms;
+use crate::forms::AbstractForm;
+use crate::reveal;
+use crate::reveal::Arity;
+use crate::reveal::FunctionName;
+use crate::reveal::RecordType;
+use crate::reveal::NO_TYPE;
+use crate::ErlAstDatabase;
+use crate::RootDatabase;
+
+/// Prefix of the functions added to a copy of the module to reveal the
+/// definitions of its types.
+const TYPE_FUNCTION_PREFIX: &str = "$elp_api_type_";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApiSurface {
+    pub module: String,
+    /// Exported types, sorted by name and arity.
+    pub types: Vec<ApiType>,
+    /// Records defined in the module itself, sorted by name.
+    pub records: Vec<RecordType>,
+    /// Exported functions and their types, sorted by name and arity, see
+    /// `reveal::exported_function_types`.
+    pub functions: Vec<(FunctionName, Arity, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiType {
+    pub name: String,
+    /// Names of the type variables.
+    pub params: Vec<String>,
+    /// The definition, as pretty-printed by eqWAlizer. `None` for opaque
+    /// types.
+    pub definition: Option<String>,
+}
+
+impl ApiSurface {
+    /// One line per type, record and function, in the order of the
+    /// surface.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "-module({}).", reveal::quote_atom(&self.module));
+        for ty in &self.types {
+            let head = format!("{}({})", reveal::quote_atom(&ty.name), ty.params.join(", "));
+            let _ = match &ty.definition {
+                Some(definition) => writeln!(out, "-type {} :: {}.", head, definition),
+                None => writeln!(out, "-opaque {}.", head),
+            };
+        }
+        for record in &self.records {
+            let fields: Vec<String> = record
+                .fields
+                .iter()
+                .map(|field| match &field.default {
+                    Some(default) => format!(
+                        "{} = {} :: {}",
+                        reveal::quote_atom(&field.name),
+                        default,
+                        field.ty
+                    ),
+                    None => format!("{} :: {}", reveal::quote_atom(&field.name), field.ty),
+                })
+                .collect();
+            let _ = writeln!(
+                out,
+                "-record({}, {{{}}}).",
+                reveal::quote_atom(&record.name),
+                fields.join(", ")
+            );
+        }
+        for (name, arity, ty) in &self.functions {
+            let _ = writeln!(out, "{}/{} :: {}", reveal::quote_atom(name), arity, ty);
+        }
+        out
+    }
+}
+
+/// The API surface of the module in `file_id`. Only the types and records
+/// defined in the module itself are part of it, not those of the headers
+/// it includes. `None` if the module doesn't parse.
+pub fn module_api_surface(db: &RootDatabase, file_id: FileId) -> Option<ApiSurface> {
+    let forms = db
+        .module_ast(file_id, Format::OffsetEtf)
+        .ok()
+        .and_then(|ast| etf::decode_forms(&ast))?;
+    let project_id = db.app_data(db.file_source_root(file_id))?.project_id;
+    let module = db
+        .module_index(project_id)
+        .module_for_file(file_id)?
+        .as_str()
+        .to_string();
+
+    let mut exported_types: FxHashSet<(String, usize)> = FxHashSet::default();
+    let mut types = Vec::new();
+    let mut record_names = Vec::new();
+    for form in fixes::own_forms(&forms) {
+        match forms::decode_form(form) {
+            Some(AbstractForm::Attribute { name, value, .. }) if name == "export_type" => {
+                for export in etf::list(&value).unwrap_or_default() {
+                    if let Some([name, arity]) = etf::tuple(export) {
+                        let arity = etf::int(arity).and_then(|arity| arity.try_into().ok());
+                        if let (Some(name), Some(arity)) = (etf::atom(name), arity) {
+                            exported_types.insert((name.to_string(), arity));
+                        }
+                    }
+                }
+            }
+            Some(form @ AbstractForm::Type { .. }) => types.push(form),
+            Some(AbstractForm::Record { name, .. }) => record_names.push(name),
+            _ => {}
+        }
+    }
+    let types: Vec<AbstractForm> = types
+        .into_iter()
+        .filter(|form| match form {
+            AbstractForm::Type { name, params, .. } => {
+                exported_types.contains(&(name.clone(), params.len()))
+            }
+            _ => false,
+        })
+        .collect();
+    let mut types = type_definitions(db, file_id, &types);
+    types.sort_by(|t1, t2| (&t1.name, t1.params.len()).cmp(&(&t2.name, t2.params.len())));
+
+    record_names.sort();
+    record_names.dedup();
+    let records = record_names
+        .iter()
+        .filter_map(|name| reveal::record_type(db, file_id, name))
+        .map(|record| RecordType {
+            referenced: vec![],
+            ..record
+        })
+        .collect();
+
+    let mut functions = reveal::exported_function_types(db, file_id);
+    functions.sort();
+    functions.dedup();
+
+    Some(ApiSurface {
+        module,
+        types,
+        records,
+        functions,
+    })
+}
+
+/// The `Type` forms `types` of the module, with the definitions of those
+/// that aren't opaque revealed together in a single run of eqWAlizer: each
+/// definition is the type of the argument of a function added to a copy
+/// of the module. `NO_TYPE` for the definitions eqWAlizer gives no type.
+pub(crate) fn type_definitions(
+    db: &RootDatabase,
+    file_id: FileId,
+    types: &[AbstractForm],
+) -> Vec<ApiType> {
+    let text = db.file_text(file_id);
+    let sources: Vec<Option<String>> = types
+        .iter()
+        .map(|form| definition_source(&text, form))
+        .collect();
+    revealed_type_definitions(db, file_id, types, &sources)
+}
+
+/// The text of the definition of the `Type` form `form` in `text`, the
+/// text of the file it comes from.
+pub(crate) fn definition_source(text: &str, form: &AbstractForm) -> Option<String> {
+    match form {
+        AbstractForm::Type { definition, .. } => etf::tuple(definition)
+            .and_then(|definition| etf::anno_range(definition.get(1)?))
+            .and_then(|range| text.get(usize::from(range.start())..usize::from(range.end())))
+            .map(str::to_string),
+        _ => None,
+    }
+}
+
+/// Same as `type_definitions`, with the text of each definition given, in
+/// the same order as `types`, e.g. for types coming from included headers.
+/// The definitions are revealed in the context of the module in `file_id`.
+pub(crate) fn revealed_type_definitions(
+    db: &RootDatabase,
+    file_id: FileId,
+    types: &[AbstractForm],
+    sources: &[Option<String>],
+) -> Vec<ApiType> {
+    let text = db.file_text(file_id);
+    let mut variant = text.to_string();
+    let mut api_types = Vec::new();
+    // Start of each revealed argument in the variant text
+    let mut starts = Vec::new();
+    for (i, (form, source)) in types.iter().zip(sources).enumerate() {
+        let (name, params, opaque) = match form {
+            AbstractForm::Type {
+                name,
+                params,
+                opaque,
+                ..
+            } => (name, params, *opaque),
+            _ => continue,
+        };
+        let params: Vec<String> = params
+            .iter()
+            .map(|param| match etf::tuple(param) {
+                Some([_tag, _anno, name]) => etf::atom(name).unwrap_or("_").to_string(),
+                _ => "_".to_string(),
+            })
+            .collect();
+        let start = match (opaque, source) {
+            (false, Some(source)) => {
+                let function = reveal::quote_atom(&format!("{}{}", TYPE_FUNCTION_PREFIX, i));
+                variant.push_str(&format!(
+                    "\n-spec {}({}) -> ok.\n{}(X) ->\n    _ = eqwalizer:reveal_type(",
+                    function, source, function
+                ));
+                let start = TextSize::of(variant.as_str());
+                variant.push_str("X),\n    ok.\n");
+                Some(start)
+            }
+            _ => None,
+        };
+        starts.push(start);
+        api_types.push(ApiType {
+            name: name.clone(),
+            params,
+            definition: if opaque {
+                None
+            } else {
+                Some(NO_TYPE.to_string())
+            },
+        });
+    }
+    if starts.iter().all(Option::is_none) {
+        return api_types;
+    }
+
+    let project_id = match db.app_data(db.file_source_root(file_id)) {
+        Some(app_data) => app_data.project_id,
+        None => return api_types,
+    };
+    let env = [("EQWALIZER_TOLERATE_ERRORS", "true")];
+    let reveals: Vec<_> = match typecheck_text(db, project_id, file_id, &variant, false, &env) {
+        Ok(EqwalizerDiagnostics::Diagnostics(diagnostics)) => diagnostics
+            .into_values()
+            .flatten()
+            .filter(|d| d.code == "reveal_type")
+            .collect(),
+        _ => vec![],
+    };
+    for (api_type, start) in api_types.iter_mut().zip(starts) {
+        if let Some(start) = start {
+            let definition = reveals
+                .iter()
+                .find(|d| d.range.start() == start)
+                .map_or_else(|| NO_TYPE.to_string(), |d| d.message.clone());
+            api_type.definition = Some(definition);
+        }
+    }
+    api_types
+}
@@ -0,0 +1,48 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Checking that a module implements the callbacks of its behaviours.
+//!
+//! eqWAlizer checks the callbacks itself, as part of the module, and
+//! reports them with the codes of `CALLBACK_CODES`: callbacks the module
+//! doesn't implement, at the `-behaviour` attribute, and implementations
+//! whose spec doesn't fit the callback's, at the function. These are the
+//! diagnostics of the module with those codes only.
+
+use elp_base_db::FileId;
+use elp_base_db::SourceDatabase;
+use elp_eqwalizer::EqwalizerDiagnostic;
+use elp_eqwalizer::EqwalizerDiagnostics;
+use elp_eqwalizer::CALLBACK_CODES;
+use elp_parse_server::Format;
+
+use crate::EqwalizerDatabase;
+
+/// Diagnostics eqWAlizer reports for the callbacks of the behaviours of
+/// the module in `file_id`, in non-strict mode, sorted by position. Derived
+/// from `eqwalizer_diagnostics` for the module alone, so the type-checking
+/// is shared. Empty if eqWAlizer couldn't get an AST.
+pub fn behaviour_conformance(
+    db: &dyn EqwalizerDatabase,
+    file_id: FileId,
+) -> Vec<EqwalizerDiagnostic> {
+    let project_id = match db.app_data(db.file_source_root(file_id)) {
+        Some(app_data) => app_data.project_id,
+        None => return vec![],
+    };
+    let diagnostics = db.eqwalizer_diagnostics(project_id, vec![file_id], Format::OffsetEtf, false);
+    let mut diagnostics: Vec<EqwalizerDiagnostic> = match &*diagnostics {
+        EqwalizerDiagnostics::Diagnostics(diagnostics) => diagnostics
+            .values()
+            .flatten()
+            .filter(|d| CALLBACK_CODES.contains(&d.code.as_str()))
+            .cloned()
+            .collect(),
+        EqwalizerDiagnostics::NoAst { .. } => vec![],
+    };
+    diagnostics.sort_by_key(|d| d.range.start());
+    diagnostics
+}
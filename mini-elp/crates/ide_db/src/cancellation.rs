@@ -0,0 +1,64 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Cancelling a single computation on a snapshot, without changing the
+//! database.
+//!
+//! Salsa only cancels the computations of snapshots when the database is
+//! changed. A snapshot can also be given a `CancellationToken`, in which
+//! case eqWAlizer checks it whenever it checks for salsa cancellation, and
+//! unwinds with `salsa::Cancelled` the same way. Queries that were running
+//! are not memoized, so the database stays consistent. Only the snapshot of
+//! the token is cancelled: the other snapshots of the same revision waiting
+//! for one of those queries see nothing changed, see
+//! `RootDatabase::is_cancelled`, and compute it again.
+
+use std::panic;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Weak;
+
+use elp_base_db::salsa::Cancelled;
+
+/// Cancels the computations of the snapshots it was given to when
+/// `cancel` is called, or when it and all its clones are dropped.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn watch(&self) -> TokenWatch {
+        TokenWatch(Arc::downgrade(&self.0))
+    }
+}
+
+/// What a snapshot keeps of its token, not to keep it alive.
+#[derive(Debug, Clone)]
+pub(crate) struct TokenWatch(Weak<AtomicBool>);
+
+impl TokenWatch {
+    pub(crate) fn unwind_if_cancelled(&self) {
+        let cancelled = self
+            .0
+            .upgrade()
+            .map_or(true, |flag| flag.load(Ordering::Relaxed));
+        if cancelled {
+            panic::resume_unwind(Box::new(Cancelled::PendingWrite));
+        }
+    }
+}
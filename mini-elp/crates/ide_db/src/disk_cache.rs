@@ -0,0 +1,333 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! eqWAlizer results saved on disk, so that they survive the process.
+//!
+//! There is one entry per module and mode (strict or not), holding the
+//! diagnostics of the module together with the files they were computed
+//! from and a hash of their contents: the module itself, the headers it
+//! includes, the modules of the project it transitively depends on, their
+//! headers, their stubs, and the build info of the project. An entry is
+//! only used if these files still have the same contents. Which files a
+//! module depends on is only known from its AST, but can only change if
+//! one of them does, files added to the project since aside, so the files
+//! checked are those recorded with the entry: this needs no parsing, and a
+//! module found in the cache never reaches the parse server. Contents are
+//! those of the database, overlays included, for the files of the project
+//! and its stubs, and those on disk for the other files, e.g. OTP's.
+//!
+//! Entries live in a directory specific to `CACHE_VERSION`, the version of
+//! ELP and the eqWAlizer executable, so that upgrading any of them starts
+//! from an empty cache.
+
+use std::fs;
+use std::hash::Hasher;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use anyhow::Result;
+use elp_base_db::AbsPath;
+use elp_base_db::AbsPathBuf;
+use elp_base_db::FileId;
+use elp_base_db::ProjectId;
+use elp_base_db::SourceDatabase;
+use elp_base_db::VfsPath;
+use elp_eqwalizer::Eqwalizer;
+use elp_eqwalizer::EqwalizerDiagnostic;
+use elp_eqwalizer::RemoteTypeId;
+use fxhash::FxHashSet;
+use fxhash::FxHasher64;
+use serde::Deserialize;
+use serde::Serialize;
+use text_size::TextRange;
+
+use crate::stubs;
+use crate::ModuleDepsDatabase;
+
+/// Bumped on every incompatible change to the layout of the entries.
+pub const CACHE_VERSION: u32 = 4;
+
+/// Number of modules found, or not, in a `DiskCache`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+#[derive(Debug)]
+pub struct DiskCache {
+    dir: PathBuf,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    files: Vec<(PathBuf, u64)>,
+    diagnostics: Vec<CachedDiagnostic>,
+}
+
+/// `EqwalizerDiagnostic` doesn't serialize the way it deserializes from
+/// eqWAlizer, and its fixes and related locations are recomputed anyway.
+#[derive(Serialize, Deserialize)]
+struct CachedDiagnostic {
+    start: u32,
+    end: u32,
+    message: String,
+    uri: String,
+    code: String,
+    expression: Option<String>,
+    explanation: Option<String>,
+    remote_types: Vec<RemoteTypeId>,
+    opaque_types: Vec<RemoteTypeId>,
+}
+
+impl DiskCache {
+    /// Uses `path` as the cache directory, creating it if needed.
+    pub fn new(path: &AbsPath, eqwalizer: &Eqwalizer) -> Result<DiskCache> {
+        let tag = format!(
+            "{}-{}-{}",
+            CACHE_VERSION,
+            env!("CARGO_PKG_VERSION"),
+            eqwalizer.version()
+        );
+        let dir = path
+            .as_ref()
+            .join(format!("v{:016x}", hash(tag.as_bytes())));
+        fs::create_dir_all(&dir)?;
+        Ok(DiskCache {
+            dir,
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Diagnostics of the module in `path`, if the files they were saved
+    /// from, see `dependency_files`, still have the same contents. Nothing
+    /// is parsed.
+    pub(crate) fn load<DB>(
+        &self,
+        db: &DB,
+        project_id: ProjectId,
+        path: &AbsPath,
+        strict: bool,
+    ) -> Option<Vec<EqwalizerDiagnostic>>
+    where
+        DB: SourceDatabase + ?Sized,
+    {
+        let entry = fs::read(self.entry_path(path, strict))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Entry>(&bytes).ok())
+            .filter(|entry| {
+                entry
+                    .files
+                    .iter()
+                    .all(|(file, file_hash)| current_hash(db, project_id, file) == Some(*file_hash))
+            });
+        match entry {
+            Some(entry) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(entry.diagnostics.into_iter().map(Into::into).collect())
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Saves the diagnostics of the module in `path`, computed from `files`,
+    /// see `dependency_files`. Failures are only logged, the cache being an
+    /// optimisation.
+    pub(crate) fn store(
+        &self,
+        path: &AbsPath,
+        strict: bool,
+        files: Vec<(PathBuf, u64)>,
+        diagnostics: &[EqwalizerDiagnostic],
+    ) {
+        let entry = Entry {
+            files,
+            diagnostics: diagnostics.iter().map(Into::into).collect(),
+        };
+        if let Err(error) = self.write_entry(&self.entry_path(path, strict), &entry) {
+            log::warn!(
+                "Could not save eqwalizer results for {}: {}",
+                path.display(),
+                error
+            );
+        }
+    }
+
+    fn write_entry(&self, entry_path: &Path, entry: &Entry) -> Result<()> {
+        // Written aside then renamed, so that readers never see a partial entry
+        let mut file = tempfile::NamedTempFile::new_in(&self.dir)?;
+        file.write_all(&serde_json::to_vec(entry)?)?;
+        file.persist(entry_path)?;
+        Ok(())
+    }
+
+    fn entry_path(&self, path: &AbsPath, strict: bool) -> PathBuf {
+        let mode = if strict { "strict" } else { "gradual" };
+        let hash = hash(path.as_ref().as_os_str().to_string_lossy().as_bytes());
+        self.dir.join(format!("{:016x}-{}.json", hash, mode))
+    }
+}
+
+/// Files the eqWAlizer results of the module in `file_id` depend on, with
+/// the hashes of their contents, see the module documentation. The module
+/// comes first. `None` if one of them can't be read. Needs the ASTs of the
+/// module and its dependencies, so only called once they are checked.
+pub(crate) fn dependency_files<DB>(
+    db: &DB,
+    project_id: ProjectId,
+    file_id: FileId,
+) -> Option<Vec<(PathBuf, u64)>>
+where
+    DB: ModuleDepsDatabase + ?Sized,
+{
+    let module_index = db.module_index(project_id);
+    let mut files = Vec::new();
+    let mut visited = FxHashSet::default();
+    let mut headers = FxHashSet::default();
+    let mut stubs = Vec::new();
+    let mut stack = vec![file_id];
+    while let Some(file_id) = stack.pop() {
+        if !visited.insert(file_id) {
+            continue;
+        }
+        push_file(db, project_id, &mut files, file_path(db, file_id)?.as_ref())?;
+        stubs.extend(stubs::stub_file(db, project_id, file_id));
+        let deps = db.module_dependencies(file_id);
+        headers.extend(deps.headers.iter().cloned());
+        stack.extend(
+            deps.modules
+                .iter()
+                .filter_map(|module| module_index.file_for_module(module)),
+        );
+    }
+    let mut headers: Vec<_> = headers.into_iter().collect();
+    headers.sort();
+    for header in headers {
+        push_file(db, project_id, &mut files, header.as_ref())?;
+    }
+    for stub_id in stubs {
+        push_file(db, project_id, &mut files, file_path(db, stub_id)?.as_ref())?;
+    }
+    if let Some(build_info) = &db.project_data(project_id).build_info_path {
+        push_file(db, project_id, &mut files, build_info.as_ref())?;
+    }
+    Some(files)
+}
+
+/// Adds `path` to `files` with the hash of its contents, see
+/// `current_hash`.
+fn push_file<DB>(
+    db: &DB,
+    project_id: ProjectId,
+    files: &mut Vec<(PathBuf, u64)>,
+    path: &Path,
+) -> Option<()>
+where
+    DB: SourceDatabase + ?Sized,
+{
+    let file_hash = current_hash(db, project_id, path)?;
+    files.push((path.to_path_buf(), file_hash));
+    Some(())
+}
+
+pub(crate) fn file_path<DB>(db: &DB, file_id: FileId) -> Option<AbsPathBuf>
+where
+    DB: SourceDatabase + ?Sized,
+{
+    let root = db.source_root(db.file_source_root(file_id));
+    let path = root.path_for_file(&file_id)?.as_path()?.to_path_buf();
+    Some(path)
+}
+
+/// Hash of the contents of `path` as `dependency_files` computes it: those
+/// of the database for a file of the project or of its stubs, those on
+/// disk otherwise. `None` if it can't be read.
+fn current_hash<DB>(db: &DB, project_id: ProjectId, path: &Path) -> Option<u64>
+where
+    DB: SourceDatabase + ?Sized,
+{
+    let vfs_path = VfsPath::from(AbsPathBuf::try_from(path.to_path_buf()).ok()?);
+    let project_data = db.project_data(project_id);
+    let in_db = project_data
+        .stubs_root
+        .iter()
+        .chain(&project_data.source_roots)
+        .find_map(|&source_root_id| db.source_root(source_root_id).file_for_path(&vfs_path));
+    match in_db {
+        Some(file_id) => Some(hash_text(db, file_id)),
+        None => hash_file(path),
+    }
+}
+
+fn hash_text<DB>(db: &DB, file_id: FileId) -> u64
+where
+    DB: SourceDatabase + ?Sized,
+{
+    hash(db.file_text(file_id).as_bytes())
+}
+
+fn hash_file(path: &Path) -> Option<u64> {
+    fs::read(path).ok().map(|bytes| hash(&bytes))
+}
+
+/// FxHash of `bytes`, the same in every process and build, unlike the
+/// hashers of the standard library.
+pub(crate) fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = FxHasher64::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+impl From<&EqwalizerDiagnostic> for CachedDiagnostic {
+    fn from(d: &EqwalizerDiagnostic) -> Self {
+        CachedDiagnostic {
+            start: d.range.start().into(),
+            end: d.range.end().into(),
+            message: d.message.clone(),
+            uri: d.uri.clone(),
+            code: d.code.clone(),
+            expression: d.expression.clone(),
+            explanation: d.explanation.clone(),
+            remote_types: d.remote_types.clone(),
+            opaque_types: d.opaque_types.clone(),
+        }
+    }
+}
+
+impl From<CachedDiagnostic> for EqwalizerDiagnostic {
+    fn from(d: CachedDiagnostic) -> Self {
+        EqwalizerDiagnostic {
+            range: TextRange::new(d.start.into(), d.end.into()),
+            message: d.message,
+            uri: d.uri,
+            code: d.code,
+            expression: d.expression,
+            explanation: d.explanation,
+            fixes: vec![],
+            remote_types: d.remote_types,
+            opaque_types: d.opaque_types,
+            related: vec![],
+            severity_override: None,
+            macro_context: None,
+        }
+    }
+}
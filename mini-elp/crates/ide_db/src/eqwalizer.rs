@@ -4,17 +4,53 @@
  * the LICENSE file in the root directory of this source tree.
  */
 
+use std::cell::Cell;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 
 use anyhow::Result;
+use eetf::Term;
 use elp_base_db::AbsPath;
 use elp_base_db::FileId;
+use elp_base_db::ModuleIndex;
 use elp_base_db::ProjectId;
 use elp_base_db::SourceDatabase;
+use elp_eqwalizer::CheckScope;
+use elp_eqwalizer::DbApi;
+use elp_eqwalizer::DynamicPolicy;
+use elp_eqwalizer::EqwalizerDiagnostic;
 use elp_eqwalizer::EqwalizerDiagnostics;
+use elp_eqwalizer::MissingSpecPolicy;
+use elp_eqwalizer::Severity;
+use elp_parse_server::Format;
+use elp_parse_server::ParseError;
+use elp_project_model::AppType;
+use fxhash::FxHashMap;
 use salsa::Database;
+use text_size::TextRange;
+use text_size::TextSize;
 
+use crate::disk_cache;
+use crate::erl_ast;
+use crate::erl_ast::AstLoader;
+use crate::etf;
+use crate::fixes;
+use crate::fixmes;
+use crate::forms;
+use crate::forms::AbstractForm;
+use crate::macros;
+use crate::missing_spec;
+use crate::related;
+use crate::reveal::Arity;
+use crate::reveal::FunctionName;
+use crate::reveal::VarName;
+use crate::stats;
+use crate::stubs::StubsDatabase;
+use crate::timing::TimingPhase;
+use crate::unreachable::UnreachableBranch;
 use crate::ErlAstDatabase;
+use crate::LineIndex;
 
 pub trait EqwalizerLoader {
     fn typecheck(
@@ -25,6 +61,68 @@ pub trait EqwalizerLoader {
         format: elp_parse_server::Format,
         strict: bool,
     ) -> Result<EqwalizerDiagnostics>;
+
+    /// Same as `typecheck`, checking the modules one at a time, each within
+    /// `timeout`, see `Eqwalizer::typecheck_with_timeout`.
+    fn typecheck_with_timeout(
+        &self,
+        project_id: ProjectId,
+        build_info_path: &AbsPath,
+        modules: Vec<FileId>,
+        format: elp_parse_server::Format,
+        strict: bool,
+        timeout: Duration,
+    ) -> Result<EqwalizerDiagnostics>;
+
+    /// Same as `typecheck` in non-strict mode, with `modules` seen as if
+    /// they had a `-typing([eqwalizer])` attribute. Saved results aren't
+    /// used, they are only valid for the modules as they are. With a
+    /// `timeout`, the modules are checked as by `typecheck_with_timeout`.
+    /// Fails for formats whose ASTs can't be rewritten, see
+    /// `typecheck_rewritten`.
+    fn typecheck_speculative(
+        &self,
+        project_id: ProjectId,
+        build_info_path: &AbsPath,
+        modules: Vec<FileId>,
+        format: elp_parse_server::Format,
+        timeout: Option<Duration>,
+    ) -> Result<EqwalizerDiagnostics>;
+
+    /// Same as `typecheck`, with the functions of `modules` left out, see
+    /// `CheckScope::SpecsOnly`. Saved results aren't used either. Timeouts
+    /// and formats are as for `typecheck_speculative`.
+    fn typecheck_specs_only(
+        &self,
+        project_id: ProjectId,
+        build_info_path: &AbsPath,
+        modules: Vec<FileId>,
+        format: elp_parse_server::Format,
+        strict: bool,
+        timeout: Option<Duration>,
+    ) -> Result<EqwalizerDiagnostics>;
+
+    /// Same as `typecheck_speculative` for the module in `file_id` alone,
+    /// with `text` as its text instead of the one of the file, e.g. to try
+    /// out fixes, see the free `typecheck_text`. Diagnostics are at offsets
+    /// of `text`. Fails with the parse errors of `text`.
+    fn typecheck_text(
+        &self,
+        project_id: ProjectId,
+        file_id: FileId,
+        text: &str,
+    ) -> Result<EqwalizerDiagnostics, Vec<ParseError>>;
+
+    /// Same as `typecheck_text`, with the ASTs of the module of `file_id`
+    /// as they are and eqWAlizer tolerating its errors, e.g. to reveal
+    /// types in a copy of the module, see `reveal`. Fails with the parse
+    /// errors of `text`.
+    fn typecheck_text_tolerating_errors(
+        &self,
+        project_id: ProjectId,
+        file_id: FileId,
+        text: &str,
+    ) -> Result<EqwalizerDiagnostics, Vec<ParseError>>;
 }
 
 impl EqwalizerLoader for crate::RootDatabase {
@@ -37,20 +135,273 @@ impl EqwalizerLoader for crate::RootDatabase {
         strict: bool,
     ) -> Result<EqwalizerDiagnostics> {
         let module_index = self.module_index(project_id);
-        let module_names = modules
-            .iter()
-            .map(|&f| -> &str { module_index.module_for_file(f).unwrap() })
-            .collect();
+        // Saved results are only valid for the default format
+        let disk_cache = self.disk_cache().filter(|_| format == Format::OffsetEtf);
+        let mut cached = FxHashMap::default();
+        let mut modules = modules_of(&module_index, &modules);
+        if let Some(disk_cache) = disk_cache {
+            modules.retain(|&(file_id, module)| {
+                let diagnostics = disk_cache::file_path(self, file_id)
+                    .and_then(|path| disk_cache.load(self, project_id, &path, strict));
+                match diagnostics {
+                    Some(diagnostics) => {
+                        if !diagnostics.is_empty() {
+                            cached.insert(module.to_string(), diagnostics);
+                        }
+                        false
+                    }
+                    None => true,
+                }
+            });
+        }
+        if modules.is_empty() {
+            return Ok(EqwalizerDiagnostics::Diagnostics(cached));
+        }
+
+        if self.own_apps_first() {
+            // Stable, so the order within each type of app is kept
+            modules.sort_by_cached_key(|&(file_id, _)| app_rank(self.file_app_type(file_id)));
+        }
+        let module_names = modules.iter().map(|&(_, module)| module).collect();
         let db_api = DbForEqwalizer {
             db: self,
             total: modules.len(),
             left: modules.len(),
             project_id,
             format,
+            started: Cell::new(None),
         };
 
-        self.eqwalizer
-            .typecheck(build_info_path.as_ref(), db_api, module_names, strict)
+        let diagnostics =
+            self.eqwalizer
+                .typecheck(build_info_path.as_ref(), db_api, module_names, strict)?;
+        record_diagnostics(self, modules.len(), &diagnostics);
+        match (disk_cache, diagnostics) {
+            (Some(disk_cache), EqwalizerDiagnostics::Diagnostics(mut diagnostics)) => {
+                for &(file_id, module) in &modules {
+                    let path = disk_cache::file_path(self, file_id);
+                    let files = disk_cache::dependency_files(self, project_id, file_id);
+                    if let (Some(path), Some(files)) = (path, files) {
+                        let module_diagnostics =
+                            diagnostics.get(module).map_or(&[][..], |d| &d[..]);
+                        disk_cache.store(&path, strict, files, module_diagnostics);
+                    }
+                }
+                diagnostics.extend(cached);
+                Ok(EqwalizerDiagnostics::Diagnostics(diagnostics))
+            }
+            (_, diagnostics) => Ok(diagnostics),
+        }
+    }
+
+    fn typecheck_with_timeout(
+        &self,
+        project_id: ProjectId,
+        build_info_path: &AbsPath,
+        modules: Vec<FileId>,
+        format: elp_parse_server::Format,
+        strict: bool,
+        timeout: Duration,
+    ) -> Result<EqwalizerDiagnostics> {
+        let module_index = self.module_index(project_id);
+        let modules = modules_of(&module_index, &modules);
+        let mut diagnostics = FxHashMap::default();
+        for (i, &(_, module)) in modules.iter().enumerate() {
+            let db_api = DbForEqwalizer {
+                db: self,
+                total: modules.len(),
+                left: modules.len() - i,
+                project_id,
+                format,
+                started: Cell::new(None),
+            };
+            let module_diagnostics = self.eqwalizer.typecheck_with_timeout(
+                build_info_path.as_ref(),
+                db_api,
+                module,
+                strict,
+                timeout,
+            )?;
+            record_diagnostics(self, 1, &module_diagnostics);
+            match module_diagnostics {
+                EqwalizerDiagnostics::Diagnostics(module_diagnostics) => {
+                    diagnostics.extend(module_diagnostics)
+                }
+                no_ast @ EqwalizerDiagnostics::NoAst { .. } => return Ok(no_ast),
+            }
+        }
+        Ok(EqwalizerDiagnostics::Diagnostics(diagnostics))
+    }
+
+    fn typecheck_speculative(
+        &self,
+        project_id: ProjectId,
+        build_info_path: &AbsPath,
+        modules: Vec<FileId>,
+        format: elp_parse_server::Format,
+        timeout: Option<Duration>,
+    ) -> Result<EqwalizerDiagnostics> {
+        typecheck_rewritten(
+            self,
+            project_id,
+            build_info_path,
+            modules,
+            format,
+            false,
+            timeout,
+            opt_in,
+        )
+    }
+
+    fn typecheck_specs_only(
+        &self,
+        project_id: ProjectId,
+        build_info_path: &AbsPath,
+        modules: Vec<FileId>,
+        format: elp_parse_server::Format,
+        strict: bool,
+        timeout: Option<Duration>,
+    ) -> Result<EqwalizerDiagnostics> {
+        typecheck_rewritten(
+            self,
+            project_id,
+            build_info_path,
+            modules,
+            format,
+            strict,
+            timeout,
+            strip_functions,
+        )
+    }
+
+    fn typecheck_text(
+        &self,
+        project_id: ProjectId,
+        file_id: FileId,
+        text: &str,
+    ) -> Result<EqwalizerDiagnostics, Vec<ParseError>> {
+        typecheck_text_rewritten(self, project_id, file_id, text, false, &[], opt_in)
+    }
+
+    fn typecheck_text_tolerating_errors(
+        &self,
+        project_id: ProjectId,
+        file_id: FileId,
+        text: &str,
+    ) -> Result<EqwalizerDiagnostics, Vec<ParseError>> {
+        let env = [("EQWALIZER_TOLERATE_ERRORS", "true")];
+        typecheck_text(self, project_id, file_id, text, false, &env)
+    }
+}
+
+/// Order in which apps are checked when the project's own apps go first,
+/// see `RootDatabase::set_own_apps_first`.
+fn app_rank(app_type: Option<AppType>) -> usize {
+    match app_type {
+        Some(AppType::App) => 0,
+        Some(AppType::Dep) => 1,
+        Some(AppType::Otp) => 2,
+        None => 3,
+    }
+}
+
+/// The modules of `file_ids` with their names. Files that aren't modules,
+/// e.g. headers, are left out: they have no diagnostics of their own.
+fn modules_of<'a>(module_index: &'a ModuleIndex, file_ids: &[FileId]) -> Vec<(FileId, &'a str)> {
+    file_ids
+        .iter()
+        .filter_map(|&file_id| {
+            let module = module_index.module_for_file(file_id)?;
+            Some((file_id, module.as_str()))
+        })
+        .collect()
+}
+
+/// Type-checks `modules` with their ASTs as `rewrite` changes them, one at
+/// a time within `timeout` if there is one, see `typecheck_with_timeout`.
+/// Fails if ASTs of `format` can't be rewritten: only ETF ones can.
+#[allow(clippy::too_many_arguments)]
+fn typecheck_rewritten(
+    db: &crate::RootDatabase,
+    project_id: ProjectId,
+    build_info_path: &AbsPath,
+    modules: Vec<FileId>,
+    format: elp_parse_server::Format,
+    strict: bool,
+    timeout: Option<Duration>,
+    rewrite: fn(&[u8]) -> Option<Vec<u8>>,
+) -> Result<EqwalizerDiagnostics> {
+    match format {
+        Format::OffsetEtf | Format::ExpandedForms => {}
+        Format::Text => anyhow::bail!("ASTs of format {:?} can't be rewritten", format),
+    }
+    let module_index = db.module_index(project_id);
+    let module_names: Vec<&str> = modules_of(&module_index, &modules)
+        .into_iter()
+        .map(|(_, module)| module)
+        .collect();
+    let total = module_names.len();
+    if let Some(timeout) = timeout {
+        let mut diagnostics = FxHashMap::default();
+        for (i, &module) in module_names.iter().enumerate() {
+            let db_api = DbForEqwalizerRewritten {
+                db_api: DbForEqwalizer {
+                    db,
+                    total,
+                    left: total - i,
+                    project_id,
+                    format,
+                    started: Cell::new(None),
+                },
+                modules: vec![module],
+                rewrite,
+            };
+            let module_diagnostics = db.eqwalizer.typecheck_with_timeout(
+                build_info_path.as_ref(),
+                db_api,
+                module,
+                strict,
+                timeout,
+            )?;
+            record_diagnostics(db, 1, &module_diagnostics);
+            match module_diagnostics {
+                EqwalizerDiagnostics::Diagnostics(module_diagnostics) => {
+                    diagnostics.extend(module_diagnostics)
+                }
+                no_ast @ EqwalizerDiagnostics::NoAst { .. } => return Ok(no_ast),
+            }
+        }
+        return Ok(EqwalizerDiagnostics::Diagnostics(diagnostics));
+    }
+    let db_api = DbForEqwalizerRewritten {
+        db_api: DbForEqwalizer {
+            db,
+            total,
+            left: total,
+            project_id,
+            format,
+            started: Cell::new(None),
+        },
+        modules: module_names.clone(),
+        rewrite,
+    };
+    let diagnostics =
+        db.eqwalizer
+            .typecheck(build_info_path.as_ref(), db_api, module_names, strict)?;
+    record_diagnostics(db, total, &diagnostics);
+    Ok(diagnostics)
+}
+
+/// Counts the diagnostics eqWAlizer reported for `modules` modules it
+/// checked in `Metrics::diagnostics`. Nothing is counted without an AST.
+fn record_diagnostics(
+    db: &crate::RootDatabase,
+    modules: usize,
+    diagnostics: &EqwalizerDiagnostics,
+) {
+    if let EqwalizerDiagnostics::Diagnostics(by_module) = diagnostics {
+        let count = by_module.values().map(Vec::len).sum();
+        db.metrics.record_diagnostics(modules, count);
     }
 }
 
@@ -60,10 +411,24 @@ struct DbForEqwalizer<'d> {
     left: usize,
     project_id: ProjectId,
     format: elp_parse_server::Format,
+    /// When eqWAlizer started checking the current module.
+    started: Cell<Option<Instant>>,
 }
 
 #[salsa::query_group(EqwalizerDatabaseStorage)]
 pub trait EqwalizerDatabase: SourceDatabase + EqwalizerLoader + ErlAstDatabase {
+    /// The output of eqWAlizer for the modules of `file_ids`, before ELP
+    /// applies ignore comments and adds fixes and related locations.
+    /// `None` if eqWAlizer failed. Shared by `eqwalizer_diagnostics` and
+    /// `suppressed_diagnostics`.
+    fn eqwalizer_typecheck_output(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: elp_parse_server::Format,
+        strict: bool,
+    ) -> Option<Arc<EqwalizerDiagnostics>>;
+
     fn eqwalizer_diagnostics(
         &self,
         project_id: ProjectId,
@@ -71,6 +436,206 @@ pub trait EqwalizerDatabase: SourceDatabase + EqwalizerLoader + ErlAstDatabase {
         format: elp_parse_server::Format,
         strict: bool,
     ) -> Arc<EqwalizerDiagnostics>;
+
+    /// Same as `eqwalizer_diagnostics_with_policies`, restricted to
+    /// diagnostics at least as severe as `min_severity`. Memoized per
+    /// threshold, separately from the unfiltered results, which changing
+    /// the threshold doesn't invalidate. Callers holding on to the results
+    /// only keep the filtered diagnostics.
+    #[allow(clippy::too_many_arguments)]
+    fn eqwalizer_diagnostics_filtered(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: elp_parse_server::Format,
+        strict: bool,
+        dynamic_policy: DynamicPolicy,
+        missing_spec_policy: MissingSpecPolicy,
+        min_severity: Severity,
+    ) -> Arc<EqwalizerDiagnostics>;
+
+    /// Same as `eqwalizer_diagnostics`, keeping at most `max_per_module`
+    /// diagnostics per module, see `EqwalizerDiagnostics::truncate`. `None`
+    /// keeps them all, and gives the results of `eqwalizer_diagnostics` as
+    /// they are. With a limit, the modules are type-checked for this query
+    /// alone and only the truncated results are memoized, so that a large
+    /// output isn't kept in memory. Each limit is memoized separately.
+    fn eqwalizer_diagnostics_truncated(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: elp_parse_server::Format,
+        strict: bool,
+        max_per_module: Option<usize>,
+    ) -> Arc<EqwalizerDiagnostics>;
+
+    /// Same as `eqwalizer_diagnostics` in non-strict mode, type-checking
+    /// the modules of `file_ids` as if they were opted in, whether they
+    /// have a `-typing([eqwalizer])` attribute or not. Useful to see what
+    /// opting a module in would report.
+    fn eqwalizer_diagnostics_speculative(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: elp_parse_server::Format,
+    ) -> Arc<EqwalizerDiagnostics>;
+
+    /// Same as `eqwalizer_diagnostics`, with the functions without a spec of
+    /// the modules of `file_ids` reported as `policy` says, see
+    /// `DynamicPolicy`. A syntactic pass over the forms of the modules,
+    /// added to the diagnostics of eqWAlizer: eqWAlizer checks the same way
+    /// under every policy. Under `DynamicPolicy::Allow`, and in strict
+    /// mode, the results of `eqwalizer_diagnostics` as they are. Memoized
+    /// per policy, all policies sharing the type-checking.
+    fn eqwalizer_diagnostics_with_dynamic_policy(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: elp_parse_server::Format,
+        strict: bool,
+        policy: DynamicPolicy,
+    ) -> Arc<EqwalizerDiagnostics>;
+
+    /// Same as `eqwalizer_diagnostics_with_dynamic_policy`, with the
+    /// exported functions without a spec of the modules of `file_ids` also
+    /// reported as `missing_spec_policy` says, see `MissingSpecPolicy`,
+    /// in strict mode too. A function both policies report is reported
+    /// once, as a missing spec. Memoized per pair of policies.
+    fn eqwalizer_diagnostics_with_policies(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: elp_parse_server::Format,
+        strict: bool,
+        dynamic_policy: DynamicPolicy,
+        missing_spec_policy: MissingSpecPolicy,
+    ) -> Arc<EqwalizerDiagnostics>;
+
+    /// Fingerprint of `eqwalizer_diagnostics_with_policies` for the same
+    /// arguments, the same in every process and build, e.g. for result ids
+    /// of pull diagnostics. Memoized alongside the diagnostics, so only
+    /// recomputed when they change.
+    fn eqwalizer_diagnostics_fingerprint(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: elp_parse_server::Format,
+        strict: bool,
+        dynamic_policy: DynamicPolicy,
+        missing_spec_policy: MissingSpecPolicy,
+    ) -> u64;
+
+    /// Same as `eqwalizer_diagnostics`, checking the modules of `file_ids`
+    /// as `scope` says. `CheckScope::Full` gives the results of
+    /// `eqwalizer_diagnostics` as they are, `CheckScope::SpecsOnly` is
+    /// memoized separately.
+    fn eqwalizer_diagnostics_scoped(
+        &self,
+        project_id: ProjectId,
+        file_ids: Vec<FileId>,
+        format: elp_parse_server::Format,
+        strict: bool,
+        scope: CheckScope,
+    ) -> Arc<EqwalizerDiagnostics>;
+
+    /// Whether eqWAlizer reports no errors for the module, warnings and
+    /// hints aside. Derived from `eqwalizer_diagnostics` for the module
+    /// alone, so the type-checking is shared. eqWAlizer reports all the
+    /// diagnostics of a module at once, but queries depending on this one
+    /// are only recomputed when the answer changes.
+    fn module_is_clean(&self, project_id: ProjectId, file_id: FileId, strict: bool) -> bool;
+
+    /// Diagnostics of the body and spec of `function/arity` in the module of
+    /// `file_id`, `None` if there is no such function or eqWAlizer couldn't
+    /// get an AST.
+    ///
+    /// eqWAlizer doesn't infer types across functions, a function is only
+    /// checked against its own spec and those of the functions it calls.
+    /// It can however only check whole modules, so this is derived from
+    /// `eqwalizer_diagnostics` for the module alone: editing a function
+    /// type-checks its whole module again, and queries depending on another
+    /// function of the module are only recomputed if its diagnostics
+    /// change. Since diagnostics hold offsets, those of the functions after
+    /// the edit do change when the edit changes the length of the text.
+    fn function_diagnostics(
+        &self,
+        project_id: ProjectId,
+        file_id: FileId,
+        function: FunctionName,
+        arity: Arity,
+        strict: bool,
+    ) -> Option<Arc<Vec<EqwalizerDiagnostic>>>;
+
+    /// Same as `eqwalizer_diagnostics`, for all the modules of the project's
+    /// applications selected by `filter`, in non-strict mode. ASTs of the
+    /// other modules are only requested if eqWAlizer needs them to check
+    /// the selected ones.
+    fn eqwalizer_diagnostics_for_apps(
+        &self,
+        project_id: ProjectId,
+        filter: AppFilter,
+        format: elp_parse_server::Format,
+    ) -> Arc<EqwalizerDiagnostics>;
+
+    /// The variables bound in the clauses of `function/arity` in the module
+    /// of `file_id`, with their types, see `reveal::variable_types`. The
+    /// module is checked once for all of them, and again only when it or
+    /// what it depends on changes.
+    #[salsa::invoke(crate::reveal::variable_types)]
+    fn variable_types(
+        &self,
+        file_id: FileId,
+        function: FunctionName,
+        arity: Arity,
+    ) -> Arc<Vec<(VarName, TextRange, String)>>;
+
+    /// The clauses of the module of `file_id` eqWAlizer proves no value
+    /// reaches, see `unreachable::unreachable_branches`. Memoized like
+    /// `variable_types`.
+    #[salsa::invoke(crate::unreachable::unreachable_branches)]
+    fn unreachable_branches(&self, file_id: FileId) -> Arc<Vec<UnreachableBranch>>;
+}
+
+/// Selects applications of a project by type or by name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AppFilter {
+    IncludeTypes(Vec<AppType>),
+    ExcludeTypes(Vec<AppType>),
+    IncludeApps(Vec<String>),
+    ExcludeApps(Vec<String>),
+}
+
+impl AppFilter {
+    pub fn matches(&self, app_type: AppType, app_name: &str) -> bool {
+        match self {
+            AppFilter::IncludeTypes(types) => types.contains(&app_type),
+            AppFilter::ExcludeTypes(types) => !types.contains(&app_type),
+            AppFilter::IncludeApps(names) => names.iter().any(|name| name == app_name),
+            AppFilter::ExcludeApps(names) => !names.iter().any(|name| name == app_name),
+        }
+    }
+}
+
+fn eqwalizer_typecheck_output(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_ids: Vec<FileId>,
+    format: elp_parse_server::Format,
+    strict: bool,
+) -> Option<Arc<EqwalizerDiagnostics>> {
+    let project = db.project_data(project_id);
+    if let Some(build_info_path) = &project.build_info_path {
+        match db.typecheck(project_id, build_info_path, file_ids, format, strict) {
+            Ok(diags) => Some(Arc::new(diags)),
+            Err(error) => {
+                log::error!("EqWAlizing failed: {}", error);
+                None
+            }
+        }
+    } else {
+        log::error!("EqWAlizing in a fixture project");
+        None
+    }
 }
 
 fn eqwalizer_diagnostics(
@@ -79,11 +644,103 @@ fn eqwalizer_diagnostics(
     file_ids: Vec<FileId>,
     format: elp_parse_server::Format,
     strict: bool,
+) -> Arc<EqwalizerDiagnostics> {
+    match db.eqwalizer_typecheck_output(project_id, file_ids.clone(), format, strict) {
+        Some(output) => {
+            let mut diags = (*output).clone();
+            assemble(db, project_id, &file_ids, CheckScope::Full, &mut diags);
+            Arc::new(diags)
+        }
+        None => Default::default(),
+    }
+}
+
+/// Same as `eqwalizer_diagnostics`, without memoizing the output of
+/// eqWAlizer or the diagnostics, for queries keeping only part of them.
+fn eqwalizer_diagnostics_unmemoized(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_ids: Vec<FileId>,
+    format: elp_parse_server::Format,
+    strict: bool,
+) -> EqwalizerDiagnostics {
+    match eqwalizer_typecheck_output(db, project_id, file_ids.clone(), format, strict) {
+        Some(output) => {
+            let mut diags = Arc::try_unwrap(output).unwrap_or_else(|output| (*output).clone());
+            assemble(db, project_id, &file_ids, CheckScope::Full, &mut diags);
+            diags
+        }
+        None => Default::default(),
+    }
+}
+
+/// A diagnostic a `% eqwalizer:ignore <code>` comment suppresses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuppressedDiagnostic {
+    pub file_id: FileId,
+    /// As eqWAlizer reports it, without fixes or related locations.
+    pub diagnostic: EqwalizerDiagnostic,
+    /// The comment suppressing it.
+    pub annotation: TextRange,
+}
+
+/// The diagnostics of the modules of `file_ids`, in non-strict mode, that
+/// their `% eqwalizer:ignore <code>` comments suppress, sorted by file and
+/// position. Fixmes and ignores without a code are applied by eqWAlizer
+/// itself, so what they suppress can't be reported. The modules are
+/// type-checked once for this and `eqwalizer_diagnostics`.
+pub fn suppressed_diagnostics(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_ids: Vec<FileId>,
+    format: elp_parse_server::Format,
+) -> Vec<SuppressedDiagnostic> {
+    let output = match db.eqwalizer_typecheck_output(project_id, file_ids.clone(), format, false) {
+        Some(output) => output,
+        None => return vec![],
+    };
+    let diagnostics = match &*output {
+        EqwalizerDiagnostics::Diagnostics(diagnostics) => diagnostics,
+        EqwalizerDiagnostics::NoAst { .. } => return vec![],
+    };
+    let module_index = db.module_index(project_id);
+    let mut suppressed = Vec::new();
+    for &file_id in &file_ids {
+        let module_diagnostics = match module_index
+            .module_for_file(file_id)
+            .and_then(|module| diagnostics.get(module.as_str()))
+        {
+            Some(module_diagnostics) => module_diagnostics,
+            None => continue,
+        };
+        let ignores =
+            fixmes::collect_code_ignores(&db.file_line_index(file_id), &db.file_text(file_id));
+        for (diagnostic, annotation) in fixmes::suppressed_by(&ignores, module_diagnostics) {
+            suppressed.push(SuppressedDiagnostic {
+                file_id,
+                diagnostic: diagnostic.clone(),
+                annotation,
+            });
+        }
+    }
+    suppressed.sort_by_key(|s| (s.file_id, s.diagnostic.range.start()));
+    suppressed
+}
+
+fn eqwalizer_diagnostics_speculative(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_ids: Vec<FileId>,
+    format: elp_parse_server::Format,
 ) -> Arc<EqwalizerDiagnostics> {
     let project = db.project_data(project_id);
     if let Some(build_info_path) = &project.build_info_path {
-        match db.typecheck(project_id, build_info_path, file_ids, format, strict) {
-            Ok(diags) => Arc::new(diags),
+        match db.typecheck_speculative(project_id, build_info_path, file_ids.clone(), format, None)
+        {
+            Ok(mut diags) => {
+                assemble(db, project_id, &file_ids, CheckScope::Full, &mut diags);
+                Arc::new(diags)
+            }
             Err(error) => {
                 log::error!("EqWAlizing failed: {}", error);
                 Default::default()
@@ -95,6 +752,482 @@ fn eqwalizer_diagnostics(
     }
 }
 
+/// Turns the output of eqWAlizer for `file_ids`, checked as `scope` says,
+/// into the final diagnostics, timed if there is a timing sink, the time
+/// being divided between the modules.
+fn assemble(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_ids: &[FileId],
+    scope: CheckScope,
+    diags: &mut EqwalizerDiagnostics,
+) {
+    let sink = match db.timing_sink() {
+        Some(sink) => sink,
+        None => return assemble_untimed(db, project_id, file_ids, scope, diags),
+    };
+    let start = Instant::now();
+    assemble_untimed(db, project_id, file_ids, scope, diags);
+    let duration = start.elapsed();
+    let module_index = db.module_index(project_id);
+    let modules: Vec<&str> = file_ids
+        .iter()
+        .filter_map(|&file_id| module_index.module_for_file(file_id))
+        .map(|module| module.as_str())
+        .collect();
+    // Shared evenly, for the times of the modules to add up to the total
+    let share = duration / (modules.len().max(1) as u32);
+    for module in modules {
+        sink.record(module, TimingPhase::Assemble, share);
+    }
+}
+
+fn assemble_untimed(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_ids: &[FileId],
+    scope: CheckScope,
+    diags: &mut EqwalizerDiagnostics,
+) {
+    apply_code_ignores(db, project_id, file_ids, scope, diags);
+    fixes::add_fixes(db, project_id, file_ids, diags);
+    related::add_related_locations(db, project_id, diags);
+    macros::add_macro_contexts(db, project_id, diags);
+    diags.sort();
+}
+
+/// Applies the `% eqwalizer:ignore <code>` comments of the checked modules,
+/// see `fixmes::CodeIgnore`. Unused ones aren't reported for modules whose
+/// function bodies weren't checked, see `CheckScope::SpecsOnly`.
+fn apply_code_ignores(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_ids: &[FileId],
+    scope: CheckScope,
+    diags: &mut EqwalizerDiagnostics,
+) {
+    let diagnostics = match diags {
+        EqwalizerDiagnostics::Diagnostics(diagnostics) => diagnostics,
+        EqwalizerDiagnostics::NoAst { .. } => return,
+    };
+    let module_index = db.module_index(project_id);
+    for &file_id in file_ids {
+        let module = match module_index.module_for_file(file_id) {
+            Some(module) => module.as_str(),
+            None => continue,
+        };
+        let ignores =
+            fixmes::collect_code_ignores(&db.file_line_index(file_id), &db.file_text(file_id));
+        if ignores.is_empty() {
+            continue;
+        }
+        let module_diagnostics = diagnostics.entry(module.to_string()).or_default();
+        fixmes::apply_code_ignores(&ignores, module_diagnostics, scope == CheckScope::Full);
+        if module_diagnostics.is_empty() {
+            diagnostics.remove(module);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn eqwalizer_diagnostics_filtered(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_ids: Vec<FileId>,
+    format: elp_parse_server::Format,
+    strict: bool,
+    dynamic_policy: DynamicPolicy,
+    missing_spec_policy: MissingSpecPolicy,
+    min_severity: Severity,
+) -> Arc<EqwalizerDiagnostics> {
+    let diagnostics = db.eqwalizer_diagnostics_with_policies(
+        project_id,
+        file_ids,
+        format,
+        strict,
+        dynamic_policy,
+        missing_spec_policy,
+    );
+    Arc::new(diagnostics.filter_severity(min_severity))
+}
+
+fn eqwalizer_diagnostics_truncated(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_ids: Vec<FileId>,
+    format: elp_parse_server::Format,
+    strict: bool,
+    max_per_module: Option<usize>,
+) -> Arc<EqwalizerDiagnostics> {
+    match max_per_module {
+        Some(max_per_module) => {
+            let diagnostics =
+                eqwalizer_diagnostics_unmemoized(db, project_id, file_ids, format, strict);
+            Arc::new(diagnostics.truncate(max_per_module))
+        }
+        None => db.eqwalizer_diagnostics(project_id, file_ids, format, strict),
+    }
+}
+
+fn eqwalizer_diagnostics_scoped(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_ids: Vec<FileId>,
+    format: elp_parse_server::Format,
+    strict: bool,
+    scope: CheckScope,
+) -> Arc<EqwalizerDiagnostics> {
+    if scope == CheckScope::Full {
+        return db.eqwalizer_diagnostics(project_id, file_ids, format, strict);
+    }
+    let project = db.project_data(project_id);
+    let build_info_path = match &project.build_info_path {
+        Some(build_info_path) => build_info_path,
+        None => {
+            log::error!("EqWAlizing in a fixture project");
+            return Default::default();
+        }
+    };
+    match db.typecheck_specs_only(
+        project_id,
+        build_info_path,
+        file_ids.clone(),
+        format,
+        strict,
+        None,
+    ) {
+        Ok(mut diags) => {
+            assemble(db, project_id, &file_ids, scope, &mut diags);
+            Arc::new(diags)
+        }
+        Err(error) => {
+            log::error!("EqWAlizing failed: {}", error);
+            Default::default()
+        }
+    }
+}
+
+fn eqwalizer_diagnostics_with_dynamic_policy(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_ids: Vec<FileId>,
+    format: elp_parse_server::Format,
+    strict: bool,
+    policy: DynamicPolicy,
+) -> Arc<EqwalizerDiagnostics> {
+    let diagnostics = db.eqwalizer_diagnostics(project_id, file_ids.clone(), format, strict);
+    if strict || policy == DynamicPolicy::Allow {
+        return diagnostics;
+    }
+    let mut diagnostics = (*diagnostics).clone();
+    if let EqwalizerDiagnostics::Diagnostics(by_module) = &mut diagnostics {
+        let module_index = db.module_index(project_id);
+        for &file_id in &file_ids {
+            let module = match module_index.module_for_file(file_id) {
+                Some(module) => module.as_str(),
+                None => continue,
+            };
+            let implicit = implicit_dynamic(db, file_id, policy);
+            if !implicit.is_empty() {
+                by_module
+                    .entry(module.to_string())
+                    .or_default()
+                    .extend(implicit);
+            }
+        }
+    }
+    diagnostics.sort();
+    Arc::new(diagnostics)
+}
+
+fn eqwalizer_diagnostics_with_policies(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_ids: Vec<FileId>,
+    format: elp_parse_server::Format,
+    strict: bool,
+    dynamic_policy: DynamicPolicy,
+    missing_spec_policy: MissingSpecPolicy,
+) -> Arc<EqwalizerDiagnostics> {
+    let diagnostics = db.eqwalizer_diagnostics_with_dynamic_policy(
+        project_id,
+        file_ids.clone(),
+        format,
+        strict,
+        dynamic_policy,
+    );
+    if missing_spec_policy == MissingSpecPolicy::Ignore {
+        return diagnostics;
+    }
+    let mut diagnostics = (*diagnostics).clone();
+    if let EqwalizerDiagnostics::Diagnostics(by_module) = &mut diagnostics {
+        let module_index = db.module_index(project_id);
+        for &file_id in &file_ids {
+            let module = match module_index.module_for_file(file_id) {
+                Some(module) => module.as_str(),
+                None => continue,
+            };
+            let missing = missing_spec::missing_specs(db, project_id, file_id, missing_spec_policy);
+            if !missing.is_empty() {
+                let diagnostics = by_module.entry(module.to_string()).or_default();
+                // A function is reported once, the missing spec coming with
+                // a fix
+                diagnostics.retain(|diagnostic| {
+                    !is_implicit_dynamic(diagnostic)
+                        || !missing.iter().any(|m| m.range == diagnostic.range)
+                });
+                diagnostics.extend(missing);
+            }
+        }
+    }
+    diagnostics.sort();
+    Arc::new(diagnostics)
+}
+
+fn eqwalizer_diagnostics_fingerprint(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_ids: Vec<FileId>,
+    format: elp_parse_server::Format,
+    strict: bool,
+    dynamic_policy: DynamicPolicy,
+    missing_spec_policy: MissingSpecPolicy,
+) -> u64 {
+    let diagnostics = db.eqwalizer_diagnostics_with_policies(
+        project_id,
+        file_ids,
+        format,
+        strict,
+        dynamic_policy,
+        missing_spec_policy,
+    );
+    // Hashed in the order of the modules, that of the map varying between
+    // processes
+    let mut bytes = Vec::new();
+    match &*diagnostics {
+        EqwalizerDiagnostics::Diagnostics(by_module) => {
+            let mut modules: Vec<_> = by_module.iter().collect();
+            modules.sort_by_key(|(module, _)| module.as_str());
+            for (module, diagnostics) in modules {
+                bytes.extend_from_slice(module.as_bytes());
+                bytes.push(0);
+                for diagnostic in diagnostics {
+                    let json = serde_json::to_string(diagnostic).unwrap_or_default();
+                    bytes.extend_from_slice(json.as_bytes());
+                    bytes.push(0);
+                }
+            }
+        }
+        EqwalizerDiagnostics::NoAst { module } => {
+            bytes.push(1);
+            bytes.extend_from_slice(module.as_bytes());
+        }
+    }
+    disk_cache::hash(&bytes)
+}
+
+fn is_implicit_dynamic(diagnostic: &EqwalizerDiagnostic) -> bool {
+    diagnostic.code == elp_eqwalizer::IMPLICIT_DYNAMIC_CODE
+        || diagnostic.code == elp_eqwalizer::IMPLICIT_DYNAMIC_ERROR_CODE
+}
+
+/// Diagnostics under `policy` for the functions without a spec of the
+/// module in `file_id`, at their names. Found from the forms of the module
+/// alone, eqWAlizer isn't involved.
+fn implicit_dynamic(
+    db: &dyn EqwalizerDatabase,
+    file_id: FileId,
+    policy: DynamicPolicy,
+) -> Vec<EqwalizerDiagnostic> {
+    let forms = match db
+        .module_ast(file_id, Format::OffsetEtf)
+        .ok()
+        .and_then(|ast| etf::decode_forms(&ast))
+    {
+        Some(forms) => forms,
+        None => return vec![],
+    };
+    let forms: Vec<AbstractForm> = fixes::own_forms(&forms)
+        .into_iter()
+        .filter_map(forms::decode_form)
+        .collect();
+    let specs = forms::specified_functions(&forms);
+    forms
+        .iter()
+        .filter_map(|form| match form {
+            AbstractForm::Function {
+                name, arity, range, ..
+            } if !specs.contains(&(name.as_str(), *arity)) => {
+                EqwalizerDiagnostic::implicit_dynamic(
+                    policy,
+                    &format!("{}/{}", name, arity),
+                    TextRange::at(range.start(), TextSize::of(name.as_str())),
+                )
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn eqwalizer_diagnostics_for_apps(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    filter: AppFilter,
+    format: elp_parse_server::Format,
+) -> Arc<EqwalizerDiagnostics> {
+    let mut file_ids: Vec<FileId> = db
+        .module_index(project_id)
+        .iter()
+        .map(|(_name, _source, file_id)| file_id)
+        .filter(
+            |&file_id| match (db.file_app_type(file_id), db.file_app_name(file_id)) {
+                (Some(app_type), Some(app_name)) => filter.matches(app_type, &app_name),
+                _ => false,
+            },
+        )
+        .collect();
+    if file_ids.is_empty() {
+        return Default::default();
+    }
+    file_ids.sort();
+    db.eqwalizer_diagnostics(project_id, file_ids, format, false)
+}
+
+/// Same as `eqwalizer_diagnostics`, except that a module taking longer than
+/// `timeout` to check gets a single `timeout` diagnostic instead of blocking
+/// the others. Not memoized, since the result depends on timing.
+pub fn eqwalizer_diagnostics_with_timeout(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_ids: Vec<FileId>,
+    format: elp_parse_server::Format,
+    strict: bool,
+    timeout: Duration,
+) -> Arc<EqwalizerDiagnostics> {
+    let project = db.project_data(project_id);
+    if let Some(build_info_path) = &project.build_info_path {
+        match db.typecheck_with_timeout(
+            project_id,
+            build_info_path,
+            file_ids.clone(),
+            format,
+            strict,
+            timeout,
+        ) {
+            Ok(mut diags) => {
+                assemble(db, project_id, &file_ids, CheckScope::Full, &mut diags);
+                Arc::new(diags)
+            }
+            Err(error) => {
+                log::error!("EqWAlizing failed: {}", error);
+                Default::default()
+            }
+        }
+    } else {
+        log::error!("EqWAlizing in a fixture project");
+        Default::default()
+    }
+}
+
+/// Same as `eqwalizer_diagnostics_speculative`, except that a module
+/// taking longer than `timeout` to check gets a single `timeout`
+/// diagnostic instead of blocking the others. Not memoized either.
+pub fn eqwalizer_diagnostics_speculative_with_timeout(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_ids: Vec<FileId>,
+    format: elp_parse_server::Format,
+    timeout: Duration,
+) -> Arc<EqwalizerDiagnostics> {
+    let project = db.project_data(project_id);
+    if let Some(build_info_path) = &project.build_info_path {
+        match db.typecheck_speculative(
+            project_id,
+            build_info_path,
+            file_ids.clone(),
+            format,
+            Some(timeout),
+        ) {
+            Ok(mut diags) => {
+                assemble(db, project_id, &file_ids, CheckScope::Full, &mut diags);
+                Arc::new(diags)
+            }
+            Err(error) => {
+                log::error!("EqWAlizing failed: {}", error);
+                Default::default()
+            }
+        }
+    } else {
+        log::error!("EqWAlizing in a fixture project");
+        Default::default()
+    }
+}
+
+fn module_is_clean(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_id: FileId,
+    strict: bool,
+) -> bool {
+    match &*db.eqwalizer_diagnostics(project_id, vec![file_id], Format::OffsetEtf, strict) {
+        EqwalizerDiagnostics::Diagnostics(diagnostics) => diagnostics
+            .values()
+            .flatten()
+            .all(|d| d.severity() < Severity::Error),
+        EqwalizerDiagnostics::NoAst { .. } => false,
+    }
+}
+
+fn function_diagnostics(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_id: FileId,
+    function: FunctionName,
+    arity: Arity,
+    strict: bool,
+) -> Option<Arc<Vec<EqwalizerDiagnostic>>> {
+    let ast = db.module_ast(file_id, Format::OffsetEtf).ok()?;
+    let forms = etf::decode_forms(&ast)?;
+    let ranges: Vec<TextRange> = fixes::own_forms(&forms)
+        .into_iter()
+        .filter_map(|form| function_form_range(form, &function, arity))
+        .collect();
+    if ranges.is_empty() {
+        return None;
+    }
+    match &*db.eqwalizer_diagnostics(project_id, vec![file_id], Format::OffsetEtf, strict) {
+        EqwalizerDiagnostics::Diagnostics(diagnostics) => Some(Arc::new(
+            diagnostics
+                .values()
+                .flatten()
+                .filter(|d| ranges.iter().any(|range| range.contains_range(d.range)))
+                .cloned()
+                .collect(),
+        )),
+        EqwalizerDiagnostics::NoAst { .. } => None,
+    }
+}
+
+/// Range of `form` if it is the definition or the spec of `name/arity`.
+fn function_form_range(form: &Term, name: &str, arity: Arity) -> Option<TextRange> {
+    let is_function =
+        |n: &Term, a: &Term| etf::atom(n) == Some(name) && etf::int(a) == Some(arity.into());
+    match etf::tuple(form)? {
+        [tag, anno, n, a, _clauses] if etf::atom(tag) == Some("function") && is_function(n, a) => {
+            etf::anno_range(anno)
+        }
+        [tag, anno, attr, value]
+            if etf::atom(tag) == Some("attribute") && etf::atom(attr) == Some("spec") =>
+        {
+            match etf::tuple(etf::tuple(value)?.first()?)? {
+                [n, a] if is_function(n, a) => etf::anno_range(anno),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
 impl<'d> elp_eqwalizer::DbApi for DbForEqwalizer<'d> {
     fn unwind_if_cancelled(&self) -> () {
         self.db.unwind_if_cancelled()
@@ -108,13 +1241,29 @@ impl<'d> elp_eqwalizer::DbApi for DbForEqwalizer<'d> {
         self.db.module_ast(file_id, self.format).ok()
     }
 
+    fn get_stubs(&mut self, module: &str) -> Option<Arc<Vec<u8>>> {
+        let file_id = self
+            .db
+            .module_index(self.project_id)
+            .file_for_module(module)?;
+        self.db.module_stubs_ast(file_id, self.format).ok()
+    }
+
     fn eqwalizing_start(&self, module: String) -> () {
+        self.started.set(Some(Instant::now()));
         if let Some(reporter) = self.db.eqwalizer_progress_reporter.lock().unwrap().as_ref() {
             reporter.report_module(module)
         }
     }
 
-    fn eqwalizing_done(&mut self, _module: String) -> () {
+    fn eqwalizing_done(&mut self, module: String) -> () {
+        if let Some(started) = self.started.take() {
+            let duration = started.elapsed();
+            self.db.metrics.record_check(duration);
+            if let Some(sink) = self.db.timing_sink() {
+                sink.record(&module, TimingPhase::Typecheck, duration);
+            }
+        }
         self.left -= 1;
         if let Some(reporter) = self.db.eqwalizer_progress_reporter.lock().unwrap().as_ref() {
             reporter.report(self.total - self.left);
@@ -125,3 +1274,334 @@ impl<'d> elp_eqwalizer::DbApi for DbForEqwalizer<'d> {
         }
     }
 }
+
+/// How far a batch of modules being type-checked is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    /// Modules type-checked so far.
+    pub done: usize,
+    pub total: usize,
+    /// The module being type-checked, if any.
+    pub module: Option<String>,
+}
+
+/// Same as `eqwalizer_diagnostics`, calling `cb` whenever eqWAlizer starts
+/// or finishes type-checking a module. Cancellation is checked between
+/// messages from eqWAlizer, so at least once per module. Not memoized, the
+/// callback is called on every use. Files that aren't modules, e.g.
+/// headers, are skipped and not counted in `Progress::total`.
+pub fn eqwalizer_diagnostics_with_progress(
+    db: &crate::RootDatabase,
+    project_id: ProjectId,
+    file_ids: Vec<FileId>,
+    format: elp_parse_server::Format,
+    strict: bool,
+    cb: &dyn Fn(Progress),
+) -> Arc<EqwalizerDiagnostics> {
+    let project = db.project_data(project_id);
+    let build_info_path = match &project.build_info_path {
+        Some(build_info_path) => build_info_path,
+        None => {
+            log::error!("EqWAlizing in a fixture project");
+            return Default::default();
+        }
+    };
+    let module_index = db.module_index(project_id);
+    let modules: Vec<&str> = modules_of(&module_index, &file_ids)
+        .into_iter()
+        .map(|(_, module)| module)
+        .collect();
+    let total = modules.len();
+    if total == 0 {
+        return Default::default();
+    }
+    let db_api = DbForEqwalizerProgress {
+        db_api: DbForEqwalizer {
+            db,
+            total,
+            left: total,
+            project_id,
+            format,
+            started: Cell::new(None),
+        },
+        cb,
+    };
+    match db
+        .eqwalizer
+        .typecheck(build_info_path.as_ref(), db_api, modules, strict)
+    {
+        Ok(mut diags) => {
+            record_diagnostics(db, total, &diags);
+            assemble(db, project_id, &file_ids, CheckScope::Full, &mut diags);
+            Arc::new(diags)
+        }
+        Err(error) => {
+            log::error!("EqWAlizing failed: {}", error);
+            Default::default()
+        }
+    }
+}
+
+/// Reports progress to a callback, on top of the database's reporter.
+struct DbForEqwalizerProgress<'d> {
+    db_api: DbForEqwalizer<'d>,
+    cb: &'d dyn Fn(Progress),
+}
+
+impl<'d> DbForEqwalizerProgress<'d> {
+    fn report(&self, module: Option<String>) {
+        (self.cb)(Progress {
+            done: self.db_api.total - self.db_api.left,
+            total: self.db_api.total,
+            module,
+        })
+    }
+}
+
+impl<'d> elp_eqwalizer::DbApi for DbForEqwalizerProgress<'d> {
+    fn unwind_if_cancelled(&self) -> () {
+        self.db_api.unwind_if_cancelled()
+    }
+
+    fn get_ast(&mut self, module: &str) -> Option<Arc<Vec<u8>>> {
+        self.db_api.get_ast(module)
+    }
+
+    fn get_stubs(&mut self, module: &str) -> Option<Arc<Vec<u8>>> {
+        self.db_api.get_stubs(module)
+    }
+
+    fn eqwalizing_start(&self, module: String) -> () {
+        self.db_api.eqwalizing_start(module.clone());
+        self.report(Some(module));
+    }
+
+    fn eqwalizing_done(&mut self, module: String) -> () {
+        self.db_api.eqwalizing_done(module);
+        self.report(None);
+    }
+}
+
+/// Serves the ASTs of `modules` as `rewrite` changes them, as they are if
+/// it returns `None`, and the database contents for everything else.
+struct DbForEqwalizerRewritten<'d> {
+    db_api: DbForEqwalizer<'d>,
+    modules: Vec<&'d str>,
+    rewrite: fn(&[u8]) -> Option<Vec<u8>>,
+}
+
+impl<'d> elp_eqwalizer::DbApi for DbForEqwalizerRewritten<'d> {
+    fn unwind_if_cancelled(&self) -> () {
+        self.db_api.unwind_if_cancelled()
+    }
+
+    fn get_ast(&mut self, module: &str) -> Option<Arc<Vec<u8>>> {
+        let ast = self.db_api.get_ast(module)?;
+        if self.modules.contains(&module) {
+            Some((self.rewrite)(&ast).map_or(ast, Arc::new))
+        } else {
+            Some(ast)
+        }
+    }
+
+    fn get_stubs(&mut self, module: &str) -> Option<Arc<Vec<u8>>> {
+        self.db_api.get_stubs(module)
+    }
+
+    fn eqwalizing_start(&self, module: String) -> () {
+        self.db_api.eqwalizing_start(module)
+    }
+
+    fn eqwalizing_done(&mut self, module: String) -> () {
+        self.db_api.eqwalizing_done(module)
+    }
+}
+
+/// Encodes the forms of `ast` with a `-typing([eqwalizer])` attribute
+/// added after the `-module` one. `None` if `ast` isn't ETF, has no
+/// `-module` attribute or is already opted in.
+fn opt_in(ast: &[u8]) -> Option<Vec<u8>> {
+    if stats::has_typing_attribute(ast)? {
+        return None;
+    }
+    let mut result = match Term::decode(ast).ok()? {
+        Term::Tuple(tuple) if tuple.elements.len() == 3 => tuple,
+        _ => return None,
+    };
+    let forms = match &mut result.elements[1] {
+        Term::List(list) => &mut list.elements,
+        _ => return None,
+    };
+    let (position, anno) =
+        forms
+            .iter()
+            .enumerate()
+            .find_map(|(i, form)| match etf::tuple(form) {
+                Some([tag, anno, name, _])
+                    if etf::atom(tag) == Some("attribute") && etf::atom(name) == Some("module") =>
+                {
+                    Some((i, anno.clone()))
+                }
+                _ => None,
+            })?;
+    let typing = eetf::Tuple::from(vec![
+        eetf::Atom::from("attribute").into(),
+        anno,
+        eetf::Atom::from("typing").into(),
+        eetf::List::from(vec![eetf::Atom::from("eqwalizer").into()]).into(),
+    ]);
+    forms.insert(position + 1, typing.into());
+    let mut bytes = Vec::new();
+    Term::Tuple(result).encode(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+/// Encodes the forms of `ast` without its functions, and without the
+/// attributes that refer to them or to their bodies: `-behaviour`, whose
+/// callbacks would all be missing, `-eqwalizer` and the fixmes of
+/// `elp_metadata`, which would all be redundant. `None` if `ast` isn't
+/// ETF.
+fn strip_functions(ast: &[u8]) -> Option<Vec<u8>> {
+    let mut result = match Term::decode(ast).ok()? {
+        Term::Tuple(tuple) if tuple.elements.len() == 3 => tuple,
+        _ => return None,
+    };
+    let forms = match &mut result.elements[1] {
+        Term::List(list) => &mut list.elements,
+        _ => return None,
+    };
+    forms.retain(|form| match etf::tuple(form) {
+        Some([tag, ..]) if etf::atom(tag) == Some("function") => false,
+        Some([tag, _anno, name, _value]) if etf::atom(tag) == Some("attribute") => !matches!(
+            etf::atom(name),
+            Some("behaviour") | Some("behavior") | Some("eqwalizer") | Some("elp_metadata")
+        ),
+        _ => true,
+    });
+    let mut bytes = Vec::new();
+    Term::Tuple(result).encode(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+/// Type-checks the module defined in `file_id` as if its text was `text`,
+/// leaving the database untouched: the other modules are seen as they are.
+/// Fails with the parse errors of `text` if it can't be parsed.
+pub(crate) fn typecheck_text(
+    db: &crate::RootDatabase,
+    project_id: ProjectId,
+    file_id: FileId,
+    text: &str,
+    strict: bool,
+    env: &[(&str, &str)],
+) -> Result<EqwalizerDiagnostics, Vec<ParseError>> {
+    typecheck_text_rewritten(db, project_id, file_id, text, strict, env, |_| None)
+}
+
+/// Same as `typecheck_text`, with the AST of `text` as `rewrite` changes
+/// it, as it is if it returns `None`, see `typecheck_rewritten`.
+fn typecheck_text_rewritten(
+    db: &crate::RootDatabase,
+    project_id: ProjectId,
+    file_id: FileId,
+    text: &str,
+    strict: bool,
+    env: &[(&str, &str)],
+    rewrite: fn(&[u8]) -> Option<Vec<u8>>,
+) -> Result<EqwalizerDiagnostics, Vec<ParseError>> {
+    let root_id = db.file_source_root(file_id);
+    let root = db.source_root(root_id);
+    let path = root.path_for_file(&file_id).unwrap().as_path().unwrap();
+    let app_data = if let Some(app_data) = db.app_data(root_id) {
+        app_data
+    } else {
+        let err = ParseError {
+            path: path.to_path_buf().into(),
+            location: None,
+            msg: "Unknown application".to_string(),
+        };
+        return Err(vec![err]);
+    };
+    let line_index = LineIndex::new(text);
+    let metadata = erl_ast::elp_metadata_for_text(&line_index, text);
+    let ast = db.load_ast(
+        project_id,
+        path,
+        &app_data.include_path,
+        &app_data.macros,
+        &app_data.parse_transforms,
+        metadata,
+        Format::OffsetEtf,
+        Some(text),
+    )?;
+    let ast = rewrite(&ast).unwrap_or(ast);
+
+    let module = match db.module_index(project_id).module_for_file(file_id) {
+        Some(module) => module.clone(),
+        None => return Ok(Default::default()),
+    };
+    let project = db.project_data(project_id);
+    let build_info_path = match &project.build_info_path {
+        Some(build_info_path) => build_info_path,
+        None => {
+            log::error!("EqWAlizing in a fixture project");
+            return Ok(Default::default());
+        }
+    };
+    let db_api = DbForEqwalizerText {
+        db_api: DbForEqwalizer {
+            db,
+            total: 1,
+            left: 1,
+            project_id,
+            format: Format::OffsetEtf,
+            started: Cell::new(None),
+        },
+        module: module.as_str(),
+        ast: Arc::new(ast),
+    };
+    let modules = vec![module.as_str()];
+    match db
+        .eqwalizer
+        .typecheck_with_env(build_info_path.as_ref(), db_api, modules, strict, env)
+    {
+        Ok(diagnostics) => Ok(diagnostics),
+        Err(error) => {
+            log::error!("EqWAlizing failed: {}", error);
+            Ok(Default::default())
+        }
+    }
+}
+
+/// Serves `ast` for `module` and the database contents for everything else.
+/// Progress isn't reported, these checks aren't part of a batch.
+struct DbForEqwalizerText<'d> {
+    db_api: DbForEqwalizer<'d>,
+    module: &'d str,
+    ast: Arc<Vec<u8>>,
+}
+
+impl<'d> elp_eqwalizer::DbApi for DbForEqwalizerText<'d> {
+    fn unwind_if_cancelled(&self) -> () {
+        self.db_api.unwind_if_cancelled()
+    }
+
+    fn get_ast(&mut self, module: &str) -> Option<Arc<Vec<u8>>> {
+        if module == self.module {
+            Some(self.ast.clone())
+        } else {
+            self.db_api.get_ast(module)
+        }
+    }
+
+    fn get_stubs(&mut self, module: &str) -> Option<Arc<Vec<u8>>> {
+        if module == self.module {
+            Some(self.ast.clone())
+        } else {
+            self.db_api.get_stubs(module)
+        }
+    }
+
+    fn eqwalizing_start(&self, _module: String) -> () {}
+
+    fn eqwalizing_done(&mut self, _module: String) -> () {}
+}
@@ -4,19 +4,29 @@
  * the LICENSE file in the root directory of this source tree.
  */
 
+use std::convert::TryFrom;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Instant;
 
 use elp_base_db::AbsPath;
 use elp_base_db::AbsPathBuf;
 use elp_base_db::FileId;
 use elp_base_db::ProjectId;
 use elp_base_db::SourceDatabase;
+use elp_base_db::VfsPath;
 use elp_parse_server::Format;
 use elp_parse_server::ParseError;
 
+use crate::etf;
 use crate::fixmes;
+use crate::forms::AbstractForm;
 use crate::parse_server::CompileOption;
 use crate::parse_server::ParseRequest;
+use crate::timing;
+use crate::timing::TimingPhase;
+use crate::timing::TimingSink;
+use crate::LineIndex;
 use crate::LineIndexDatabase;
 
 pub trait AstLoader {
@@ -29,7 +39,16 @@ pub trait AstLoader {
         parse_transforms: &[eetf::Term],
         elp_metadata: eetf::Term,
         format: Format,
+        file_text: Option<&str>,
     ) -> Result<Vec<u8>, Vec<ParseError>>;
+
+    /// Whether the text of the file differs from the one on disk, see
+    /// `RootDatabase::set_file_overlay`.
+    fn has_overlay(&self, file_id: FileId) -> bool;
+
+    /// Where to report the time spent in each phase, see
+    /// `RootDatabase::set_timing_sink`.
+    fn timing_sink(&self) -> Option<&dyn TimingSink>;
 }
 
 impl AstLoader for crate::RootDatabase {
@@ -42,17 +61,25 @@ impl AstLoader for crate::RootDatabase {
         parse_transforms: &[eetf::Term],
         elp_metadata: eetf::Term,
         format: Format,
+        file_text: Option<&str>,
     ) -> Result<Vec<u8>, Vec<ParseError>> {
         let includes = include_path
             .iter()
             .map(|path| path.clone().into())
             .collect();
-        let options = vec![
+        let mut options = vec![
             CompileOption::Includes(includes),
             CompileOption::Macros(macros.to_vec()),
             CompileOption::ParseTransforms(parse_transforms.to_vec()),
-            CompileOption::ElpMetadata(elp_metadata),
         ];
+        // Expanded forms are exactly what the preprocessor produced
+        if format != Format::ExpandedForms {
+            options.push(CompileOption::ElpMetadata(elp_metadata));
+        }
+        if let Some(file_text) = file_text {
+            options.push(CompileOption::FileText(file_text.to_string()));
+        }
+        let module = || module_name(path);
         let path = path.to_path_buf().into();
         let req = ParseRequest {
             options,
@@ -61,7 +88,38 @@ impl AstLoader for crate::RootDatabase {
         };
 
         let parse_server = self.parse_servers.read().unwrap().get(&project_id).cloned();
-        parse_server.unwrap().request(req)
+        let parse_server = parse_server.unwrap();
+        // Code paths may have been added since the parse server started.
+        // Reading the project data also makes the ASTs depend on them
+        let code_paths = self
+            .project_data(project_id)
+            .code_paths()
+            .map(|path| path.clone().into())
+            .collect();
+        parse_server.add_code_path(code_paths);
+        let start = Instant::now();
+        let result = timing::timed(self.timing_sink(), module, TimingPhase::Parse, || {
+            self.parse_requests.run(|| match parse_server.try_request(req.clone()) {
+                Some(result) => {
+                    self.metrics.record_parse_answer(result.is_ok());
+                    result
+                }
+                None => {
+                    self.metrics.record_parse_failure();
+                    self.retry_parse_request(project_id, req, parse_server.clone())
+                }
+            })
+        });
+        self.metrics.record_parse(start.elapsed());
+        result
+    }
+
+    fn has_overlay(&self, file_id: FileId) -> bool {
+        self.file_has_overlay(file_id)
+    }
+
+    fn timing_sink(&self) -> Option<&dyn TimingSink> {
+        self.timing_sink.as_deref()
     }
 }
 
@@ -72,6 +130,15 @@ pub trait ErlAstDatabase: SourceDatabase + AstLoader + LineIndexDatabase {
         file_id: FileId,
         format: Format,
     ) -> Result<Arc<Vec<u8>>, Arc<Vec<ParseError>>>;
+
+    /// The forms of the module, decoded from `module_ast`. Only decoded
+    /// again when the ETF changes, instead of on every request.
+    #[salsa::invoke(crate::forms::module_forms)]
+    fn module_forms(
+        &self,
+        file_id: FileId,
+        format: Format,
+    ) -> Result<Arc<Vec<AbstractForm>>, Arc<Vec<ParseError>>>;
 }
 
 fn module_ast(
@@ -79,9 +146,6 @@ fn module_ast(
     file_id: FileId,
     format: Format,
 ) -> Result<Arc<Vec<u8>>, Arc<Vec<ParseError>>> {
-    // We rely on the state of the file system. Let salsa always re-execute this query after changes
-    db.salsa_runtime().report_untracked_read();
-
     let root_id = db.file_source_root(file_id);
     let root = db.source_root(root_id);
     let path = root.path_for_file(&file_id).unwrap().as_path().unwrap();
@@ -96,7 +160,13 @@ fn module_ast(
         return Err(Arc::new(vec![err]));
     };
     let metadata = elp_metadata(db, file_id).into();
-    db.load_ast(
+    // The parse server would read the file from disk otherwise
+    let file_text = if db.has_overlay(file_id) {
+        Some(db.file_text(file_id))
+    } else {
+        None
+    };
+    let ast = db.load_ast(
         app_data.project_id,
         path,
         &app_data.include_path,
@@ -104,15 +174,76 @@ fn module_ast(
         &app_data.parse_transforms,
         metadata,
         format,
-    )
-    .map(Arc::new)
-    .map_err(Arc::new)
+        file_text.as_ref().map(|text| text.as_str()),
+    );
+    // The text of the file itself was read for the metadata. The parse
+    // server also reads the included headers, which salsa must know about
+    // for the AST, and everything depending on it, to be recomputed only
+    // when they change, instead of after every change to any file.
+    let module = || module_name(path);
+    let forms = timing::timed(db.timing_sink(), module, TimingPhase::Decode, || {
+        ast.as_deref().ok().and_then(etf::decode_forms)
+    });
+    match forms {
+        Some(forms) => track_headers(db, app_data.project_id, &forms),
+        // Headers are unknown, e.g. a missing one may be created
+        None => db.salsa_runtime().report_untracked_read(),
+    }
+    ast.map(Arc::new).map_err(Arc::new)
+}
+
+/// The name of the module in the file at `path`, for timings.
+fn module_name(path: &AbsPath) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Reads the text of the headers in the `file` attributes of `forms`, so
+/// the AST depends on them. Headers outside of the source roots of the
+/// project, e.g. OTP's, are assumed not to change.
+fn track_headers(db: &dyn ErlAstDatabase, project_id: ProjectId, forms: &[eetf::Term]) {
+    for path in forms.iter().filter_map(file_attribute_path) {
+        if let Some(header_id) = project_file(db, project_id, &path) {
+            db.file_text(header_id);
+        }
+    }
+}
+
+/// The path of a `file` attribute, which the preprocessor adds at the start
+/// of the module and of the forms of each included header.
+pub(crate) fn file_attribute_path(form: &eetf::Term) -> Option<String> {
+    match etf::tuple(form)? {
+        [tag, _anno, name, value]
+            if etf::atom(tag) == Some("attribute") && etf::atom(name) == Some("file") =>
+        {
+            etf::string(etf::tuple(value)?.first()?)
+        }
+        _ => None,
+    }
+}
+
+/// The file at `path` in the source roots of the project, if any.
+pub(crate) fn project_file<DB>(db: &DB, project_id: ProjectId, path: &str) -> Option<FileId>
+where
+    DB: SourceDatabase + ?Sized,
+{
+    let path = AbsPathBuf::try_from(PathBuf::from(path)).ok()?.normalize();
+    let path = VfsPath::from(path);
+    db.project_data(project_id)
+        .source_roots
+        .iter()
+        .find_map(|&source_root_id| db.source_root(source_root_id).file_for_path(&path))
 }
 
 fn elp_metadata(db: &dyn ErlAstDatabase, file_id: FileId) -> eetf::Term {
     let line_index = db.file_line_index(file_id);
     let file_text = db.file_text(file_id);
-    let fixmes = fixmes::fixmes_eetf(&line_index, &file_text);
+    elp_metadata_for_text(&line_index, &file_text)
+}
+
+pub(crate) fn elp_metadata_for_text(line_index: &LineIndex, file_text: &str) -> eetf::Term {
+    let fixmes = fixmes::fixmes_eetf(line_index, file_text);
     // Erlang proplist: [{eqwalizer_fixmes, [Fixme1, Fixme2....]}]
     eetf::List::from(vec![
         eetf::Tuple::from(vec![eetf::Atom::from("eqwalizer_fixmes").into(), fixmes]).into(),
@@ -0,0 +1,111 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Helpers to inspect the Erlang abstract forms returned by `module_ast`
+//! in the `OffsetEtf` format, where every annotation is a
+//! `{StartByte, EndByte}` tuple.
+
+use std::convert::TryInto;
+
+use eetf::Term;
+use text_size::TextRange;
+
+/// Decodes the `{ok, Forms, Warnings}` result of the parse server.
+pub(crate) fn decode_forms(bytes: &[u8]) -> Option<Vec<Term>> {
+    let mut elements = match Term::decode(bytes).ok()? {
+        Term::Tuple(tuple) => tuple.elements,
+        _ => return None,
+    };
+    if elements.len() != 3 || atom(&elements[0]) != Some("ok") {
+        return None;
+    }
+    match elements.swap_remove(1) {
+        Term::List(list) => Some(list.elements),
+        _ => None,
+    }
+}
+
+pub(crate) fn atom(term: &Term) -> Option<&str> {
+    match term {
+        Term::Atom(atom) => Some(&atom.name),
+        _ => None,
+    }
+}
+
+pub(crate) fn tuple(term: &Term) -> Option<&[Term]> {
+    match term {
+        Term::Tuple(tuple) => Some(&tuple.elements),
+        _ => None,
+    }
+}
+
+pub(crate) fn list(term: &Term) -> Option<&[Term]> {
+    match term {
+        Term::List(list) => Some(&list.elements),
+        _ => None,
+    }
+}
+
+pub(crate) fn int(term: &Term) -> Option<i64> {
+    match term {
+        Term::FixInteger(int) => Some(int.value.into()),
+        _ => None,
+    }
+}
+
+/// An Erlang string, i.e. a list of code points.
+pub(crate) fn string(term: &Term) -> Option<String> {
+    match term {
+        Term::ByteList(bytes) => Some(bytes.bytes.iter().map(|&b| b as char).collect()),
+        Term::List(list) => list
+            .elements
+            .iter()
+            .map(|c| char::from_u32(int(c)?.try_into().ok()?))
+            .collect(),
+        _ => None,
+    }
+}
+
+/// The value of an `{atom, Anno, Value}` literal.
+pub(crate) fn atom_literal(term: &Term) -> Option<&str> {
+    match tuple(term)? {
+        [tag, _anno, value] if atom(tag) == Some("atom") => atom(value),
+        _ => None,
+    }
+}
+
+/// The source range of an `{Start, End}` annotation.
+pub(crate) fn anno_range(anno: &Term) -> Option<TextRange> {
+    match tuple(anno)? {
+        [start, end] => {
+            let start: u32 = int(start)?.try_into().ok()?;
+            let end: u32 = int(end)?.try_into().ok()?;
+            if start <= end {
+                Some(TextRange::new(start.into(), end.into()))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Calls `f` on `term` and all its subterms, parents before children.
+pub(crate) fn walk<'a>(term: &'a Term, f: &mut impl FnMut(&'a Term)) {
+    f(term);
+    let children: &[Term] = match term {
+        Term::Tuple(tuple) => &tuple.elements,
+        Term::List(list) => &list.elements,
+        Term::ImproperList(list) => {
+            walk(&list.last, &mut *f);
+            &list.elements
+        }
+        _ => &[],
+    };
+    for child in children {
+        walk(child, &mut *f);
+    }
+}
@@ -0,0 +1,416 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Suggested fixes for eqWAlizer diagnostics. eqWAlizer doesn't suggest
+//! any itself, so they are derived from the diagnostics, the module text
+//! and its forms.
+//!
+//! Missing specs can't be suggested from them: eqWAlizer doesn't check
+//! functions without a spec, so it reports nothing about them. They are
+//! suggested by the diagnostics of `MissingSpecPolicy` instead.
+
+use std::fmt;
+
+use eetf::Term;
+use elp_base_db::FileId;
+use elp_base_db::ProjectId;
+use elp_eqwalizer::EqwalizerDiagnostic;
+use elp_eqwalizer::EqwalizerDiagnostics;
+use elp_eqwalizer::TextEdit;
+use elp_eqwalizer::UNUSED_IGNORE_CODE;
+use elp_parse_server::Format;
+use text_size::TextRange;
+use text_size::TextSize;
+
+use crate::etf;
+use crate::EqwalizerDatabase;
+use crate::LineIndex;
+
+/// Which suggested fixes `apply_fixes` applies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FixSelector {
+    All,
+    /// The fixes of the diagnostics with one of these codes.
+    Codes(Vec<String>),
+}
+
+impl FixSelector {
+    fn selects(&self, diagnostic: &EqwalizerDiagnostic) -> bool {
+        match self {
+            FixSelector::All => true,
+            FixSelector::Codes(codes) => codes.contains(&diagnostic.code),
+        }
+    }
+}
+
+/// Two selected fixes editing the same part of the text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictError {
+    pub code: String,
+    pub range: TextRange,
+    pub other_code: String,
+    pub other_range: TextRange,
+}
+
+impl fmt::Display for ConflictError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Fix for {} at {:?} conflicts with the fix for {} at {:?}",
+            self.code, self.range, self.other_code, self.other_range
+        )
+    }
+}
+
+impl std::error::Error for ConflictError {}
+
+/// Why `apply_fixes` applied none of the fixes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FixError {
+    Conflict(ConflictError),
+    /// The range of a fix isn't one of the text, which isn't the one the
+    /// diagnostics were computed from.
+    OutOfText {
+        code: String,
+        range: TextRange,
+    },
+}
+
+impl fmt::Display for FixError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FixError::Conflict(conflict) => conflict.fmt(f),
+            FixError::OutOfText { code, range } => write!(
+                f,
+                "Fix for {} at {:?} is out of the text, which has changed",
+                code, range
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FixError {}
+
+/// `text` with the suggested fixes of the `diagnostics` `selector` selects
+/// applied, all of them or none: edits overlapping each other, or
+/// inserting different text at the same place, are a conflict, and an
+/// edit whose range doesn't fall on characters of `text` fails. The same
+/// edit suggested by several diagnostics is applied once.
+pub fn apply_fixes(
+    text: &str,
+    diagnostics: &[EqwalizerDiagnostic],
+    selector: &FixSelector,
+) -> Result<String, FixError> {
+    let mut edits: Vec<(&TextEdit, &str)> = Vec::new();
+    for diagnostic in diagnostics.iter().filter(|d| selector.selects(d)) {
+        for edit in &diagnostic.fixes {
+            if !edits.iter().any(|(e, _)| *e == edit) {
+                edits.push((edit, &diagnostic.code));
+            }
+        }
+    }
+    edits.sort_by_key(|(edit, _)| (edit.range.start(), edit.range.end()));
+    for pair in edits.windows(2) {
+        let ((edit, code), (next, next_code)) = (pair[0], pair[1]);
+        let overlapping = edit.range.end() > next.range.start();
+        let same_insertion_point = edit.range.is_empty() && edit.range == next.range;
+        if overlapping || same_insertion_point {
+            return Err(FixError::Conflict(ConflictError {
+                code: code.to_string(),
+                range: edit.range,
+                other_code: next_code.to_string(),
+                other_range: next.range,
+            }));
+        }
+    }
+    // Edits don't overlap, so their ranges are the same once the edits
+    // after them are applied
+    if let Some((edit, code)) = edits.iter().find(|(edit, _)| {
+        !text.is_char_boundary(edit.range.start().into())
+            || !text.is_char_boundary(edit.range.end().into())
+    }) {
+        return Err(FixError::OutOfText {
+            code: code.to_string(),
+            range: edit.range,
+        });
+    }
+    let mut result = text.to_string();
+    for (edit, _) in edits.iter().rev() {
+        result.replace_range(
+            std::ops::Range::<usize>::from(edit.range),
+            &edit.replacement,
+        );
+    }
+    Ok(result)
+}
+
+/// Fills in `EqwalizerDiagnostic::fixes` for the diagnostics of the given
+/// modules.
+pub(crate) fn add_fixes(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_ids: &[FileId],
+    diags: &mut EqwalizerDiagnostics,
+) {
+    let diagnostics = match diags {
+        EqwalizerDiagnostics::Diagnostics(diagnostics) => diagnostics,
+        EqwalizerDiagnostics::NoAst { .. } => return,
+    };
+    let module_index = db.module_index(project_id);
+    for &file_id in file_ids {
+        let module_diagnostics = match module_index
+            .module_for_file(file_id)
+            .and_then(|module| diagnostics.get_mut(module.as_str()))
+        {
+            Some(module_diagnostics) => module_diagnostics,
+            None => continue,
+        };
+        let text = db.file_text(file_id);
+        let line_index = db.file_line_index(file_id);
+        let forms = db
+            .module_ast(file_id, Format::OffsetEtf)
+            .ok()
+            .and_then(|ast| etf::decode_forms(&ast))
+            .unwrap_or_default();
+        let forms = own_forms(&forms);
+        for diagnostic in module_diagnostics.iter_mut() {
+            diagnostic.fixes = match diagnostic.code.as_str() {
+                "redundant_fixme" | UNUSED_IGNORE_CODE => {
+                    remove_comment(&text, &line_index, diagnostic.range)
+                }
+                "expected_subtype" => widen_spec(&text, &forms, diagnostic).unwrap_or_default(),
+                _ => vec![],
+            };
+        }
+    }
+}
+
+/// Forms of the module itself, leaving out those of included headers whose
+/// offsets are relative to the header.
+pub(crate) fn own_forms(forms: &[Term]) -> Vec<&Term> {
+    let mut own_path = None;
+    let mut in_module = true;
+    let mut own = Vec::new();
+    for form in forms {
+        if let Some([tag, _anno, name, value]) = etf::tuple(form) {
+            if etf::atom(tag) == Some("attribute") && etf::atom(name) == Some("file") {
+                let path = etf::tuple(value).and_then(|value| etf::string(value.first()?));
+                match &own_path {
+                    None => own_path = path,
+                    Some(own_path) => in_module = path.as_ref() == Some(own_path),
+                }
+                continue;
+            }
+        }
+        if in_module {
+            own.push(form);
+        }
+    }
+    own
+}
+
+/// Deletes the comment starting at `range`, with its line if nothing else
+/// is on it.
+fn remove_comment(text: &str, line_index: &LineIndex, range: TextRange) -> Vec<TextEdit> {
+    let line = line_index.line_col(range.start()).line as usize;
+    let line_start = match line_index.line_at(line) {
+        Some(line_start) => line_start,
+        None => return vec![],
+    };
+    let start: usize = range.start().into();
+    let line_end = text[start..].find('\n').map_or(text.len(), |i| start + i);
+    let range = if text[line_start.into()..start].trim().is_empty() {
+        let next_line_start = line_index
+            .line_at(line + 1)
+            .unwrap_or_else(|| TextSize::of(text));
+        TextRange::new(line_start, next_line_start)
+    } else {
+        let code_end = text[..start].trim_end().len();
+        TextRange::new(
+            TextSize::from(code_end as u32),
+            TextSize::from(line_end as u32),
+        )
+    };
+    vec![TextEdit {
+        range,
+        replacement: String::new(),
+    }]
+}
+
+/// For a function returning a value of the wrong type, adds the type that
+/// eqWAlizer got to the return type of the spec, when there is a single one.
+fn widen_spec(
+    text: &str,
+    forms: &[&Term],
+    diagnostic: &EqwalizerDiagnostic,
+) -> Option<Vec<TextEdit>> {
+    let got = got_type(&diagnostic.message)?;
+    let (name, arity) = forms
+        .iter()
+        .find_map(|form| returning_function(form, diagnostic.range))?;
+    let range = forms
+        .iter()
+        .find_map(|form| spec_return_range(form, name, arity))?;
+    let start: usize = range.start().into();
+    let end: usize = range.end().into();
+    let ret = text.get(start..end)?;
+    Some(vec![TextEdit {
+        range,
+        replacement: format!("{} | {}", ret, got),
+    }])
+}
+
+/// The type in an `expected_subtype` message, see `Show.showNotSubtype`.
+/// Types shown with extra details are left out.
+fn got_type(message: &str) -> Option<&str> {
+    let (_, got) = message.split_once("\nGot     : ")?;
+    let detailed = got.contains('\n')
+        || got.contains(" type parameter")
+        || got.starts_with("dict  map")
+        || got.starts_with("shape map");
+    if detailed {
+        None
+    } else {
+        Some(got)
+    }
+}
+
+/// Name and arity of the function if `range` is the last expression of one
+/// of its clauses.
+fn returning_function(form: &Term, range: TextRange) -> Option<(&str, i64)> {
+    match etf::tuple(form)? {
+        [tag, _anno, name, arity, clauses] if etf::atom(tag) == Some("function") => {
+            let returns = etf::list(clauses)?
+                .iter()
+                .any(|clause| match etf::tuple(clause) {
+                    Some([_tag, _anno, _patterns, _guards, body]) => {
+                        etf::list(body).and_then(|body| expr_range(body.last()?)) == Some(range)
+                    }
+                    _ => false,
+                });
+            if returns {
+                Some((etf::atom(name)?, etf::int(arity)?))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Range of the return type of the spec of `name/arity`, if it has a single
+/// clause without constraints.
+fn spec_return_range(form: &Term, name: &str, arity: i64) -> Option<TextRange> {
+    let value = match etf::tuple(form)? {
+        [tag, _anno, attr, value]
+            if etf::atom(tag) == Some("attribute") && etf::atom(attr) == Some("spec") =>
+        {
+            value
+        }
+        _ => return None,
+    };
+    let clauses = match etf::tuple(value)? {
+        [id, clauses] => match etf::tuple(id)? {
+            [n, a] if etf::atom(n) == Some(name) && etf::int(a) == Some(arity) => clauses,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    match etf::list(clauses)? {
+        [clause] => match etf::tuple(clause)? {
+            [tag, _anno, fun, parts]
+                if etf::atom(tag) == Some("type") && etf::atom(fun) == Some("fun") =>
+            {
+                match etf::list(parts)? {
+                    [_args, ret] => expr_range(ret),
+                    _ => None,
+                }
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn expr_range(expr: &Term) -> Option<TextRange> {
+    etf::anno_range(etf::tuple(expr)?.get(1)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(code: &str, fixes: Vec<TextEdit>) -> EqwalizerDiagnostic {
+        EqwalizerDiagnostic {
+            range: TextRange::default(),
+            message: String::new(),
+            uri: String::new(),
+            code: code.to_string(),
+            expression: None,
+            explanation: None,
+            fixes,
+            remote_types: vec![],
+            opaque_types: vec![],
+            related: vec![],
+            severity_override: None,
+            macro_context: None,
+        }
+    }
+
+    fn edit(start: u32, end: u32, replacement: &str) -> TextEdit {
+        TextEdit {
+            range: TextRange::new(start.into(), end.into()),
+            replacement: replacement.to_string(),
+        }
+    }
+
+    #[test]
+    fn applies_selected_fixes_once() {
+        let diagnostics = vec![
+            diagnostic("a", vec![edit(0, 3, "bar")]),
+            diagnostic("a", vec![edit(0, 3, "bar")]),
+            diagnostic("b", vec![edit(4, 7, "qux")]),
+        ];
+        let selector = FixSelector::Codes(vec!["a".to_string()]);
+        let fixed = apply_fixes("foo baz", &diagnostics, &selector);
+        assert_eq!(fixed, Ok("bar baz".to_string()));
+        let fixed = apply_fixes("foo baz", &diagnostics, &FixSelector::All);
+        assert_eq!(fixed, Ok("bar qux".to_string()));
+    }
+
+    #[test]
+    fn overlapping_fixes_conflict() {
+        let diagnostics = vec![
+            diagnostic("a", vec![edit(0, 5, "")]),
+            diagnostic("b", vec![edit(4, 7, "")]),
+        ];
+        let fixed = apply_fixes("foo baz", &diagnostics, &FixSelector::All);
+        assert!(matches!(fixed, Err(FixError::Conflict(_))));
+    }
+
+    #[test]
+    fn fixes_out_of_the_text_fail() {
+        let diagnostics = vec![
+            diagnostic("a", vec![edit(0, 1, "x")]),
+            diagnostic("b", vec![edit(8, 12, "")]),
+        ];
+        let fixed = apply_fixes("foo baz", &diagnostics, &FixSelector::All);
+        assert_eq!(
+            fixed,
+            Err(FixError::OutOfText {
+                code: "b".to_string(),
+                range: TextRange::new(8.into(), 12.into()),
+            })
+        );
+        // Not on a character boundary
+        let fixed = apply_fixes(
+            "é",
+            &[diagnostic("a", vec![edit(1, 2, "")])],
+            &FixSelector::All,
+        );
+        assert!(matches!(fixed, Err(FixError::OutOfText { .. })));
+    }
+}
@@ -6,11 +6,16 @@
 
 use std::convert::TryInto;
 
+use elp_eqwalizer::all_diagnostic_codes;
+use elp_eqwalizer::EqwalizerDiagnostic;
+use elp_eqwalizer::UNUSED_IGNORE_CODE;
 use text_size::TextRange;
 use text_size::TextSize;
 
 use crate::LineIndex;
 
+const IGNORE: &str = "% eqwalizer:ignore";
+
 #[derive(Debug)]
 struct Fixme {
     comment_range: TextRange,
@@ -47,26 +52,18 @@ pub fn fixmes_eetf(line_index: &LineIndex, file_text: &str) -> eetf::Term {
 
 fn collect_fixmes(line_index: &LineIndex, file_text: &str) -> Vec<Fixme> {
     let mut fixmes = Vec::new();
-    let pats = vec!["% eqwalizer:fixme", "% eqwalizer:ignore"];
+    let pats = vec!["% eqwalizer:fixme", IGNORE];
     for pat in pats {
         let len = pat.len();
         for (i, _) in file_text.match_indices(pat) {
+            // Ignores with a code are applied by ELP, see `CodeIgnore`
+            if pat == IGNORE && may_have_code(&file_text[i + len..]) {
+                continue;
+            }
             let start = TextSize::from(i as u32);
             let end = TextSize::from((i + len) as u32);
-            let line_num = line_index.line_col(start).line;
-            if let Some(suppression_start) = line_index.line_at(line_num as usize + 1) {
-                let suppression_end = {
-                    let next_next_line_start: u32 = line_index
-                        .line_at(line_num as usize + 2)
-                        .unwrap_or_else(
-                            // end of last line
-                            || TextSize::from(file_text.chars().count() as u32),
-                        )
-                        .into();
-                    TextSize::from(next_next_line_start - 1)
-                };
+            if let Some(suppression_range) = suppression_range(line_index, file_text, start) {
                 let comment_range = TextRange::new(start, end);
-                let suppression_range = TextRange::new(suppression_start, suppression_end);
                 fixmes.push(Fixme {
                     comment_range,
                     suppression_range,
@@ -76,3 +73,144 @@ fn collect_fixmes(line_index: &LineIndex, file_text: &str) -> Vec<Fixme> {
     }
     fixmes
 }
+
+/// Whether the text has fixme or ignore comments, with or without a code.
+pub(crate) fn has_pragmas(file_text: &str) -> bool {
+    file_text.contains("% eqwalizer:fixme") || file_text.contains(IGNORE)
+}
+
+/// The line following the one of `comment_start`.
+fn suppression_range(
+    line_index: &LineIndex,
+    file_text: &str,
+    comment_start: TextSize,
+) -> Option<TextRange> {
+    let line_num = line_index.line_col(comment_start).line;
+    let suppression_start = line_index.line_at(line_num as usize + 1)?;
+    let suppression_end = {
+        let next_next_line_start: u32 = line_index
+            .line_at(line_num as usize + 2)
+            .unwrap_or_else(
+                // end of last line
+                || TextSize::from(file_text.chars().count() as u32),
+            )
+            .into();
+        TextSize::from(next_next_line_start - 1)
+    };
+    Some(TextRange::new(suppression_start, suppression_end))
+}
+
+/// A `% eqwalizer:ignore <code>` comment, where `<code>` is the code of an
+/// eqWAlizer diagnostic. Unlike fixmes and code-less ignores, which are
+/// applied by eqWAlizer, these only suppress diagnostics with that code on
+/// the next line, and are applied to the results of eqWAlizer.
+#[derive(Debug)]
+pub(crate) struct CodeIgnore {
+    comment_range: TextRange,
+    code: &'static str,
+    suppression_range: TextRange,
+}
+
+/// The code following an ignore marker, if the first word of `rest` is one.
+/// Anything else is the reason of a code-less ignore.
+fn ignored_code(rest: &str) -> Option<&'static str> {
+    let word = first_word(rest)?;
+    all_diagnostic_codes()
+        .iter()
+        .find(|descriptor| descriptor.code == word)
+        .map(|descriptor| descriptor.code)
+}
+
+/// Whether the ignore marker followed by `rest` may be an ignore with a
+/// code. Without codes to tell them from reasons, e.g. when ELP was built
+/// without the eqWAlizer sources, any word may be one: the ignore then
+/// suppresses nothing rather than being taken for a code-less one.
+fn may_have_code(rest: &str) -> bool {
+    if all_diagnostic_codes().is_empty() {
+        first_word(rest).is_some()
+    } else {
+        ignored_code(rest).is_some()
+    }
+}
+
+fn first_word(rest: &str) -> Option<&str> {
+    rest.lines().next()?.split_whitespace().next()
+}
+
+pub(crate) fn collect_code_ignores(line_index: &LineIndex, file_text: &str) -> Vec<CodeIgnore> {
+    let mut ignores = Vec::new();
+    for (i, _) in file_text.match_indices(IGNORE) {
+        let rest = &file_text[i + IGNORE.len()..];
+        if let Some(code) = ignored_code(rest) {
+            let start = TextSize::from(i as u32);
+            let code_end = rest.find(code).unwrap() + code.len();
+            let end = TextSize::from((i + IGNORE.len() + code_end) as u32);
+            if let Some(suppression_range) = suppression_range(line_index, file_text, start) {
+                ignores.push(CodeIgnore {
+                    comment_range: TextRange::new(start, end),
+                    code,
+                    suppression_range,
+                });
+            }
+        }
+    }
+    ignores
+}
+
+/// The ignore of `ignores` suppressing `diagnostic`, if any.
+fn suppressing(ignores: &[CodeIgnore], diagnostic: &EqwalizerDiagnostic) -> Option<usize> {
+    let start = diagnostic.range.start();
+    ignores.iter().position(|ignore| {
+        ignore.code == diagnostic.code
+            && start >= ignore.suppression_range.start()
+            && start <= ignore.suppression_range.end()
+    })
+}
+
+/// The diagnostics `ignores` suppress, with the comment suppressing each.
+pub(crate) fn suppressed_by<'a>(
+    ignores: &[CodeIgnore],
+    diagnostics: &'a [EqwalizerDiagnostic],
+) -> Vec<(&'a EqwalizerDiagnostic, TextRange)> {
+    diagnostics
+        .iter()
+        .filter_map(|diagnostic| {
+            suppressing(ignores, diagnostic).map(|i| (diagnostic, ignores[i].comment_range))
+        })
+        .collect()
+}
+
+/// Drops the diagnostics suppressed by `ignores`, and reports the ignores
+/// that didn't suppress anything if `report_unused`.
+pub(crate) fn apply_code_ignores(
+    ignores: &[CodeIgnore],
+    diagnostics: &mut Vec<EqwalizerDiagnostic>,
+    report_unused: bool,
+) {
+    let mut used = vec![false; ignores.len()];
+    diagnostics.retain(|diagnostic| match suppressing(ignores, diagnostic) {
+        Some(i) => {
+            used[i] = true;
+            false
+        }
+        None => true,
+    });
+    for (ignore, used) in ignores.iter().zip(used) {
+        if report_unused && !used {
+            diagnostics.push(EqwalizerDiagnostic {
+                range: ignore.comment_range,
+                message: format!("unused eqwalizer:ignore for {}", ignore.code),
+                uri: String::new(),
+                code: UNUSED_IGNORE_CODE.to_string(),
+                expression: None,
+                explanation: None,
+                fixes: vec![],
+                remote_types: vec![],
+                opaque_types: vec![],
+                related: vec![],
+                severity_override: None,
+                macro_context: None,
+            });
+        }
+    }
+}
@@ -0,0 +1,213 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Abstract forms of a module, decoded from the ETF returned by
+//! `module_ast`. Only the structure of the forms themselves is decoded:
+//! function clauses, types and expressions are kept as terms, in the
+//! abstract format documented in OTP's `erl_parse`.
+
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use eetf::Term;
+use elp_base_db::FileId;
+use elp_parse_server::Format;
+use elp_parse_server::ParseError;
+use fxhash::FxHashSet;
+use text_size::TextRange;
+
+use crate::etf;
+use crate::reveal::Arity;
+use crate::ErlAstDatabase;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AbstractForm {
+    Function {
+        name: String,
+        arity: Arity,
+        range: TextRange,
+        clauses: Vec<Term>,
+    },
+    /// A `-spec` or, if `callback`, a `-callback`. `module` is only set for
+    /// `-spec M:F(...) -> ...` specs.
+    Spec {
+        module: Option<String>,
+        name: String,
+        arity: Arity,
+        range: TextRange,
+        callback: bool,
+        clauses: Vec<Term>,
+    },
+    /// A `-type` or, if `opaque`, a `-opaque`.
+    Type {
+        name: String,
+        params: Vec<Term>,
+        range: TextRange,
+        opaque: bool,
+        definition: Term,
+    },
+    Record {
+        name: String,
+        range: TextRange,
+        fields: Vec<RecordField>,
+    },
+    /// Any other attribute, e.g. `-module` or `-export`.
+    Attribute {
+        name: String,
+        range: TextRange,
+        value: Term,
+    },
+    /// Forms that aren't attributes or functions, e.g. errors and `eof`, or
+    /// that don't have the expected shape.
+    Raw(Term),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordField {
+    pub name: String,
+    pub range: TextRange,
+    pub default: Option<Term>,
+    pub ty: Option<Term>,
+}
+
+impl AbstractForm {
+    /// The range of the form in the text of the module or, for forms coming
+    /// from a header, of the header. `None` for `Raw` forms.
+    pub fn range(&self) -> Option<TextRange> {
+        match self {
+            AbstractForm::Function { range, .. }
+            | AbstractForm::Spec { range, .. }
+            | AbstractForm::Type { range, .. }
+            | AbstractForm::Record { range, .. }
+            | AbstractForm::Attribute { range, .. } => Some(*range),
+            AbstractForm::Raw(_) => None,
+        }
+    }
+}
+
+/// The forms of the module in `file_id`, in the given format. The `Text`
+/// format isn't ETF, so has no forms. See `ErlAstDatabase::module_forms`
+/// for the memoized version.
+pub(crate) fn module_forms(
+    db: &dyn ErlAstDatabase,
+    file_id: FileId,
+    format: Format,
+) -> Result<Arc<Vec<AbstractForm>>, Arc<Vec<ParseError>>> {
+    let ast = db.module_ast(file_id, format)?;
+    let forms = etf::decode_forms(&ast)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|form| decode_form(&form).unwrap_or(AbstractForm::Raw(form)))
+        .collect();
+    Ok(Arc::new(forms))
+}
+
+/// The functions of `forms` with a `-spec`, callbacks aside.
+pub(crate) fn specified_functions(forms: &[AbstractForm]) -> FxHashSet<(&str, Arity)> {
+    forms
+        .iter()
+        .filter_map(|form| match form {
+            AbstractForm::Spec {
+                name,
+                arity,
+                callback: false,
+                ..
+            } => Some((name.as_str(), *arity)),
+            _ => None,
+        })
+        .collect()
+}
+
+pub(crate) fn decode_form(form: &Term) -> Option<AbstractForm> {
+    match etf::tuple(form)? {
+        [tag, anno, name, arity, clauses] if etf::atom(tag) == Some("function") => {
+            Some(AbstractForm::Function {
+                name: etf::atom(name)?.to_string(),
+                arity: etf::int(arity)?.try_into().ok()?,
+                range: etf::anno_range(anno)?,
+                clauses: etf::list(clauses)?.to_vec(),
+            })
+        }
+        [tag, anno, name, value] if etf::atom(tag) == Some("attribute") => {
+            let range = etf::anno_range(anno)?;
+            let name = etf::atom(name)?;
+            match name {
+                "spec" | "callback" => decode_spec(range, name == "callback", value),
+                "type" | "opaque" => match etf::tuple(value)? {
+                    [type_name, definition, params] => Some(AbstractForm::Type {
+                        name: etf::atom(type_name)?.to_string(),
+                        params: etf::list(params)?.to_vec(),
+                        range,
+                        opaque: name == "opaque",
+                        definition: definition.clone(),
+                    }),
+                    _ => None,
+                },
+                "record" => match etf::tuple(value)? {
+                    [record_name, fields] => Some(AbstractForm::Record {
+                        name: etf::atom(record_name)?.to_string(),
+                        range,
+                        fields: etf::list(fields)?
+                            .iter()
+                            .map(decode_record_field)
+                            .collect::<Option<_>>()?,
+                    }),
+                    _ => None,
+                },
+                _ => Some(AbstractForm::Attribute {
+                    name: name.to_string(),
+                    range,
+                    value: value.clone(),
+                }),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// `{{Name, Arity}, Clauses}` or `{{Module, Name, Arity}, Clauses}`.
+fn decode_spec(range: TextRange, callback: bool, value: &Term) -> Option<AbstractForm> {
+    let (id, clauses) = match etf::tuple(value)? {
+        [id, clauses] => (etf::tuple(id)?, etf::list(clauses)?),
+        _ => return None,
+    };
+    let (module, name, arity) = match id {
+        [name, arity] => (None, name, arity),
+        [module, name, arity] => (Some(etf::atom(module)?.to_string()), name, arity),
+        _ => return None,
+    };
+    Some(AbstractForm::Spec {
+        module,
+        name: etf::atom(name)?.to_string(),
+        arity: etf::int(arity)?.try_into().ok()?,
+        range,
+        callback,
+        clauses: clauses.to_vec(),
+    })
+}
+
+/// `{record_field, Anno, Name[, Default]}`, possibly wrapped in
+/// `{typed_record_field, Field, Type}`.
+fn decode_record_field(field: &Term) -> Option<RecordField> {
+    match etf::tuple(field)? {
+        [tag, field, ty] if etf::atom(tag) == Some("typed_record_field") => {
+            let mut field = decode_record_field(field)?;
+            field.ty = Some(ty.clone());
+            Some(field)
+        }
+        [tag, anno, name, rest @ ..]
+            if etf::atom(tag) == Some("record_field") && rest.len() <= 1 =>
+        {
+            Some(RecordField {
+                name: etf::atom_literal(name)?.to_string(),
+                range: etf::anno_range(anno)?,
+                default: rest.first().cloned(),
+                ty: None,
+            })
+        }
+        _ => None,
+    }
+}
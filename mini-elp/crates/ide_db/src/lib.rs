@@ -6,39 +6,138 @@
 
 use std::fmt;
 use std::panic::RefUnwindSafe;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Condvar;
 use std::sync::Mutex;
 use std::sync::RwLock;
+use std::thread;
+use std::time::Duration;
 
 use anyhow::Result;
 use elp_base_db::salsa;
+use elp_base_db::Change;
 use elp_base_db::FileId;
 use elp_base_db::FileLoader;
 use elp_base_db::FileLoaderDelegate;
 use elp_base_db::ProjectId;
 use elp_base_db::SourceDatabase;
+use elp_base_db::SourceDatabaseExt;
 use elp_base_db::Upcast;
 use fxhash::FxHashMap;
 use parse_server::Connection;
+use parse_server::ParseError;
+use parse_server::ParseRequest;
+use parse_server::ServerHealth;
 use salsa::Database;
 
+use crate::cancellation::TokenWatch;
+use crate::metrics::MetricsRecorder;
+
+pub mod api_surface;
+pub mod behaviours;
+mod cancellation;
+pub mod disk_cache;
 pub mod eqwalizer;
 mod erl_ast;
+mod etf;
+mod fixes;
 mod fixmes;
+pub mod forms;
 mod line_index;
+mod macros;
+pub mod metrics;
+mod missing_spec;
+pub mod module_deps;
+pub mod narrow;
+pub mod opacity;
+mod related;
+pub mod reveal;
+pub mod spec;
+pub mod stats;
+pub mod stubs;
+pub mod timing;
+pub mod type_aliases;
+pub mod type_env;
+pub mod unreachable;
 
 // ---------------------------------------------------------------------
 // pub mod fixture;
 
+pub use api_surface::ApiSurface;
+pub use api_surface::ApiType;
+pub use cancellation::CancellationToken;
+pub use disk_cache::CacheStats;
+pub use disk_cache::DiskCache;
 pub use elp_base_db;
+pub use elp_eqwalizer::all_diagnostic_codes;
+pub use elp_eqwalizer::Baseline;
+pub use elp_eqwalizer::BaselineCheck;
+pub use elp_eqwalizer::BaselineEntry;
+pub use elp_eqwalizer::CheckMode;
+pub use elp_eqwalizer::CheckScope;
+pub use elp_eqwalizer::DiagnosticDescriptor;
+pub use elp_eqwalizer::DiagnosticsDiff;
+pub use elp_eqwalizer::DiffCounts;
+pub use elp_eqwalizer::DynamicPolicy;
 pub use elp_eqwalizer::Eqwalizer;
 pub use elp_eqwalizer::EqwalizerDiagnostic;
 pub use elp_eqwalizer::EqwalizerDiagnostics;
+pub use elp_eqwalizer::Explanation;
+pub use elp_eqwalizer::FingerprintedDiagnostic;
+pub use elp_eqwalizer::MacroContext;
+pub use elp_eqwalizer::MissingSpecPolicy;
+pub use elp_eqwalizer::Obligation;
+pub use elp_eqwalizer::RelatedLocation;
+pub use elp_eqwalizer::RemoteTypeId;
+pub use elp_eqwalizer::Severity;
+pub use elp_eqwalizer::TextEdit;
+pub use elp_eqwalizer::VersionInfo;
 pub use elp_parse_server as parse_server;
+pub use eqwalizer::AppFilter;
 pub use eqwalizer::EqwalizerDatabase;
+pub use eqwalizer::Progress;
+pub use eqwalizer::SuppressedDiagnostic;
 pub use erl_ast::ErlAstDatabase;
+pub use fixes::apply_fixes;
+pub use fixes::ConflictError;
+pub use fixes::FixError;
+pub use fixes::FixSelector;
+pub use forms::AbstractForm;
+pub use forms::RecordField;
 pub use line_index::LineCol;
 pub use line_index::LineIndex;
+pub use line_index::Position;
+pub use line_index::PositionEncoding;
+pub use metrics::Histogram;
+pub use metrics::Metrics;
+pub use module_deps::FunctionCallers;
+pub use module_deps::ModuleDependencies;
+pub use module_deps::ModuleDepsDatabase;
+pub use module_deps::Reference;
+pub use module_deps::UnusedExports;
+pub use reveal::Arity;
+pub use reveal::CallTypeInfo;
+pub use reveal::FunctionName;
+pub use reveal::ModuleExports;
+pub use reveal::RecordFieldType;
+pub use reveal::RecordType;
+pub use reveal::TypeInfo;
+pub use reveal::VarName;
+pub use spec::EffectiveSpec;
+pub use spec::SpecValidation;
+pub use stats::Coverage;
+pub use stats::ProjectStats;
+pub use stubs::StubsDatabase;
+pub use timing::TimingPhase;
+pub use timing::TimingSink;
+pub use type_aliases::TypeName;
+pub use type_env::ScopedType;
+pub use type_env::TypeEnv;
+pub use unreachable::UnreachableBranch;
 // ---------------------------------------------------------------------
 
 type EqwalizerProgressReporterBox = Arc<Mutex<Option<Box<dyn EqwalizerProgressReporter>>>>;
@@ -55,14 +154,59 @@ pub trait EqwalizerProgressReporter: Send + Sync + RefUnwindSafe {
     elp_base_db::SourceDatabaseExtStorage,
     elp_base_db::SourceDatabaseStorage,
     eqwalizer::EqwalizerDatabaseStorage,
-    erl_ast::ErlAstDatabaseStorage
+    erl_ast::ErlAstDatabaseStorage,
+    module_deps::ModuleDepsDatabaseStorage,
+    stubs::StubsDatabaseStorage
 )]
-#[derive(Default)]
 pub struct RootDatabase {
     storage: salsa::Storage<Self>,
     parse_servers: Arc<RwLock<FxHashMap<ProjectId, Connection>>>,
+    /// Number of parse servers started again after dying.
+    parse_server_restarts: Arc<AtomicUsize>,
+    /// Held while a parse server is started, so that requests seeing the
+    /// same server die only start one.
+    parse_server_start: Arc<Mutex<()>>,
+    parse_requests: Arc<RequestLimit>,
+    parse_retry_policy: Arc<RwLock<ParseRetryPolicy>>,
+    /// Number of times requests for the file were retried, by path.
+    parse_retries: Arc<Mutex<FxHashMap<PathBuf, usize>>>,
     eqwalizer: Eqwalizer,
     eqwalizer_progress_reporter: EqwalizerProgressReporterBox,
+    disk_cache: Option<Arc<DiskCache>>,
+    timing_sink: Option<Arc<dyn TimingSink>>,
+    metrics: Arc<MetricsRecorder>,
+    /// Text on disk of the files with an overlay, as the latest changes
+    /// applied with `RootDatabase::apply_change` have it. Which files have
+    /// an overlay is tracked by `SourceDatabase::overlaid_files`.
+    overlaid: FxHashMap<FileId, Arc<String>>,
+    /// Cancels the computations of this snapshot only.
+    cancellation: Option<TokenWatch>,
+    /// See `RootDatabase::set_own_apps_first`.
+    own_apps_first: bool,
+}
+
+impl Default for RootDatabase {
+    fn default() -> Self {
+        let mut db = RootDatabase {
+            storage: Default::default(),
+            parse_servers: Default::default(),
+            parse_server_restarts: Default::default(),
+            parse_server_start: Default::default(),
+            parse_requests: Default::default(),
+            parse_retry_policy: Default::default(),
+            parse_retries: Default::default(),
+            eqwalizer: Default::default(),
+            eqwalizer_progress_reporter: Default::default(),
+            disk_cache: None,
+            timing_sink: None,
+            metrics: Default::default(),
+            overlaid: Default::default(),
+            cancellation: None,
+            own_apps_first: false,
+        };
+        db.set_overlaid_files(Default::default());
+        db
+    }
 }
 
 impl Upcast<dyn SourceDatabase> for RootDatabase {
@@ -87,19 +231,60 @@ impl salsa::Database for RootDatabase {}
 
 impl salsa::ParallelDatabase for RootDatabase {
     fn snapshot(&self) -> salsa::Snapshot<RootDatabase> {
+        self.snapshot_with(self.cancellation.clone())
+    }
+}
+
+impl RootDatabase {
+    pub fn request_cancellation(&mut self) {
+        self.salsa_runtime_mut()
+            .synthetic_write(salsa::Durability::LOW);
+    }
+
+    /// A snapshot whose computations are also cancelled by `token`, see the
+    /// `cancellation` module.
+    pub fn snapshot_with_cancellation(
+        &self,
+        token: &CancellationToken,
+    ) -> salsa::Snapshot<RootDatabase> {
+        self.snapshot_with(Some(token.watch()))
+    }
+
+    fn snapshot_with(&self, cancellation: Option<TokenWatch>) -> salsa::Snapshot<RootDatabase> {
         salsa::Snapshot::new(RootDatabase {
             storage: self.storage.snapshot(),
             parse_servers: self.parse_servers.clone(),
+            parse_server_restarts: self.parse_server_restarts.clone(),
+            parse_server_start: self.parse_server_start.clone(),
+            parse_requests: self.parse_requests.clone(),
+            parse_retry_policy: self.parse_retry_policy.clone(),
+            parse_retries: self.parse_retries.clone(),
             eqwalizer: self.eqwalizer.clone(),
             eqwalizer_progress_reporter: self.eqwalizer_progress_reporter.clone(),
+            disk_cache: self.disk_cache.clone(),
+            timing_sink: self.timing_sink.clone(),
+            metrics: self.metrics.clone(),
+            overlaid: self.overlaid.clone(),
+            cancellation,
+            own_apps_first: self.own_apps_first,
         })
     }
-}
 
-impl RootDatabase {
-    pub fn request_cancellation(&mut self) {
-        self.salsa_runtime_mut()
-            .synthetic_write(salsa::Durability::LOW);
+    /// Unwinds with `salsa::Cancelled` if the database changed or the
+    /// snapshot's token, if any, was cancelled.
+    pub fn unwind_if_cancelled(&self) {
+        salsa::Database::unwind_if_cancelled(self);
+        if let Some(cancellation) = &self.cancellation {
+            cancellation.unwind_if_cancelled();
+        }
+    }
+
+    /// Whether the computations of the snapshot are cancelled, i.e. the
+    /// database changed or the snapshot's token, if any, was cancelled.
+    /// Unlike the other snapshots of the same revision, those waiting for a
+    /// query this one was computing when its token was cancelled aren't.
+    pub fn is_cancelled(&self) -> bool {
+        salsa::Cancelled::catch(|| self.unwind_if_cancelled()).is_err()
     }
 
     pub fn clear_parse_servers(&mut self) {
@@ -107,12 +292,18 @@ impl RootDatabase {
     }
 
     pub fn ensure_parse_server(&self, project_id: ProjectId) -> Result<()> {
+        let _starting = self.parse_server_start.lock().unwrap();
+        self.start_parse_server(project_id)
+    }
+
+    /// Starts a parse server for the project, replacing the one it had.
+    /// Callers hold `parse_server_start`.
+    fn start_parse_server(&self, project_id: ProjectId) -> Result<()> {
         let connection = Connection::start()?;
 
         let project_data = self.project_data(project_id);
         let path = project_data
-            .deps_ebins
-            .iter()
+            .code_paths()
             .map(|path| path.clone().into())
             .collect();
         connection.add_code_path(path);
@@ -124,6 +315,155 @@ impl RootDatabase {
         Ok(())
     }
 
+    /// Whether the parse server of the project is still running, `None` if
+    /// it has none.
+    pub fn parse_server_health(&self, project_id: ProjectId) -> Option<ServerHealth> {
+        self.parse_servers
+            .read()
+            .unwrap()
+            .get(&project_id)
+            .map(Connection::health)
+    }
+
+    /// Replaces the parse server of the project with a new one, e.g. after
+    /// it died. Restarts are counted and logged, to notice servers dying
+    /// over and over.
+    pub fn restart_parse_server(&self, project_id: ProjectId) -> Result<()> {
+        let _starting = self.parse_server_start.lock().unwrap();
+        self.restart_parse_server_locked(project_id)
+    }
+
+    fn restart_parse_server_locked(&self, project_id: ProjectId) -> Result<()> {
+        self.start_parse_server(project_id)?;
+        let restarts = self.parse_server_restarts.fetch_add(1, Ordering::Relaxed) + 1;
+        log::warn!(
+            "Restarted the parse server of {:?}, {} restart(s) so far",
+            project_id,
+            restarts
+        );
+        Ok(())
+    }
+
+    /// The parse server of the project replacing `dead`, which died: the
+    /// one another request started already, if any, or a new one. All the
+    /// requests seeing `dead` die thus restart it once.
+    fn replace_dead_parse_server(
+        &self,
+        project_id: ProjectId,
+        dead: &Connection,
+    ) -> Result<Connection> {
+        let _starting = self.parse_server_start.lock().unwrap();
+        let current = self.parse_servers.read().unwrap().get(&project_id).cloned();
+        match current {
+            Some(current) if !current.is_same_server(dead) => Ok(current),
+            _ => {
+                self.restart_parse_server_locked(project_id)?;
+                self.parse_servers
+                    .read()
+                    .unwrap()
+                    .get(&project_id)
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("No parse server for {:?}", project_id))
+            }
+        }
+    }
+
+    /// Caps the number of AST requests in flight to the parse servers, for
+    /// all projects and snapshots, `0` meaning the available parallelism.
+    /// Requests over the cap wait for others to finish, whichever query
+    /// makes them. By default, requests are not capped.
+    pub fn set_parse_server_concurrency(&self, n: usize) {
+        let n = if n == 0 {
+            thread::available_parallelism().map_or(1, |n| n.get())
+        } else {
+            n
+        };
+        self.parse_requests.set_max(n);
+    }
+
+    /// Number of parse servers restarted, by `restart_parse_server` or
+    /// after dying, for all projects.
+    pub fn parse_server_restarts(&self) -> usize {
+        self.parse_server_restarts.load(Ordering::Relaxed)
+    }
+
+    /// Sets how requests the parse server dies before answering are
+    /// retried, for all projects and snapshots. Parse errors are answers,
+    /// so are never retried.
+    pub fn set_parse_retry_policy(&self, policy: ParseRetryPolicy) {
+        *self.parse_retry_policy.write().unwrap() = policy;
+    }
+
+    /// Number of times requests for the file at `path` were retried.
+    pub fn parse_retries(&self, path: &Path) -> usize {
+        self.parse_retries
+            .lock()
+            .unwrap()
+            .get(path)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Number of times requests were retried, for the files retried at
+    /// least once.
+    pub fn all_parse_retries(&self) -> FxHashMap<PathBuf, usize> {
+        self.parse_retries.lock().unwrap().clone()
+    }
+
+    /// Repeats `request`, which `dead`, the parse server of the project,
+    /// died before answering, with a new server each time, as the retry
+    /// policy allows, see `replace_dead_parse_server`.
+    fn retry_parse_request(
+        &self,
+        project_id: ProjectId,
+        request: ParseRequest,
+        mut dead: Connection,
+    ) -> Result<Vec<u8>, Vec<ParseError>> {
+        let policy = *self.parse_retry_policy.read().unwrap();
+        let path = request.path.clone();
+        let error = |msg: String| {
+            vec![ParseError {
+                path: path.clone(),
+                location: None,
+                msg,
+            }]
+        };
+        log::error!("The parse server of {:?} died", project_id);
+        let mut last_error = "The parse server died".to_string();
+        for attempt in 1..=policy.max_retries {
+            if attempt > 1 && !policy.backoff.is_zero() {
+                thread::sleep(policy.backoff);
+            }
+            self.unwind_if_cancelled();
+            *self
+                .parse_retries
+                .lock()
+                .unwrap()
+                .entry(path.clone())
+                .or_default() += 1;
+            let parse_server = match self.replace_dead_parse_server(project_id, &dead) {
+                Ok(parse_server) => parse_server,
+                Err(err) => {
+                    self.metrics.record_parse_failure();
+                    last_error = format!("Could not restart the parse server: {}", err);
+                    continue;
+                }
+            };
+            match parse_server.try_request(request.clone()) {
+                Some(result) => {
+                    self.metrics.record_parse_answer(result.is_ok());
+                    return result;
+                }
+                None => {
+                    self.metrics.record_parse_failure();
+                    last_error = "The parse server died again".to_string();
+                    dead = parse_server;
+                }
+            }
+        }
+        Err(error(last_error))
+    }
+
     pub fn set_eqwalizer_progress_reporter(
         &self,
         report: Option<Box<dyn EqwalizerProgressReporter>>,
@@ -134,6 +474,163 @@ impl RootDatabase {
     pub fn eqwalizer(&self) -> &Eqwalizer {
         &self.eqwalizer
     }
+
+    /// Saves and restores the results of `EqwalizerLoader::typecheck` in
+    /// `cache`, see the `disk_cache` module.
+    pub fn set_disk_cache(&mut self, cache: Option<DiskCache>) {
+        self.disk_cache = cache.map(Arc::new);
+    }
+
+    pub fn disk_cache(&self) -> Option<&DiskCache> {
+        self.disk_cache.as_deref()
+    }
+
+    /// Makes the snapshots taken from now on hand the modules of the
+    /// project's own apps to eqWAlizer first in each batch, then those of
+    /// dependencies and of OTP. Only the order they are checked in changes:
+    /// batches are memoized by the list of files as given.
+    pub fn set_own_apps_first(&mut self, own_apps_first: bool) {
+        self.own_apps_first = own_apps_first;
+    }
+
+    pub(crate) fn own_apps_first(&self) -> bool {
+        self.own_apps_first
+    }
+
+    /// Reports the time spent in each phase of checking modules to `sink`,
+    /// see the `timing` module. `None` stops timing them.
+    pub fn set_timing_sink(&mut self, sink: Option<Arc<dyn TimingSink>>) {
+        self.timing_sink = sink;
+    }
+
+    /// The metrics recorded by the database and its snapshots so far, see
+    /// the `metrics` module.
+    pub fn metrics(&self) -> Metrics {
+        let cache = self.disk_cache().map(DiskCache::stats).unwrap_or_default();
+        Metrics {
+            cache_hits: cache.hits as u64,
+            cache_misses: cache.misses as u64,
+            parse_server_restarts: self.parse_server_restarts() as u64,
+            ..self.metrics.snapshot()
+        }
+    }
+
+    /// Counts a request for eqwalizer diagnostics in `Metrics::queries`.
+    pub fn record_diagnostics_query(&self) {
+        self.metrics.record_query();
+    }
+
+    /// Applies `change`, see `Change::apply`. The files with an overlay
+    /// keep it: their new text is the one `clear_file_overlay` reverts to,
+    /// and they aren't returned as changed.
+    pub fn apply_change(&mut self, mut change: Change) -> Vec<FileId> {
+        let overlaid = &mut self.overlaid;
+        change
+            .files_changed
+            .retain(|(file_id, text)| match overlaid.get_mut(file_id) {
+                Some(disk_text) => {
+                    *disk_text = text.clone().unwrap_or_default();
+                    false
+                }
+                None => true,
+            });
+        change.apply(self)
+    }
+
+    /// Replaces the text of the file with unsaved `contents`, including for
+    /// the parse server, which otherwise reads files from disk.
+    pub fn set_file_overlay(&mut self, file_id: FileId, contents: Arc<String>) {
+        if !self.overlaid.contains_key(&file_id) {
+            let text = SourceDatabaseExt::file_text(self, file_id);
+            self.overlaid.insert(file_id, text);
+            self.set_overlaid(file_id, true);
+        }
+        self.set_file_text(file_id, contents);
+    }
+
+    /// Reverts the text of the file to its text on disk, as of the latest
+    /// change applied.
+    pub fn clear_file_overlay(&mut self, file_id: FileId) {
+        if let Some(text) = self.overlaid.remove(&file_id) {
+            self.set_overlaid(file_id, false);
+            self.set_file_text(file_id, text);
+        }
+    }
+
+    /// Drops every overlay, leaving the files with their current text, for
+    /// it to be replaced e.g. by a restored state.
+    pub fn drop_file_overlays(&mut self) {
+        if !self.overlaid.is_empty() {
+            self.overlaid.clear();
+            self.set_overlaid_files(Default::default());
+        }
+    }
+
+    fn set_overlaid(&mut self, file_id: FileId, overlaid: bool) {
+        let mut files = (*self.overlaid_files()).clone();
+        if overlaid {
+            files.insert(file_id);
+        } else {
+            files.remove(&file_id);
+        }
+        self.set_overlaid_files(Arc::new(files));
+    }
+}
+
+/// How requests the parse server dies before answering are retried: up to
+/// `max_retries` times, with a new server each time, waiting `backoff`
+/// between retries. The first retry is immediate. By default, requests are
+/// retried once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseRetryPolicy {
+    pub max_retries: usize,
+    pub backoff: Duration,
+}
+
+impl Default for ParseRetryPolicy {
+    fn default() -> Self {
+        ParseRetryPolicy {
+            max_retries: 1,
+            backoff: Duration::ZERO,
+        }
+    }
+}
+
+/// Number of requests in flight, and how many there can be (`None` for no
+/// limit).
+#[derive(Debug, Default)]
+struct RequestLimit {
+    state: Mutex<(usize, Option<usize>)>,
+    released: Condvar,
+}
+
+impl RequestLimit {
+    fn set_max(&self, max: usize) {
+        self.state.lock().unwrap().1 = Some(max);
+        self.released.notify_all();
+    }
+
+    /// Runs `f` once there are fewer requests in flight than the limit.
+    fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        let mut state = self.state.lock().unwrap();
+        while matches!(*state, (in_flight, Some(max)) if in_flight >= max) {
+            state = self.released.wait(state).unwrap();
+        }
+        state.0 += 1;
+        drop(state);
+        let _permit = Permit(self);
+        f()
+    }
+}
+
+/// Releases its request on drop, including when unwinding on cancellation.
+struct Permit<'a>(&'a RequestLimit);
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().0 -= 1;
+        self.0.released.notify_one();
+    }
 }
 
 #[salsa::query_group(LineIndexDatabaseStorage)]
@@ -21,6 +21,8 @@ pub struct LineIndex {
     pub(crate) newlines: Vec<TextSize>,
     /// List of offsets of utf16 surrogates on each line
     pub(crate) utf16_lines: FxHashMap<u32, Vec<Utf16Char>>,
+    /// Length of the text
+    pub(crate) len: TextSize,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -31,6 +33,25 @@ pub struct LineCol {
     pub col_utf16: u32,
 }
 
+/// How the columns of a `Position` are counted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PositionEncoding {
+    /// In bytes.
+    Utf8,
+    /// In UTF-16 code units, as in LSP by default.
+    Utf16,
+}
+
+/// A position in a text, as in LSP, with columns counted as a
+/// `PositionEncoding` says.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Position {
+    /// Zero-based
+    pub line: u32,
+    /// Zero-based
+    pub character: u32,
+}
+
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub(crate) struct Utf16Char {
     /// Start offset of a character inside a line, zero-based
@@ -100,6 +121,56 @@ impl LineIndex {
         LineIndex {
             newlines,
             utf16_lines,
+            len: TextSize::of(text),
+        }
+    }
+
+    /// The position of `offset`, the end of the text if it is past it.
+    pub fn position(&self, offset: TextSize, encoding: PositionEncoding) -> Position {
+        let offset = offset.min(self.len);
+        let line_col = self.line_col(offset);
+        let character = match encoding {
+            PositionEncoding::Utf8 => u32::from(offset - self.newlines[line_col.line as usize]),
+            PositionEncoding::Utf16 => line_col.col_utf16,
+        };
+        Position {
+            line: line_col.line,
+            character,
+        }
+    }
+
+    /// The offset of `position`. `None` if it is past the end of its line,
+    /// the end of a line being the offset of its newline, or for UTF-8
+    /// columns in the middle of a character.
+    pub fn checked_offset(
+        &self,
+        position: Position,
+        encoding: PositionEncoding,
+    ) -> Option<TextSize> {
+        let line = position.line as usize;
+        let line_start = *self.newlines.get(line)?;
+        let line_end = match self.newlines.get(line + 1) {
+            Some(&next_line_start) => next_line_start - TextSize::from(1),
+            None => self.len,
+        };
+        let col = match encoding {
+            PositionEncoding::Utf8 => {
+                let col = TextSize::from(position.character);
+                let splits_char = self.utf16_lines.get(&position.line).map_or(false, |chars| {
+                    chars.iter().any(|c| c.start < col && col < c.end)
+                });
+                if splits_char {
+                    return None;
+                }
+                col
+            }
+            PositionEncoding::Utf16 => self.utf16_to_utf8_col(position.line, position.character),
+        };
+        let offset = line_start.checked_add(col)?;
+        if offset <= line_end {
+            Some(offset)
+        } else {
+            None
         }
     }
 
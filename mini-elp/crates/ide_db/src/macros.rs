@@ -0,0 +1,134 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Macro calls eqWAlizer diagnostics originate from.
+//!
+//! The preprocessor gives the tokens of an expansion the location of the
+//! whole macro call, `?NAME(Args)`, while the tokens of the arguments keep
+//! their own. So a diagnostic about code coming from an expansion starts at
+//! the `?` of a call in the text of the module, which is the only trace of
+//! the expansion left by the time diagnostics reach ELP. Predefined macros,
+//! e.g. `?MODULE`, expand to a single literal: diagnostics starting at them
+//! are about the code around them.
+
+use elp_base_db::FileLoader;
+use elp_base_db::ProjectId;
+use elp_eqwalizer::EqwalizerDiagnostics;
+use elp_eqwalizer::MacroContext;
+use text_size::TextRange;
+use text_size::TextSize;
+
+use crate::EqwalizerDatabase;
+
+/// The macros the preprocessor defines for every module.
+const PREDEFINED_MACROS: &[&str] = &[
+    "MODULE",
+    "MODULE_STRING",
+    "FILE",
+    "LINE",
+    "MACHINE",
+    "FUNCTION_NAME",
+    "FUNCTION_ARITY",
+    "OTP_RELEASE",
+    "FEATURE_AVAILABLE",
+    "FEATURE_ENABLED",
+];
+
+/// Fills in `EqwalizerDiagnostic::macro_context` for the diagnostics
+/// starting at a macro call.
+pub(crate) fn add_macro_contexts(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    diags: &mut EqwalizerDiagnostics,
+) {
+    let diagnostics = match diags {
+        EqwalizerDiagnostics::Diagnostics(diagnostics) => diagnostics,
+        EqwalizerDiagnostics::NoAst { .. } => return,
+    };
+    let module_index = db.module_index(project_id);
+    for (module, module_diagnostics) in diagnostics.iter_mut() {
+        let file_id = match module_index.file_for_module(module.as_str()) {
+            Some(file_id) => file_id,
+            None => continue,
+        };
+        let text = db.file_text(file_id);
+        for diagnostic in module_diagnostics.iter_mut() {
+            diagnostic.macro_context = macro_call(&text, diagnostic.range.start());
+        }
+    }
+}
+
+/// The macro call starting at `offset`, arguments included. Calls of
+/// `??Arg`, which only stringify arguments, and of predefined macros yield
+/// no context.
+fn macro_call(text: &str, offset: TextSize) -> Option<MacroContext> {
+    let start: usize = offset.into();
+    let rest = text.get(start..)?.strip_prefix('?')?;
+    let name_len = rest
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '@'))
+        .unwrap_or(rest.len());
+    if name_len == 0 {
+        return None;
+    }
+    let name = &rest[..name_len];
+    if PREDEFINED_MACROS.contains(&name) {
+        return None;
+    }
+    let after_name = &rest[name_len..];
+    let mut end = start + 1 + name_len;
+    let trimmed = after_name.trim_start();
+    if trimmed.starts_with('(') {
+        let open = end + (after_name.len() - trimmed.len());
+        end = matching_paren(text, open)? + 1;
+    }
+    Some(MacroContext {
+        name: name.to_string(),
+        call_site: TextRange::new(offset, TextSize::try_from(end).ok()?),
+    })
+}
+
+/// The offset of the parenthesis closing the one at `open`, skipping
+/// strings, quoted atoms, character literals and comments.
+fn matching_paren(text: &str, open: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut chars = text[open..].char_indices().map(|(i, c)| (open + i, c));
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    return if c == ')' { Some(i) } else { None };
+                }
+            }
+            '"' | '\'' => {
+                while let Some((_, d)) = chars.next() {
+                    match d {
+                        '\\' => {
+                            chars.next();
+                        }
+                        d if d == c => break,
+                        _ => {}
+                    }
+                }
+            }
+            '$' => {
+                if let Some((_, '\\')) = chars.next() {
+                    chars.next();
+                }
+            }
+            '%' => {
+                for (_, d) in chars.by_ref() {
+                    if d == '\n' {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
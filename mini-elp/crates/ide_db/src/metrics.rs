@@ -0,0 +1,246 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Counters and latency histograms of a database and its snapshots, e.g.
+//! for a `/metrics` handler to scrape.
+//!
+//! Unlike a `TimingSink`, which is pushed every measure, metrics are
+//! always recorded, into atomics shared by all the snapshots, and pulled
+//! with `RootDatabase::metrics`. Recording and taking a snapshot allocate
+//! nothing. As with timings, only actual work is timed: results salsa or
+//! the disk cache already have are not measured again.
+
+use std::fmt::Write;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+/// Upper bounds, in milliseconds, of the buckets of the latency
+/// histograms. A last bucket holds the samples above all of them.
+pub const LATENCY_BUCKETS_MS: [u64; 12] =
+    [1, 5, 10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000];
+
+const BUCKETS: usize = LATENCY_BUCKETS_MS.len() + 1;
+
+/// A snapshot of the metrics, see `RootDatabase::metrics`. Counters only
+/// grow, from when the database was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Requests for eqwalizer diagnostics, whether salsa had them or not.
+    pub queries: u64,
+    /// Modules found in the disk cache, if any, see `CacheStats`.
+    pub cache_hits: u64,
+    /// Modules not found in the disk cache, if any.
+    pub cache_misses: u64,
+    /// Requests the parse servers failed to answer, e.g. because they
+    /// died, retries included.
+    pub parse_failures: u64,
+    /// Requests the parse servers answered with errors of the module text,
+    /// e.g. syntax errors.
+    pub parse_errors: u64,
+    /// See `RootDatabase::parse_server_restarts`.
+    pub parse_server_restarts: u64,
+    /// Round trips to the parse servers, retries included.
+    pub parse_latency: Histogram,
+    /// Time eqWAlizer took to check each module.
+    pub check_latency: Histogram,
+    /// Diagnostics eqWAlizer reported for the modules it checked, see
+    /// `diagnostics_per_module`.
+    pub diagnostics: u64,
+    /// Modules eqWAlizer checked, whether or not they have diagnostics.
+    pub modules_checked: u64,
+}
+
+/// Latencies, as the number of samples in each of the buckets of
+/// `LATENCY_BUCKETS_MS`, with their count and total.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Histogram {
+    /// Number of samples in each bucket of `LATENCY_BUCKETS_MS`, not
+    /// cumulative, then of those above the last bound.
+    pub buckets: [u64; BUCKETS],
+    pub count: u64,
+    pub sum: Duration,
+}
+
+impl Histogram {
+    /// Upper bound of the bucket the `q` quantile falls in, the last bound
+    /// if it falls above it. `None` without samples.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let total: u64 = self.buckets.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let bound = LATENCY_BUCKETS_MS[bucket.min(LATENCY_BUCKETS_MS.len() - 1)];
+                return Some(Duration::from_millis(bound));
+            }
+        }
+        None
+    }
+
+    pub fn p50(&self) -> Option<Duration> {
+        self.quantile(0.5)
+    }
+
+    pub fn p95(&self) -> Option<Duration> {
+        self.quantile(0.95)
+    }
+}
+
+impl Metrics {
+    /// Average number of diagnostics of the modules checked so far, `None`
+    /// if none was.
+    pub fn diagnostics_per_module(&self) -> Option<f64> {
+        if self.modules_checked == 0 {
+            return None;
+        }
+        Some(self.diagnostics as f64 / self.modules_checked as f64)
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            ("elp_eqwalizer_queries_total", self.queries),
+            ("elp_disk_cache_hits_total", self.cache_hits),
+            ("elp_disk_cache_misses_total", self.cache_misses),
+            ("elp_parse_failures_total", self.parse_failures),
+            ("elp_parse_errors_total", self.parse_errors),
+            (
+                "elp_parse_server_restarts_total",
+                self.parse_server_restarts,
+            ),
+            ("elp_eqwalizer_diagnostics_total", self.diagnostics),
+            ("elp_eqwalizer_modules_checked_total", self.modules_checked),
+        ];
+        for (name, value) in counters {
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+        let histograms = [
+            ("elp_parse_duration_seconds", &self.parse_latency),
+            ("elp_eqwalizer_check_duration_seconds", &self.check_latency),
+        ];
+        for (name, histogram) in histograms {
+            let _ = writeln!(out, "# TYPE {} histogram", name);
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS_MS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let le = *bound as f64 / 1000.0;
+                let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative);
+            }
+            // From the buckets rather than `count`, which samples recorded
+            // while taking the snapshot may have reached already
+            let total: u64 = histogram.buckets.iter().sum();
+            let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, total);
+            let _ = writeln!(out, "{}_sum {}", name, histogram.sum.as_secs_f64());
+            let _ = writeln!(out, "{}_count {}", name, total);
+        }
+        out
+    }
+}
+
+/// Where the metrics are recorded, shared by a database and its snapshots.
+#[derive(Debug, Default)]
+pub(crate) struct MetricsRecorder {
+    queries: AtomicU64,
+    parse_failures: AtomicU64,
+    parse_errors: AtomicU64,
+    parse_latency: AtomicHistogram,
+    check_latency: AtomicHistogram,
+    diagnostics: AtomicU64,
+    modules_checked: AtomicU64,
+}
+
+impl MetricsRecorder {
+    pub(crate) fn record_query(&self) {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Times a request to the parse servers, retries included.
+    pub(crate) fn record_parse(&self, duration: Duration) {
+        self.parse_latency.record(duration);
+    }
+
+    /// Counts a request a parse server failed to answer.
+    pub(crate) fn record_parse_failure(&self) {
+        self.parse_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts an answer of a parse server, `ok` unless with errors of the
+    /// module text.
+    pub(crate) fn record_parse_answer(&self, ok: bool) {
+        if !ok {
+            self.parse_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn record_check(&self, duration: Duration) {
+        self.check_latency.record(duration);
+    }
+
+    /// Counts `modules` checked by eqWAlizer, with `diagnostics` between
+    /// them.
+    pub(crate) fn record_diagnostics(&self, modules: usize, diagnostics: usize) {
+        self.modules_checked
+            .fetch_add(modules as u64, Ordering::Relaxed);
+        self.diagnostics
+            .fetch_add(diagnostics as u64, Ordering::Relaxed);
+    }
+
+    /// The metrics recorded so far, the counters kept elsewhere aside.
+    pub(crate) fn snapshot(&self) -> Metrics {
+        Metrics {
+            queries: self.queries.load(Ordering::Relaxed),
+            parse_failures: self.parse_failures.load(Ordering::Relaxed),
+            parse_errors: self.parse_errors.load(Ordering::Relaxed),
+            parse_latency: self.parse_latency.snapshot(),
+            check_latency: self.check_latency.snapshot(),
+            diagnostics: self.diagnostics.load(Ordering::Relaxed),
+            modules_checked: self.modules_checked.load(Ordering::Relaxed),
+            ..Metrics::default()
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct AtomicHistogram {
+    buckets: [AtomicU64; BUCKETS],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl AtomicHistogram {
+    fn record(&self, duration: Duration) {
+        let millis = duration.as_millis();
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&bound| millis <= bound as u128)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// The samples recorded so far. Samples recorded meanwhile may be
+    /// counted in `count` and not in `buckets`, or the other way around.
+    fn snapshot(&self) -> Histogram {
+        let mut buckets = [0; BUCKETS];
+        for (bucket, count) in buckets.iter_mut().zip(&self.buckets) {
+            *bucket = count.load(Ordering::Relaxed);
+        }
+        Histogram {
+            buckets,
+            count: self.count.load(Ordering::Relaxed),
+            sum: Duration::from_micros(self.sum_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
@@ -0,0 +1,243 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Diagnostics for the exported functions without a spec, see
+//! `MissingSpecPolicy`.
+//!
+//! eqWAlizer infers no types for a function without a spec, to gradual
+//! mode all of them are `dynamic()`. The spec suggested is thus inferred
+//! from the clauses alone: an argument matched by literals or constructors
+//! only gets the union of their types, and so does the result when all the
+//! clauses end with one, `dynamic()` standing for everything else. The
+//! checks of the callers then only get stricter where the clauses would
+//! fail to match anyway. A spec is only suggested if the module checks
+//! with it, see `keep_checked_fixes`.
+
+use eetf::Term;
+use elp_base_db::FileId;
+use elp_base_db::ProjectId;
+use elp_eqwalizer::EqwalizerDiagnostic;
+use elp_eqwalizer::EqwalizerDiagnostics;
+use elp_eqwalizer::MissingSpecPolicy;
+use elp_eqwalizer::TextEdit;
+use elp_parse_server::Format;
+use fxhash::FxHashSet;
+use text_size::TextRange;
+use text_size::TextSize;
+
+use crate::etf;
+use crate::fixes;
+use crate::fixes::FixSelector;
+use crate::forms;
+use crate::forms::AbstractForm;
+use crate::reveal;
+use crate::reveal::Arity;
+use crate::EqwalizerDatabase;
+
+const DYNAMIC: &str = "dynamic()";
+
+/// Diagnostics under `policy` for the exported functions without a spec of
+/// the module in `file_id`, at their names, each with a fix adding the
+/// inferred spec before the function if the module checks with it, see
+/// `keep_checked_fixes`.
+pub(crate) fn missing_specs(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_id: FileId,
+    policy: MissingSpecPolicy,
+) -> Vec<EqwalizerDiagnostic> {
+    if policy == MissingSpecPolicy::Ignore {
+        return vec![];
+    }
+    let forms = match db
+        .module_ast(file_id, Format::OffsetEtf)
+        .ok()
+        .and_then(|ast| etf::decode_forms(&ast))
+    {
+        Some(forms) => forms,
+        None => return vec![],
+    };
+    let exports: FxHashSet<(String, Arity)> =
+        reveal::exported_functions(&forms).into_iter().collect();
+    let forms: Vec<AbstractForm> = fixes::own_forms(&forms)
+        .into_iter()
+        .filter_map(forms::decode_form)
+        .collect();
+    let specs = forms::specified_functions(&forms);
+    let mut functions = Vec::new();
+    let mut diagnostics = Vec::new();
+    for form in &forms {
+        match form {
+            AbstractForm::Function {
+                name,
+                arity,
+                range,
+                clauses,
+            } if !specs.contains(&(name.as_str(), *arity))
+                && exports.contains(&(name.clone(), *arity)) =>
+            {
+                let spec = TextEdit {
+                    range: TextRange::empty(range.start()),
+                    replacement: format!("{}\n", inferred_spec(name, *arity, clauses)),
+                };
+                let diagnostic = EqwalizerDiagnostic::missing_spec(
+                    policy,
+                    &format!("{}/{}", name, arity),
+                    TextRange::at(range.start(), TextSize::of(name.as_str())),
+                    spec,
+                );
+                if let Some(diagnostic) = diagnostic {
+                    functions.push(*range);
+                    diagnostics.push(diagnostic);
+                }
+            }
+            _ => {}
+        }
+    }
+    if !diagnostics.is_empty() {
+        keep_checked_fixes(db, project_id, file_id, &functions, &mut diagnostics);
+    }
+    diagnostics
+}
+
+/// Drops the fixes of `diagnostics` whose spec doesn't check, `functions`
+/// being the ranges of the functions they add a spec to: the module is
+/// checked once with all the specs added, and a spec is kept if eqWAlizer
+/// reports nothing in it or in its function, e.g. no clause returning
+/// something else than the spec says. All the fixes are dropped if the
+/// module can't be checked with the specs, e.g. doesn't parse.
+fn keep_checked_fixes(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_id: FileId,
+    functions: &[TextRange],
+    diagnostics: &mut [EqwalizerDiagnostic],
+) {
+    let reported = checked_with_fixes(db, project_id, file_id, diagnostics);
+    // Specs are inserted in the order of the functions, each shifting the
+    // ones after it
+    let mut shift = TextSize::from(0);
+    for (diagnostic, function) in diagnostics.iter_mut().zip(functions) {
+        let inserted = diagnostic
+            .fixes
+            .iter()
+            .map(|edit| TextSize::of(edit.replacement.as_str()))
+            .sum::<TextSize>();
+        let span = TextRange::new(function.start() + shift, function.end() + shift + inserted);
+        shift += inserted;
+        let checks = match &reported {
+            Some(reported) => !reported
+                .iter()
+                .any(|d| span.contains(d.range.start()) || d.range.contains(span.start())),
+            None => false,
+        };
+        if !checks {
+            diagnostic.fixes.clear();
+        }
+    }
+}
+
+/// What eqWAlizer reports for the module in `file_id` with the fixes of
+/// `diagnostics` applied, at offsets of the new text. `None` if it can't
+/// be checked.
+fn checked_with_fixes(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_id: FileId,
+    diagnostics: &[EqwalizerDiagnostic],
+) -> Option<Vec<EqwalizerDiagnostic>> {
+    let text = fixes::apply_fixes(&db.file_text(file_id), diagnostics, &FixSelector::All).ok()?;
+    // Nothing would be reported in a fixture project
+    db.project_data(project_id).build_info_path.as_ref()?;
+    match db.typecheck_text(project_id, file_id, &text) {
+        Ok(EqwalizerDiagnostics::Diagnostics(mut by_module)) => {
+            let module_index = db.module_index(project_id);
+            let module = module_index.module_for_file(file_id)?;
+            Some(by_module.remove(module.as_str()).unwrap_or_default())
+        }
+        Ok(EqwalizerDiagnostics::NoAst { .. }) => None,
+        Err(errors) => {
+            log::warn!(
+                "Checking the inferred specs failed: {} parse error(s)",
+                errors.len()
+            );
+            None
+        }
+    }
+}
+
+/// `-spec Name(Args) -> Result.` for the function with `clauses`.
+fn inferred_spec(name: &str, arity: Arity, clauses: &[Term]) -> String {
+    let clauses: Vec<(&[Term], Option<&Term>)> = clauses
+        .iter()
+        .filter_map(|clause| match etf::tuple(clause)? {
+            [tag, _anno, patterns, _guards, body] if etf::atom(tag) == Some("clause") => {
+                Some((etf::list(patterns)?, etf::list(body)?.last()))
+            }
+            _ => None,
+        })
+        .collect();
+    let args: Vec<String> = (0..arity as usize)
+        .map(|i| union(clauses.iter().map(|(patterns, _)| patterns.get(i))))
+        .collect();
+    let result = union(clauses.iter().map(|(_, last)| *last));
+    format!(
+        "-spec {}({}) -> {}.",
+        reveal::quote_atom(name),
+        args.join(", "),
+        result
+    )
+}
+
+/// The union of the types of `terms`, `dynamic()` if one of them has none.
+fn union<'a>(terms: impl Iterator<Item = Option<&'a Term>>) -> String {
+    let mut types: Vec<String> = Vec::new();
+    for term in terms {
+        match term.and_then(literal_type) {
+            Some(ty) if !types.contains(&ty) => types.push(ty),
+            Some(_) => {}
+            None => return DYNAMIC.to_string(),
+        }
+    }
+    if types.is_empty() {
+        DYNAMIC.to_string()
+    } else {
+        types.join(" | ")
+    }
+}
+
+/// The type of a pattern or expression that is a literal or a constructor,
+/// `None` for the others, e.g. variables and calls.
+fn literal_type(term: &Term) -> Option<String> {
+    let ty = match etf::tuple(term)? {
+        [tag, _anno, value] if etf::atom(tag) == Some("atom") => {
+            reveal::quote_atom(etf::atom(value)?)
+        }
+        [tag, _anno, _value] if etf::atom(tag) == Some("integer") => "integer()".to_string(),
+        [tag, _anno, _value] if etf::atom(tag) == Some("char") => "char()".to_string(),
+        [tag, _anno, _value] if etf::atom(tag) == Some("float") => "float()".to_string(),
+        [tag, _anno, _value] if etf::atom(tag) == Some("string") => "string()".to_string(),
+        [tag, _anno] if etf::atom(tag) == Some("nil") => "list()".to_string(),
+        [tag, _anno, _head, _tail] if etf::atom(tag) == Some("cons") => "list()".to_string(),
+        [tag, _anno, _elements] if etf::atom(tag) == Some("bin") => "binary()".to_string(),
+        [tag, _anno, _assocs] if etf::atom(tag) == Some("map") => "map()".to_string(),
+        [tag, _anno, _elements] if etf::atom(tag) == Some("tuple") => "tuple()".to_string(),
+        [tag, _anno, name, _fields] if etf::atom(tag) == Some("record") => {
+            format!("#{}{{}}", reveal::quote_atom(etf::atom(name)?))
+        }
+        [tag, _anno, op, operand]
+            if etf::atom(tag) == Some("op") && matches!(etf::atom(op), Some("-") | Some("+")) =>
+        {
+            return literal_type(operand).filter(|ty| ty == "integer()" || ty == "float()");
+        }
+        // `P1 = P2` matches what both match
+        [tag, _anno, left, right] if etf::atom(tag) == Some("match") => {
+            return literal_type(left).or_else(|| literal_type(right));
+        }
+        _ => return None,
+    };
+    Some(ty)
+}
@@ -0,0 +1,699 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use elp_base_db::AbsPathBuf;
+use elp_base_db::FileId;
+use elp_base_db::FileSource;
+use elp_base_db::ModuleIndex;
+use elp_base_db::ModuleName;
+use elp_base_db::ProjectId;
+use elp_base_db::SourceDatabase;
+use elp_base_db::VfsPath;
+use elp_parse_server::Format;
+use elp_project_model::AppType;
+use fxhash::FxHashMap;
+use fxhash::FxHashSet;
+use salsa::Database;
+use text_size::TextRange;
+
+use crate::etf;
+use crate::fixes;
+use crate::forms;
+use crate::forms::AbstractForm;
+use crate::reveal;
+use crate::reveal::Arity;
+use crate::reveal::FunctionName;
+use crate::ErlAstDatabase;
+
+/// What a module refers to outside of itself, as seen in its abstract forms.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ModuleDependencies {
+    /// Modules used in behaviours, imports, remote calls, `fun M:F/A`
+    /// references and remote types, sorted.
+    pub modules: Vec<ModuleName>,
+    /// Headers included directly or transitively, sorted.
+    pub headers: Vec<AbsPathBuf>,
+}
+
+#[salsa::query_group(ModuleDepsDatabaseStorage)]
+pub trait ModuleDepsDatabase: ErlAstDatabase {
+    /// Dependencies of the module defined in `file_id`. Forms coming from
+    /// included headers are taken into account. A module that fails to
+    /// parse has no dependencies.
+    fn module_dependencies(&self, file_id: FileId) -> Arc<ModuleDependencies>;
+
+    /// For every module of the project, the modules of the project that
+    /// directly depend on it.
+    fn reverse_dependencies(
+        &self,
+        project_id: ProjectId,
+    ) -> Arc<FxHashMap<ModuleName, Vec<ModuleName>>>;
+}
+
+fn module_dependencies(db: &dyn ModuleDepsDatabase, file_id: FileId) -> Arc<ModuleDependencies> {
+    let forms = match db.module_ast(file_id, Format::OffsetEtf) {
+        Ok(ast) => etf::decode_forms(&ast).unwrap_or_default(),
+        Err(_) => return Default::default(),
+    };
+
+    let mut module = None;
+    let mut own_path = None;
+    let mut modules = BTreeSet::new();
+    let mut headers = BTreeSet::new();
+    for form in &forms {
+        if let Some([tag, _anno, name, value]) = etf::tuple(form) {
+            if etf::atom(tag) == Some("attribute") {
+                match etf::atom(name) {
+                    Some("module") => module = etf::atom(value),
+                    Some("behaviour") | Some("behavior") => {
+                        modules.extend(etf::atom(value));
+                    }
+                    Some("import") => {
+                        if let Some([imported, _funs]) = etf::tuple(value) {
+                            modules.extend(etf::atom(imported));
+                        }
+                    }
+                    Some("file") => {
+                        let path = etf::tuple(value)
+                            .and_then(|value| etf::string(value.first()?))
+                            .and_then(|path| AbsPathBuf::try_from(PathBuf::from(path)).ok())
+                            .map(|path| path.normalize());
+                        if let Some(path) = path {
+                            // The first file attribute is the module itself
+                            if own_path.is_none() {
+                                own_path = Some(path);
+                            } else if own_path.as_ref() != Some(&path) {
+                                headers.insert(path);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        etf::walk(form, &mut |term| {
+            if let Some(dep) = referenced_module(term) {
+                modules.insert(dep);
+            }
+        });
+    }
+    if let Some(module) = module {
+        modules.remove(module);
+    }
+
+    Arc::new(ModuleDependencies {
+        modules: modules.into_iter().map(ModuleName::new).collect(),
+        headers: headers.into_iter().collect(),
+    })
+}
+
+/// The module referred to by a remote call, a `fun M:F/A` or a remote type.
+fn referenced_module(term: &eetf::Term) -> Option<&str> {
+    match etf::tuple(term)? {
+        [tag, _anno, callee, _args] if etf::atom(tag) == Some("call") => {
+            match etf::tuple(callee)? {
+                [tag, _anno, module, _fun] if etf::atom(tag) == Some("remote") => {
+                    etf::atom_literal(module)
+                }
+                _ => None,
+            }
+        }
+        [tag, _anno, function] if etf::atom(tag) == Some("fun") => match etf::tuple(function)? {
+            [tag, module, _fun, _arity] if etf::atom(tag) == Some("function") => {
+                etf::atom_literal(module)
+            }
+            _ => None,
+        },
+        [tag, _anno, remote] if etf::atom(tag) == Some("remote_type") => match etf::list(remote)? {
+            [module, _type, _args] => etf::atom_literal(module),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn reverse_dependencies(
+    db: &dyn ModuleDepsDatabase,
+    project_id: ProjectId,
+) -> Arc<FxHashMap<ModuleName, Vec<ModuleName>>> {
+    let module_index = db.module_index(project_id);
+    let mut modules: Vec<_> = module_index.iter().collect();
+    modules.sort_by(|(m1, _, _), (m2, _, _)| m1.cmp(m2));
+
+    let mut reverse: FxHashMap<ModuleName, Vec<ModuleName>> = FxHashMap::default();
+    for (module, _source, file_id) in modules {
+        db.unwind_if_cancelled();
+        for dep in &db.module_dependencies(file_id).modules {
+            if module_index.file_for_module(dep).is_some() {
+                reverse.entry(dep.clone()).or_default().push(module.clone());
+            }
+        }
+    }
+    Arc::new(reverse)
+}
+
+/// The modules of the project whose eqWAlizer results may be affected by a
+/// change to any of `changed`: the changed modules themselves, the modules
+/// including a changed header, and everything transitively depending on
+/// those. Sorted, files outside the project are ignored.
+pub fn affected_files(
+    db: &dyn ModuleDepsDatabase,
+    project_id: ProjectId,
+    changed: &[FileId],
+) -> Vec<FileId> {
+    let module_index = db.module_index(project_id);
+    let mut worklist: Vec<ModuleName> = Vec::new();
+    let mut changed_headers: Vec<AbsPathBuf> = Vec::new();
+    for &file_id in changed {
+        match module_index.module_for_file(file_id) {
+            Some(module) => worklist.push(module.clone()),
+            None => {
+                let root = db.source_root(db.file_source_root(file_id));
+                if let Some(path) = root.path_for_file(&file_id).and_then(|p| p.as_path()) {
+                    changed_headers.push(path.normalize());
+                }
+            }
+        }
+    }
+    if !changed_headers.is_empty() {
+        worklist.extend(
+            including_modules(db, &module_index, &changed_headers)
+                .into_iter()
+                .map(|(module, _file_id)| module),
+        );
+    }
+
+    let reverse = db.reverse_dependencies(project_id);
+    let mut affected = BTreeSet::new();
+    while let Some(module) = worklist.pop() {
+        if let Some(file_id) = module_index.file_for_module(&module) {
+            if affected.insert(file_id) {
+                if let Some(dependents) = reverse.get(&module) {
+                    worklist.extend(dependents.iter().cloned());
+                }
+            }
+        }
+    }
+    affected.into_iter().collect()
+}
+
+/// The modules of `module_index` including any of `headers`, directly or
+/// through other headers.
+fn including_modules(
+    db: &dyn ModuleDepsDatabase,
+    module_index: &ModuleIndex,
+    headers: &[AbsPathBuf],
+) -> Vec<(ModuleName, FileId)> {
+    let mut modules = Vec::new();
+    for (module, _source, file_id) in module_index.iter() {
+        db.unwind_if_cancelled();
+        let deps = db.module_dependencies(file_id);
+        if deps.headers.iter().any(|h| headers.contains(h)) {
+            modules.push((module.clone(), file_id));
+        }
+    }
+    modules
+}
+
+/// The modules of the project including the header in `header_file`,
+/// directly or through other headers, sorted: those to check again after
+/// an edit of the header. Unlike `affected_files`, the modules depending
+/// on them aren't part of it, even though the types of the header they see
+/// through them may have changed too.
+pub fn header_users(db: &dyn ModuleDepsDatabase, header_file: FileId) -> Vec<FileId> {
+    let source_root_id = db.file_source_root(header_file);
+    let project_id = match db.app_data(source_root_id) {
+        Some(app_data) => app_data.project_id,
+        None => return vec![],
+    };
+    let path = match db
+        .source_root(source_root_id)
+        .path_for_file(&header_file)
+        .and_then(|p| p.as_path())
+    {
+        Some(path) => path.normalize(),
+        None => return vec![],
+    };
+    let module_index = db.module_index(project_id);
+    let mut users: Vec<FileId> = including_modules(db, &module_index, &[path])
+        .into_iter()
+        .map(|(_module, file_id)| file_id)
+        .collect();
+    users.sort();
+    users
+}
+
+/// Files of the headers the module in `file_id` includes, directly or
+/// through other headers, sorted. These are the headers of
+/// `ModuleDependencies`, as recorded by the preprocessor, which already
+/// resolved include guards and cycles. Headers outside of the source roots
+/// of the project, e.g. OTP's, have no file and are left out.
+pub fn include_closure(db: &dyn ModuleDepsDatabase, file_id: FileId) -> Vec<FileId> {
+    let project_id = match db.app_data(db.file_source_root(file_id)) {
+        Some(app_data) => app_data.project_id,
+        None => return vec![],
+    };
+    let source_roots: Vec<_> = db
+        .project_data(project_id)
+        .source_roots
+        .iter()
+        .map(|&source_root_id| db.source_root(source_root_id))
+        .collect();
+    let headers: BTreeSet<FileId> = db
+        .module_dependencies(file_id)
+        .headers
+        .iter()
+        .filter_map(|header| {
+            let path = VfsPath::from(header.clone());
+            source_roots
+                .iter()
+                .find_map(|root| root.file_for_path(&path))
+        })
+        .collect();
+    headers.into_iter().collect()
+}
+
+/// Result of `unused_exports`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnusedExports {
+    /// Sorted.
+    pub unused: Vec<(FileId, FunctionName, Arity)>,
+    /// Modules that don't parse, sorted. Their exports aren't reported,
+    /// and functions only they refer to may be reported in `unused`.
+    pub unknown: Vec<FileId>,
+}
+
+/// Exported functions of the modules of the project's own applications
+/// that no module of those applications refers to. References are
+/// remote calls, `fun M:F/A`, imports, and `apply/3` calls whose module
+/// and function are literals, including those of a module to itself.
+/// Callbacks of the behaviours a module implements are never reported,
+/// nor are the exports of test modules. Calls whose module or function
+/// are only known at runtime, e.g. `apply(M, F, Args)` with variables or
+/// `M:F()`, can't be tracked: the functions they call are reported too.
+/// Neither can the references of modules that don't parse, which are
+/// reported as unknown instead.
+pub fn unused_exports(db: &dyn ModuleDepsDatabase, project_id: ProjectId) -> UnusedExports {
+    let module_index = db.module_index(project_id);
+    let mut modules: Vec<_> = module_index
+        .iter()
+        .filter(|&(_, _, file_id)| db.file_app_type(file_id) == Some(AppType::App))
+        .collect();
+    modules.sort_by(|(m1, _, _), (m2, _, _)| m1.cmp(m2));
+
+    let mut used: FxHashSet<FunctionRef> = FxHashSet::default();
+    let mut candidates = Vec::new();
+    let mut unknown = Vec::new();
+    for (module, source, file_id) in modules {
+        db.unwind_if_cancelled();
+        let forms = match db
+            .module_ast(file_id, Format::OffsetEtf)
+            .ok()
+            .and_then(|ast| etf::decode_forms(&ast))
+        {
+            Some(forms) => forms,
+            None => {
+                unknown.push(file_id);
+                continue;
+            }
+        };
+        for form in &forms {
+            collect_references(form, &mut used);
+        }
+        if source == FileSource::Src {
+            candidates.push((module.as_str().to_string(), file_id, forms));
+        }
+    }
+
+    let mut unused = Vec::new();
+    for (module, file_id, forms) in candidates {
+        let callbacks = behaviour_callbacks(db, &module_index, &forms);
+        for (name, arity) in reveal::exported_functions(&forms) {
+            let is_used = used.contains(&(module.clone(), name.clone(), Some(arity)))
+                || used.contains(&(module.clone(), name.clone(), None));
+            if !is_used && !callbacks.contains(&(name.clone(), arity)) {
+                unused.push((file_id, name, arity));
+            }
+        }
+    }
+    unused.sort();
+    unused.dedup();
+    unknown.sort();
+    UnusedExports { unused, unknown }
+}
+
+/// A function of a module, `None` for any arity.
+type FunctionRef = (String, FunctionName, Option<Arity>);
+
+/// Adds the functions `form` refers to with a literal module and function.
+fn collect_references(form: &eetf::Term, used: &mut FxHashSet<FunctionRef>) {
+    if let Some([tag, _anno, name, value]) = etf::tuple(form) {
+        if etf::atom(tag) == Some("attribute") && etf::atom(name) == Some("import") {
+            if let Some([module, functions]) = etf::tuple(value) {
+                for function in etf::list(functions).unwrap_or_default() {
+                    if let (Some(module), Some([name, arity])) =
+                        (etf::atom(module), etf::tuple(function))
+                    {
+                        if let (Some(name), Some(arity)) = (etf::atom(name), etf::int(arity)) {
+                            let arity = Arity::try_from(arity).ok();
+                            used.insert((module.to_string(), name.to_string(), arity));
+                        }
+                    }
+                }
+            }
+            return;
+        }
+    }
+    etf::walk(form, &mut |term| {
+        if let Some(reference) = referenced_function(term) {
+            used.insert(reference);
+        }
+    });
+}
+
+/// The function referred to by a remote call, a `fun M:F/A` or an
+/// `apply(M, F, Args)`, when its module and function are literals. The
+/// arity of an `apply` is only known when `Args` is a literal list.
+fn referenced_function(term: &eetf::Term) -> Option<FunctionRef> {
+    match etf::tuple(term)? {
+        [tag, _anno, callee, args] if etf::atom(tag) == Some("call") => {
+            let args = etf::list(args)?;
+            let is_apply = match etf::tuple(callee)? {
+                [tag, _anno, module, fun] if etf::atom(tag) == Some("remote") => {
+                    let module = etf::atom_literal(module)?;
+                    let fun = etf::atom_literal(fun)?;
+                    if module != "erlang" || fun != "apply" {
+                        let arity = Arity::try_from(args.len()).ok();
+                        return Some((module.to_string(), fun.to_string(), arity));
+                    }
+                    true
+                }
+                [tag, _anno, fun] if etf::atom(tag) == Some("atom") => {
+                    etf::atom(fun) == Some("apply")
+                }
+                _ => false,
+            };
+            match args {
+                [module, fun, args] if is_apply => Some((
+                    etf::atom_literal(module)?.to_string(),
+                    etf::atom_literal(fun)?.to_string(),
+                    list_length(args),
+                )),
+                _ => None,
+            }
+        }
+        [tag, _anno, function] if etf::atom(tag) == Some("fun") => match etf::tuple(function)? {
+            [tag, module, fun, arity] if etf::atom(tag) == Some("function") => {
+                let arity = match etf::tuple(arity)? {
+                    [tag, _anno, arity] if etf::atom(tag) == Some("integer") => etf::int(arity)?,
+                    _ => return None,
+                };
+                Some((
+                    etf::atom_literal(module)?.to_string(),
+                    etf::atom_literal(fun)?.to_string(),
+                    Arity::try_from(arity).ok(),
+                ))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The length of a `{cons, _, H, T}` … `{nil, _}` literal list.
+fn list_length(mut list: &eetf::Term) -> Option<Arity> {
+    let mut length = 0;
+    loop {
+        match etf::tuple(list)? {
+            [tag, _anno] if etf::atom(tag) == Some("nil") => return Some(length),
+            [tag, _anno, _head, tail] if etf::atom(tag) == Some("cons") => {
+                length += 1;
+                list = tail;
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// The callbacks of the behaviours of the module with forms `forms`, which
+/// the behaviour module calls without it being visible in the sources.
+fn behaviour_callbacks(
+    db: &dyn ModuleDepsDatabase,
+    module_index: &ModuleIndex,
+    forms: &[eetf::Term],
+) -> FxHashSet<(FunctionName, Arity)> {
+    let mut callbacks = FxHashSet::default();
+    for form in forms {
+        let behaviour = match forms::decode_form(form) {
+            Some(AbstractForm::Attribute { name, value, .. })
+                if name == "behaviour" || name == "behavior" =>
+            {
+                match etf::atom(&value) {
+                    Some(behaviour) => behaviour.to_string(),
+                    None => continue,
+                }
+            }
+            _ => continue,
+        };
+        let file_id = match module_index.file_for_module(behaviour.as_str()) {
+            Some(file_id) => file_id,
+            None => continue,
+        };
+        let behaviour_forms = match db.module_ast(file_id, Format::OffsetEtf) {
+            Ok(ast) => etf::decode_forms(&ast).unwrap_or_default(),
+            Err(_) => continue,
+        };
+        for form in &behaviour_forms {
+            if let Some(AbstractForm::Spec {
+                module: None,
+                name,
+                arity,
+                callback: true,
+                ..
+            }) = forms::decode_form(form)
+            {
+                callbacks.insert((name, arity));
+            }
+        }
+    }
+    callbacks
+}
+
+/// A place in a module referring to a function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reference {
+    pub file_id: FileId,
+    /// The callee of a call (e.g. `m:f`), the whole `fun m:f/1`, or the
+    /// whole `apply(m, f, [X])`.
+    pub range: TextRange,
+}
+
+/// The calls to a function, see `function_callers`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FunctionCallers {
+    /// Sorted by file and position.
+    pub references: Vec<Reference>,
+    /// Calls with the right arity that may call the function, but whose
+    /// module or function is only known at runtime, e.g. `M:f(X)` or
+    /// `apply(M, F, [X])`. They aren't part of `references`.
+    pub dynamic_calls: usize,
+}
+
+/// The calls to `module:function/arity` in the modules of the project's
+/// own applications, resolved as the compiler does: local calls to the
+/// function from its module, local calls through an `-import`, local calls
+/// to auto-imported BIFs for `erlang` functions, remote calls, `fun`
+/// references, and `apply/3` with a literal module, function and argument
+/// list. Calls in included headers aren't taken into account.
+pub fn function_callers(
+    db: &dyn ModuleDepsDatabase,
+    project_id: ProjectId,
+    module: &str,
+    function: &str,
+    arity: Arity,
+) -> FunctionCallers {
+    let target = (module, function, arity);
+    let module_index = db.module_index(project_id);
+    let mut callers = FunctionCallers::default();
+    for (caller, _source, file_id) in module_index.iter() {
+        if db.file_app_type(file_id) != Some(AppType::App) {
+            continue;
+        }
+        db.unwind_if_cancelled();
+        let forms = match db.module_ast(file_id, Format::OffsetEtf) {
+            Ok(ast) => etf::decode_forms(&ast).unwrap_or_default(),
+            Err(_) => continue,
+        };
+        let scope = CallScope::new(caller.as_str(), &forms);
+        for form in fixes::own_forms(&forms) {
+            etf::walk(form, &mut |term| match scope.resolve(term) {
+                Some(Call::Static(callee, range)) if callee == target => {
+                    callers.references.push(Reference { file_id, range })
+                }
+                Some(Call::Dynamic(m, f, a))
+                    if m.map_or(true, |m| m == module)
+                        && f.map_or(true, |f| f == function)
+                        && a.map_or(true, |a| a == arity) =>
+                {
+                    callers.dynamic_calls += 1
+                }
+                _ => {}
+            });
+        }
+    }
+    callers
+        .references
+        .sort_by_key(|reference| (reference.file_id, reference.range.start()));
+    callers
+}
+
+/// A call, resolved to the function called.
+pub(crate) enum Call<'a> {
+    Static((&'a str, &'a str, Arity), TextRange),
+    /// The parts of the callee that are known.
+    Dynamic(Option<&'a str>, Option<&'a str>, Option<Arity>),
+}
+
+/// What local calls in a module resolve to.
+pub(crate) struct CallScope<'a> {
+    module: &'a str,
+    functions: FxHashSet<(&'a str, Arity)>,
+    imports: FxHashMap<(&'a str, Arity), &'a str>,
+}
+
+impl<'a> CallScope<'a> {
+    pub(crate) fn new(module: &'a str, forms: &'a [eetf::Term]) -> CallScope<'a> {
+        let mut functions = FxHashSet::default();
+        let mut imports = FxHashMap::default();
+        for form in forms {
+            match etf::tuple(form) {
+                Some([tag, _anno, name, arity, _clauses]) if etf::atom(tag) == Some("function") => {
+                    if let (Some(name), Some(arity)) = (etf::atom(name), int_arity(arity)) {
+                        functions.insert((name, arity));
+                    }
+                }
+                Some([tag, _anno, name, value])
+                    if etf::atom(tag) == Some("attribute") && etf::atom(name) == Some("import") =>
+                {
+                    if let Some([imported, imported_functions]) = etf::tuple(value) {
+                        for function in etf::list(imported_functions).unwrap_or_default() {
+                            if let (Some(imported), Some([name, arity])) =
+                                (etf::atom(imported), etf::tuple(function))
+                            {
+                                if let (Some(name), Some(arity)) =
+                                    (etf::atom(name), int_arity(arity))
+                                {
+                                    imports.insert((name, arity), imported);
+                                }
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        CallScope {
+            module,
+            functions,
+            imports,
+        }
+    }
+
+    /// The module of the local function `name/arity`.
+    fn local(&self, name: &'a str, arity: Arity) -> &'a str {
+        if self.functions.contains(&(name, arity)) {
+            self.module
+        } else {
+            self.imports
+                .get(&(name, arity))
+                .copied()
+                .unwrap_or("erlang")
+        }
+    }
+
+    /// The function `term` calls, if it is a call or a `fun`: local
+    /// functions first, then imported ones, then the BIFs of `erlang`.
+    /// `erlang:apply/3` calls resolve to the function applied.
+    pub(crate) fn resolve(&self, term: &'a eetf::Term) -> Option<Call<'a>> {
+        match etf::tuple(term)? {
+            [tag, call_anno, callee, args] if etf::atom(tag) == Some("call") => {
+                let args = etf::list(args)?;
+                let arity = Arity::try_from(args.len()).ok()?;
+                let (module, name, range) = match etf::tuple(callee)? {
+                    [tag, anno, module, name] if etf::atom(tag) == Some("remote") => (
+                        etf::atom_literal(module),
+                        etf::atom_literal(name),
+                        etf::anno_range(anno)?,
+                    ),
+                    [tag, anno, name] if etf::atom(tag) == Some("atom") => {
+                        let name = etf::atom(name)?;
+                        (
+                            Some(self.local(name, arity)),
+                            Some(name),
+                            etf::anno_range(anno)?,
+                        )
+                    }
+                    // A call to a fun
+                    _ => return None,
+                };
+                match (module, name, args) {
+                    (Some("erlang"), Some("apply"), [module, name, args]) => {
+                        let range = etf::anno_range(call_anno)?;
+                        match (
+                            etf::atom_literal(module),
+                            etf::atom_literal(name),
+                            list_length(args),
+                        ) {
+                            (Some(module), Some(name), Some(arity)) => {
+                                Some(Call::Static((module, name, arity), range))
+                            }
+                            (module, name, arity) => Some(Call::Dynamic(module, name, arity)),
+                        }
+                    }
+                    (Some(module), Some(name), _) => {
+                        Some(Call::Static((module, name, arity), range))
+                    }
+                    (module, name, _) => Some(Call::Dynamic(module, name, Some(arity))),
+                }
+            }
+            [tag, anno, function] if etf::atom(tag) == Some("fun") => {
+                let range = etf::anno_range(anno)?;
+                match etf::tuple(function)? {
+                    [tag, name, arity] if etf::atom(tag) == Some("function") => {
+                        let name = etf::atom(name)?;
+                        let arity = int_arity(arity)?;
+                        Some(Call::Static((self.local(name, arity), name, arity), range))
+                    }
+                    [tag, module, name, arity] if etf::atom(tag) == Some("function") => {
+                        let arity = match etf::tuple(arity)? {
+                            [tag, _anno, arity] if etf::atom(tag) == Some("integer") => {
+                                int_arity(arity)
+                            }
+                            _ => None,
+                        };
+                        match (etf::atom_literal(module), etf::atom_literal(name), arity) {
+                            (Some(module), Some(name), Some(arity)) => {
+                                Some(Call::Static((module, name, arity), range))
+                            }
+                            (module, name, arity) => Some(Call::Dynamic(module, name, arity)),
+                        }
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+fn int_arity(term: &eetf::Term) -> Option<Arity> {
+    Arity::try_from(etf::int(term)?).ok()
+}
@@ -0,0 +1,146 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Narrowing a diagnostic down to the sub-expression causing it, for
+//! errors reported on long expressions.
+//!
+//! A sub-expression is to blame when making it `dynamic()` makes the
+//! diagnostic go away: each sub-expression is wrapped in a call to
+//! `eqwalizer:dynamic_cast/1` in a copy of the module text, which is then
+//! type-checked on the side, the database being left untouched. The
+//! search then goes on in the sub-expressions of the one to blame, until
+//! none of them is.
+
+use elp_base_db::FileId;
+use elp_base_db::FileLoader;
+use elp_base_db::SourceDatabase;
+use elp_eqwalizer::EqwalizerDiagnostic;
+use elp_eqwalizer::EqwalizerDiagnostics;
+use elp_parse_server::Format;
+use text_size::TextRange;
+use text_size::TextSize;
+
+use crate::eqwalizer::typecheck_text;
+use crate::etf;
+use crate::reveal;
+use crate::EqwalizerDatabase;
+use crate::ErlAstDatabase;
+use crate::RootDatabase;
+
+/// Most modules type-checked to narrow a single diagnostic.
+pub const MAX_NARROWING_CHECKS: usize = 16;
+
+const CAST_PREFIX: &str = "eqwalizer:dynamic_cast(";
+const CAST_SUFFIX: &str = ")";
+
+/// The smallest sub-expression of the range of the diagnostic of the module
+/// in `file_id` whose fingerprint is `diag_id` causing it, see
+/// `EqwalizerDiagnostic::fingerprint`. At most `MAX_NARROWING_CHECKS`
+/// variants of the module are checked, the smallest one found so far is
+/// the result then. The range of the diagnostic itself if no
+/// sub-expression is to blame, `None` if the module, in non-strict mode,
+/// has no such diagnostic.
+pub fn narrow_diagnostic(db: &RootDatabase, file_id: FileId, diag_id: u64) -> Option<TextRange> {
+    let project_id = db.app_data(db.file_source_root(file_id))?.project_id;
+    let module = db
+        .module_index(project_id)
+        .module_for_file(file_id)?
+        .as_str()
+        .to_string();
+    let diagnostics = db.eqwalizer_diagnostics(project_id, vec![file_id], Format::OffsetEtf, false);
+    let diagnostic = match &*diagnostics {
+        EqwalizerDiagnostics::Diagnostics(diagnostics) => diagnostics
+            .get(&module)?
+            .iter()
+            .find(|d| d.fingerprint(&module) == diag_id)?
+            .clone(),
+        EqwalizerDiagnostics::NoAst { .. } => return None,
+    };
+    let ast = db.module_ast(file_id, Format::OffsetEtf).ok()?;
+    let forms = etf::decode_forms(&ast)?;
+    let text = db.file_text(file_id);
+
+    let mut range = diagnostic.range;
+    let mut checks = 0;
+    'narrowing: while checks < MAX_NARROWING_CHECKS {
+        for child in sub_expressions(&forms, range) {
+            if checks == MAX_NARROWING_CHECKS {
+                break 'narrowing;
+            }
+            db.unwind_if_cancelled();
+            checks += 1;
+            if !still_reported(db, file_id, &text, &diagnostic, child) {
+                range = child;
+                continue 'narrowing;
+            }
+        }
+        break;
+    }
+    Some(range)
+}
+
+/// The outermost expressions strictly within `range`, in order.
+fn sub_expressions(forms: &[eetf::Term], range: TextRange) -> Vec<TextRange> {
+    let expressions = reveal::body_expressions(forms, &|r| {
+        r.contains_range(range) || range.contains_range(r)
+    });
+    let inner: Vec<TextRange> = expressions
+        .into_iter()
+        .filter(|&r| r != range && range.contains_range(r))
+        .collect();
+    let mut children: Vec<TextRange> = inner
+        .iter()
+        .copied()
+        .filter(|&r| !inner.iter().any(|&o| o != r && o.contains_range(r)))
+        .collect();
+    children.sort_by_key(|r| r.start());
+    children.dedup();
+    children
+}
+
+/// Whether `diagnostic` is still reported with `child`, within its range,
+/// made `dynamic()`. It is when the variant doesn't parse, e.g. when
+/// `child` isn't an expression after all.
+fn still_reported(
+    db: &RootDatabase,
+    file_id: FileId,
+    text: &str,
+    diagnostic: &EqwalizerDiagnostic,
+    child: TextRange,
+) -> bool {
+    let start: usize = child.start().into();
+    let end: usize = child.end().into();
+    if !text.is_char_boundary(start) || !text.is_char_boundary(end) {
+        return true;
+    }
+    let project_id = match db.app_data(db.file_source_root(file_id)) {
+        Some(app_data) => app_data.project_id,
+        None => return true,
+    };
+    let variant = format!(
+        "{}{}{}{}{}",
+        &text[..start],
+        CAST_PREFIX,
+        &text[start..end],
+        CAST_SUFFIX,
+        &text[end..]
+    );
+    // The cast is within the range of the diagnostic, which only moves its
+    // end
+    let shift = TextSize::of(CAST_PREFIX) + TextSize::of(CAST_SUFFIX);
+    let shifted = TextRange::new(diagnostic.range.start(), diagnostic.range.end() + shift);
+    let reported =
+        |d: &EqwalizerDiagnostic| d.code == diagnostic.code && shifted.contains(d.range.start());
+    // Tolerate errors, so that other errors don't stop the function from
+    // being checked
+    let env = [("EQWALIZER_TOLERATE_ERRORS", "true")];
+    match typecheck_text(db, project_id, file_id, &variant, false, &env) {
+        Ok(EqwalizerDiagnostics::Diagnostics(diagnostics)) => {
+            diagnostics.values().flatten().any(reported)
+        }
+        _ => true,
+    }
+}
@@ -0,0 +1,25 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Diagnostics about code relying on the definition of opaque types of
+//! other modules.
+//!
+//! Besides the `opacity_violation` code of the error reference, eqWAlizer
+//! reports such code as `expected_subtype` errors, a value of the opaque
+//! type being given where its representation, e.g. a map, is expected. It
+//! reports the opaque types relied on as `opaqueTypes`, see
+//! `EqwalizerDiagnostic::opaque_types`.
+
+use elp_eqwalizer::EqwalizerDiagnostic;
+
+const OPACITY_VIOLATION: &str = "opacity_violation";
+
+/// Whether `diagnostic` is about code relying on the definition of an
+/// opaque type of another module: either an `opacity_violation`, or an
+/// error eqWAlizer reports opaque types relied on for.
+pub fn is_opacity_violation(diagnostic: &EqwalizerDiagnostic) -> bool {
+    diagnostic.code == OPACITY_VIOLATION || !diagnostic.opaque_types.is_empty()
+}
@@ -0,0 +1,108 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Related locations of eqWAlizer diagnostics.
+//!
+//! eqWAlizer reports the types of other modules a diagnostic is about,
+//! see `EqwalizerDiagnostic::remote_types`, but not where they are
+//! defined. They are looked up in the forms of their module.
+
+use eetf::Term;
+use elp_base_db::FileId;
+use elp_base_db::ModuleIndex;
+use elp_base_db::ProjectId;
+use elp_eqwalizer::EqwalizerDiagnostics;
+use elp_eqwalizer::RelatedLocation;
+use elp_eqwalizer::RemoteTypeId;
+use elp_parse_server::Format;
+use fxhash::FxHashMap;
+
+use crate::etf;
+use crate::fixes;
+use crate::EqwalizerDatabase;
+
+const OPAQUE_DEFINITION: &str = "opaque type ";
+const TYPE_DEFINITION: &str = "type alias ";
+
+/// Fills in `EqwalizerDiagnostic::related` with the definitions of the
+/// types of other modules of the project named in the diagnostics.
+pub(crate) fn add_related_locations(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    diags: &mut EqwalizerDiagnostics,
+) {
+    let diagnostics = match diags {
+        EqwalizerDiagnostics::Diagnostics(diagnostics) => diagnostics,
+        EqwalizerDiagnostics::NoAst { .. } => return,
+    };
+    let module_index = db.module_index(project_id);
+    let mut forms = FxHashMap::default();
+    for (module, module_diagnostics) in diagnostics.iter_mut() {
+        for diagnostic in module_diagnostics.iter_mut() {
+            let mut related: Vec<RelatedLocation> = Vec::new();
+            for remote in &diagnostic.remote_types {
+                if remote.module == module.as_str() {
+                    continue;
+                }
+                if let Some(location) = definition(db, &module_index, &mut forms, remote) {
+                    if !related.contains(&location) {
+                        related.push(location);
+                    }
+                }
+            }
+            diagnostic.related = related;
+        }
+    }
+}
+
+/// The definition of `remote`, if it is a type or opaque of a module of
+/// the project. Forms of modules are decoded once per call.
+fn definition(
+    db: &dyn EqwalizerDatabase,
+    module_index: &ModuleIndex,
+    forms: &mut FxHashMap<FileId, Vec<Term>>,
+    remote: &RemoteTypeId,
+) -> Option<RelatedLocation> {
+    let RemoteTypeId {
+        module,
+        name,
+        arity,
+    } = remote;
+    let arity = *arity as usize;
+    let file_id = module_index.file_for_module(module)?;
+    let forms = forms.entry(file_id).or_insert_with(|| {
+        db.module_ast(file_id, Format::OffsetEtf)
+            .ok()
+            .and_then(|ast| etf::decode_forms(&ast))
+            .unwrap_or_default()
+    });
+    fixes::own_forms(forms).into_iter().find_map(|form| {
+        let (kind, range) = match etf::tuple(form)? {
+            [tag, anno, attr, value] if etf::atom(tag) == Some("attribute") => {
+                match (etf::atom(attr)?, etf::tuple(value)?) {
+                    (kind @ ("type" | "opaque"), [n, _type, params])
+                        if etf::atom(n) == Some(name.as_str())
+                            && etf::list(params)?.len() == arity =>
+                    {
+                        (kind, etf::anno_range(anno)?)
+                    }
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        let kind = if kind == "opaque" {
+            OPAQUE_DEFINITION
+        } else {
+            TYPE_DEFINITION
+        };
+        Some(RelatedLocation {
+            file_id,
+            range,
+            message: format!("{}{}:{}/{} defined here", kind, module, name, arity),
+        })
+    })
+}
@@ -0,0 +1,1060 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Types of expressions, as inferred by eqWAlizer.
+//!
+//! eqWAlizer only reports types through `eqwalizer:reveal_type/1`, so the
+//! expression is wrapped in a call to it in a copy of the module text, which
+//! is then type-checked on the side. The database is left untouched.
+
+use std::sync::Arc;
+
+use eetf::Term;
+use elp_base_db::FileId;
+use elp_base_db::FileLoader;
+use elp_base_db::ProjectId;
+use elp_base_db::SourceDatabase;
+use elp_eqwalizer::EqwalizerDiagnostics;
+use elp_parse_server::Format;
+use elp_parse_server::ParseError;
+use fxhash::FxHashMap;
+use fxhash::FxHashSet;
+use salsa::Database;
+use text_size::TextRange;
+use text_size::TextSize;
+
+use crate::disk_cache;
+use crate::eqwalizer::typecheck_text;
+use crate::eqwalizer::EqwalizerLoader;
+use crate::erl_ast;
+use crate::etf;
+use crate::forms;
+use crate::forms::AbstractForm;
+use crate::forms::RecordField;
+use crate::module_deps::Call;
+use crate::module_deps::CallScope;
+use crate::EqwalizerDatabase;
+use crate::ErlAstDatabase;
+use crate::RootDatabase;
+
+pub(crate) const REVEAL_PREFIX: &str = "eqwalizer:reveal_type(";
+pub(crate) const REVEAL_SUFFIX: &str = ")";
+/// Function added to a copy of the module to reveal the types of its
+/// exported functions.
+const REVEAL_FUNCTION: &str = "'$elp_function_types'";
+/// Function added to a copy of the module to reveal the types of the
+/// fields of records.
+const REVEAL_RECORD_FUNCTION: &str = "'$elp_record_types'";
+/// Function added to a copy of the module to reveal the type of an
+/// expression.
+const REVEAL_EXPRESSION_FUNCTION: &str = "'$elp_expression_type'";
+/// Marker for functions eqWAlizer has no type for.
+pub const NO_TYPE: &str = "<error>";
+
+pub type FunctionName = String;
+pub type Arity = u32;
+pub type VarName = String;
+
+/// The type of an expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeInfo {
+    /// The type, as pretty-printed by eqWAlizer.
+    pub ty: String,
+    /// The expression the type applies to.
+    pub range: TextRange,
+}
+
+/// The type of the innermost expression at `offset`. `None` in comments and
+/// whitespace, outside of function bodies, or when eqWAlizer doesn't check
+/// the function (e.g. it has no spec).
+pub fn type_at_offset(db: &RootDatabase, file_id: FileId, offset: TextSize) -> Option<TypeInfo> {
+    let text = db.file_text(file_id);
+    let start: usize = offset.into();
+    if !text.is_char_boundary(start)
+        || text[start..]
+            .chars()
+            .next()
+            .map_or(true, char::is_whitespace)
+        || in_comment(&text, start)
+    {
+        return None;
+    }
+    let ast = db.module_ast(file_id, Format::OffsetEtf).ok()?;
+    let forms = etf::decode_forms(&ast)?;
+    let project_id = db.app_data(db.file_source_root(file_id))?.project_id;
+
+    for range in enclosing_expressions(&forms, offset) {
+        db.unwind_if_cancelled();
+        match reveal_types(db, project_id, file_id, &text, &[range]) {
+            // Not an expression after all, try the enclosing one
+            None => continue,
+            Some(mut types) => return types.pop().flatten(),
+        }
+    }
+    None
+}
+
+/// A call and the types eqWAlizer checks it with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallTypeInfo {
+    /// The function called, as `module:name/arity`: local calls are
+    /// resolved to the functions of the module, those it imports or the
+    /// BIFs of `erlang`, see `module_deps::CallScope`. The text of the
+    /// callee when it can't be resolved, e.g. a variable.
+    pub callee: String,
+    /// The whole call.
+    pub range: TextRange,
+    /// Types of the arguments, in order. `None` for those eqWAlizer gives
+    /// no type.
+    pub args: Vec<Option<TypeInfo>>,
+    /// Type of the result, `None` if eqWAlizer gives it no type.
+    pub result: Option<String>,
+}
+
+/// The innermost call at `offset` in a function body, with the types of its
+/// arguments and result. `None` if there is no such call, e.g. within a
+/// guard, or if the module doesn't parse. The arguments and the call are
+/// revealed in two runs of eqWAlizer on copies of the module, the
+/// arguments together.
+pub fn call_result_type(
+    db: &RootDatabase,
+    file_id: FileId,
+    offset: TextSize,
+) -> Option<CallTypeInfo> {
+    let ast = db.module_ast(file_id, Format::OffsetEtf).ok()?;
+    let forms = etf::decode_forms(&ast)?;
+    let (range, call, callee, args) = enclosing_call(&forms, offset)?;
+    let text = db.file_text(file_id);
+    let project_id = db.app_data(db.file_source_root(file_id))?.project_id;
+
+    let module_index = db.module_index(project_id);
+    let module = module_index.module_for_file(file_id)?;
+    let callee = match CallScope::new(module.as_str(), &forms).resolve(call) {
+        Some(Call::Static((module, name, arity), _)) => {
+            format!("{}:{}/{}", quote_atom(module), quote_atom(name), arity)
+        }
+        _ => source_text(&text, callee)?,
+    };
+    let arg_ranges: Vec<TextRange> = args
+        .iter()
+        .filter_map(|arg| etf::anno_range(etf::tuple(arg)?.get(1)?))
+        .collect();
+    let args = if arg_ranges.is_empty() {
+        vec![]
+    } else {
+        db.unwind_if_cancelled();
+        reveal_types(db, project_id, file_id, &text, &arg_ranges)
+            .unwrap_or_else(|| vec![None; arg_ranges.len()])
+    };
+    db.unwind_if_cancelled();
+    let result = reveal_types(db, project_id, file_id, &text, &[range])
+        .and_then(|mut types| types.pop().flatten())
+        .map(|info| info.ty);
+    Some(CallTypeInfo {
+        callee,
+        range,
+        args,
+        result,
+    })
+}
+
+/// The smallest call containing `offset` in the functions of `forms`, with
+/// its callee and arguments.
+fn enclosing_call(forms: &[Term], offset: TextSize) -> Option<(TextRange, &Term, &Term, &[Term])> {
+    let mut innermost: Option<(TextRange, &Term, &Term, &[Term])> = None;
+    for form in forms {
+        match etf::tuple(form) {
+            Some([tag, ..]) if etf::atom(tag) == Some("function") => {}
+            _ => continue,
+        }
+        etf::walk(form, &mut |term| {
+            if let Some([tag, anno, callee, args]) = etf::tuple(term) {
+                if etf::atom(tag) != Some("call") {
+                    return;
+                }
+                if let (Some(range), Some(args)) = (etf::anno_range(anno), etf::list(args)) {
+                    let smaller = innermost.map_or(true, |(r, ..)| r.contains_range(range));
+                    if range.contains(offset) && smaller {
+                        innermost = Some((range, term, callee, args));
+                    }
+                }
+            }
+        });
+    }
+    innermost
+}
+
+fn source_text(text: &str, expr: &Term) -> Option<String> {
+    let range = etf::anno_range(etf::tuple(expr)?.get(1)?)?;
+    text.get(std::ops::Range::<usize>::from(range))
+        .map(|source| source.to_string())
+}
+
+/// Types of the given non-overlapping expressions, in a single run of
+/// eqWAlizer. `None` if the text doesn't parse with the reveals inserted,
+/// which happens when a range isn't an expression.
+pub(crate) fn reveal_types(
+    db: &RootDatabase,
+    project_id: ProjectId,
+    file_id: FileId,
+    text: &str,
+    ranges: &[TextRange],
+) -> Option<Vec<Option<TypeInfo>>> {
+    let mut sorted: Vec<(usize, TextRange)> = ranges.iter().copied().enumerate().collect();
+    sorted.sort_by_key(|(_, range)| range.start());
+
+    let mut variant = String::with_capacity(text.len() + ranges.len() * REVEAL_PREFIX.len());
+    let mut last = 0;
+    // Start of each wrapped expression in the variant text
+    let mut starts = vec![TextSize::from(0); ranges.len()];
+    for (i, range) in &sorted {
+        let start: usize = range.start().into();
+        let end: usize = range.end().into();
+        if start < last || !text.is_char_boundary(start) || !text.is_char_boundary(end) {
+            return None;
+        }
+        variant.push_str(&text[last..start]);
+        variant.push_str(REVEAL_PREFIX);
+        starts[*i] = TextSize::from(variant.len() as u32);
+        variant.push_str(&text[start..end]);
+        variant.push_str(REVEAL_SUFFIX);
+        last = end;
+    }
+    variant.push_str(&text[last..]);
+
+    // Tolerate errors, so that an error before the reveal doesn't stop the
+    // function from being checked
+    let env = [("EQWALIZER_TOLERATE_ERRORS", "true")];
+    let diagnostics = typecheck_text(db, project_id, file_id, &variant, false, &env).ok()?;
+    let reveals: Vec<_> = match &diagnostics {
+        EqwalizerDiagnostics::Diagnostics(diagnostics) => diagnostics
+            .values()
+            .flatten()
+            .filter(|d| d.code == "reveal_type")
+            .collect(),
+        EqwalizerDiagnostics::NoAst { .. } => vec![],
+    };
+    Some(
+        ranges
+            .iter()
+            .zip(starts)
+            .map(|(range, start)| {
+                reveals
+                    .iter()
+                    .find(|d| d.range.start() == start)
+                    .map(|d| TypeInfo {
+                        ty: d.message.clone(),
+                        range: *range,
+                    })
+            })
+            .collect(),
+    )
+}
+
+/// Types of the exported functions of the module, in order of export, as
+/// eqWAlizer pretty-prints them. eqWAlizer only infers types within
+/// function bodies, so the type of a function is the one of its spec
+/// (`NO_TYPE` without one, or when it doesn't apply). The references to the
+/// functions are revealed together in a single run of eqWAlizer, on a copy
+/// of the module.
+pub fn exported_function_types(
+    db: &RootDatabase,
+    file_id: FileId,
+) -> Vec<(FunctionName, Arity, String)> {
+    let exports = match db
+        .module_ast(file_id, Format::OffsetEtf)
+        .ok()
+        .and_then(|ast| etf::decode_forms(&ast))
+    {
+        Some(forms) => exported_functions(&forms),
+        None => return vec![],
+    };
+    if exports.is_empty() {
+        return vec![];
+    }
+    let project_id = match db.app_data(db.file_source_root(file_id)) {
+        Some(app_data) => app_data.project_id,
+        None => return vec![],
+    };
+    let text = db.file_text(file_id);
+
+    let mut variant = text.to_string();
+    variant.push_str(&format!(
+        "\n-spec {}() -> term().\n{}() ->\n    {{",
+        REVEAL_FUNCTION, REVEAL_FUNCTION
+    ));
+    let mut starts = Vec::with_capacity(exports.len());
+    for (i, (name, arity)) in exports.iter().enumerate() {
+        if i > 0 {
+            variant.push_str(", ");
+        }
+        variant.push_str(REVEAL_PREFIX);
+        starts.push(TextSize::from(variant.len() as u32));
+        variant.push_str(&format!("fun {}/{}", quote_atom(name), arity));
+        variant.push_str(REVEAL_SUFFIX);
+    }
+    variant.push_str("}.\n");
+
+    let env = [("EQWALIZER_TOLERATE_ERRORS", "true")];
+    let reveals: Vec<_> = match typecheck_text(db, project_id, file_id, &variant, false, &env) {
+        Ok(EqwalizerDiagnostics::Diagnostics(diagnostics)) => diagnostics
+            .into_values()
+            .flatten()
+            .filter(|d| d.code == "reveal_type")
+            .collect(),
+        _ => vec![],
+    };
+    exports
+        .into_iter()
+        .zip(starts)
+        .map(|((name, arity), start)| {
+            let ty = reveals
+                .iter()
+                .find(|d| d.range.start() == start)
+                .map_or_else(|| NO_TYPE.to_string(), |d| d.message.clone());
+            (name, arity, ty)
+        })
+        .collect()
+}
+
+/// The type eqWAlizer infers for the expression `expr` in the scope of the
+/// module in `file_id`: its imports, macros, records and types are
+/// visible, but no variable is bound. The expression is the body of a
+/// function added to a copy of the module. `NO_TYPE` if eqWAlizer gives no
+/// type, e.g. when the expression has a type error. Fails with the parse
+/// errors of the copy, e.g. when `expr` isn't an expression, whose
+/// locations are in the text of the copy.
+pub fn type_of_expression(
+    db: &RootDatabase,
+    file_id: FileId,
+    expr: &str,
+) -> Result<String, Vec<ParseError>> {
+    let project_id = match db.app_data(db.file_source_root(file_id)) {
+        Some(app_data) => app_data.project_id,
+        None => {
+            return Err(vec![ParseError {
+                path: disk_cache::file_path(db, file_id)
+                    .map(Into::into)
+                    .unwrap_or_default(),
+                location: None,
+                msg: "Unknown application".to_string(),
+            }]);
+        }
+    };
+    let text = db.file_text(file_id);
+    let expr = expr.trim().trim_end_matches('.').trim_end();
+
+    let mut variant = text.to_string();
+    variant.push_str(&format!(
+        "\n-spec {}() -> term().\n{}() ->\n    ",
+        REVEAL_EXPRESSION_FUNCTION, REVEAL_EXPRESSION_FUNCTION
+    ));
+    // On a line of its own, so that a trailing comment doesn't hide the end
+    variant.push_str(REVEAL_PREFIX);
+    variant.push('\n');
+    let start = TextSize::from(variant.len() as u32);
+    variant.push_str(expr);
+    variant.push('\n');
+    variant.push_str(REVEAL_SUFFIX);
+    variant.push_str(".\n");
+
+    let env = [("EQWALIZER_TOLERATE_ERRORS", "true")];
+    let ty = match typecheck_text(db, project_id, file_id, &variant, false, &env)? {
+        EqwalizerDiagnostics::Diagnostics(diagnostics) => diagnostics
+            .into_values()
+            .flatten()
+            .find(|d| d.code == "reveal_type" && d.range.start() == start)
+            .map(|d| d.message),
+        EqwalizerDiagnostics::NoAst { .. } => None,
+    };
+    Ok(ty.unwrap_or_else(|| NO_TYPE.to_string()))
+}
+
+/// The variables bound in the clauses of `function/arity` in the
+/// module in `file_id`, with the ranges of their binding occurrences and
+/// their types, as eqWAlizer narrowed them there, sorted by range. Each
+/// clause binds its own variables, so a name bound in several clauses
+/// appears once per clause.
+///
+/// eqWAlizer doesn't keep the types it infers, so they are revealed
+/// together in a single run of eqWAlizer, on a copy of the module where
+/// each variable is revealed right after its binding: at the start of the
+/// body for those bound by the head of a clause, including the clauses of
+/// `case`, `receive`, `try` and funs, and before the next expression for
+/// those bound by a match. Variables bound by the last expression of a body,
+/// or within another expression, are left out. `NO_TYPE` for the variables
+/// eqWAlizer gives no type, e.g. when the function has no spec.
+pub(crate) fn variable_types(
+    db: &dyn EqwalizerDatabase,
+    file_id: FileId,
+    function: FunctionName,
+    arity: Arity,
+) -> Arc<Vec<(VarName, TextRange, String)>> {
+    let forms = match db
+        .module_ast(file_id, Format::OffsetEtf)
+        .ok()
+        .and_then(|ast| etf::decode_forms(&ast))
+    {
+        Some(forms) => forms,
+        None => return vec![],
+    };
+    let clauses = forms.iter().find_map(|form| match etf::tuple(form) {
+        Some([tag, _anno, name, a, clauses])
+            if etf::atom(tag) == Some("function")
+                && etf::atom(name) == Some(function.as_str())
+                && etf::int(a) == Some(arity.into()) =>
+        {
+            etf::list(clauses)
+        }
+        _ => None,
+    });
+    let mut bindings = Vec::new();
+    for clause in clauses.unwrap_or_default() {
+        clause_bindings(clause, &FxHashSet::default(), true, &mut bindings);
+    }
+    if bindings.is_empty() {
+        return Default::default();
+    }
+    let project_id = match db.app_data(db.file_source_root(file_id)) {
+        Some(app_data) => app_data.project_id,
+        None => return vec![],
+    };
+    let text = db.file_text(file_id);
+
+    bindings.sort_by_key(|binding| binding.reveal_at);
+    let mut variant = String::with_capacity(text.len());
+    let mut last = 0;
+    // Start of the reveal of each binding in the variant text
+    let mut starts = Vec::with_capacity(bindings.len());
+    for binding in &bindings {
+        let at: usize = binding.reveal_at.into();
+        if at < last || !text.is_char_boundary(at) {
+            return Default::default();
+        }
+        variant.push_str(&text[last..at]);
+        variant.push_str(REVEAL_PREFIX);
+        starts.push(TextSize::of(variant.as_str()));
+        variant.push_str(&binding.name);
+        variant.push_str(REVEAL_SUFFIX);
+        variant.push_str(", ");
+        last = at;
+    }
+    variant.push_str(&text[last..]);
+
+    let reveals: Vec<_> = match db.typecheck_text_tolerating_errors(project_id, file_id, &variant) {
+        Ok(EqwalizerDiagnostics::Diagnostics(diagnostics)) => diagnostics
+            .into_values()
+            .flatten()
+            .filter(|d| d.code == "reveal_type")
+            .collect(),
+        _ => vec![],
+    };
+    let mut types: Vec<(VarName, TextRange, String)> = bindings
+        .into_iter()
+        .zip(starts)
+        .map(|(binding, start)| {
+            let ty = reveals
+                .iter()
+                .find(|d| d.range.start() == start)
+                .map_or_else(|| NO_TYPE.to_string(), |d| d.message.clone());
+            (binding.name, binding.range, ty)
+        })
+        .collect();
+    types.sort_by_key(|(_, range, _)| range.start());
+    Arc::new(types)
+}
+
+/// A variable bound at `range`, whose type is revealed at `reveal_at`.
+struct Binding {
+    name: VarName,
+    range: TextRange,
+    reveal_at: TextSize,
+}
+
+/// The bindings of `clause`, and of the clauses nested in its body, given
+/// the variables `bound` before it. The head of a fun clause shadows
+/// these, unlike the one of a `case` clause.
+fn clause_bindings(
+    clause: &Term,
+    bound: &FxHashSet<String>,
+    shadows: bool,
+    acc: &mut Vec<Binding>,
+) {
+    let (patterns, body) = match etf::tuple(clause) {
+        Some([tag, _anno, patterns, _guards, body]) if etf::atom(tag) == Some("clause") => {
+            (patterns, etf::list(body).unwrap_or_default())
+        }
+        _ => return,
+    };
+    let mut bound = bound.clone();
+    let body_start = body
+        .first()
+        .and_then(|e| etf::anno_range(etf::tuple(e)?.get(1)?))
+        .map(|range| range.start());
+    let head_bound = if shadows {
+        FxHashSet::default()
+    } else {
+        bound.clone()
+    };
+    for (name, range) in new_variables(patterns, &head_bound) {
+        if let Some(reveal_at) = body_start {
+            acc.push(Binding {
+                name: name.clone(),
+                range,
+                reveal_at,
+            });
+        }
+        bound.insert(name);
+    }
+    for (i, expr) in body.iter().enumerate() {
+        nested_clauses(expr, &mut |clause, shadows| {
+            clause_bindings(clause, &bound, shadows, acc)
+        });
+        if let Some([tag, _anno, pattern, _e]) = etf::tuple(expr) {
+            if etf::atom(tag) == Some("match") {
+                let next_start = body
+                    .get(i + 1)
+                    .and_then(|e| etf::anno_range(etf::tuple(e)?.get(1)?))
+                    .map(|range| range.start());
+                for (name, range) in new_variables(pattern, &bound) {
+                    if let Some(reveal_at) = next_start {
+                        acc.push(Binding {
+                            name: name.clone(),
+                            range,
+                            reveal_at,
+                        });
+                    }
+                    bound.insert(name);
+                }
+            }
+        }
+    }
+}
+
+/// The first occurrences of the variables of `pattern` not in `bound`.
+pub(crate) fn new_variables(
+    pattern: &Term,
+    bound: &FxHashSet<String>,
+) -> Vec<(VarName, TextRange)> {
+    let mut found: Vec<(VarName, TextRange)> = Vec::new();
+    etf::walk(pattern, &mut |term| {
+        if let Some([tag, anno, name]) = etf::tuple(term) {
+            if etf::atom(tag) == Some("var") {
+                if let (Some(name), Some(range)) = (etf::atom(name), etf::anno_range(anno)) {
+                    if name != "_"
+                        && !bound.contains(name)
+                        && !found.iter().any(|(found, _)| found == name)
+                    {
+                        found.push((name.to_string(), range));
+                    }
+                }
+            }
+        }
+    });
+    found
+}
+
+/// Calls `f` on the outermost clauses within `expr`, telling whether they
+/// are fun clauses.
+fn nested_clauses<'a>(expr: &'a Term, f: &mut impl FnMut(&'a Term, bool)) {
+    match etf::tuple(expr) {
+        Some([tag, ..]) if etf::atom(tag) == Some("clause") => {
+            f(expr, false);
+            return;
+        }
+        Some([tag, _anno, fun]) if etf::atom(tag) == Some("fun") => {
+            if let Some([tag, clauses]) = etf::tuple(fun) {
+                if etf::atom(tag) == Some("clauses") {
+                    for clause in etf::list(clauses).unwrap_or_default() {
+                        f(clause, true);
+                    }
+                }
+            }
+            return;
+        }
+        Some([tag, _anno, _name, clauses]) if etf::atom(tag) == Some("named_fun") => {
+            for clause in etf::list(clauses).unwrap_or_default() {
+                f(clause, true);
+            }
+            return;
+        }
+        _ => {}
+    }
+    let children: &[Term] = match expr {
+        Term::Tuple(tuple) => &tuple.elements,
+        Term::List(list) => &list.elements,
+        _ => &[],
+    };
+    for child in children {
+        nested_clauses(child, &mut *f);
+    }
+}
+
+/// The exports of a module, each list sorted by name and arity.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModuleExports {
+    /// Functions listed in `-export` attributes, or all of them with
+    /// `export_all`.
+    pub functions: Vec<(FunctionName, Arity)>,
+    /// Types listed in `-export_type` attributes.
+    pub types: Vec<(String, Arity)>,
+}
+
+/// The exports of the module in `file_id`, including those of the headers
+/// it includes, from its forms alone. `None` if the module doesn't parse.
+pub fn module_exports(db: &dyn ErlAstDatabase, file_id: FileId) -> Option<ModuleExports> {
+    let forms = db
+        .module_ast(file_id, Format::OffsetEtf)
+        .ok()
+        .and_then(|ast| etf::decode_forms(&ast))?;
+    let mut functions = exported_functions(&forms);
+    functions.sort();
+    functions.dedup();
+    let mut types = exported_types(&forms);
+    types.sort();
+    types.dedup();
+    Some(ModuleExports { functions, types })
+}
+
+/// Types listed in `-export_type` attributes.
+fn exported_types(forms: &[Term]) -> Vec<(String, Arity)> {
+    let mut exports = Vec::new();
+    for form in forms {
+        if let Some([tag, _anno, name, value]) = etf::tuple(form) {
+            if etf::atom(tag) == Some("attribute") && etf::atom(name) == Some("export_type") {
+                exports.extend(
+                    etf::list(value)
+                        .unwrap_or_default()
+                        .iter()
+                        .filter_map(|export| match etf::tuple(export)? {
+                            [name, arity] => Some((
+                                etf::atom(name)?.to_string(),
+                                etf::int(arity)?.try_into().ok()?,
+                            )),
+                            _ => None,
+                        }),
+                )
+            }
+        }
+    }
+    exports
+}
+
+/// Functions listed in `-export` attributes, or all of them with
+/// `export_all`.
+pub(crate) fn exported_functions(forms: &[Term]) -> Vec<(FunctionName, Arity)> {
+    let mut exports = Vec::new();
+    let mut export_all = false;
+    let mut functions = Vec::new();
+    for form in forms {
+        match etf::tuple(form) {
+            Some([tag, _anno, name, value]) if etf::atom(tag) == Some("attribute") => {
+                match etf::atom(name) {
+                    Some("export") => {
+                        exports.extend(etf::list(value).unwrap_or_default().iter().filter_map(
+                            |export| match etf::tuple(export)? {
+                                [name, arity] => Some((
+                                    etf::atom(name)?.to_string(),
+                                    etf::int(arity)?.try_into().ok()?,
+                                )),
+                                _ => None,
+                            },
+                        ))
+                    }
+                    Some("compile") => {
+                        let options =
+                            etf::list(value).unwrap_or_else(|| std::slice::from_ref(value));
+                        export_all |= options
+                            .iter()
+                            .any(|option| etf::atom(option) == Some("export_all"));
+                    }
+                    _ => {}
+                }
+            }
+            Some([tag, _anno, name, arity, _clauses]) if etf::atom(tag) == Some("function") => {
+                if let (Some(name), Some(Ok(arity))) =
+                    (etf::atom(name), etf::int(arity).map(Arity::try_from))
+                {
+                    functions.push((name.to_string(), arity));
+                }
+            }
+            _ => {}
+        }
+    }
+    if export_all {
+        functions
+    } else {
+        exports
+    }
+}
+
+/// A record as eqWAlizer sees it, see `record_type`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordType {
+    pub name: String,
+    pub fields: Vec<RecordFieldType>,
+    /// Records the field types refer to, directly or through other records,
+    /// in order of discovery. Only filled in for the record asked for.
+    pub referenced: Vec<RecordType>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordFieldType {
+    pub name: String,
+    /// The type, as pretty-printed by eqWAlizer (`NO_TYPE` if it has none
+    /// for the field).
+    pub ty: String,
+    /// The text of the default value. `None` without one, or when the
+    /// record is defined in a header outside of the project.
+    pub default: Option<String>,
+    /// Whether the field can be left out when building the record: it has
+    /// a default value, or its type accepts `undefined`.
+    pub optional: bool,
+}
+
+/// The record `name` visible in the module in `file_id`, defined in the
+/// module itself or in a header it includes, with the types eqWAlizer gives
+/// its fields. The fields of all the records involved are revealed together
+/// in a single run of eqWAlizer, on a copy of the module. `None` if there is
+/// no such record.
+pub fn record_type(db: &RootDatabase, file_id: FileId, name: &str) -> Option<RecordType> {
+    let forms = db
+        .module_ast(file_id, Format::OffsetEtf)
+        .ok()
+        .and_then(|ast| etf::decode_forms(&ast))?;
+    let project_id = db.app_data(db.file_source_root(file_id))?.project_id;
+    let records = visible_records(db, project_id, &forms);
+    records.get(name)?;
+
+    // The record itself first, then the ones it refers to
+    let mut names = vec![name.to_string()];
+    let mut i = 0;
+    while i < names.len() {
+        for referenced in referenced_records(&records[&names[i]].0) {
+            if records.contains_key(&referenced) && !names.contains(&referenced) {
+                names.push(referenced);
+            }
+        }
+        i += 1;
+    }
+
+    let text = db.file_text(file_id);
+    let mut variant = text.to_string();
+    let params: Vec<String> = names
+        .iter()
+        .map(|name| format!("#{}{{}}", quote_atom(name)))
+        .collect();
+    let args: Vec<String> = (0..names.len()).map(|i| format!("R{}", i)).collect();
+    variant.push_str(&format!(
+        "\n-spec {}({}) -> term().\n{}({}) ->\n    {{",
+        REVEAL_RECORD_FUNCTION,
+        params.join(", "),
+        REVEAL_RECORD_FUNCTION,
+        args.join(", ")
+    ));
+    let mut starts = Vec::new();
+    for (i, name) in names.iter().enumerate() {
+        for field in &records[name].0 {
+            if !starts.is_empty() {
+                variant.push_str(", ");
+            }
+            variant.push_str(REVEAL_PREFIX);
+            starts.push(TextSize::from(variant.len() as u32));
+            variant.push_str(&format!(
+                "R{}#{}.{}",
+                i,
+                quote_atom(name),
+                quote_atom(&field.name)
+            ));
+            variant.push_str(REVEAL_SUFFIX);
+        }
+    }
+    variant.push_str("}.\n");
+
+    let env = [("EQWALIZER_TOLERATE_ERRORS", "true")];
+    let reveals: Vec<_> = match typecheck_text(db, project_id, file_id, &variant, false, &env) {
+        Ok(EqwalizerDiagnostics::Diagnostics(diagnostics)) => diagnostics
+            .into_values()
+            .flatten()
+            .filter(|d| d.code == "reveal_type")
+            .collect(),
+        _ => vec![],
+    };
+    let mut starts = starts.into_iter();
+    let mut types: Vec<RecordType> = names
+        .into_iter()
+        .map(|name| {
+            let (fields, text) = &records[&name];
+            let fields = fields
+                .iter()
+                .zip(starts.by_ref())
+                .map(|(field, start)| {
+                    let ty = reveals
+                        .iter()
+                        .find(|d| d.range.start() == start)
+                        .map_or_else(|| NO_TYPE.to_string(), |d| d.message.clone());
+                    let default =
+                        text.as_ref()
+                            .zip(field.default.as_ref())
+                            .and_then(|(text, default)| {
+                                let range = etf::anno_range(etf::tuple(default)?.get(1)?)?;
+                                text.get(usize::from(range.start())..usize::from(range.end()))
+                                    .map(str::to_string)
+                            });
+                    RecordFieldType {
+                        name: field.name.clone(),
+                        optional: field.default.is_some() || accepts_undefined(&ty),
+                        ty,
+                        default,
+                    }
+                })
+                .collect();
+            RecordType {
+                name,
+                fields,
+                referenced: vec![],
+            }
+        })
+        .collect();
+    let mut record = types.remove(0);
+    record.referenced = types;
+    Some(record)
+}
+
+/// Fields of the records defined in `forms`, with the text of the file
+/// defining them, if known.
+fn visible_records(
+    db: &RootDatabase,
+    project_id: ProjectId,
+    forms: &[Term],
+) -> FxHashMap<String, (Vec<RecordField>, Option<Arc<String>>)> {
+    let mut records = FxHashMap::default();
+    // The text of the module, then of each header in turn
+    let mut text = None;
+    for form in forms {
+        if let Some(path) = erl_ast::file_attribute_path(form) {
+            text =
+                erl_ast::project_file(db, project_id, &path).map(|file_id| db.file_text(file_id));
+            continue;
+        }
+        if let Some(AbstractForm::Record { name, fields, .. }) = forms::decode_form(form) {
+            records.insert(name, (fields, text.clone()));
+        }
+    }
+    records
+}
+
+/// Names of the records in `#name{}` types of `fields`.
+fn referenced_records(fields: &[RecordField]) -> Vec<String> {
+    let mut referenced = Vec::new();
+    for ty in fields.iter().filter_map(|field| field.ty.as_ref()) {
+        etf::walk(ty, &mut |term| {
+            if let Some([tag, _anno, kind, args]) = etf::tuple(term) {
+                if etf::atom(tag) == Some("type") && etf::atom(kind) == Some("record") {
+                    let name = etf::list(args)
+                        .and_then(|args| etf::atom_literal(args.first()?))
+                        .map(str::to_string);
+                    referenced.extend(name);
+                }
+            }
+        });
+    }
+    referenced
+}
+
+/// Whether `undefined` is a member of the (pretty-printed) type, or the
+/// type accepts anything.
+fn accepts_undefined(ty: &str) -> bool {
+    let mut members = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in ty.char_indices() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            '|' if depth == 0 => {
+                members.push(&ty[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    members.push(&ty[start..]);
+    members.iter().any(|member| {
+        matches!(
+            member.trim(),
+            "undefined" | "'undefined'" | "term()" | "any()" | "dynamic()"
+        )
+    })
+}
+
+pub(crate) fn quote_atom(name: &str) -> String {
+    format!("'{}'", name.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// Whether `offset` is in a `%` comment, ignoring multi-line strings.
+fn in_comment(text: &str, offset: usize) -> bool {
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let mut quote = None;
+    let mut chars = text[line_start..offset].chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, '%') => return true,
+            (None, '"') | (None, '\'') => quote = Some(c),
+            // Character literal, e.g. $%
+            (None, '$') | (Some(_), '\\') => {
+                chars.next();
+            }
+            (Some(q), c) if c == q => quote = None,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Ranges of the expressions of function bodies containing `offset`,
+/// innermost first. Patterns and guards are skipped.
+pub(crate) fn enclosing_expressions(forms: &[Term], offset: TextSize) -> Vec<TextRange> {
+    let mut acc = body_expressions(forms, &|range| range.contains(offset));
+    acc.reverse();
+    acc
+}
+
+/// Ranges of the expressions of function bodies for which `keep` holds,
+/// outermost first. The expressions of those it doesn't hold for aren't
+/// visited.
+pub(crate) fn body_expressions(forms: &[Term], keep: &dyn Fn(TextRange) -> bool) -> Vec<TextRange> {
+    let mut acc = Vec::new();
+    for form in forms {
+        if let Some([tag, _anno, _name, _arity, clauses]) = etf::tuple(form) {
+            if etf::atom(tag) == Some("function") {
+                visit_clauses(clauses, keep, &mut acc);
+            }
+        }
+    }
+    acc
+}
+
+fn visit_clauses(clauses: &Term, keep: &dyn Fn(TextRange) -> bool, acc: &mut Vec<TextRange>) {
+    for clause in etf::list(clauses).unwrap_or_default() {
+        if let Some([tag, _anno, _patterns, _guards, body]) = etf::tuple(clause) {
+            if etf::atom(tag) == Some("clause") {
+                visit_exprs(body, keep, acc);
+            }
+        }
+    }
+}
+
+fn visit_exprs(exprs: &Term, keep: &dyn Fn(TextRange) -> bool, acc: &mut Vec<TextRange>) {
+    for expr in etf::list(exprs).unwrap_or_default() {
+        visit_expr(expr, keep, acc);
+    }
+}
+
+fn visit_expr(expr: &Term, keep: &dyn Fn(TextRange) -> bool, acc: &mut Vec<TextRange>) {
+    let (tag, anno, children) = match etf::tuple(expr) {
+        Some([tag, anno, children @ ..]) => (tag, anno, children),
+        _ => return,
+    };
+    match etf::anno_range(anno) {
+        Some(range) if keep(range) => acc.push(range),
+        _ => return,
+    }
+    let tag = match etf::atom(tag) {
+        Some(tag) => tag,
+        None => return,
+    };
+    match (tag, children) {
+        ("match", [_pattern, e]) | ("catch", [e]) | ("record_field", [e, _, _]) => {
+            visit_expr(e, keep, acc)
+        }
+        ("cons", [e1, e2]) | ("op", [_, e1, e2]) => {
+            visit_expr(e1, keep, acc);
+            visit_expr(e2, keep, acc);
+        }
+        ("op", [_, e]) => visit_expr(e, keep, acc),
+        ("tuple", [es]) | ("block", [es]) => visit_exprs(es, keep, acc),
+        ("bin", [elements]) => {
+            for element in etf::list(elements).unwrap_or_default() {
+                if let Some([_, _, e, _, _]) = etf::tuple(element) {
+                    visit_expr(e, keep, acc);
+                }
+            }
+        }
+        ("record", [_name, fields]) => visit_fields(fields, keep, acc),
+        ("record", [e, _name, fields]) => {
+            visit_expr(e, keep, acc);
+            visit_fields(fields, keep, acc);
+        }
+        ("map", [fields]) => visit_fields(fields, keep, acc),
+        ("map", [e, fields]) => {
+            visit_expr(e, keep, acc);
+            visit_fields(fields, keep, acc);
+        }
+        ("call", [callee, args]) => {
+            // Function names aren't expressions on their own
+            if etf::atom_literal(callee).is_none() && !is_remote(callee) {
+                visit_expr(callee, keep, acc);
+            }
+            visit_exprs(args, keep, acc);
+        }
+        ("lc", [e, qualifiers]) | ("bc", [e, qualifiers]) | ("mc", [e, qualifiers]) => {
+            visit_expr(e, keep, acc);
+            for qualifier in etf::list(qualifiers).unwrap_or_default() {
+                match etf::tuple(qualifier) {
+                    Some([tag, _, _pattern, e])
+                        if matches!(
+                            etf::atom(tag),
+                            Some("generate") | Some("b_generate") | Some("m_generate")
+                        ) =>
+                    {
+                        visit_expr(e, keep, acc)
+                    }
+                    _ => visit_expr(qualifier, keep, acc),
+                }
+            }
+        }
+        ("if", [clauses]) | ("receive", [clauses]) | ("named_fun", [_, clauses]) => {
+            visit_clauses(clauses, keep, acc)
+        }
+        ("case", [e, clauses]) => {
+            visit_expr(e, keep, acc);
+            visit_clauses(clauses, keep, acc);
+        }
+        ("receive", [clauses, timeout, after]) => {
+            visit_clauses(clauses, keep, acc);
+            visit_expr(timeout, keep, acc);
+            visit_exprs(after, keep, acc);
+        }
+        ("try", [body, clauses, catch_clauses, after]) => {
+            visit_exprs(body, keep, acc);
+            visit_clauses(clauses, keep, acc);
+            visit_clauses(catch_clauses, keep, acc);
+            visit_exprs(after, keep, acc);
+        }
+        ("fun", [fun]) => {
+            if let Some([tag, clauses]) = etf::tuple(fun) {
+                if etf::atom(tag) == Some("clauses") {
+                    visit_clauses(clauses, keep, acc);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Values of record and map fields.
+fn visit_fields(fields: &Term, keep: &dyn Fn(TextRange) -> bool, acc: &mut Vec<TextRange>) {
+    for field in etf::list(fields).unwrap_or_default() {
+        if let Some([_tag, _, _name, value]) = etf::tuple(field) {
+            visit_expr(value, keep, acc);
+        }
+    }
+}
+
+fn is_remote(callee: &Term) -> bool {
+    matches!(etf::tuple(callee), Some([tag, ..]) if etf::atom(tag) == Some("remote"))
+}
@@ -0,0 +1,254 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Checking candidate specs of a function, before writing them, and
+//! comparing the current spec with the type eqWAlizer enforces.
+//!
+//! The spec is substituted for the current one, or added before the
+//! function, in a copy of the module text, which is then type-checked on
+//! the side. The database is left untouched.
+
+use eetf::Term;
+use elp_base_db::FileId;
+use elp_base_db::FileLoader;
+use elp_base_db::SourceDatabase;
+use elp_eqwalizer::EqwalizerDiagnostic;
+use elp_eqwalizer::EqwalizerDiagnostics;
+use elp_eqwalizer::Severity;
+use elp_parse_server::Format;
+use elp_parse_server::ParseError;
+use text_size::TextRange;
+use text_size::TextSize;
+
+use crate::eqwalizer::typecheck_text;
+use crate::etf;
+use crate::fixes;
+use crate::forms;
+use crate::forms::AbstractForm;
+use crate::reveal;
+use crate::reveal::Arity;
+use crate::reveal::NO_TYPE;
+use crate::ErlAstDatabase;
+use crate::RootDatabase;
+
+/// Result of `validate_spec`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpecValidation {
+    /// The name of the function in its definition, in the current text.
+    pub function_range: TextRange,
+    /// The function and its current spec, if any, in the current text:
+    /// the errors in it decide whether the spec conforms.
+    pub checked_range: TextRange,
+    /// Errors parsing the module with the spec, e.g. when the spec is
+    /// malformed. Their locations are in the text with the spec.
+    pub parse_errors: Vec<ParseError>,
+    /// Diagnostics of the module with the spec. Their ranges are in the
+    /// current text: those inside the spec cover the spec it replaces, or
+    /// the start of the function when it had none.
+    pub diagnostics: Vec<EqwalizerDiagnostic>,
+    /// Module eqWAlizer couldn't get the AST of, in which case there are no
+    /// diagnostics.
+    pub no_ast: Option<String>,
+}
+
+impl SpecValidation {
+    /// Whether the spec parses and eqWAlizer reports no error for it or the
+    /// function body. Other functions may still be affected, e.g. callers.
+    pub fn conforms(&self) -> bool {
+        self.parse_errors.is_empty()
+            && self.no_ast.is_none()
+            && !self.diagnostics.iter().any(|d| {
+                d.severity() == Severity::Error && self.checked_range.contains_range(d.range)
+            })
+    }
+}
+
+/// Type-checks the module in `file_id` in non-strict mode, with `spec`
+/// (`-spec f(...) -> ...`, the final dot being optional) as the spec of
+/// `function/arity`. `None` if the module has no such function.
+pub fn validate_spec(
+    db: &RootDatabase,
+    file_id: FileId,
+    function: &str,
+    arity: Arity,
+    spec: &str,
+) -> Option<SpecValidation> {
+    let forms = db
+        .module_ast(file_id, Format::OffsetEtf)
+        .ok()
+        .and_then(|ast| etf::decode_forms(&ast))?;
+    let forms = own_abstract_forms(&forms);
+    let definition_range = function_definition(&forms, function, arity)?;
+    let spec_range = function_spec(&forms, function, arity).map(|(range, _clauses)| range);
+    let project_id = db.app_data(db.file_source_root(file_id))?.project_id;
+    let text = db.file_text(file_id);
+
+    let spec = spec.trim().trim_end_matches('.').trim_end();
+    let (replaced, replacement) = match spec_range {
+        Some(range) => (range, spec.to_string()),
+        None => (
+            TextRange::empty(definition_range.start()),
+            format!("{}.\n", spec),
+        ),
+    };
+    let mut variant = text.to_string();
+    variant.replace_range(
+        usize::from(replaced.start())..usize::from(replaced.end()),
+        &replacement,
+    );
+
+    let name = reveal::quote_atom(function);
+    let mut validation = SpecValidation {
+        function_range: TextRange::at(definition_range.start(), TextSize::of(name.as_str())),
+        checked_range: match spec_range {
+            Some(spec_range) => spec_range.cover(definition_range),
+            None => definition_range,
+        },
+        parse_errors: vec![],
+        diagnostics: vec![],
+        no_ast: None,
+    };
+    match typecheck_text(db, project_id, file_id, &variant, false, &[]) {
+        Err(parse_errors) => validation.parse_errors = parse_errors,
+        Ok(EqwalizerDiagnostics::NoAst { module }) => validation.no_ast = Some(module),
+        Ok(EqwalizerDiagnostics::Diagnostics(diagnostics)) => {
+            let inserted = TextSize::of(replacement.as_str());
+            validation.diagnostics = diagnostics
+                .into_values()
+                .flatten()
+                .map(|mut d| {
+                    d.range = map_back(d.range, replaced, inserted);
+                    d
+                })
+                .collect();
+            validation.diagnostics.sort_by_key(|d| d.range.start());
+        }
+    }
+    Some(validation)
+}
+
+/// Result of `effective_function_spec`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EffectiveSpec {
+    /// The `-spec` attribute as written, without the final dot.
+    pub declared: String,
+    /// The clauses of `declared` as the parser splits them, each as a fun
+    /// type, `fun((A) -> B)` or `fun((A) -> B when A :: atom())`, with its
+    /// whitespace collapsed.
+    pub declared_clauses: Vec<String>,
+    /// The type eqWAlizer checks the function and its callers against, as
+    /// it pretty-prints it. `NO_TYPE` if there is none, e.g. when eqWAlizer
+    /// rejects the spec.
+    pub enforced: String,
+}
+
+impl EffectiveSpec {
+    /// Whether `enforced` isn't the single declared clause: with overloaded
+    /// specs, specs eqWAlizer rejects, or whose types it normalizes, e.g.
+    /// `any()` into `term()`. The comparison is textual, so annotated
+    /// arguments, `(X :: atom())`, differ as well.
+    pub fn differs(&self) -> bool {
+        !matches!(self.declared_clauses.as_slice(), [clause] if *clause == self.enforced)
+    }
+}
+
+/// The spec of `function/arity` in the module in `file_id`, along with the
+/// type eqWAlizer enforces for it: the type of a reference to the function,
+/// revealed in a copy of the module, see `reveal::type_of_expression`.
+/// `None` if the module has no spec for the function.
+pub fn effective_function_spec(
+    db: &RootDatabase,
+    file_id: FileId,
+    function: &str,
+    arity: Arity,
+) -> Option<EffectiveSpec> {
+    let forms = db
+        .module_ast(file_id, Format::OffsetEtf)
+        .ok()
+        .and_then(|ast| etf::decode_forms(&ast))?;
+    let forms = own_abstract_forms(&forms);
+    let (spec_range, clauses) = function_spec(&forms, function, arity)?;
+    let text = db.file_text(file_id);
+    let declared = text
+        .get(usize::from(spec_range.start())..usize::from(spec_range.end()))?
+        .to_string();
+    let declared_clauses = clauses
+        .iter()
+        .filter_map(|clause| {
+            let range = etf::anno_range(etf::tuple(clause)?.get(1)?)?;
+            let clause = text.get(usize::from(range.start())..usize::from(range.end()))?;
+            let clause = clause.split_whitespace().collect::<Vec<_>>();
+            Some(format!("fun({})", clause.join(" ")))
+        })
+        .collect();
+    let reference = format!("fun {}/{}", reveal::quote_atom(function), arity);
+    let enforced =
+        reveal::type_of_expression(db, file_id, &reference).unwrap_or_else(|_| NO_TYPE.to_string());
+    Some(EffectiveSpec {
+        declared,
+        declared_clauses,
+        enforced,
+    })
+}
+
+/// Maps `range` in the text where `replaced` was replaced by `inserted`
+/// bytes back to the original text.
+fn map_back(range: TextRange, replaced: TextRange, inserted: TextSize) -> TextRange {
+    let map = |offset: TextSize, inside: TextSize| {
+        if offset <= replaced.start() {
+            offset
+        } else if offset >= replaced.start() + inserted {
+            offset - inserted + replaced.len()
+        } else {
+            inside
+        }
+    };
+    TextRange::new(
+        map(range.start(), replaced.start()),
+        map(range.end(), replaced.end()),
+    )
+}
+
+/// The forms of the module itself, leaving out those of included files.
+fn own_abstract_forms(forms: &[Term]) -> Vec<AbstractForm> {
+    fixes::own_forms(forms)
+        .into_iter()
+        .filter_map(forms::decode_form)
+        .collect()
+}
+
+/// Range of the definition of `name/arity`.
+fn function_definition(forms: &[AbstractForm], name: &str, arity: Arity) -> Option<TextRange> {
+    forms.iter().find_map(|form| match form {
+        AbstractForm::Function {
+            name: n,
+            arity: a,
+            range,
+            ..
+        } if n == name && *a == arity => Some(*range),
+        _ => None,
+    })
+}
+
+/// Range of the spec attribute of `name/arity`, from the `-` to the final
+/// dot excluded, and its clauses.
+fn function_spec<'a>(
+    forms: &'a [AbstractForm],
+    name: &str,
+    arity: Arity,
+) -> Option<(TextRange, &'a [Term])> {
+    forms.iter().find_map(|form| match form {
+        AbstractForm::Spec {
+            name: n,
+            arity: a,
+            range,
+            callback: false,
+            clauses,
+            ..
+        } if n == name && *a == arity => Some((*range, clauses.as_slice())),
+        _ => None,
+    })
+}
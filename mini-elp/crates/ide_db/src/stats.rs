@@ -0,0 +1,179 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Adoption of eqWAlizer across a project.
+
+use std::sync::Arc;
+
+use elp_base_db::FileId;
+use elp_base_db::ProjectId;
+use elp_eqwalizer::EqwalizerDiagnostics;
+use elp_eqwalizer::Severity;
+use elp_parse_server::Format;
+use elp_parse_server::ParseError;
+use elp_project_model::AppType;
+use fxhash::FxHashSet;
+use salsa::Database;
+
+use crate::etf;
+use crate::fixmes;
+use crate::EqwalizerDatabase;
+
+/// Summary of the modules of the project's own applications. Modules of
+/// dependencies and OTP are left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProjectStats {
+    /// Modules with `-typing([eqwalizer])`.
+    pub opted_in: usize,
+    /// Opted-in modules without fixme or ignore comments.
+    pub fully_checked: usize,
+    /// Opted-in modules with fixme or ignore comments.
+    pub partially_checked: usize,
+    pub not_opted_in: usize,
+    /// Modules that failed to parse, so whether they are opted in is
+    /// unknown. They are not counted in the other categories.
+    pub unparsable: usize,
+    /// Diagnostics of the opted-in modules, by severity.
+    pub errors: usize,
+    pub warnings: usize,
+    pub hints: usize,
+    /// Opted-in modules with at least one error.
+    pub modules_with_errors: usize,
+    /// Module whose AST eqWAlizer couldn't get, in which case there are no
+    /// diagnostic counts.
+    pub no_ast: Option<String>,
+}
+
+pub fn project_stats(db: &dyn EqwalizerDatabase, project_id: ProjectId) -> ProjectStats {
+    let mut stats = ProjectStats::default();
+    let mut opted_in = Vec::new();
+    for (_name, _source, file_id) in db.module_index(project_id).iter() {
+        if db.file_app_type(file_id) != Some(AppType::App) {
+            continue;
+        }
+        match is_opted_in(db, file_id) {
+            None => stats.unparsable += 1,
+            Some(false) => stats.not_opted_in += 1,
+            Some(true) => {
+                stats.opted_in += 1;
+                if fixmes::has_pragmas(&db.file_text(file_id)) {
+                    stats.partially_checked += 1;
+                } else {
+                    stats.fully_checked += 1;
+                }
+                opted_in.push(file_id);
+            }
+        }
+    }
+    if opted_in.is_empty() {
+        return stats;
+    }
+    opted_in.sort();
+
+    match &*db.eqwalizer_diagnostics(project_id, opted_in, Format::OffsetEtf, false) {
+        EqwalizerDiagnostics::Diagnostics(diagnostics) => {
+            let mut with_errors = FxHashSet::default();
+            for (module, diagnostics) in diagnostics {
+                for diagnostic in diagnostics {
+                    match diagnostic.severity() {
+                        Severity::Error => {
+                            stats.errors += 1;
+                            with_errors.insert(module);
+                        }
+                        Severity::Warning => stats.warnings += 1,
+                        Severity::Hint => stats.hints += 1,
+                    }
+                }
+            }
+            stats.modules_with_errors = with_errors.len();
+        }
+        EqwalizerDiagnostics::NoAst { module } => stats.no_ast = Some(module.clone()),
+    }
+    stats
+}
+
+/// Which modules eqWAlizer can check, see `coverage`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    /// Modules with `-typing([eqwalizer])`, parsing fine.
+    pub checked: Vec<FileId>,
+    pub not_opted_in: Vec<FileId>,
+    /// Modules that failed to parse, so that eqWAlizer can't check them,
+    /// whether they are opted in or not.
+    pub parse_failed: Vec<(FileId, Arc<Vec<ParseError>>)>,
+}
+
+/// Sorts the modules of the project among `file_ids` by whether eqWAlizer
+/// can check them. Only parses the modules, without type-checking them.
+pub fn coverage(
+    db: &dyn EqwalizerDatabase,
+    project_id: ProjectId,
+    file_ids: Vec<FileId>,
+    format: Format,
+) -> Coverage {
+    let module_index = db.module_index(project_id);
+    let mut coverage = Coverage::default();
+    for file_id in file_ids {
+        if module_index.module_for_file(file_id).is_none() {
+            continue;
+        }
+        db.unwind_if_cancelled();
+        // Attributes are looked up in the forms of the default format
+        let ast = db
+            .module_ast(file_id, format)
+            .and_then(|_| db.module_ast(file_id, Format::OffsetEtf));
+        match ast {
+            Err(errors) => coverage.parse_failed.push((file_id, errors)),
+            Ok(ast) => match has_typing_attribute(&ast) {
+                Some(true) => coverage.checked.push(file_id),
+                Some(false) | None => coverage.not_opted_in.push(file_id),
+            },
+        }
+    }
+    coverage
+}
+
+/// Modules of the project's own applications with `-typing([eqwalizer])`,
+/// sorted. Only parses the modules, without type-checking them. Modules of
+/// dependencies and OTP, and modules that failed to parse, are left out.
+pub fn enabled_modules(db: &dyn EqwalizerDatabase, project_id: ProjectId) -> Vec<FileId> {
+    let mut enabled: Vec<FileId> = db
+        .module_index(project_id)
+        .iter()
+        .map(|(_name, _source, file_id)| file_id)
+        .filter(|&file_id| db.file_app_type(file_id) == Some(AppType::App))
+        .filter(|&file_id| {
+            db.unwind_if_cancelled();
+            is_opted_in(db, file_id) == Some(true)
+        })
+        .collect();
+    enabled.sort();
+    enabled
+}
+
+/// Whether the module has a `-typing([eqwalizer])` attribute, `None` if it
+/// doesn't parse.
+pub fn is_opted_in(db: &dyn EqwalizerDatabase, file_id: FileId) -> Option<bool> {
+    let ast = db.module_ast(file_id, Format::OffsetEtf).ok()?;
+    has_typing_attribute(&ast)
+}
+
+pub(crate) fn has_typing_attribute(ast: &[u8]) -> Option<bool> {
+    let forms = etf::decode_forms(ast)?;
+    Some(forms.iter().any(|form| {
+        match etf::tuple(form) {
+            Some([tag, _anno, name, checkers])
+                if etf::atom(tag) == Some("attribute") && etf::atom(name) == Some("typing") =>
+            {
+                etf::list(checkers)
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|checker| etf::atom(checker) == Some("eqwalizer"))
+            }
+            _ => false,
+        }
+    }))
+}
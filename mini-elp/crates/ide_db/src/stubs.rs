@@ -0,0 +1,274 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Specs maintained outside of the modules they describe, e.g. for NIFs
+//! whose Erlang side has none.
+//!
+//! The stub of module `m` is `m.erl` in the `stubs_dir` of the project, a
+//! module with `-spec` attributes only. eqWAlizer asks for the stubs of
+//! the modules it needs the specs of separately from their ASTs: the
+//! stubs it gets are the forms of the module, plus the specs of the stub
+//! for the functions of the module that have none. The in-source spec of
+//! a function always wins over the one of its stub. Stub specs can only
+//! refer to the types of the module or to remote types.
+//!
+//! Specs of OTP modules can be those of another OTP release than the one
+//! installed, e.g. when building for several releases: when the project has
+//! an `otp_version`, the stub of OTP module `m` is `otp-<version>/m.erl`
+//! in the `stubs_dir`, if it exists. Such a stub is the whole API of the
+//! module for that release, types and exports included, whose forms are
+//! the stubs eqWAlizer gets instead of those of the installed module. OTP
+//! modules without one fall back to the stubs above, and their installed
+//! specs.
+//!
+//! Stubs are files of the database, like modules: those of the source
+//! roots of the project, or of its `stubs_root` when the `stubs_dir` is
+//! elsewhere, see `ProjectData::stubs_root`. Other files of the
+//! `stubs_dir` are ignored. Adding, removing or changing a stub thus
+//! invalidates what depends on it, as does changing the `stubs_dir` of the
+//! project.
+
+use std::sync::Arc;
+
+use eetf::Term;
+use elp_base_db::AbsPathBuf;
+use elp_base_db::FileId;
+use elp_base_db::ProjectId;
+use elp_base_db::SourceDatabase;
+use elp_base_db::VfsPath;
+use elp_parse_server::Format;
+use elp_parse_server::ParseError;
+use elp_project_model::AppType;
+use fxhash::FxHashSet;
+
+use crate::disk_cache;
+use crate::erl_ast;
+use crate::erl_ast::AstLoader;
+use crate::etf;
+use crate::forms;
+use crate::forms::AbstractForm;
+use crate::reveal::Arity;
+use crate::reveal::FunctionName;
+use crate::ErlAstDatabase;
+use crate::LineIndex;
+
+#[salsa::query_group(StubsDatabaseStorage)]
+pub trait StubsDatabase: ErlAstDatabase {
+    /// The AST of the module in `file_id` in the given format, with the
+    /// specs of its stub for the functions that have none. The AST itself
+    /// if the module has no stub, or if its stub doesn't parse.
+    fn module_stubs_ast(
+        &self,
+        file_id: FileId,
+        format: Format,
+    ) -> Result<Arc<Vec<u8>>, Arc<Vec<ParseError>>>;
+}
+
+fn module_stubs_ast(
+    db: &dyn StubsDatabase,
+    file_id: FileId,
+    format: Format,
+) -> Result<Arc<Vec<u8>>, Arc<Vec<ParseError>>> {
+    let ast = db.module_ast(file_id, format)?;
+    let project_id = match db.app_data(db.file_source_root(file_id)) {
+        Some(app_data) => app_data.project_id,
+        None => return Ok(ast),
+    };
+    if let Some(otp_stub) = otp_stub_file(db, project_id, file_id) {
+        let otp_stub_ast = stub_ast(db, project_id, file_id, otp_stub, format);
+        return Ok(otp_stub_ast.map_or(ast, Arc::new));
+    }
+    let stub = match stub_file(db, project_id, file_id) {
+        Some(stub) => stub,
+        None => return Ok(ast),
+    };
+    let stub_specs = match stub_specs(db, project_id, file_id, stub, format) {
+        Some(stub_specs) => stub_specs,
+        None => return Ok(ast),
+    };
+    Ok(layer_specs(&ast, stub_specs).map_or(ast, Arc::new))
+}
+
+/// The file of the stub of the module in `file_id`, if the project has a
+/// stub directory and the stub is a file of the database, the one of the
+/// OTP release of the project first.
+pub(crate) fn stub_file<DB>(db: &DB, project_id: ProjectId, file_id: FileId) -> Option<FileId>
+where
+    DB: SourceDatabase + ?Sized,
+{
+    if let Some(otp_stub) = otp_stub_file(db, project_id, file_id) {
+        return Some(otp_stub);
+    }
+    let stubs_dir = db.project_data(project_id).stubs_dir.clone()?;
+    let module = db
+        .module_index(project_id)
+        .module_for_file(file_id)?
+        .clone();
+    stub_file_at(
+        db,
+        project_id,
+        stubs_dir.join(format!("{}.erl", module.as_str())),
+    )
+}
+
+/// The file of the stub of the OTP module in `file_id` for the OTP release
+/// of the project, if the project has one and the stub is a file of the
+/// database.
+fn otp_stub_file<DB>(db: &DB, project_id: ProjectId, file_id: FileId) -> Option<FileId>
+where
+    DB: SourceDatabase + ?Sized,
+{
+    if db.file_app_type(file_id) != Some(AppType::Otp) {
+        return None;
+    }
+    let project_data = db.project_data(project_id);
+    let version = project_data.otp_version.as_ref()?;
+    let stubs_dir = project_data.stubs_dir.as_ref()?;
+    let module = db
+        .module_index(project_id)
+        .module_for_file(file_id)?
+        .clone();
+    let stub = stubs_dir
+        .join(format!("otp-{}", version))
+        .join(format!("{}.erl", module.as_str()));
+    stub_file_at(db, project_id, stub)
+}
+
+/// The file at `path` in the `stubs_root` or the source roots of the
+/// project, see `ProjectData::stubs_root`.
+fn stub_file_at<DB>(db: &DB, project_id: ProjectId, path: AbsPathBuf) -> Option<FileId>
+where
+    DB: SourceDatabase + ?Sized,
+{
+    let path = VfsPath::from(path.normalize());
+    let project_data = db.project_data(project_id);
+    project_data
+        .stubs_root
+        .iter()
+        .chain(&project_data.source_roots)
+        .find_map(|&source_root_id| db.source_root(source_root_id).file_for_path(&path))
+}
+
+/// The `-spec` forms of the stub in `stub_id`, see `stub_ast`.
+fn stub_specs(
+    db: &dyn StubsDatabase,
+    project_id: ProjectId,
+    file_id: FileId,
+    stub_id: FileId,
+    format: Format,
+) -> Option<Vec<Term>> {
+    let stub_ast = stub_ast(db, project_id, file_id, stub_id, format)?;
+    let forms = etf::decode_forms(&stub_ast)?;
+    Some(
+        forms
+            .into_iter()
+            .filter(|form| {
+                matches!(
+                    forms::decode_form(form),
+                    Some(AbstractForm::Spec {
+                        callback: false,
+                        ..
+                    })
+                )
+            })
+            .collect(),
+    )
+}
+
+/// The AST of the stub in `stub_id`, parsed with the settings of the app
+/// of `file_id`. `None` if it doesn't parse.
+fn stub_ast(
+    db: &dyn StubsDatabase,
+    project_id: ProjectId,
+    file_id: FileId,
+    stub_id: FileId,
+    format: Format,
+) -> Option<Vec<u8>> {
+    let path = disk_cache::file_path(db, stub_id)?;
+    let text = db.file_text(stub_id);
+    let app_data = db.app_data(db.file_source_root(file_id))?;
+    let line_index = LineIndex::new(&text);
+    let metadata = erl_ast::elp_metadata_for_text(&line_index, &text);
+    let ast = db.load_ast(
+        project_id,
+        &path,
+        &app_data.include_path,
+        &app_data.macros,
+        &app_data.parse_transforms,
+        metadata,
+        format,
+        Some(text.as_str()),
+    );
+    match ast {
+        Ok(ast) => Some(ast),
+        Err(errors) => {
+            log::warn!(
+                "Ignoring the stub {}, which doesn't parse: {:?}",
+                path.display(),
+                errors
+            );
+            None
+        }
+    }
+}
+
+/// Encodes the forms of `ast` with the specs of `stub_specs` for the
+/// functions of `ast` without one added before `eof`. `None` if `ast`
+/// isn't ETF or no spec is missing.
+fn layer_specs(ast: &[u8], stub_specs: Vec<Term>) -> Option<Vec<u8>> {
+    let mut result = match Term::decode(ast).ok()? {
+        Term::Tuple(tuple) if tuple.elements.len() == 3 => tuple,
+        _ => return None,
+    };
+    let forms = match &mut result.elements[1] {
+        Term::List(list) => &mut list.elements,
+        _ => return None,
+    };
+    let mut functions: FxHashSet<(FunctionName, Arity)> = FxHashSet::default();
+    let mut specs: FxHashSet<(FunctionName, Arity)> = FxHashSet::default();
+    for form in forms.iter() {
+        match forms::decode_form(form) {
+            Some(AbstractForm::Function { name, arity, .. }) => {
+                functions.insert((name, arity));
+            }
+            Some(AbstractForm::Spec {
+                name,
+                arity,
+                callback: false,
+                ..
+            }) => {
+                specs.insert((name, arity));
+            }
+            _ => {}
+        }
+    }
+    let missing: Vec<Term> = stub_specs
+        .into_iter()
+        .filter(|spec| match forms::decode_form(spec) {
+            Some(AbstractForm::Spec { name, arity, .. }) => {
+                let function = (name, arity);
+                functions.contains(&function) && !specs.contains(&function)
+            }
+            _ => false,
+        })
+        .collect();
+    if missing.is_empty() {
+        return None;
+    }
+    let eof = forms
+        .iter()
+        .position(|form| {
+            etf::tuple(form)
+                .and_then(|form| form.first())
+                .and_then(etf::atom)
+                == Some("eof")
+        })
+        .unwrap_or(forms.len());
+    forms.splice(eof..eof, missing);
+    let mut bytes = Vec::new();
+    Term::Tuple(result).encode(&mut bytes).ok()?;
+    Some(bytes)
+}
@@ -0,0 +1,56 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Time spent in each phase of checking a module, for profiling.
+//!
+//! Phases are only timed once a sink is set with
+//! `RootDatabase::set_timing_sink`, otherwise only parsing and
+//! type-checking are measured, for the `metrics` module. Only actual work
+//! is timed: results salsa or the disk cache already have are not reported
+//! again.
+
+use std::panic::RefUnwindSafe;
+use std::time::Duration;
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimingPhase {
+    /// Round trip to the parse server for the AST of the module, retries
+    /// included.
+    Parse,
+    /// Decoding the ETF of the AST, e.g. to track included headers.
+    Decode,
+    /// eqWAlizer checking the module, from the moment it starts checking it
+    /// until it is done, including the time spent serving it ASTs.
+    Typecheck,
+    /// Turning eqWAlizer's output into the final diagnostics: ignore
+    /// comments, fixes and related locations. Done once for all the modules
+    /// checked together, so each of them is reported an equal share.
+    Assemble,
+}
+
+pub trait TimingSink: Send + Sync + RefUnwindSafe {
+    fn record(&self, module: &str, phase: TimingPhase, duration: Duration);
+}
+
+/// Runs `f`, reporting how long it took to `sink`, if any. `module` is
+/// only called then.
+pub(crate) fn timed<T>(
+    sink: Option<&dyn TimingSink>,
+    module: impl FnOnce() -> String,
+    phase: TimingPhase,
+    f: impl FnOnce() -> T,
+) -> T {
+    match sink {
+        Some(sink) => {
+            let start = Instant::now();
+            let result = f();
+            sink.record(&module(), phase, start.elapsed());
+            result
+        }
+        None => f(),
+    }
+}
@@ -0,0 +1,235 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Cycles of type aliases that aren't productive, which eqWAlizer rejects
+//! with an opaque `type_alias_is_non_productive`.
+//!
+//! An alias refers to another one productively when the reference is
+//! nested in a type constructor, e.g. a tuple, a list or a map: expanding
+//! the alias then terminates. References that are the alias itself, one
+//! of its union members or an annotated type don't, and a cycle of those
+//! has no meaning. References in the arguments of an alias aren't taken
+//! into account, nor are the types of included headers.
+
+use std::collections::VecDeque;
+
+use eetf::Term;
+use elp_base_db::FileId;
+use elp_base_db::ModuleIndex;
+use elp_base_db::ModuleName;
+use elp_base_db::SourceDatabase;
+use elp_parse_server::Format;
+use fxhash::FxHashMap;
+use fxhash::FxHashSet;
+use salsa::Database;
+use text_size::TextRange;
+
+use crate::etf;
+use crate::fixes;
+use crate::forms;
+use crate::forms::AbstractForm;
+use crate::reveal::Arity;
+use crate::ErlAstDatabase;
+
+/// A type defined in a module of the project.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TypeName {
+    pub module: ModuleName,
+    pub name: String,
+    pub arity: Arity,
+    pub file_id: FileId,
+    /// The whole `-type` or `-opaque` attribute.
+    pub range: TextRange,
+}
+
+/// A type, as it is referred to.
+type TypeKey = (String, String, Arity);
+
+/// The unproductive cycles of aliases going through the types of the
+/// module in `file_id`, possibly through other modules of the project.
+/// Each cycle starts at a type of the module, and lists the types in the
+/// order they refer to each other, the last one referring to the first.
+/// Types are visited in the order of their definitions, and a type is
+/// part of at most one cycle, the shortest one found first.
+pub fn type_alias_cycles(db: &dyn ErlAstDatabase, file_id: FileId) -> Vec<Vec<TypeName>> {
+    let project_id = match db.app_data(db.file_source_root(file_id)) {
+        Some(app_data) => app_data.project_id,
+        None => return vec![],
+    };
+    let module_index = db.module_index(project_id);
+    let module = match module_index.module_for_file(file_id) {
+        Some(module) => module.as_str().to_string(),
+        None => return vec![],
+    };
+    let mut graph = AliasGraph {
+        db,
+        module_index: &module_index,
+        modules: FxHashMap::default(),
+    };
+    let mut types: Vec<(TextRange, TypeKey)> = graph
+        .module_types(&module)
+        .iter()
+        .map(|(key, alias)| (alias.name.range, key.clone()))
+        .collect();
+    types.sort_by_key(|(range, _)| range.start());
+
+    let mut in_cycle: FxHashSet<TypeKey> = FxHashSet::default();
+    let mut cycles = Vec::new();
+    for (_, start) in types {
+        if in_cycle.contains(&start) {
+            continue;
+        }
+        if let Some(cycle) = graph.shortest_cycle(&start) {
+            if cycle.iter().any(|key| in_cycle.contains(key)) {
+                continue;
+            }
+            in_cycle.extend(cycle.iter().cloned());
+            cycles.push(
+                cycle
+                    .iter()
+                    .filter_map(|key| graph.alias(key).map(|alias| alias.name.clone()))
+                    .collect(),
+            );
+        }
+    }
+    cycles
+}
+
+struct Alias {
+    name: TypeName,
+    /// The types the alias refers to unproductively.
+    references: Vec<TypeKey>,
+}
+
+/// The aliases of the modules seen so far, loaded as they are referred to.
+struct AliasGraph<'a> {
+    db: &'a dyn ErlAstDatabase,
+    module_index: &'a ModuleIndex,
+    modules: FxHashMap<String, FxHashMap<TypeKey, Alias>>,
+}
+
+impl<'a> AliasGraph<'a> {
+    fn module_types(&mut self, module: &str) -> &FxHashMap<TypeKey, Alias> {
+        if !self.modules.contains_key(module) {
+            let types = self.load(module);
+            self.modules.insert(module.to_string(), types);
+        }
+        &self.modules[module]
+    }
+
+    fn alias(&mut self, key: &TypeKey) -> Option<&Alias> {
+        self.module_types(&key.0).get(key)
+    }
+
+    fn load(&self, module: &str) -> FxHashMap<TypeKey, Alias> {
+        let mut types = FxHashMap::default();
+        let file_id = match self.module_index.file_for_module(module) {
+            Some(file_id) => file_id,
+            None => return types,
+        };
+        self.db.unwind_if_cancelled();
+        let forms = match self.db.module_ast(file_id, Format::OffsetEtf) {
+            Ok(ast) => etf::decode_forms(&ast).unwrap_or_default(),
+            Err(_) => return types,
+        };
+        for form in fixes::own_forms(&forms) {
+            if let Some(AbstractForm::Type {
+                name,
+                params,
+                range,
+                definition,
+                ..
+            }) = forms::decode_form(form)
+            {
+                let arity = match Arity::try_from(params.len()) {
+                    Ok(arity) => arity,
+                    Err(_) => continue,
+                };
+                let mut references = Vec::new();
+                unproductive_references(module, &definition, &mut references);
+                let key = (module.to_string(), name.clone(), arity);
+                let name = TypeName {
+                    module: ModuleName::new(module),
+                    name,
+                    arity,
+                    file_id,
+                    range,
+                };
+                types.insert(key, Alias { name, references });
+            }
+        }
+        types
+    }
+
+    /// The shortest path from `start` back to itself, `start` first.
+    fn shortest_cycle(&mut self, start: &TypeKey) -> Option<Vec<TypeKey>> {
+        let mut previous: FxHashMap<TypeKey, TypeKey> = FxHashMap::default();
+        let mut queue = VecDeque::new();
+        queue.push_back(start.clone());
+        while let Some(key) = queue.pop_front() {
+            let references = match self.alias(&key) {
+                Some(alias) => alias.references.clone(),
+                None => continue,
+            };
+            for reference in references {
+                if &reference == start {
+                    let mut cycle = vec![key.clone()];
+                    while let Some(before) = previous.get(cycle.last()?) {
+                        cycle.push(before.clone());
+                    }
+                    cycle.reverse();
+                    return Some(cycle);
+                }
+                if !previous.contains_key(&reference) {
+                    previous.insert(reference.clone(), key.clone());
+                    queue.push_back(reference);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Adds the types `ty`, a type of `module`, refers to outside of any type
+/// constructor.
+fn unproductive_references(module: &str, ty: &Term, references: &mut Vec<TypeKey>) {
+    match etf::tuple(ty) {
+        Some([tag, _anno, name, args]) if etf::atom(tag) == Some("user_type") => {
+            if let (Some(name), Some(arity)) = (
+                etf::atom(name),
+                etf::list(args).and_then(|args| Arity::try_from(args.len()).ok()),
+            ) {
+                references.push((module.to_string(), name.to_string(), arity));
+            }
+        }
+        Some([tag, _anno, remote]) if etf::atom(tag) == Some("remote_type") => {
+            if let Some([module, name, args]) = etf::list(remote) {
+                if let (Some(module), Some(name), Some(arity)) = (
+                    etf::atom_literal(module),
+                    etf::atom_literal(name),
+                    etf::list(args).and_then(|args| Arity::try_from(args.len()).ok()),
+                ) {
+                    references.push((module.to_string(), name.to_string(), arity));
+                }
+            }
+        }
+        Some([tag, _anno, types])
+            if matches!(etf::atom(tag), Some("ann_type") | Some("paren_type")) =>
+        {
+            if let Some(ty) = etf::list(types).and_then(|types| types.last()) {
+                unproductive_references(module, ty, references);
+            }
+        }
+        Some([tag, _anno, name, types])
+            if etf::atom(tag) == Some("type") && etf::atom(name) == Some("union") =>
+        {
+            for ty in etf::list(types).unwrap_or_default() {
+                unproductive_references(module, ty, references);
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,238 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! The types in scope in a module once preprocessed, with their
+//! definitions as eqWAlizer resolves them, e.g. for documentation.
+//!
+//! The scope is made of the types defined in the module, those of the
+//! headers it includes, and the remote types it refers to. Definitions are
+//! revealed with the same trick as in `api_surface`: the types of headers
+//! in the context of the module, remote types in the context of their own
+//! module. Built-in types aren't part of it.
+//!
+//! This is a best-effort approximation of the environment eqWAlizer checks
+//! the module in, which it doesn't expose: each definition is what
+//! eqWAlizer reveals when the type is spliced into a copy of the module,
+//! so a type whose copy doesn't check, e.g. because a header doesn't
+//! compile in the context of the module, gets `reveal::NO_TYPE` as its
+//! definition.
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use eetf::Term;
+use elp_base_db::FileId;
+use elp_base_db::FileLoader;
+use elp_base_db::ModuleIndex;
+use elp_base_db::ProjectId;
+use elp_base_db::SourceDatabase;
+use elp_parse_server::Format;
+
+use crate::api_surface;
+use crate::api_surface::ApiType;
+use crate::erl_ast;
+use crate::etf;
+use crate::fixes;
+use crate::forms;
+use crate::forms::AbstractForm;
+use crate::reveal;
+use crate::reveal::Arity;
+use crate::ErlAstDatabase;
+use crate::RootDatabase;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TypeEnv {
+    pub module: String,
+    /// The types in scope by the name they are referred to with: `t/1` for
+    /// local types and `m:t/1` for remote ones.
+    pub types: BTreeMap<String, ScopedType>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopedType {
+    /// Names of the type variables.
+    pub params: Vec<String>,
+    /// The definition, as pretty-printed by eqWAlizer. `None` for opaque
+    /// types, whose definition is hidden outside of their module.
+    pub definition: Option<String>,
+    /// The module the type is defined in.
+    pub module: String,
+    /// The header the type is defined in, as included, for the types of
+    /// included headers.
+    pub header: Option<String>,
+}
+
+/// The types in scope in the module in `file_id`. Remote types are only
+/// part of it if they are defined in a module of the project, and opaque
+/// local types have their definition. `None` if the module doesn't parse.
+/// Definitions are revealed, not taken from eqWAlizer's own environment,
+/// see the module documentation.
+pub fn type_environment(db: &RootDatabase, file_id: FileId) -> Option<TypeEnv> {
+    let forms = db
+        .module_ast(file_id, Format::OffsetEtf)
+        .ok()
+        .and_then(|ast| etf::decode_forms(&ast))?;
+    let project_id = db.app_data(db.file_source_root(file_id))?.project_id;
+    let module_index = db.module_index(project_id);
+    let module = module_index.module_for_file(file_id)?.as_str().to_string();
+
+    let mut types = BTreeMap::new();
+    let (local, headers, sources) = in_scope_types(db, project_id, file_id, &forms);
+    let definitions = api_surface::revealed_type_definitions(db, file_id, &local, &sources);
+    for (ty, header) in definitions.into_iter().zip(headers) {
+        let key = format!("{}/{}", reveal::quote_atom(&ty.name), ty.params.len());
+        types.insert(key, scoped_type(ty, &module, header));
+    }
+    for (key, ty) in remote_types(db, &module_index, &module, &forms) {
+        types.insert(key, ty);
+    }
+    Some(TypeEnv { module, types })
+}
+
+/// The `Type` forms of the module and of its headers, with the header each
+/// one comes from and the text of its definition.
+fn in_scope_types(
+    db: &RootDatabase,
+    project_id: ProjectId,
+    file_id: FileId,
+    forms: &[Term],
+) -> (Vec<AbstractForm>, Vec<Option<String>>, Vec<Option<String>>) {
+    let text = db.file_text(file_id).to_string();
+    let mut header_texts: BTreeMap<String, Option<String>> = BTreeMap::new();
+    let mut own_path = None;
+    let mut current = None;
+    let mut types = Vec::new();
+    let mut headers = Vec::new();
+    let mut sources = Vec::new();
+    for form in forms {
+        match forms::decode_form(form) {
+            Some(AbstractForm::Attribute { name, value, .. }) if name == "file" => {
+                let path = etf::tuple(&value).and_then(|value| etf::string(value.first()?));
+                if own_path.is_none() {
+                    own_path = path.clone();
+                }
+                current = path.filter(|path| Some(path) != own_path.as_ref());
+            }
+            Some(ty @ AbstractForm::Type { .. }) => {
+                let source = match &current {
+                    None => api_surface::definition_source(&text, &ty),
+                    Some(header) => header_texts
+                        .entry(header.clone())
+                        .or_insert_with(|| header_text(db, project_id, header))
+                        .as_deref()
+                        .and_then(|text| api_surface::definition_source(text, &ty)),
+                };
+                // Opaque types are visible in their own module
+                let ty = match ty {
+                    AbstractForm::Type {
+                        name,
+                        params,
+                        range,
+                        definition,
+                        ..
+                    } => AbstractForm::Type {
+                        name,
+                        params,
+                        range,
+                        opaque: false,
+                        definition,
+                    },
+                    ty => ty,
+                };
+                types.push(ty);
+                headers.push(current.clone());
+                sources.push(source);
+            }
+            _ => {}
+        }
+    }
+    (types, headers, sources)
+}
+
+/// The text of the header at `path`, tracked if it is in the source roots
+/// of the project.
+fn header_text(db: &RootDatabase, project_id: ProjectId, path: &str) -> Option<String> {
+    match erl_ast::project_file(db, project_id, path) {
+        Some(header_id) => Some(db.file_text(header_id).to_string()),
+        None => fs::read_to_string(path).ok(),
+    }
+}
+
+/// The remote types `forms` refer to, defined in modules of the project.
+fn remote_types(
+    db: &RootDatabase,
+    module_index: &ModuleIndex,
+    module: &str,
+    forms: &[Term],
+) -> Vec<(String, ScopedType)> {
+    let mut referenced: BTreeMap<String, Vec<(String, Arity)>> = BTreeMap::new();
+    for form in forms {
+        etf::walk(form, &mut |term| {
+            if let Some([tag, _anno, remote]) = etf::tuple(term) {
+                if etf::atom(tag) == Some("remote_type") {
+                    if let Some([remote, name, args]) = etf::list(remote) {
+                        if let (Some(remote), Some(name), Some(arity)) = (
+                            etf::atom_literal(remote),
+                            etf::atom_literal(name),
+                            etf::list(args).and_then(|args| Arity::try_from(args.len()).ok()),
+                        ) {
+                            referenced
+                                .entry(remote.to_string())
+                                .or_default()
+                                .push((name.to_string(), arity));
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    let mut types = Vec::new();
+    for (remote, names) in referenced {
+        if remote == module {
+            continue;
+        }
+        let remote_id = match module_index.file_for_module(remote.as_str()) {
+            Some(remote_id) => remote_id,
+            None => continue,
+        };
+        let remote_forms = match db.module_ast(remote_id, Format::OffsetEtf) {
+            Ok(ast) => etf::decode_forms(&ast).unwrap_or_default(),
+            Err(_) => continue,
+        };
+        let remote_types: Vec<AbstractForm> = fixes::own_forms(&remote_forms)
+            .into_iter()
+            .filter_map(|form| match forms::decode_form(form) {
+                Some(ty @ AbstractForm::Type { .. }) => Some(ty),
+                _ => None,
+            })
+            .filter(|ty| match ty {
+                AbstractForm::Type { name, params, .. } => Arity::try_from(params.len())
+                    .map_or(false, |arity| names.contains(&(name.clone(), arity))),
+                _ => false,
+            })
+            .collect();
+        for ty in api_surface::type_definitions(db, remote_id, &remote_types) {
+            let key = format!(
+                "{}:{}/{}",
+                reveal::quote_atom(&remote),
+                reveal::quote_atom(&ty.name),
+                ty.params.len()
+            );
+            types.push((key, scoped_type(ty, &remote, None)));
+        }
+    }
+    types
+}
+
+fn scoped_type(ty: ApiType, module: &str, header: Option<String>) -> ScopedType {
+    ScopedType {
+        params: ty.params,
+        definition: ty.definition,
+        module: module.to_string(),
+        header,
+    }
+}
@@ -0,0 +1,278 @@
+/* Copyright (c) Meta Platforms, Inc. and affiliates. All rights reserved.
+ *
+ * This source code is licensed under the Apache 2.0 license found in
+ * the LICENSE file in the root directory of this source tree.
+ */
+
+//! Clauses eqWAlizer proves no value can reach.
+//!
+//! eqWAlizer narrows the types of the values a clause matches with its
+//! patterns and guards, and with the clauses before it, but only reports
+//! these types through `eqwalizer:reveal_type/1`. So in a copy of the
+//! module, the variables the patterns of the function and `case` clauses
+//! bind are revealed at the start of the bodies, the patterns being left
+//! as they are: a clause no value reaches gives one of them the type
+//! `none()`. Clauses whose patterns bind no variable are thus never
+//! reported. The copy is type-checked on the side, the database being left
+//! untouched.
+
+use std::sync::Arc;
+
+use eetf::Term;
+use elp_base_db::FileId;
+use elp_base_db::SourceDatabase;
+use elp_eqwalizer::EqwalizerDiagnostics;
+use elp_parse_server::Format;
+use fxhash::FxHashMap;
+use fxhash::FxHashSet;
+use text_size::TextRange;
+use text_size::TextSize;
+
+use crate::eqwalizer::EqwalizerLoader;
+use crate::etf;
+use crate::fixes;
+use crate::forms;
+use crate::forms::AbstractForm;
+use crate::reveal;
+use crate::reveal::Arity;
+use crate::reveal::VarName;
+use crate::EqwalizerDatabase;
+use crate::ErlAstDatabase;
+
+const NONE: &str = "none()";
+
+/// A clause no value can reach.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnreachableBranch {
+    /// The whole clause: patterns, guards and body.
+    pub range: TextRange,
+    /// Why, e.g. ``no value of `X` passes the guard``.
+    pub reason: String,
+}
+
+struct Clause {
+    range: TextRange,
+    guarded: bool,
+}
+
+/// Reveal inserted at `at` in the copy of the module, of the variable
+/// `name` of the clause at index `clause`.
+struct Insertion {
+    at: TextSize,
+    clause: usize,
+    name: VarName,
+}
+
+/// The function and `case` clauses of the module in `file_id` eqWAlizer
+/// proves no value reaches, in non-strict mode, sorted by position. Only
+/// the functions eqWAlizer checks, e.g. those with a spec, have any. The
+/// arguments a spec gives the type `none()` are left out, all their
+/// clauses being unreachable by design. Empty if the module doesn't parse.
+pub(crate) fn unreachable_branches(
+    db: &dyn EqwalizerDatabase,
+    file_id: FileId,
+) -> Arc<Vec<UnreachableBranch>> {
+    let forms = match db
+        .module_ast(file_id, Format::OffsetEtf)
+        .ok()
+        .and_then(|ast| etf::decode_forms(&ast))
+    {
+        Some(forms) => forms,
+        None => return Default::default(),
+    };
+    let project_id = match db.app_data(db.file_source_root(file_id)) {
+        Some(app_data) => app_data.project_id,
+        None => return Default::default(),
+    };
+
+    let own_forms = fixes::own_forms(&forms);
+    let mut none_params: FxHashMap<(String, Arity), FxHashSet<usize>> = FxHashMap::default();
+    for form in own_forms.iter().filter_map(|form| forms::decode_form(form)) {
+        if let AbstractForm::Spec {
+            module: None,
+            name,
+            arity,
+            callback: false,
+            clauses,
+            ..
+        } = form
+        {
+            none_params.insert((name, arity), spec_none_params(&clauses));
+        }
+    }
+    let mut clauses = Vec::new();
+    let mut insertions = Vec::new();
+    for form in own_forms {
+        let (name, arity, function_clauses) = match etf::tuple(form) {
+            Some([tag, _anno, name, arity, function_clauses])
+                if etf::atom(tag) == Some("function") =>
+            {
+                (
+                    etf::atom(name).unwrap_or_default(),
+                    etf::int(arity).and_then(|arity| Arity::try_from(arity).ok()),
+                    etf::list(function_clauses).unwrap_or_default(),
+                )
+            }
+            _ => continue,
+        };
+        let skipped = arity
+            .and_then(|arity| none_params.get(&(name.to_string(), arity)))
+            .cloned()
+            .unwrap_or_default();
+        for clause in function_clauses {
+            probe_clause(clause, &skipped, &mut clauses, &mut insertions);
+        }
+        etf::walk(form, &mut |term| {
+            if let Some([tag, _anno, _expr, case_clauses]) = etf::tuple(term) {
+                if etf::atom(tag) == Some("case") {
+                    for clause in etf::list(case_clauses).unwrap_or_default() {
+                        probe_clause(clause, &FxHashSet::default(), &mut clauses, &mut insertions);
+                    }
+                }
+            }
+        });
+    }
+    if insertions.is_empty() {
+        return Default::default();
+    }
+    let text = db.file_text(file_id);
+
+    // Stable, so that the reveals at the start of a body stay in order
+    insertions.sort_by_key(|insertion| insertion.at);
+    let mut variant = String::with_capacity(text.len());
+    let mut last = 0;
+    // Start of the revealed expression in the variant text, by clause
+    let mut reveals: Vec<Vec<(VarName, TextSize)>> = clauses.iter().map(|_| vec![]).collect();
+    for insertion in insertions {
+        let at: usize = insertion.at.into();
+        if at < last || !text.is_char_boundary(at) {
+            return Default::default();
+        }
+        variant.push_str(&text[last..at]);
+        variant.push_str(reveal::REVEAL_PREFIX);
+        reveals[insertion.clause].push((insertion.name.clone(), TextSize::of(variant.as_str())));
+        variant.push_str(&insertion.name);
+        variant.push_str(reveal::REVEAL_SUFFIX);
+        variant.push_str(", ");
+        last = at;
+    }
+    variant.push_str(&text[last..]);
+
+    let types: FxHashMap<TextSize, String> =
+        match db.typecheck_text_tolerating_errors(project_id, file_id, &variant) {
+            Ok(EqwalizerDiagnostics::Diagnostics(diagnostics)) => diagnostics
+                .into_values()
+                .flatten()
+                .filter(|d| d.code == "reveal_type")
+                .map(|d| (d.range.start(), d.message))
+                .collect(),
+            _ => return Default::default(),
+        };
+    let is_none = |start: &TextSize| types.get(start).map(String::as_str) == Some(NONE);
+
+    let mut branches: Vec<UnreachableBranch> = clauses
+        .iter()
+        .zip(reveals)
+        .filter_map(|(clause, reveals)| {
+            let (name, _) = reveals.iter().find(|(_, start)| is_none(start))?;
+            let reason = if clause.guarded {
+                format!("no value of `{}` passes the guard", name)
+            } else {
+                format!("`{}` can't have any value here", name)
+            };
+            Some(UnreachableBranch {
+                range: clause.range,
+                reason,
+            })
+        })
+        .collect();
+    branches.sort_by_key(|branch| branch.range.start());
+    Arc::new(branches)
+}
+
+/// The indexes of the arguments some clause of a spec gives the type
+/// `none()`, or `no_return()`. No value can be passed there, so the
+/// variables bound by these arguments are `none()` in every clause.
+fn spec_none_params(spec_clauses: &[Term]) -> FxHashSet<usize> {
+    let mut none_params = FxHashSet::default();
+    for clause in spec_clauses {
+        let fun = match etf::tuple(clause) {
+            Some([tag, _anno, kind, args])
+                if etf::atom(tag) == Some("type") && etf::atom(kind) == Some("bounded_fun") =>
+            {
+                etf::list(args).and_then(|args| args.first())
+            }
+            _ => Some(clause),
+        };
+        let params = match fun.and_then(etf::tuple) {
+            Some([tag, _anno, kind, args])
+                if etf::atom(tag) == Some("type") && etf::atom(kind) == Some("fun") =>
+            {
+                match etf::list(args).and_then(|args| etf::tuple(args.first()?)) {
+                    Some([_tag, _anno, product, params])
+                        if etf::atom(product) == Some("product") =>
+                    {
+                        etf::list(params).unwrap_or_default()
+                    }
+                    _ => continue,
+                }
+            }
+            _ => continue,
+        };
+        for (i, param) in params.iter().enumerate() {
+            if let Some([tag, _anno, name, _args]) = etf::tuple(param) {
+                if etf::atom(tag) == Some("type")
+                    && matches!(etf::atom(name), Some("none") | Some("no_return"))
+                {
+                    none_params.insert(i);
+                }
+            }
+        }
+    }
+    none_params
+}
+
+/// Adds `clause` to `clauses`, with the insertions revealing the variables
+/// its patterns bind at the start of its body, those of the patterns at
+/// the indexes of `skipped` aside.
+fn probe_clause(
+    clause: &Term,
+    skipped: &FxHashSet<usize>,
+    clauses: &mut Vec<Clause>,
+    insertions: &mut Vec<Insertion>,
+) {
+    let (range, patterns, guards, body) = match etf::tuple(clause) {
+        Some([tag, anno, patterns, guards, body]) if etf::atom(tag) == Some("clause") => {
+            match (etf::anno_range(anno), etf::list(patterns), etf::list(body)) {
+                (Some(range), Some(patterns), Some(body)) => (range, patterns, guards, body),
+                _ => return,
+            }
+        }
+        _ => return,
+    };
+    let body_start = match body
+        .first()
+        .and_then(|e| etf::anno_range(etf::tuple(e)?.get(1)?))
+    {
+        Some(range) => range.start(),
+        None => return,
+    };
+    let index = clauses.len();
+    let mut revealed: FxHashSet<String> = FxHashSet::default();
+    for (i, pattern) in patterns.iter().enumerate() {
+        for (name, _range) in reveal::new_variables(pattern, &revealed) {
+            revealed.insert(name.clone());
+            if !skipped.contains(&i) {
+                insertions.push(Insertion {
+                    at: body_start,
+                    clause: index,
+                    name,
+                });
+            }
+        }
+    }
+    clauses.push(Clause {
+        range,
+        guarded: etf::list(guards).map_or(false, |guards| !guards.is_empty()),
+    });
+}
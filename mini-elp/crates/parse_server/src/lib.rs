@@ -16,6 +16,8 @@ use std::process::ChildStdin;
 use std::process::ChildStdout;
 use std::process::Command;
 use std::process::Stdio;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::sync::Mutex;
 
@@ -27,6 +29,7 @@ use crossbeam_channel::Receiver;
 use crossbeam_channel::Sender;
 use eetf::pattern;
 use fxhash::FxHashMap;
+use fxhash::FxHashSet;
 use jod_thread::JoinHandle;
 use stdx::JodChild;
 use tempfile::Builder;
@@ -63,15 +66,30 @@ struct SharedState {
 #[derive(Clone, Debug)]
 pub struct Connection {
     sender: Sender<Request>,
+    code_paths: Arc<Mutex<FxHashSet<PathBuf>>>,
+    /// Cleared once the server stops answering, e.g. when it crashed.
+    alive: Arc<AtomicBool>,
     _for_drop: Arc<SharedState>,
 }
 
+/// Whether a `Connection` still has a parse server on the other end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerHealth {
+    Alive,
+    /// The server exited or closed its output, so requests can't succeed.
+    /// A new `Connection` has to be started.
+    Dead,
+}
+
 #[derive(Debug, Clone)]
 pub enum CompileOption {
     Includes(Vec<PathBuf>),
     Macros(Vec<eetf::Term>),
     ParseTransforms(Vec<eetf::Term>),
     ElpMetadata(eetf::Term),
+    /// Parse this text instead of reading the file from disk. The path is
+    /// still used to resolve relative includes and for `?FILE`.
+    FileText(String),
 }
 
 impl Into<eetf::Term> for CompileOption {
@@ -99,14 +117,36 @@ impl Into<eetf::Term> for CompileOption {
                 let label = eetf::Atom::from("elp_metadata");
                 eetf::Tuple::from(vec![label.into(), elp_metadata]).into()
             }
+            CompileOption::FileText(text) => {
+                let label = eetf::Atom::from("file_text");
+                let text = eetf::Binary {
+                    bytes: text.into_bytes(),
+                };
+                eetf::Tuple::from(vec![label.into(), text.into()]).into()
+            }
         }
     }
 }
 
+/// Version of the requests and replies exchanged with the parse server,
+/// bumped on every incompatible change to them.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// Discriminants are explicit since they end up in on-disk caches: new
+// variants must be added with a new value
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Format {
-    OffsetEtf,
-    Text,
+    OffsetEtf = 0,
+    Text = 1,
+    /// Abstract forms after preprocessing (macros expanded, includes
+    /// resolved), with the same offset annotations as `OffsetEtf`, but
+    /// neither ELP metadata nor linting.
+    ExpandedForms = 2,
+}
+
+impl Format {
+    /// Every format the parse server can produce.
+    pub const ALL: [Format; 3] = [Format::OffsetEtf, Format::Text, Format::ExpandedForms];
 }
 
 #[derive(Debug, Clone)]
@@ -166,10 +206,13 @@ impl Connection {
         let mut proc = cmd.spawn()?;
         let escript = escript.into_temp_path();
 
-        let (sender, writer, reader) = stdio_transport(&mut proc);
+        let alive = Arc::new(AtomicBool::new(true));
+        let (sender, writer, reader) = stdio_transport(&mut proc, alive.clone());
 
         Ok(Connection {
             sender,
+            code_paths: Default::default(),
+            alive,
             _for_drop: Arc::new(SharedState {
                 _file_for_drop: escript,
                 _child_for_drop: JodChild(proc),
@@ -179,21 +222,57 @@ impl Connection {
         })
     }
 
+    /// Panics if the server is dead, see `try_request`.
     pub fn request(&self, request: ParseRequest) -> Result<Vec<u8>, Vec<ParseError>> {
+        self.try_request(request)
+            .expect("parse_server is not running")
+    }
+
+    /// Same as `request`, `None` if the server died before answering.
+    pub fn try_request(&self, request: ParseRequest) -> Option<Result<Vec<u8>, Vec<ParseError>>> {
         let (sender, receiver) = bounded::<Response>(0);
         let request = Request::ParseRequest(request, sender);
-        self.sender.send(request).unwrap();
-        let response = receiver.recv().unwrap();
-        response.decode()
+        self.sender.send(request).ok()?;
+        let response = receiver.recv().ok()?;
+        Some(response.decode())
+    }
+
+    /// Whether `self` and `other` are connections to the same server, e.g.
+    /// clones of one another.
+    pub fn is_same_server(&self, other: &Connection) -> bool {
+        Arc::ptr_eq(&self.alive, &other.alive)
     }
 
+    pub fn health(&self) -> ServerHealth {
+        if self.alive.load(Ordering::SeqCst) {
+            ServerHealth::Alive
+        } else {
+            ServerHealth::Dead
+        }
+    }
+
+    /// Adds `paths` to the code path of the parse server. Paths that were
+    /// already added are skipped, so this is cheap to call repeatedly.
     pub fn add_code_path(&self, paths: Vec<PathBuf>) {
-        let request = Request::AddCodePath(paths);
-        self.sender.send(request).unwrap();
+        let paths: Vec<_> = {
+            let mut code_paths = self.code_paths.lock().unwrap();
+            paths
+                .into_iter()
+                .filter(|path| code_paths.insert(path.clone()))
+                .collect()
+        };
+        if !paths.is_empty() {
+            let request = Request::AddCodePath(paths);
+            // A dead server fails the next request anyway
+            let _ = self.sender.send(request);
+        }
     }
 }
 
-fn stdio_transport(proc: &mut Child) -> (Sender<Request>, JoinHandle, JoinHandle) {
+fn stdio_transport(
+    proc: &mut Child,
+    alive: Arc<AtomicBool>,
+) -> (Sender<Request>, JoinHandle, JoinHandle) {
     let instream = BufWriter::new(proc.stdin.take().unwrap());
     let outstream = BufReader::new(proc.stdout.take().unwrap());
 
@@ -209,9 +288,14 @@ fn stdio_transport(proc: &mut Child) -> (Sender<Request>, JoinHandle, JoinHandle
     });
 
     let reader = jod_thread::spawn({
-        move || match reader_run(outstream, inflight) {
-            Ok(()) => {}
-            Err(err) => log::error!("reader failed with {}", err),
+        move || {
+            match reader_run(outstream, inflight.clone()) {
+                Ok(()) => {}
+                Err(err) => log::error!("reader failed with {}", err),
+            }
+            // Nothing will answer the pending requests anymore
+            alive.store(false, Ordering::SeqCst);
+            inflight.lock().unwrap().clear();
         }
     });
 
@@ -317,6 +401,7 @@ impl ParseRequest {
         match self.format {
             Format::OffsetEtf { .. } => "COMPILE",
             Format::Text => "TEXT",
+            Format::ExpandedForms => "EXPAND",
         }
     }
 
@@ -28,12 +28,16 @@ process(<<"ADD_PATHS ", BinLen/binary>>, State) ->
     add_paths(BinLen, State);
 process(<<"COMPILE ", BinLen/binary>>, State) ->
     PostProcess = fun(Forms, _FileName) -> term_to_binary({ok, Forms, []}) end,
-    process1(BinLen, State, PostProcess);
+    process1(BinLen, State, lint, PostProcess);
 process(<<"TEXT ", BinLen/binary>>, State) ->
     PostProcess = fun(Forms, _) ->
         unicode:characters_to_binary(lists:join($\n, [io_lib:format("~p.~n", [Form]) || Form <- Forms]))
     end,
-    process1(BinLen, State, PostProcess);
+    process1(BinLen, State, lint, PostProcess);
+%% Preprocessed forms only, without linting them
+process(<<"EXPAND ", BinLen/binary>>, State) ->
+    PostProcess = fun(Forms, _FileName) -> term_to_binary({ok, Forms, []}) end,
+    process1(BinLen, State, nolint, PostProcess);
 process(<<"EXIT">>, State) ->
     init:stop(),
     State.
@@ -55,13 +59,13 @@ collect_paths(Len, State) ->
             [Path | collect_paths(Len - 1, State)]
     end.
 
-process1(BinLen, State, PostProcess) ->
+process1(BinLen, State, Lint, PostProcess) ->
     Len = binary_to_integer(BinLen),
     %% Use file:read/2 since it reads bytes
     {ok, Data} = file:read(State#state.io, Len),
     spawn_link(fun() ->
         {Id, FileName, Options} = binary_to_term(Data),
-        try run_process(Id, FileName, Options, State, PostProcess)
+        try run_process(Id, FileName, Options, State, Lint, PostProcess)
         catch
             Class:Reason:StackTrace ->
                 Formatted = erl_error:format_exception(Class, Reason, StackTrace),
@@ -71,8 +75,20 @@ process1(BinLen, State, PostProcess) ->
     end),
     State.
 
-run_process(Id, FileName, Options, State, PostProcess) ->
-    Module = epp_module(Options),
+run_process(Id, FileName, Options0, State, nolint, PostProcess) ->
+    Module = epp_module(Options0),
+    Options = open_file_text(Options0),
+    case Module:parse_file(FileName, Options) of
+        {ok, Forms} ->
+            Result = PostProcess(Forms, FileName),
+            reply(<<"OK">>, Id, Result, State);
+        {error, Reason} ->
+            Msg = unicode:characters_to_binary(file:format_error(Reason)),
+            reply(<<"ERROR">>, Id, Msg, State)
+    end;
+run_process(Id, FileName, Options0, State, lint, PostProcess) ->
+    Module = epp_module(Options0),
+    Options = open_file_text(Options0),
     case Module:parse_file(FileName, Options) of
         {ok, Forms0} ->
             Forms1 = case proplists:get_value(elp_metadata, Options) of
@@ -100,6 +116,17 @@ run_process(Id, FileName, Options, State, PostProcess) ->
             reply(<<"ERROR">>, Id, Msg, State)
     end.
 
+%% When the text of the file is provided, parse it from memory
+%% instead of reading the file from disk
+open_file_text(Options) ->
+    case proplists:get_value(file_text, Options) of
+        undefined ->
+            Options;
+        Text ->
+            {ok, Fd} = file:open(Text, [ram, binary, read]),
+            [{fd, Fd} | proplists:delete(file_text, Options)]
+    end.
+
 epp_module(Options) ->
     case proplists:get_value(location, Options) of
         offset -> elp_epp;